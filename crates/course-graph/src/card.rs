@@ -10,6 +10,17 @@ pub struct CardNode {
     pub dependents: Vec<String>,
 }
 
+impl CardNode {
+    /// Nothing depends on this card.
+    pub fn is_root(&self) -> bool {
+        self.dependents.is_empty()
+    }
+    /// Card doesn't depend on anything, so it's an entry point of course.
+    pub fn is_leaf(&self) -> bool {
+        self.dependencies.is_empty()
+    }
+}
+
 impl Card {
     /// # Safety
     /// Should not contain cycles
@@ -1,7 +1,11 @@
-use std::{collections::HashMap, fmt::Debug, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    str::FromStr,
+};
 
-use dot_structures::{Graph, Node, Stmt};
-use graphviz_rust::attributes::NodeAttributes;
+use dot_structures::{Attribute, Graph, Id, Node, Stmt, Subgraph};
+use graphviz_rust::attributes::{NodeAttributes, color_name, shape};
 use serde::{
     Deserialize, Serialize,
     de::{Error, Visitor},
@@ -9,6 +13,18 @@ use serde::{
 
 use crate::card::CardNode;
 
+/// Card names, that are only in graph or only in deque. Both lists are sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CardDiff {
+    pub missing_in_deque: Vec<String>,
+    pub extra_in_deque: Vec<String>,
+}
+impl CardDiff {
+    pub fn is_empty(&self) -> bool {
+        self.missing_in_deque.is_empty() && self.extra_in_deque.is_empty()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct CourseGraph {
     pub(crate) text: String,
@@ -33,26 +49,104 @@ impl CourseGraph {
             .chain(
                 self.cards
                     .iter()
-                    .filter(|(_, card)| card.dependents.is_empty())
+                    .filter(|(_, card)| card.is_root())
                     .map(|(x, _)| x)
                     .flat_map(|top_level_dependency| {
                         generate_edge_stmts("Finish", top_level_dependency)
                     }),
             )
     }
-    pub fn generate_structure_graph(&self) -> Graph {
+    /// `layered` puts cards of the same depth(see [`CourseGraph::card_depths`]) in one row.
+    pub fn generate_structure_graph(&self, layered: bool) -> Graph {
+        let mut stmts = self.generate_stmts().collect::<Vec<_>>();
+        if layered {
+            stmts.extend(self.generate_rank_stmts());
+        }
         Graph::Graph {
             id: id_from_string("G"),
             strict: true,
-            stmts: self.generate_stmts().collect(),
+            stmts,
+        }
+    }
+    /// One `rank=same` subgraph per depth.
+    fn generate_rank_stmts(&self) -> impl Iterator<Item = Stmt> {
+        let mut layers = BTreeMap::<usize, Vec<String>>::new();
+        for (name, depth) in self.card_depths() {
+            layers.entry(depth).or_default().push(name);
         }
+        layers.into_iter().map(|(depth, mut names)| {
+            names.sort();
+            Stmt::Subgraph(Subgraph {
+                id: Id::Plain(format!("depth_{depth}")),
+                stmts: [Stmt::Attribute(Attribute(
+                    Id::Plain("rank".into()),
+                    Id::Plain("same".into()),
+                ))]
+                .into_iter()
+                .chain(names.iter().map(|name| node_stmt(name)))
+                .collect(),
+            })
+        })
     }
     pub fn cards(&self) -> &HashMap<String, CardNode> {
         &self.cards
     }
+    /// Cards, nothing depends on. In arbitrary order.
+    pub fn roots(&self) -> impl Iterator<Item = &String> {
+        self.cards
+            .iter()
+            .filter(|(_, card)| card.is_root())
+            .map(|(name, _)| name)
+    }
+    /// Cards without dependencies, learning starts from them. In arbitrary order.
+    pub fn leaves(&self) -> impl Iterator<Item = &String> {
+        self.cards
+            .iter()
+            .filter(|(_, card)| card.is_leaf())
+            .map(|(name, _)| name)
+    }
     pub fn get_source(&self) -> &str {
         &self.text
     }
+    /// Compares cards with card names of deque. Names should be already normalized.
+    pub fn diff_cards<'a>(&self, deque_names: impl Iterator<Item = &'a str>) -> CardDiff {
+        let deque_names = deque_names.collect::<HashSet<_>>();
+        let mut missing_in_deque = self
+            .cards
+            .keys()
+            .filter(|name| !deque_names.contains(name.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        missing_in_deque.sort();
+        let mut extra_in_deque = deque_names
+            .into_iter()
+            .filter(|&name| !self.cards.contains_key(name))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        extra_in_deque.sort();
+        CardDiff {
+            missing_in_deque,
+            extra_in_deque,
+        }
+    }
+    /// Graph with cards of both graphs. Card names should be unique across both graphs:
+    /// shared name is an error, because it's unclear, whether it means the same card.
+    pub fn merge(&self, other: &CourseGraph, case_sensitive: bool) -> Result<CourseGraph, String> {
+        let mut shared = self
+            .cards
+            .keys()
+            .filter(|&name| other.cards.contains_key(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !shared.is_empty() {
+            shared.sort();
+            return Err(format!("both graphs have cards: {}", shared.join(", ")));
+        }
+        CourseGraph::parse(
+            &format!("{}\n{}", self.text.trim_end(), other.text.trim_start()),
+            case_sensitive,
+        )
+    }
 }
 
 fn generate_edge_stmts(first: &str, second: &str) -> impl Iterator<Item = Stmt> {
@@ -72,46 +166,494 @@ fn node_stmt(name: &str) -> Stmt {
 }
 
 use crate::{
-    progress_store::{TaskProgress, TaskProgressStore},
+    parsing::normalize_card_name,
+    progress_store::{TaskProgress, TaskProgressStore, progress_mark},
     utils::*,
 };
 
 impl CourseGraph {
-    fn propagate_fail(&self, name: &String, store: &mut impl TaskProgressStore<Id = String>) {
-        store.update_recursive_failed(name);
-        self.cards[name]
-            .dependents
-            .iter()
-            .for_each(|x| self.propagate_fail(x, store));
+    /// `depth_left` is how many levels of dependents should be updated. None means unlimited.
+    /// Graphs can be deep, so explicit stack is used instead of recursion.
+    /// Dependents are pushed in reverse, so cards are visited in the same order as recursively.
+    /// `visited` is shared between calls of one pass. Card is skipped, if it was already
+    /// processed with at least the same `depth_left`, because its dependents are updated too.
+    fn propagate_fail<'a>(
+        &'a self,
+        name: &'a String,
+        store: &mut impl TaskProgressStore<Id = String>,
+        depth_left: Option<usize>,
+        visited: &mut HashMap<&'a String, Option<usize>>,
+    ) {
+        let mut stack = vec![(name, depth_left)];
+        while let Some((name, depth_left)) = stack.pop() {
+            if !store.contains(name) {
+                continue;
+            }
+            let processed =
+                visited
+                    .get(name)
+                    .is_some_and(|&visited_depth| match (visited_depth, depth_left) {
+                        (None, _) => true,
+                        (Some(_), None) => false,
+                        (Some(visited_depth), Some(depth_left)) => visited_depth >= depth_left,
+                    });
+            if processed {
+                continue;
+            }
+            visited.insert(name, depth_left);
+            store.update_recursive_failed(name);
+            if depth_left == Some(0) {
+                continue;
+            }
+            let depth_left = depth_left.map(|depth| depth - 1);
+            stack.extend(
+                self.cards[name]
+                    .dependents
+                    .iter()
+                    .rev()
+                    .map(|x| (x, depth_left)),
+            );
+        }
     }
 
-    fn propagate_no_fail(&self, name: &String, store: &mut impl TaskProgressStore<Id = String>) {
-        if self.cards[name]
-            .dependencies
-            .iter()
-            .any(|x| store[x] != TaskProgress::Good)
-        {
-            return;
+    /// Cards, which dependencies are all `Good`, are updated. Every card is visited once,
+    /// after all its dependencies, so it sees their final progress.
+    fn propagate_no_fail(&self, store: &mut impl TaskProgressStore<Id = String>) {
+        let mut waiting = self
+            .cards
+            .keys()
+            .map(|name| (name, 0))
+            .collect::<HashMap<_, usize>>();
+        for card in self.cards.values() {
+            for dependent in &card.dependents {
+                *waiting.get_mut(dependent).unwrap() += 1;
+            }
         }
-        store.update_no_recursive_failed(name);
-        self.cards[name]
-            .dependents
+        let mut ready = waiting
             .iter()
-            .for_each(|x| self.propagate_no_fail(x, store));
+            .filter(|(_, waiting)| **waiting == 0)
+            .map(|(&name, _)| name)
+            .collect::<Vec<_>>();
+        while let Some(name) = ready.pop() {
+            let card = &self.cards[name];
+            if store.contains(name)
+                && card
+                    .dependencies
+                    .iter()
+                    .all(|x| store.contains(x) && store[x] == TaskProgress::Good)
+            {
+                store.update_no_recursive_failed(name);
+            }
+            for dependent in &card.dependents {
+                let waiting = waiting.get_mut(dependent).unwrap();
+                *waiting -= 1;
+                if *waiting == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
     }
 
+    /// Returns cards of graph, that store doesn't have. They are skipped and treated as not `Good`.
     pub fn detect_recursive_fails(
         &self,
         store: &mut (impl TaskProgressStore<Id = String> + Debug),
-    ) {
+    ) -> Vec<String> {
+        self.detect_recursive_fails_with_depth(store, None)
+    }
+
+    /// Fail propagates only to `max_depth` levels of dependents. None means unlimited.
+    /// Returns cards of graph, that store doesn't have.
+    pub fn detect_recursive_fails_with_depth(
+        &self,
+        store: &mut (impl TaskProgressStore<Id = String> + Debug),
+        max_depth: Option<usize>,
+    ) -> Vec<String> {
+        let missing = self.missing_cards(&*store);
+        let mut visited = HashMap::new();
         self.cards.keys().for_each(|name| {
-            if store[name] == TaskProgress::Failed {
-                self.propagate_fail(name, store);
+            if store.contains(name) && store[name] == TaskProgress::Failed {
+                self.propagate_fail(name, store, max_depth, &mut visited);
             }
         });
-        self.cards.keys().for_each(|name| {
-            self.propagate_no_fail(name, store);
-        });
+        self.propagate_no_fail(store);
+        missing
+    }
+
+    /// Fails don't make dependents `RecursiveFailed`, so learned dependents of failed card
+    /// stay `Good`. Card still can be started only after all its dependencies are learned.
+    /// Returns cards of graph, that store doesn't have.
+    pub fn detect_fails_without_cascade(
+        &self,
+        store: &mut (impl TaskProgressStore<Id = String> + Debug),
+    ) -> Vec<String> {
+        let missing = self.missing_cards(&*store);
+        for name in self.cards.keys() {
+            if !store.contains(name) {
+                continue;
+            }
+            // `RecursiveFailed` cards are left from cascading and become `Good` here.
+            let learnable = self.cards[name].dependencies.iter().all(|x| {
+                store.contains(x)
+                    && matches!(store[x], TaskProgress::Good | TaskProgress::RecursiveFailed)
+            });
+            match store[name] {
+                TaskProgress::NotStarted { .. } if !learnable => {
+                    store.update_recursive_failed(name)
+                }
+                TaskProgress::NotStarted { .. } | TaskProgress::RecursiveFailed => {
+                    store.update_no_recursive_failed(name)
+                }
+                TaskProgress::Good | TaskProgress::Failed => {}
+            }
+        }
+        missing
+    }
+
+    /// Sorted.
+    fn missing_cards(&self, store: &impl TaskProgressStore<Id = String>) -> Vec<String> {
+        let mut missing = self
+            .cards
+            .keys()
+            .filter(|name| !store.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing
+    }
+
+    /// Checks invariants, that [`Self::detect_recursive_fails`] should maintain:
+    /// - store has every card of graph;
+    /// - card with not `Good` dependency is not `Good`;
+    /// - card reachable from `Failed` card via dependents is `RecursiveFailed` or worse.
+    ///
+    /// Returns all violations.
+    pub fn verify_fail_propagation(
+        &self,
+        store: &impl TaskProgressStore<Id = String>,
+    ) -> Result<(), Vec<String>> {
+        let is =
+            |name: &String, progress: TaskProgress| store.contains(name) && store[name] == progress;
+        let state = |name: &String| {
+            if store.contains(name) {
+                format!("{:?}", store[name])
+            } else {
+                "missing".to_owned()
+            }
+        };
+        let mut violations = self
+            .missing_cards(store)
+            .into_iter()
+            .map(|name| format!("'{name}' is missing in store"))
+            .collect::<Vec<_>>();
+        let mut names = self.cards.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in &names {
+            if !is(name, TaskProgress::Good) {
+                continue;
+            }
+            if let Some(dependency) = self.cards[*name]
+                .dependencies
+                .iter()
+                .find(|dependency| !is(dependency, TaskProgress::Good))
+            {
+                violations.push(format!(
+                    "'{name}' is good, but its dependency '{dependency}' is {}",
+                    state(dependency)
+                ));
+            }
+        }
+        for name in names {
+            if !is(name, TaskProgress::Failed) {
+                continue;
+            }
+            let mut visited = HashSet::new();
+            let mut stack = self.cards[name].dependents.iter().collect::<Vec<_>>();
+            while let Some(dependent) = stack.pop() {
+                if !visited.insert(dependent) {
+                    continue;
+                }
+                // Missing dependent is already reported.
+                if store.contains(dependent)
+                    && matches!(
+                        store[dependent],
+                        TaskProgress::Good
+                            | TaskProgress::NotStarted {
+                                could_be_learned: true
+                            }
+                    )
+                {
+                    violations.push(format!(
+                        "'{dependent}' depends on failed '{name}', but is {:?}",
+                        store[dependent]
+                    ));
+                }
+                stack.extend(&self.cards[dependent].dependents);
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+/// How far learner is from some goal card.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoalProgress {
+    /// Mastered prerequisites of goal.
+    pub mastered: usize,
+    /// All prerequisites(transitive dependencies) of goal.
+    pub total: usize,
+    /// First not mastered card on the way to goal.
+    /// Goal itself, if all prerequisites are mastered. None, if goal is mastered too.
+    pub next: Option<String>,
+}
+impl GoalProgress {
+    pub fn percentage(&self) -> f64 {
+        if self.total == 0 {
+            100.
+        } else {
+            self.mastered as f64 / self.total as f64 * 100.
+        }
+    }
+}
+
+impl CourseGraph {
+    /// All cards, each one after all its dependencies.
+    pub fn topological_order(&self) -> Vec<&String> {
+        let mut names = self.cards.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for name in names {
+            self.visit_dependencies(name, &mut visited, &mut order);
+        }
+        order
+    }
+
+    /// Cards without dependencies have depth 0, other cards are one level deeper
+    /// than their deepest dependency.
+    pub fn card_depths(&self) -> HashMap<String, usize> {
+        let mut depths = HashMap::new();
+        for name in self.topological_order() {
+            let depth = self.cards[name]
+                .dependencies
+                .iter()
+                .map(|dependency| depths[dependency] + 1)
+                .max()
+                .unwrap_or(0);
+            depths.insert(name.clone(), depth);
+        }
+        depths
+    }
+
+    /// Text alternative to graph image: cards in topological order, indented by depth.
+    /// Cards, missing in `store`, are skipped.
+    pub fn progress_tree(&self, store: &impl TaskProgressStore<Id = String>) -> String {
+        let depths = self.card_depths();
+        let mut tree = String::new();
+        for name in self.topological_order() {
+            if !store.contains(name) {
+                continue;
+            }
+            tree.push_str(&"  ".repeat(depths[name]));
+            tree.push_str(progress_mark(store[name]));
+            tree.push(' ');
+            tree.push_str(name);
+            tree.push('\n');
+        }
+        tree
+    }
+
+    /// Cards, grouped by state, from failed to good. Names in group are sorted.
+    pub fn progress_list(&self, store: &impl TaskProgressStore<Id = String>) -> String {
+        let mut groups = BTreeMap::<_, (TaskProgress, Vec<_>)>::new();
+        for name in self.cards.keys().filter(|name| store.contains(name)) {
+            let progress = store[name];
+            let order = match progress {
+                TaskProgress::Failed => 0,
+                TaskProgress::RecursiveFailed => 1,
+                TaskProgress::NotStarted {
+                    could_be_learned: true,
+                } => 2,
+                TaskProgress::NotStarted {
+                    could_be_learned: false,
+                } => 3,
+                TaskProgress::Good => 4,
+            };
+            groups
+                .entry(order)
+                .or_insert_with(|| (progress, Vec::new()))
+                .1
+                .push(name.as_str());
+        }
+        groups
+            .into_values()
+            .map(|(progress, mut names)| {
+                names.sort();
+                format!("{} {}\n", progress_mark(progress), names.join(", "))
+            })
+            .collect()
+    }
+
+    /// Cards, blocked because of failed `card`: its transitive dependents, which are recursive
+    /// failed or can't be learned. Failed dependents are not listed, but cards behind them are.
+    /// Empty, if `card` isn't failed. Sorted by name.
+    pub fn blocked_by(
+        &self,
+        card: &str,
+        store: &impl TaskProgressStore<Id = String>,
+    ) -> Vec<&String> {
+        let Some((card, _)) = self.cards.get_key_value(card) else {
+            return Vec::new();
+        };
+        if !store.contains(card) || store[card] != TaskProgress::Failed {
+            return Vec::new();
+        }
+        let mut visited = HashSet::from([card]);
+        let mut stack = vec![card];
+        let mut blocked = Vec::new();
+        while let Some(name) = stack.pop() {
+            for dependent in &self.cards[name].dependents {
+                if !store.contains(dependent) || !visited.insert(dependent) {
+                    continue;
+                }
+                match store[dependent] {
+                    TaskProgress::RecursiveFailed
+                    | TaskProgress::NotStarted {
+                        could_be_learned: false,
+                    } => {
+                        blocked.push(dependent);
+                        stack.push(dependent);
+                    }
+                    TaskProgress::Failed => stack.push(dependent),
+                    TaskProgress::Good
+                    | TaskProgress::NotStarted {
+                        could_be_learned: true,
+                    } => {}
+                }
+            }
+        }
+        blocked.sort();
+        blocked
+    }
+
+    /// Transitive dependencies of `card`, each one after all its dependencies.
+    /// Doesn't contain `card` itself.
+    pub fn path_to(&self, card: &str) -> Vec<&String> {
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        self.visit_dependencies(card, &mut visited, &mut order);
+        order.pop();
+        order
+    }
+
+    fn visit_dependencies<'a>(
+        &'a self,
+        name: &str,
+        visited: &mut HashSet<&'a String>,
+        order: &mut Vec<&'a String>,
+    ) {
+        let (name, card) = self.cards.get_key_value(name).unwrap();
+        if !visited.insert(name) {
+            return;
+        }
+        let mut dependencies = card.dependencies.iter().collect::<Vec<_>>();
+        dependencies.sort();
+        for dependency in dependencies {
+            self.visit_dependencies(dependency, visited, order);
+        }
+        order.push(name);
+    }
+
+    /// None, if goal isn't in graph, because course is changed after goal was set.
+    pub fn goal_progress(
+        &self,
+        goal: &str,
+        store: &impl TaskProgressStore<Id = String>,
+    ) -> Option<GoalProgress> {
+        let (goal, _) = self.cards.get_key_value(goal)?;
+        // Card, missing in store(graph changed before synchronization), isn't mastered.
+        let is_mastered = |name: &String| store.contains(name) && store[name] == TaskProgress::Good;
+        let path = self.path_to(goal);
+        let mastered = path.iter().filter(|&&name| is_mastered(name)).count();
+        let next = path
+            .iter()
+            .copied()
+            .chain([goal])
+            .find(|&name| !is_mastered(name))
+            .cloned();
+        Some(GoalProgress {
+            mastered,
+            total: path.len(),
+            next,
+        })
+    }
+
+    /// Progress with given states, like `a=failed,b=good`; other cards are not started.
+    /// Recursive fails are already detected, with `max_depth` like in
+    /// [`Self::detect_recursive_fails_with_depth`], or without cascading, if `cascade` is false.
+    pub fn synthetic_progress(
+        &self,
+        states: &str,
+        case_sensitive: bool,
+        cascade: bool,
+        max_depth: Option<usize>,
+    ) -> Result<HashMap<String, TaskProgress>, String> {
+        let mut store = self
+            .cards
+            .keys()
+            .map(|name| (name.clone(), TaskProgress::default()))
+            .collect::<HashMap<_, _>>();
+        for assignment in states.split(',').map(str::trim) {
+            let (name, state) = assignment
+                .split_once('=')
+                .ok_or_else(|| format!("'{assignment}' should look like CARD=STATE"))?;
+            let name = normalize_card_name(name, case_sensitive);
+            let Some(progress) = store.get_mut(&name) else {
+                return Err(format!("there is no '{name}' card"));
+            };
+            *progress = state
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid state of '{name}': {err}"))?;
+        }
+        if cascade {
+            self.detect_recursive_fails_with_depth(&mut store, max_depth);
+        } else {
+            self.detect_fails_without_cascade(&mut store);
+        }
+        Ok(store)
+    }
+
+    /// Highlights goals and next cards to learn for them. Goals, that aren't in graph, are skipped.
+    pub fn generate_goal_stmts(
+        &self,
+        goals: &[String],
+        store: &impl TaskProgressStore<Id = String>,
+    ) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        for goal in goals {
+            let Some(progress) = self.goal_progress(goal, store) else {
+                continue;
+            };
+            stmts.push(Stmt::Node(Node {
+                id: NodeId(id_from_string(goal), None),
+                attributes: vec![NodeAttributes::shape(shape::doubleoctagon)],
+            }));
+            if let Some(next) = progress.next {
+                stmts.push(Stmt::Node(Node {
+                    id: NodeId(id_from_string(&next), None),
+                    attributes: vec![
+                        NodeAttributes::color(color_name::blue),
+                        NodeAttributes::penwidth(3.),
+                    ],
+                }));
+            }
+        }
+        stmts
     }
 }
 
@@ -154,3 +696,542 @@ impl<'de> Deserialize<'de> for CourseGraph {
         deserializer.deserialize_str(CourseGraphVisitor)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::ops::Index;
+
+    use super::*;
+    use crate::progress_store::TaskProgressStoreExt;
+
+    const GRAPH: &str = "a\nb: a\nc: b\nd: a, c\ne";
+
+    /// Example from `main.rs`.
+    const EXAMPLE: &str = "a0\na1: a0\na2: a1\na3: a2
+b0\nb1: b0\nb2: b1\nb3: b2
+d0: a3, b3\nd1: d0\nd2: d1\nd3: d2\nd4: d3
+c0\nc1: c0\nc2: c1\nc3: c2, smth\nc4: c3\nsmth: d1, c0";
+
+    #[test]
+    fn improved_cards_are_highlighted() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut checkpoint = HashMap::new();
+        graph.init_store(&mut checkpoint);
+        checkpoint.insert("a".to_owned(), TaskProgress::Good);
+        checkpoint.insert("b".to_owned(), TaskProgress::Failed);
+        graph.detect_recursive_fails(&mut checkpoint);
+
+        let mut store = checkpoint.clone();
+        store.insert("b".to_owned(), TaskProgress::Good);
+        store.insert("e".to_owned(), TaskProgress::Failed);
+        graph.detect_recursive_fails(&mut store);
+
+        let node = |name: &str, color| {
+            Stmt::Node(Node {
+                id: NodeId(id_from_string(name), None),
+                attributes: vec![
+                    NodeAttributes::style("filled".into()),
+                    NodeAttributes::fillcolor(color),
+                ],
+            })
+        };
+        let stmts = store.generate_diff_stmts(&checkpoint);
+        assert_eq!(stmts.len(), graph.cards().len());
+        // Newly mastered.
+        assert!(stmts.contains(&node("b", color_name::chartreuse)));
+        // Dependency became good, so card can be learned now.
+        assert!(stmts.contains(&node("c", color_name::palegreen)));
+        // Mastered before checkpoint.
+        assert!(stmts.contains(&node("a", color_name::lightgray)));
+        // Got worse.
+        assert!(stmts.contains(&node("e", color_name::lightgray)));
+    }
+
+    #[test]
+    fn cards_diff() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        assert!(
+            graph
+                .diff_cards(["a", "b", "c", "d", "e"].into_iter())
+                .is_empty()
+        );
+        assert_eq!(
+            graph.diff_cards(["a", "b", "c", "d", "x", "e", "f"].into_iter()),
+            CardDiff {
+                missing_in_deque: vec![],
+                extra_in_deque: vec!["f".into(), "x".into()],
+            }
+        );
+        assert_eq!(
+            graph.diff_cards(["e", "c", "a"].into_iter()),
+            CardDiff {
+                missing_in_deque: vec!["b".into(), "d".into()],
+                extra_in_deque: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn roots_and_leaves() {
+        let graph = CourseGraph::from_str(EXAMPLE).unwrap();
+        let mut roots = graph.roots().collect::<Vec<_>>();
+        roots.sort();
+        assert_eq!(roots, ["c4", "d4"]);
+        let mut leaves = graph.leaves().collect::<Vec<_>>();
+        leaves.sort();
+        assert_eq!(leaves, ["a0", "b0", "c0"]);
+
+        let smth = &graph.cards()["smth"];
+        assert!(!smth.is_root() && !smth.is_leaf());
+        let single = CourseGraph::from_str("a").unwrap();
+        assert!(single.cards()["a"].is_root() && single.cards()["a"].is_leaf());
+    }
+
+    #[test]
+    fn example_topological_order() {
+        let graph = CourseGraph::from_str(EXAMPLE).unwrap();
+        let order = graph.topological_order();
+        assert_eq!(order.len(), graph.cards().len());
+        let position = |name: &str| order.iter().position(|x| *x == name).unwrap();
+        assert!(position("a0") < position("a3"));
+        for (name, card) in graph.cards() {
+            for dependency in &card.dependencies {
+                assert!(
+                    position(dependency) < position(name),
+                    "{dependency} -> {name}"
+                );
+            }
+        }
+        assert_eq!(
+            order,
+            CourseGraph::from_str(EXAMPLE).unwrap().topological_order()
+        );
+    }
+
+    #[test]
+    fn failed_card_blocks_dependents() {
+        let graph = CourseGraph::from_str(EXAMPLE).unwrap();
+        let mut store = HashMap::new();
+        graph.init_store(&mut store);
+        for progress in store.values_mut() {
+            *progress = TaskProgress::Good;
+        }
+        store.insert("c1".to_owned(), TaskProgress::Failed);
+        graph.detect_recursive_fails(&mut store);
+
+        assert_eq!(graph.blocked_by("c1", &store), ["c2", "c3", "c4"]);
+        // Card isn't failed.
+        assert!(graph.blocked_by("c0", &store).is_empty());
+        assert!(graph.blocked_by("c2", &store).is_empty());
+        assert!(graph.blocked_by("unknown", &store).is_empty());
+    }
+
+    #[test]
+    fn text_views() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = HashMap::new();
+        graph.init_store(&mut store);
+        store.insert("a".to_owned(), TaskProgress::Good);
+        store.insert("b".to_owned(), TaskProgress::Failed);
+        store.insert("c".to_owned(), TaskProgress::RecursiveFailed);
+        store.insert(
+            "d".to_owned(),
+            TaskProgress::NotStarted {
+                could_be_learned: false,
+            },
+        );
+
+        assert_eq!(
+            graph.progress_tree(&store),
+            "🟩 a\n  🟥 b\n    🟨 c\n      🔒 d\n⬜ e\n"
+        );
+        assert_eq!(
+            graph.progress_list(&store),
+            "🟥 b\n🟨 c\n⬜ e\n🔒 d\n🟩 a\n"
+        );
+    }
+
+    #[test]
+    fn depths_of_example() {
+        let graph = CourseGraph::from_str(EXAMPLE).unwrap();
+        let depths = graph.card_depths();
+        assert_eq!(depths.len(), graph.cards().len());
+        for (name, depth) in [
+            ("a0", 0),
+            ("a3", 3),
+            ("d0", 4),
+            ("d4", 8),
+            ("smth", 6),
+            ("c3", 7),
+            ("c4", 8),
+        ] {
+            assert_eq!(depths[name], depth, "{name}");
+        }
+
+        let Graph::Graph { stmts, .. } = graph.generate_structure_graph(true) else {
+            unreachable!()
+        };
+        let layers = stmts
+            .iter()
+            .filter(|stmt| matches!(stmt, Stmt::Subgraph(_)))
+            .count();
+        assert_eq!(layers, 9);
+        let Graph::Graph { stmts, .. } = graph.generate_structure_graph(false) else {
+            unreachable!()
+        };
+        assert!(!stmts.iter().any(|stmt| matches!(stmt, Stmt::Subgraph(_))));
+    }
+
+    /// Counts updates of every card.
+    #[derive(Debug, Default)]
+    struct CountingStore {
+        progress: HashMap<String, TaskProgress>,
+        recursive_failed_updates: HashMap<String, usize>,
+    }
+    impl Index<&String> for CountingStore {
+        type Output = TaskProgress;
+
+        fn index(&self, index: &String) -> &Self::Output {
+            &self.progress[index]
+        }
+    }
+    impl TaskProgressStore for CountingStore {
+        type Id = String;
+
+        fn init(&mut self, id: &String) {
+            self.progress.init(id);
+        }
+        fn contains(&self, id: &String) -> bool {
+            self.progress.contains(id)
+        }
+        fn update_recursive_failed(&mut self, id: &String) {
+            *self.recursive_failed_updates.entry(id.clone()).or_default() += 1;
+            self.progress.update_recursive_failed(id);
+        }
+        fn update_no_recursive_failed(&mut self, id: &String) {
+            self.progress.update_no_recursive_failed(id);
+        }
+        fn iter(&self) -> impl Iterator<Item = (&String, TaskProgress)> {
+            TaskProgressStore::iter(&self.progress)
+        }
+    }
+
+    #[test]
+    fn diamond_is_propagated_once() {
+        let graph = CourseGraph::from_str(EXAMPLE).unwrap();
+        for max_depth in [None, Some(1), Some(3)] {
+            let mut store = CountingStore::default();
+            graph.init_store(&mut store);
+            for name in graph.cards().keys() {
+                store.progress.insert(name.clone(), TaskProgress::Good);
+            }
+            // Both branches of `d0: a3, b3` fail.
+            store.progress.insert("a1".into(), TaskProgress::Failed);
+            store.progress.insert("b1".into(), TaskProgress::Failed);
+            graph.detect_recursive_fails_with_depth(&mut store, max_depth);
+
+            assert!(
+                store
+                    .recursive_failed_updates
+                    .values()
+                    .all(|count| *count == 1),
+                "{max_depth:?}: {:?}",
+                store.recursive_failed_updates
+            );
+            let expected_failed = match max_depth {
+                None => &[
+                    "a2", "a3", "b2", "b3", "c3", "c4", "d0", "d1", "d2", "d3", "d4", "smth",
+                ][..],
+                Some(1) => &["a2", "b2"][..],
+                Some(_) => &["a2", "a3", "b2", "b3", "d0"][..],
+            };
+            let mut failed = store
+                .progress
+                .iter()
+                .filter(|(_, progress)| **progress == TaskProgress::RecursiveFailed)
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            failed.sort();
+            assert_eq!(failed, expected_failed, "{max_depth:?}");
+        }
+    }
+
+    #[test]
+    fn long_chain_doesnt_overflow_stack() {
+        const LEN: usize = 5000;
+        let name = |ix: usize| format!("c{ix}");
+        let cards = (0..LEN)
+            .map(|ix| {
+                let card = CardNode {
+                    dependencies: (ix > 0).then(|| name(ix - 1)).into_iter().collect(),
+                    dependents: (ix + 1 < LEN).then(|| name(ix + 1)).into_iter().collect(),
+                };
+                (name(ix), card)
+            })
+            .collect();
+        let graph = CourseGraph {
+            text: String::new(),
+            cards,
+        };
+        let mut store = (0..LEN)
+            .map(|ix| (name(ix), TaskProgress::Good))
+            .collect::<HashMap<_, _>>();
+        store.insert(name(0), TaskProgress::Failed);
+        graph.detect_recursive_fails(&mut store);
+        assert_eq!(store[&name(LEN - 1)], TaskProgress::RecursiveFailed);
+
+        store.insert(name(0), TaskProgress::Good);
+        graph.detect_recursive_fails(&mut store);
+        assert!(
+            store
+                .values()
+                .all(|progress| *progress == TaskProgress::Good)
+        );
+    }
+
+    #[test]
+    fn dependencies_first() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        assert_eq!(graph.topological_order(), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(graph.path_to("d"), vec!["a", "b", "c"]);
+        assert!(graph.path_to("e").is_empty());
+    }
+
+    #[test]
+    fn goal_progress_reflects_mastery() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = HashMap::new();
+        graph.init_store(&mut store);
+
+        let progress = graph.goal_progress("d", &store).unwrap();
+        assert_eq!((progress.mastered, progress.total), (0, 3));
+        assert_eq!(progress.next.as_deref(), Some("a"));
+
+        store.insert("a".into(), TaskProgress::Good);
+        store.insert("c".into(), TaskProgress::Good);
+        let progress = graph.goal_progress("d", &store).unwrap();
+        assert_eq!((progress.mastered, progress.total), (2, 3));
+        assert_eq!(progress.next.as_deref(), Some("b"));
+
+        store.insert("b".into(), TaskProgress::Good);
+        let progress = graph.goal_progress("d", &store).unwrap();
+        assert_eq!(progress.percentage(), 100.);
+        assert_eq!(progress.next.as_deref(), Some("d"));
+
+        store.insert("d".into(), TaskProgress::Good);
+        assert_eq!(graph.goal_progress("d", &store).unwrap().next, None);
+        assert_eq!(graph.goal_progress("e", &store).unwrap().percentage(), 100.);
+    }
+
+    #[test]
+    fn stale_goal_is_skipped() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = HashMap::new();
+        graph.init_store(&mut store);
+
+        assert_eq!(graph.goal_progress("removed", &store), None);
+        let goals = ["removed".to_owned(), "d".to_owned()];
+        let stmts = graph.generate_goal_stmts(&goals, &store);
+        assert_eq!(stmts, graph.generate_goal_stmts(&goals[1..], &store));
+        assert!(graph.generate_goal_stmts(&goals[..1], &store).is_empty());
+    }
+
+    #[test]
+    fn prerequisite_missing_in_store_isnt_mastered() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let store = HashMap::from([
+            ("a".to_owned(), TaskProgress::Good),
+            ("c".to_owned(), TaskProgress::Good),
+        ]);
+        assert_eq!(
+            graph.goal_progress("d", &store),
+            Some(GoalProgress {
+                mastered: 2,
+                total: 3,
+                next: Some("b".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn synthetic_fail_propagates_yellow() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let store = graph
+            .synthetic_progress("a=good, B=failed,c=good", false, true, None)
+            .unwrap();
+        assert_eq!(store["b"], TaskProgress::Failed);
+        assert_eq!(store["c"], TaskProgress::RecursiveFailed);
+        assert_eq!(
+            store["d"],
+            TaskProgress::NotStarted {
+                could_be_learned: false
+            }
+        );
+
+        let yellow = Stmt::Node(Node {
+            id: NodeId(id_from_string("c"), None),
+            attributes: vec![
+                NodeAttributes::style("filled".into()),
+                NodeAttributes::fillcolor(color_name::yellow),
+            ],
+        });
+        assert!(store.generate_stmts().contains(&yellow));
+
+        assert!(
+            graph
+                .synthetic_progress("x=good", false, true, None)
+                .is_err()
+        );
+        assert!(
+            graph
+                .synthetic_progress("a=great", false, true, None)
+                .is_err()
+        );
+        assert!(graph.synthetic_progress("a", false, true, None).is_err());
+    }
+
+    #[test]
+    fn fail_propagation_invariants() {
+        for graph in [
+            CourseGraph::default(),
+            CourseGraph::from_str(GRAPH).unwrap(),
+        ] {
+            for failed in graph.cards().keys() {
+                let mut store = graph
+                    .cards()
+                    .keys()
+                    .map(|name| (name.clone(), TaskProgress::Good))
+                    .collect::<HashMap<_, _>>();
+                store.insert(failed.clone(), TaskProgress::Failed);
+                graph.detect_recursive_fails(&mut store);
+                assert_eq!(graph.verify_fail_propagation(&store), Ok(()), "{failed}");
+            }
+        }
+    }
+
+    #[test]
+    fn fail_propagation_violations() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = graph
+            .cards()
+            .keys()
+            .map(|name| (name.clone(), TaskProgress::Good))
+            .collect::<HashMap<_, _>>();
+        store.insert("b".into(), TaskProgress::Failed);
+        assert_eq!(
+            graph.verify_fail_propagation(&store),
+            Err(vec![
+                "'c' is good, but its dependency 'b' is Failed".to_owned(),
+                "'c' depends on failed 'b', but is Good".to_owned(),
+                "'d' depends on failed 'b', but is Good".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn missing_card_is_violation() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = graph
+            .cards()
+            .keys()
+            .map(|name| (name.clone(), TaskProgress::Good))
+            .collect::<HashMap<_, _>>();
+        store.remove("b");
+        store.insert("a".into(), TaskProgress::Failed);
+        graph.detect_recursive_fails(&mut store);
+        assert_eq!(
+            graph.verify_fail_propagation(&store),
+            // Fail doesn't pass through missing card.
+            Err(vec![
+                "'b' is missing in store".to_owned(),
+                "'c' is good, but its dependency 'b' is missing".to_owned(),
+                "'c' depends on failed 'a', but is Good".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn limited_fail_propagation() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = graph
+            .cards()
+            .keys()
+            .map(|name| (name.clone(), TaskProgress::Good))
+            .collect::<HashMap<_, _>>();
+        store.insert("a".into(), TaskProgress::Failed);
+        graph.detect_recursive_fails_with_depth(&mut store, Some(1));
+        assert_eq!(store["b"], TaskProgress::RecursiveFailed);
+        assert_eq!(store["d"], TaskProgress::RecursiveFailed);
+        assert_eq!(store["c"], TaskProgress::Good);
+        assert_eq!(store["e"], TaskProgress::Good);
+
+        graph.detect_recursive_fails_with_depth(&mut store, None);
+        assert_eq!(store["c"], TaskProgress::RecursiveFailed);
+    }
+
+    #[test]
+    fn fails_without_cascade() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = graph
+            .cards()
+            .keys()
+            .map(|name| (name.clone(), TaskProgress::Good))
+            .collect::<HashMap<_, _>>();
+        store.insert("b".into(), TaskProgress::Failed);
+        store.insert("d".into(), TaskProgress::RecursiveFailed);
+        store.insert("e".into(), TaskProgress::default());
+        graph.detect_fails_without_cascade(&mut store);
+        assert_eq!(store["c"], TaskProgress::Good);
+        assert_eq!(store["d"], TaskProgress::Good);
+        assert_eq!(
+            store["e"],
+            TaskProgress::NotStarted {
+                could_be_learned: true
+            }
+        );
+
+        store.insert("c".into(), TaskProgress::default());
+        graph.detect_fails_without_cascade(&mut store);
+        assert_eq!(
+            store["c"],
+            TaskProgress::NotStarted {
+                could_be_learned: false
+            }
+        );
+    }
+
+    #[test]
+    fn merge_disjoint_graphs() {
+        let first = CourseGraph::from_str("a\nb: a").unwrap();
+        let second = CourseGraph::from_str("c\nd: c, x\nx").unwrap();
+        let merged = first.merge(&second, false).unwrap();
+        assert_eq!(merged.cards().len(), 5);
+        assert_eq!(merged.cards()["d"].dependencies, vec!["c", "x"]);
+        assert_eq!(merged.cards()["a"].dependents, vec!["b"]);
+    }
+
+    #[test]
+    fn merge_with_shared_card() {
+        let first = CourseGraph::from_str("a\nb: a").unwrap();
+        let second = CourseGraph::from_str("b\na: b\nc").unwrap();
+        assert_eq!(
+            first.merge(&second, false).unwrap_err(),
+            "both graphs have cards: a, b"
+        );
+    }
+
+    #[test]
+    fn store_missing_graph_card() {
+        let graph = CourseGraph::from_str(GRAPH).unwrap();
+        let mut store = graph
+            .cards()
+            .keys()
+            .map(|name| (name.clone(), TaskProgress::Good))
+            .collect::<HashMap<_, _>>();
+        store.insert("a".into(), TaskProgress::Failed);
+        store.remove("b");
+        assert_eq!(graph.detect_recursive_fails(&mut store), vec!["b"]);
+        assert_eq!(store["d"], TaskProgress::RecursiveFailed);
+        assert!(!store.contains_key("b"));
+    }
+}
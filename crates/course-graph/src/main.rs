@@ -37,7 +37,7 @@ smth: d1, c0
         panic!("parsing error");
     });
 
-    let mut graph = course_graph.generate_structure_graph();
+    let mut graph = course_graph.generate_structure_graph(false);
 
     let mut progress_store = HashMap::new();
     course_graph.init_store(&mut progress_store);
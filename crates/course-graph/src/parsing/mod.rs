@@ -8,25 +8,44 @@ use crate::{card::CardNode, graph::CourseGraph, parsing::prototypes::CardName};
 
 mod prototypes;
 
+/// Canonical form of a card name, used everywhere cards are looked up by name.
+/// Name is trimmed and inner whitespace is collapsed to single space.
+pub fn normalize_card_name(name: &str, case_sensitive: bool) -> String {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if case_sensitive {
+        name
+    } else {
+        name.to_lowercase()
+    }
+}
+
 impl FromStr for CourseGraph {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let deque_prototype = DequePrototype::from_str(s);
+        Self::parse(s, false)
+    }
+}
+
+impl CourseGraph {
+    /// Same as [`FromStr`], but card names are kept as is if `case_sensitive` is set.
+    pub fn parse(s: &str, case_sensitive: bool) -> Result<Self, String> {
+        let deque_prototype = DequePrototype::parse(s, case_sensitive);
         if let Err(err) = deque_prototype {
             let mut errors = Vec::new();
             report_error(s, &mut errors, &err);
             return Err(String::from_utf8(errors).unwrap());
         }
         let mut card_prototypes = deque_prototype.unwrap().cards;
+        if let Some(report) = report_unknown_dependencies(s, &card_prototypes) {
+            return Err(report);
+        }
         let mut graph_cards = HashMap::<CardName, CardNode>::new();
         while !card_prototypes.is_empty() {
             let Some((name, _)) = card_prototypes
                 .iter()
                 .find(|(_, dependencies)| dependencies.iter().all(|d| graph_cards.contains_key(d)))
             else {
-                todo!(
-                    "report cycle detection or dangling dependency(without it's own dependencies specified)"
-                )
+                return Err(report_cycle(s, &card_prototypes));
             };
             let (name, dependencies) = card_prototypes.remove_entry(&name.to_owned()).unwrap();
             for dependency in &dependencies {
@@ -57,6 +76,82 @@ impl FromStr for CourseGraph {
     }
 }
 
+/// Dependencies, which are not defined as cards, usually typos. Known cards are listed in note.
+fn report_unknown_dependencies(
+    input: &str,
+    cards: &HashMap<CardName, Vec<CardName>>,
+) -> Option<String> {
+    let mut unknown = cards
+        .values()
+        .flatten()
+        .filter(|dependency| !cards.contains_key(*dependency))
+        .collect::<Vec<_>>();
+    unknown.sort_by_key(|dependency| dependency.span.start);
+    let first = unknown.first()?;
+    let labels = unknown.iter().map(|dependency| {
+        Label::new(dependency.span.start..dependency.span.end)
+            .with_message(format!("unknown card '{}'", dependency.name))
+            .with_color(Color::Red)
+    });
+    let mut known = cards
+        .keys()
+        .map(|card| card.name.as_str())
+        .collect::<Vec<_>>();
+    known.sort();
+    let mut output = Vec::new();
+    Report::build(ReportKind::Error, first.span.start..first.span.end)
+        .with_message("dependency is not defined as card")
+        .with_labels(labels)
+        .with_note(format!("known cards: {}", known.join(", ")))
+        .finish()
+        .write_for_stdout(Source::from(input), &mut output)
+        .unwrap();
+    Some(String::from_utf8(output).unwrap())
+}
+
+/// Every remaining card waits for another remaining card, so they form a cycle.
+fn report_cycle(input: &str, remaining: &HashMap<CardName, Vec<CardName>>) -> String {
+    let cycle = find_cycle(remaining);
+    let path = cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|card| card.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" -> ");
+    let labels = cycle.iter().enumerate().map(|(ix, card)| {
+        let next = cycle[(ix + 1) % cycle.len()];
+        Label::new(card.span.start..card.span.end)
+            .with_message(format!("'{}' depends on '{}'", card.name, next.name))
+            .with_color(Color::Red)
+    });
+    let mut output = Vec::new();
+    Report::build(ReportKind::Error, cycle[0].span.start..cycle[0].span.end)
+        .with_message(format!("dependency cycle: {path}"))
+        .with_labels(labels)
+        .finish()
+        .write_for_stdout(Source::from(input), &mut output)
+        .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+/// Each of `remaining` cards depends on other remaining card. Following these dependencies
+/// from alphabetically first card gives a cycle. Returned cards are definitions, not references.
+fn find_cycle(remaining: &HashMap<CardName, Vec<CardName>>) -> Vec<&CardName> {
+    let mut current = remaining.keys().min_by(|a, b| a.name.cmp(&b.name)).unwrap();
+    let mut path = Vec::new();
+    loop {
+        if let Some(start) = path.iter().position(|card| *card == current) {
+            return path.split_off(start);
+        }
+        path.push(current);
+        let next = remaining[current]
+            .iter()
+            .find(|dependency| remaining.contains_key(*dependency))
+            .unwrap();
+        current = remaining.get_key_value(next).unwrap().0;
+    }
+}
+
 fn report_error(input: &str, output: &mut Vec<u8>, err: &Rich<'_, char>) {
     let span = err.span();
     let span = span.start()..span.end();
@@ -75,3 +170,65 @@ fn report_error(input: &str, output: &mut Vec<u8>, err: &Rich<'_, char>) {
         .write_for_stdout(Source::from(input), output)
         .unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_names() {
+        let graph = CourseGraph::parse("Foo: Bar\nbar", false).unwrap();
+        assert!(graph.cards().contains_key("foo"));
+        assert_eq!(graph.cards()["foo"].dependencies, vec!["bar".to_owned()]);
+        assert!(CourseGraph::parse("Foo\nfoo", false).is_err());
+    }
+
+    #[test]
+    fn case_sensitive_names() {
+        let graph = CourseGraph::parse("Foo: foo\nfoo", true).unwrap();
+        assert_eq!(graph.cards().len(), 2);
+        assert!(graph.cards().contains_key("Foo"));
+        assert_eq!(graph.cards()["Foo"].dependencies, vec!["foo".to_owned()]);
+        assert_eq!(graph.cards()["foo"].dependents, vec!["Foo".to_owned()]);
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(normalize_card_name("  My \t Card ", false), "my card");
+        assert_eq!(normalize_card_name("My  Card", true), "My Card");
+        assert_eq!(
+            normalize_card_name("my card ", false),
+            normalize_card_name("My Card", false)
+        );
+    }
+
+    #[test]
+    fn two_cards_cycle() {
+        let err = CourseGraph::parse("a: b\nb: a", false).unwrap_err();
+        assert!(err.contains("dependency cycle: a -> b -> a"), "{err}");
+        assert!(err.contains("'a' depends on 'b'"), "{err}");
+        assert!(err.contains("'b' depends on 'a'"), "{err}");
+    }
+
+    #[test]
+    fn three_cards_cycle() {
+        let err = CourseGraph::parse("a: c\nb: a\nc: b\nd: a", false).unwrap_err();
+        assert!(err.contains("dependency cycle: a -> c -> b -> a"), "{err}");
+        assert!(!err.contains("'d' depends"), "{err}");
+    }
+
+    #[test]
+    fn self_loop() {
+        let err = CourseGraph::parse("a: a\nb", false).unwrap_err();
+        assert!(err.contains("dependency cycle: a -> a"), "{err}");
+    }
+
+    #[test]
+    fn unknown_dependency() {
+        let err = CourseGraph::parse("a: b\nc: a, d\ncapitals", false).unwrap_err();
+        assert!(err.contains("unknown card 'b'"), "{err}");
+        assert!(err.contains("unknown card 'd'"), "{err}");
+        assert!(err.contains("known cards: a, c, capitals"), "{err}");
+        assert!(!err.contains("cycle"), "{err}");
+    }
+}
@@ -2,6 +2,8 @@ use std::{collections::HashMap, hash::Hash, str::FromStr};
 
 use chumsky::prelude::*;
 
+use super::normalize_card_name;
+
 #[derive(Debug, Clone)]
 pub struct CardName {
     pub name: String,
@@ -19,8 +21,8 @@ impl Hash for CardName {
     }
 }
 impl CardName {
-    /// Name would be lowercased.
-    fn new(name: &str, start: usize, end_non_inclusive: usize) -> Self {
+    /// Name would be normalized with [`normalize_card_name`].
+    fn new(name: &str, start: usize, end_non_inclusive: usize, case_sensitive: bool) -> Self {
         assert!(end_non_inclusive > start);
         assert_eq!(
             name.len(),
@@ -28,12 +30,18 @@ impl CardName {
             "name is '{name}', range is {start}..{end_non_inclusive}"
         );
         CardName {
-            name: name.to_lowercase(),
+            name: normalize_card_name(name, case_sensitive),
             span: SimpleSpan::from(start..end_non_inclusive),
         }
     }
 }
 
+/// Line, which starts with `#`(maybe after indentation), is a comment.
+/// `#` elsewhere is part of card name, like in `c#`.
+fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct DequePrototype {
     pub cards: HashMap<CardName, Vec<CardName>>,
@@ -42,6 +50,14 @@ impl FromStr for DequePrototype {
     type Err = chumsky::error::Rich<'static, char>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, false)
+    }
+}
+impl DequePrototype {
+    pub fn parse(
+        s: &str,
+        case_sensitive: bool,
+    ) -> Result<Self, chumsky::error::Rich<'static, char>> {
         enum State {
             Default,
             NameParsing {
@@ -64,6 +80,7 @@ impl FromStr for DequePrototype {
             cards: &mut HashMap<CardName, Vec<CardName>>,
             ch: char,
             ix: usize,
+            case_sensitive: bool,
         ) -> Result<State, chumsky::error::Rich<'static, char>> {
             match state {
                 State::Default => match ch {
@@ -79,7 +96,7 @@ impl FromStr for DequePrototype {
                 },
                 State::NameParsing { mut name, start } => match ch {
                     '\n' => {
-                        let name = CardName::new(&name, start, ix);
+                        let name = CardName::new(&name, start, ix, case_sensitive);
                         let prev = cards.insert(name.clone(), Vec::new());
                         if prev.is_some() {
                             return Err(Rich::custom(
@@ -89,7 +106,7 @@ impl FromStr for DequePrototype {
                         }
                         Ok(State::Default)
                     }
-                    ch if ch.is_alphanumeric() || ch == ' ' => {
+                    ch if ch.is_alphanumeric() || ch == ' ' || ch == '#' => {
                         name.push(ch);
                         Ok(State::NameParsing { name, start })
                     }
@@ -102,7 +119,7 @@ impl FromStr for DequePrototype {
                                 "space in not allowed between card name and column",
                             ));
                         }
-                        let name = CardName::new(&name, start, ix);
+                        let name = CardName::new(&name, start, ix, case_sensitive);
                         Ok(State::DependenciesParsing {
                             name,
                             dependencies: Vec::new(),
@@ -145,6 +162,7 @@ impl FromStr for DequePrototype {
                             &current_dependency,
                             current_dependency_start,
                             ix - spaces_at_the_end,
+                            case_sensitive,
                         );
                         dependencies.push(dependency);
                         let prev = cards.insert(name.clone(), dependencies);
@@ -156,7 +174,7 @@ impl FromStr for DequePrototype {
                         }
                         Ok(State::Default)
                     }
-                    ch if ch.is_alphanumeric() || ch == ' ' => {
+                    ch if ch.is_alphanumeric() || ch == ' ' || ch == '#' => {
                         current_dependency.push(ch);
                         Ok(State::DependencyParsing {
                             name,
@@ -175,8 +193,12 @@ impl FromStr for DequePrototype {
                                 "space in not allowed in card names",
                             ));
                         }
-                        let dependency =
-                            CardName::new(&current_dependency, current_dependency_start, ix);
+                        let dependency = CardName::new(
+                            &current_dependency,
+                            current_dependency_start,
+                            ix,
+                            case_sensitive,
+                        );
                         if dependencies.contains(&dependency) {
                             return Err(Rich::custom(
                                 dependency.span,
@@ -195,11 +217,23 @@ impl FromStr for DequePrototype {
         }
         let mut cards = HashMap::new();
         let mut state = State::Default;
-        for (ix, ch) in s.char_indices() {
-            state = process_char(state, &mut cards, ch, ix)?;
+        let mut line_start = 0;
+        // Comments are skipped, but offsets stay the same as in `s`, so spans point to source.
+        for line in s.split_inclusive('\n') {
+            let line_content = line.strip_suffix('\n').unwrap_or(line);
+            let content = if is_comment(line_content) {
+                ""
+            } else {
+                line_content
+            };
+            for (ix, ch) in content.char_indices() {
+                state = process_char(state, &mut cards, ch, line_start + ix, case_sensitive)?;
+            }
+            let line_end = line_start + content.len();
+            state = process_char(state, &mut cards, '\n', line_end, case_sensitive)?;
+            line_start += line.len();
         }
-        let last_state = process_char(state, &mut cards, '\n', s.len())?;
-        assert!(matches!(last_state, State::Default));
+        assert!(matches!(state, State::Default));
         Ok(Self { cards })
     }
 }
@@ -218,28 +252,33 @@ mod test {
         assert_eq!(
             DequePrototype::from_str("a: b").unwrap(),
             DequePrototype {
-                cards: [(CardName::new("a", 0, 1), vec![CardName::new("b", 3, 4)])]
-                    .into_iter()
-                    .collect()
+                cards: [(
+                    CardName::new("a", 0, 1, false),
+                    vec![CardName::new("b", 3, 4, false)]
+                )]
+                .into_iter()
+                .collect()
             }
         );
         assert_eq!(
             DequePrototype::from_str("hI").unwrap(),
             DequePrototype {
-                cards: [(CardName::new("hi", 0, 2), vec![])].into_iter().collect()
+                cards: [(CardName::new("hi", 0, 2, false), vec![])]
+                    .into_iter()
+                    .collect()
             }
         );
         assert_eq!(
             DequePrototype::from_str("some: long, line, should, BE, handled").unwrap(),
             DequePrototype {
                 cards: [(
-                    CardName::new("some", 0, 4),
+                    CardName::new("some", 0, 4, false),
                     vec![
-                        CardName::new("long", 6, 10),
-                        CardName::new("line", 12, 16),
-                        CardName::new("should", 18, 24),
-                        CardName::new("be", 26, 28),
-                        CardName::new("handled", 30, 37)
+                        CardName::new("long", 6, 10, false),
+                        CardName::new("line", 12, 16, false),
+                        CardName::new("should", 18, 24, false),
+                        CardName::new("be", 26, 28, false),
+                        CardName::new("handled", 30, 37, false)
                     ]
                 )]
                 .into_iter()
@@ -251,11 +290,11 @@ mod test {
                 .unwrap(),
             DequePrototype {
                 cards: [(
-                    CardName::new("spaces is allowed", 0, 17),
+                    CardName::new("spaces is allowed", 0, 17, false),
                     vec![
-                        CardName::new("a", 19, 20),
-                        CardName::new("and here too", 22, 34),
-                        CardName::new("with caseinsensitivity", 36, 58)
+                        CardName::new("a", 19, 20, false),
+                        CardName::new("and here too", 22, 34, false),
+                        CardName::new("with caseinsensitivity", 36, 58, false)
                     ]
                 )]
                 .into_iter()
@@ -285,8 +324,11 @@ b
             .unwrap(),
             DequePrototype {
                 cards: [
-                    (CardName::new("a", 1, 2), vec![CardName::new("b", 4, 5)]),
-                    (CardName::new("b", 6, 7), vec![])
+                    (
+                        CardName::new("a", 1, 2, false),
+                        vec![CardName::new("b", 4, 5, false)]
+                    ),
+                    (CardName::new("b", 6, 7, false), vec![])
                 ]
                 .into_iter()
                 .collect()
@@ -304,16 +346,16 @@ other node: some node
             DequePrototype {
                 cards: [
                     (
-                        CardName::new("first multi word", 0, 16),
+                        CardName::new("first multi word", 0, 16, false),
                         vec![
-                            CardName::new("some node", 18, 27),
-                            CardName::new("other node", 29, 39)
+                            CardName::new("some node", 18, 27, false),
+                            CardName::new("other node", 29, 39, false)
                         ]
                     ),
-                    (CardName::new("some node", 40, 49), vec![]),
+                    (CardName::new("some node", 40, 49, false), vec![]),
                     (
-                        CardName::new("other node", 50, 60),
-                        vec![CardName::new("some node", 62, 71)]
+                        CardName::new("other node", 50, 60, false),
+                        vec![CardName::new("some node", 62, 71, false)]
                     )
                 ]
                 .into_iter()
@@ -321,4 +363,64 @@ other node: some node
             }
         );
     }
+    #[test]
+    fn comments_are_skipped() {
+        let source =
+            "# Geography\n  # indented comment\ncapitals: countries\n# between cards\ncountries";
+        let prototype = DequePrototype::from_str(source).unwrap();
+        assert_eq!(
+            prototype,
+            DequePrototype {
+                cards: [
+                    (
+                        CardName::new("capitals", 0, 8, false),
+                        vec![CardName::new("countries", 0, 9, false)]
+                    ),
+                    (CardName::new("countries", 0, 9, false), vec![])
+                ]
+                .into_iter()
+                .collect()
+            }
+        );
+        // Spans point to original source.
+        let (capitals, dependencies) = prototype
+            .cards
+            .get_key_value(&CardName::new("capitals", 0, 8, false))
+            .unwrap();
+        assert_eq!(&source[capitals.span.start..capitals.span.end], "capitals");
+        assert_eq!(
+            &source[dependencies[0].span.start..dependencies[0].span.end],
+            "countries"
+        );
+        let (countries, _) = prototype
+            .cards
+            .get_key_value(&CardName::new("countries", 0, 9, false))
+            .unwrap();
+        assert_eq!(countries.span.start, source.len() - "countries".len());
+
+        let err = DequePrototype::from_str("# comment\na: b, #c").unwrap_err();
+        assert_eq!(err.span().start, "# comment\na: b, ".len());
+    }
+    #[test]
+    fn hash_inside_card_name() {
+        let source = "# languages\nc#: basics\nbasics\nf# intro: c#";
+        assert_eq!(
+            DequePrototype::from_str(source).unwrap(),
+            DequePrototype {
+                cards: [
+                    (
+                        CardName::new("c#", 0, 2, false),
+                        vec![CardName::new("basics", 0, 6, false)]
+                    ),
+                    (CardName::new("basics", 0, 6, false), vec![]),
+                    (
+                        CardName::new("f# intro", 0, 8, false),
+                        vec![CardName::new("c#", 0, 2, false)]
+                    ),
+                ]
+                .into_iter()
+                .collect()
+            }
+        );
+    }
 }
@@ -92,6 +92,40 @@ use crate::utils::*;
 
 pub trait TaskProgressStoreExt {
     fn generate_stmts(&self) -> Vec<Stmt>;
+    /// Highlights cards, that improved since `checkpoint`: newly mastered ones are bright green,
+    /// other improvements are pale green. Everything else is gray.
+    /// Cards, missing in `checkpoint`, are compared with not started state.
+    fn generate_diff_stmts(&self, checkpoint: &impl TaskProgressStore<Id = String>) -> Vec<Stmt>;
+}
+
+/// Symbol of card state in text views. Colors are the same as in graph image.
+pub fn progress_mark(progress: TaskProgress) -> &'static str {
+    match progress {
+        TaskProgress::Good => "🟩",
+        TaskProgress::Failed => "🟥",
+        TaskProgress::RecursiveFailed => "🟨",
+        TaskProgress::NotStarted {
+            could_be_learned: true,
+        } => "⬜",
+        TaskProgress::NotStarted {
+            could_be_learned: false,
+        } => "🔒",
+    }
+}
+
+/// Higher is better. Used to find improved cards.
+fn progress_rank(progress: TaskProgress) -> u8 {
+    match progress {
+        TaskProgress::Failed => 0,
+        TaskProgress::RecursiveFailed
+        | TaskProgress::NotStarted {
+            could_be_learned: false,
+        } => 1,
+        TaskProgress::NotStarted {
+            could_be_learned: true,
+        } => 2,
+        TaskProgress::Good => 3,
+    }
 }
 
 impl<T> TaskProgressStoreExt for T
@@ -126,4 +160,29 @@ where
         }
         stmts
     }
+    fn generate_diff_stmts(&self, checkpoint: &impl TaskProgressStore<Id = String>) -> Vec<Stmt> {
+        self.iter()
+            .map(|(id, progress)| {
+                let before = if checkpoint.contains(id) {
+                    checkpoint[id]
+                } else {
+                    TaskProgress::default()
+                };
+                let color = if progress == TaskProgress::Good && before != TaskProgress::Good {
+                    color_name::chartreuse
+                } else if progress_rank(progress) > progress_rank(before) {
+                    color_name::palegreen
+                } else {
+                    color_name::lightgray
+                };
+                Stmt::Node(Node {
+                    id: NodeId(id_from_string(id), None),
+                    attributes: vec![
+                        NodeAttributes::style("filled".into()),
+                        NodeAttributes::fillcolor(color),
+                    ],
+                })
+            })
+            .collect()
+    }
 }
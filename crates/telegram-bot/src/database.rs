@@ -1,11 +1,29 @@
-use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
 
-use course_graph::graph::CourseGraph;
-use rusqlite::{Connection, OptionalExtension, Row};
+use chrono::{DateTime, Local, NaiveDate};
+use course_graph::{graph::CourseGraph, progress_store::TaskProgress};
+use rusqlite::{Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
 use serde::{Deserialize, Serialize};
 use teloxide_core::types::UserId;
 
-use crate::{event_handler::progress_store::UserProgress, interaction_types::deque::Deque};
+use crate::{
+    db_pool::{ConnectionPool, PooledConnection},
+    event_handler::{
+        clock::TimeAnchor,
+        progress_store::{PROGRESS_VERSION, UserProgress, normalize_keys},
+    },
+    interaction_types::{
+        Task,
+        card::random_task,
+        deque::{self, ConsistencyReport, Deque},
+    },
+    settings::{CourseSettings, UserSettings},
+    state::Screen,
+};
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug)]
 pub struct CourseId(pub u64);
@@ -14,30 +32,94 @@ pub struct Course {
     pub owner_id: UserId,
     pub structure: CourseGraph,
     pub tasks: Deque,
+    pub settings: CourseSettings,
+    /// Shown to learners, when they enter the course. Sent as is, without formatting.
+    pub description: String,
 }
 
-static STORAGE: LazyLock<Mutex<Connection>> =
-    LazyLock::new(|| Mutex::new(Connection::open("db.sqlite").unwrap()));
+/// Failed query or malformed stored data. Handler, which got it, fails, but bot keeps working.
+#[derive(Debug, thiserror::Error)]
+pub enum DbError {
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("malformed json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("malformed time: {0}")]
+    Time(#[from] chrono::ParseError),
+    /// Stored course sources can't be parsed.
+    #[error("malformed course: {0}")]
+    Course(String),
+    #[error("course {0} not found")]
+    MissingCourse(u64),
+}
+pub type DbResult<T> = Result<T, DbError>;
 
-fn get_connection<'a>() -> MutexGuard<'a, Connection> {
-    STORAGE.lock().unwrap_or_else(|err| {
-        log::error!("Some thread panicked while holding mutex");
-        err.into_inner()
-    })
+pub const MAX_DESCRIPTION_LEN: usize = 1000;
+/// Longer titles are cut in search results.
+pub const MAX_TITLE_LEN: usize = 64;
+
+static STORAGE: LazyLock<ConnectionPool> = LazyLock::new(|| {
+    #[cfg(test)]
+    for suffix in ["", "-wal", "-shm"] {
+        // Left by earlier run with the same pid.
+        let _ = std::fs::remove_file(format!("{}{suffix}", test_database_path().display()));
+    }
+    let connections = (0..ConnectionPool::size_from_env())
+        .map(|_| open_connection())
+        .collect();
+    ConnectionPool::new(connections)
+});
+
+fn open_connection() -> Connection {
+    #[cfg(not(test))]
+    let connection = Connection::open("db.sqlite").unwrap();
+    // Tests shouldn't touch real database. In-memory database is private to one connection,
+    // so pooled connections use temporary file.
+    #[cfg(test)]
+    let connection = Connection::open(test_database_path()).unwrap();
+    connection.busy_timeout(Duration::from_secs(5)).unwrap();
+    // Readers don't wait for writer.
+    connection
+        .query_one("PRAGMA journal_mode = WAL", (), |row| {
+            row.get::<_, String>(0)
+        })
+        .unwrap();
+    // SQLite ignores `ON DELETE CASCADE` without it. It's per connection.
+    connection
+        .execute_batch("PRAGMA foreign_keys = ON")
+        .unwrap();
+    connection
 }
 
-pub fn db_create_tables() {
+#[cfg(test)]
+fn test_database_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("telegram-bot-test-{}.sqlite", std::process::id()))
+}
+
+fn get_connection() -> PooledConnection<'static> {
+    STORAGE.get()
+}
+
+/// Takes write lock at start. Deferred transaction, which reads first, fails instead of waiting,
+/// if other connection writes in between.
+fn write_transaction(conn: &mut Connection) -> rusqlite::Result<Transaction<'_>> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate)
+}
+
+pub fn db_create_tables() -> DbResult<()> {
     let conn = get_connection();
 
     conn.execute_batch(
         "
-BEGIN;
+BEGIN IMMEDIATE;
 
 CREATE TABLE IF NOT EXISTS courses (
     course_id INTEGER PRIMARY KEY AUTOINCREMENT,
     owner_id INTEGER NOT NULL,
     structure TEXT NOT NULL,  -- JSON serialized CourseGraph
-    tasks TEXT NOT NULL       -- JSON serialized Deque
+    tasks TEXT NOT NULL,      -- JSON serialized Deque
+    settings TEXT NOT NULL DEFAULT '{}',  -- JSON serialized CourseSettings
+    description TEXT NOT NULL DEFAULT ''
 );
 
 CREATE TABLE IF NOT EXISTS user_progress (
@@ -48,158 +130,396 @@ CREATE TABLE IF NOT EXISTS user_progress (
     FOREIGN KEY (course_id) REFERENCES courses(course_id) ON DELETE CASCADE
 );
 
+CREATE TABLE IF NOT EXISTS user_settings (
+    user_id INTEGER PRIMARY KEY,
+    settings TEXT NOT NULL    -- JSON serialized UserSettings
+);
+
+CREATE TABLE IF NOT EXISTS daily_reviews (
+    user_id INTEGER NOT NULL,
+    course_id INTEGER NOT NULL,
+    day TEXT NOT NULL,        -- learner's local date, YYYY-MM-DD
+    count INTEGER NOT NULL,
+    PRIMARY KEY (user_id, course_id, day),
+    FOREIGN KEY (course_id) REFERENCES courses(course_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS progress_checkpoints (
+    user_id INTEGER NOT NULL,
+    course_id INTEGER NOT NULL,
+    states TEXT NOT NULL,     -- JSON map of card name to TaskProgress
+    PRIMARY KEY (user_id, course_id),
+    FOREIGN KEY (course_id) REFERENCES courses(course_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS user_state (
+    user_id INTEGER PRIMARY KEY,
+    screen TEXT NOT NULL,     -- 'main', 'settings' or 'course'
+    course_id INTEGER,        -- only for 'course' screen
+    FOREIGN KEY (course_id) REFERENCES courses(course_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS clock (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    real_anchor TEXT NOT NULL,       -- RFC 3339
+    simulated_anchor TEXT NOT NULL,  -- RFC 3339
+    scale INTEGER NOT NULL
+);
+
 CREATE INDEX IF NOT EXISTS idx_courses_owner ON courses(owner_id);
 
 CREATE INDEX IF NOT EXISTS idx_user_progress_user ON user_progress(user_id);
 
 COMMIT;
 ",
-    )
-    .unwrap();
+    )?;
+
+    add_column_if_missing(&conn, "courses", "settings", "TEXT NOT NULL DEFAULT '{}'")?;
+    add_column_if_missing(&conn, "courses", "description", "TEXT NOT NULL DEFAULT ''")?;
+    Ok(())
+}
+
+/// Database answers queries.
+pub fn db_ping() -> bool {
+    get_connection()
+        .query_one("SELECT 1", (), |_| Ok(()))
+        .is_ok()
+}
+
+/// Migration for databases created before `column` was added to `CREATE TABLE`.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> DbResult<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{table}') WHERE name = ?"
+        ))?
+        .exists((column,))?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            (),
+        )?;
+        log::info!("added column '{column}' to '{table}' table");
+    }
+    Ok(())
 }
 
-pub fn db_insert(course: Course) -> CourseId {
+pub fn db_insert(course: Course) -> DbResult<CourseId> {
     let mut conn = get_connection();
 
-    let tr = conn.transaction().unwrap();
+    let tr = write_transaction(&mut conn)?;
     let owner_id = course.owner_id.0;
-    let structure = serde_json::to_string(&course.structure).unwrap();
-    let tasks = serde_json::to_string(&course.tasks).unwrap();
+    let structure = serde_json::to_string(&course.structure)?;
+    let tasks = serde_json::to_string(&course.tasks)?;
+    let settings = serde_json::to_string(&course.settings)?;
     tr.execute(
         "
-        INSERT INTO courses (owner_id, structure, tasks)
-        VALUES (?1, ?2, ?3);
+        INSERT INTO courses (owner_id, structure, tasks, settings, description)
+        VALUES (?1, ?2, ?3, ?4, ?5);
         ",
-        (owner_id, structure, tasks),
-    )
-    .unwrap();
+        (owner_id, structure, tasks, settings, course.description),
+    )?;
     let course_id = CourseId(tr.last_insert_rowid() as u64);
-    tr.commit().unwrap();
+    tr.commit()?;
 
-    course_id
+    Ok(course_id)
 }
 
-fn row_to_course(row: &Row) -> rusqlite::Result<Course> {
-    let owner_id = UserId(row.get_unwrap("owner_id"));
-    let structure: String = row.get_unwrap("structure");
-    let structure = serde_json::from_str(&structure).unwrap();
-    let tasks: String = row.get_unwrap("tasks");
-    let tasks = serde_json::from_str(&tasks).unwrap();
-    Ok(Course {
-        owner_id,
-        structure,
-        tasks,
-    })
+/// Columns of `courses` table, as they are stored.
+struct CourseRow {
+    owner_id: u64,
+    structure: String,
+    tasks: String,
+    settings: String,
+    description: String,
 }
-pub fn db_get_course(CourseId(course_id): CourseId) -> Option<Course> {
+impl CourseRow {
+    fn read(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            owner_id: row.get("owner_id")?,
+            structure: row.get("structure")?,
+            tasks: row.get("tasks")?,
+            settings: row.get("settings")?,
+            description: row.get("description")?,
+        })
+    }
+    fn parse(self) -> DbResult<Course> {
+        let settings: CourseSettings = serde_json::from_str(&self.settings)?;
+        // Sources are parsed by hand, because parsing depends on course settings.
+        let structure: String = serde_json::from_str(&self.structure)?;
+        let structure = CourseGraph::parse(&structure, settings.case_sensitive_names)
+            .map_err(DbError::Course)?;
+        let tasks: String = serde_json::from_str(&self.tasks)?;
+        let tasks = deque::from_stored(&tasks, settings.case_sensitive_names)
+            .map_err(|err| DbError::Course(err.to_string()))?;
+        Ok(Course {
+            owner_id: UserId(self.owner_id),
+            structure,
+            tasks,
+            settings,
+            description: self.description,
+        })
+    }
+}
+pub fn db_get_course(CourseId(course_id): CourseId) -> DbResult<Option<Course>> {
     let conn = get_connection();
 
     conn.query_one(
         "
-        SELECT owner_id, structure, tasks
+        SELECT owner_id, structure, tasks, settings, description
         FROM courses
         WHERE course_id = ?;
         ",
         (course_id,),
-        row_to_course,
+        CourseRow::read,
     )
-    .optional()
-    .unwrap()
+    .optional()?
+    .map(CourseRow::parse)
+    .transpose()
 }
-pub fn db_set_course(CourseId(course_id): CourseId, course: Course) {
-    let conn = get_connection();
-
+/// For courses, that should exist, like current course of user.
+pub fn db_get_existing_course(course_id: CourseId) -> DbResult<Course> {
+    db_get_course(course_id)?.ok_or(DbError::MissingCourse(course_id.0))
+}
+pub fn db_set_course(course_id: CourseId, course: Course) -> DbResult<()> {
+    update_course(&get_connection(), course_id, &course)
+}
+fn update_course(
+    conn: &Connection,
+    CourseId(course_id): CourseId,
+    course: &Course,
+) -> DbResult<()> {
     let owner_id = course.owner_id.0;
-    let structure = serde_json::to_string(&course.structure).unwrap();
-    let tasks = serde_json::to_string(&course.tasks).unwrap();
+    let structure = serde_json::to_string(&course.structure)?;
+    let tasks = serde_json::to_string(&course.tasks)?;
+    let settings = serde_json::to_string(&course.settings)?;
     conn.execute(
         "
         UPDATE courses
-        SET owner_id = ?, structure = ?, tasks = ?
+        SET owner_id = ?, structure = ?, tasks = ?, settings = ?, description = ?
         WHERE course_id = ?;
         ",
-        (owner_id, structure, tasks, course_id),
-    )
-    .unwrap();
+        (
+            owner_id,
+            structure,
+            tasks,
+            settings,
+            &course.description,
+            course_id,
+        ),
+    )?;
+    Ok(())
+}
+/// Saves course with changed `case_sensitive_names`. Learners progress and checkpoints keep card
+/// names, normalized by old setting, so they are renamed in the same transaction.
+pub fn db_set_course_with_names_case(course_id: CourseId, course: Course) -> DbResult<()> {
+    let case_sensitive = course.settings.case_sensitive_names;
+    let mut conn = get_connection();
+    let tr = write_transaction(&mut conn)?;
+    update_course(&tr, course_id, &course)?;
+
+    let progresses = tr
+        .prepare("SELECT user_id, progress FROM user_progress WHERE course_id = ?")?
+        .query_map((course_id.0,), |row| {
+            Ok((
+                row.get::<_, u64>("user_id")?,
+                row.get::<_, String>("progress")?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (user_id, progress) in progresses {
+        let mut progress = load_progress(&tr, user_id, course_id.0, &progress)?;
+        progress.normalize_names(case_sensitive);
+        tr.execute(
+            "UPDATE user_progress SET progress = ? WHERE user_id = ? AND course_id = ?",
+            (serde_json::to_string(&progress)?, user_id, course_id.0),
+        )?;
+    }
+
+    let checkpoints = tr
+        .prepare("SELECT user_id, states FROM progress_checkpoints WHERE course_id = ?")?
+        .query_map((course_id.0,), |row| {
+            Ok((
+                row.get::<_, u64>("user_id")?,
+                row.get::<_, String>("states")?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (user_id, states) in checkpoints {
+        let states: HashMap<String, TaskProgress> = serde_json::from_str(&states)?;
+        let states = normalize_keys(states, case_sensitive);
+        tr.execute(
+            "UPDATE progress_checkpoints SET states = ? WHERE user_id = ? AND course_id = ?",
+            (serde_json::to_string(&states)?, user_id, course_id.0),
+        )?;
+    }
+    tr.commit()?;
+    Ok(())
 }
-pub fn db_select_courses_by_owner(owner: UserId) -> Vec<CourseId> {
+pub fn db_select_courses_by_owner(owner: UserId) -> DbResult<Vec<CourseId>> {
     let conn = get_connection();
 
-    conn.prepare(
-        "
+    let courses = conn
+        .prepare(
+            "
         SELECT course_id
         FROM courses
         WHERE owner_id = ?;
         ",
-    )
-    .unwrap()
-    .query_map((owner.0,), |row| Ok(CourseId(row.get_unwrap("course_id"))))
-    .unwrap()
-    .collect::<Result<_, _>>()
-    .unwrap()
+        )?
+        .query_map((owner.0,), |row| Ok(CourseId(row.get("course_id")?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(courses)
 }
-pub fn db_list_user_learned_courses(user_id: UserId) -> Vec<CourseId> {
+/// Most recent first.
+pub fn db_public_courses() -> DbResult<Vec<(CourseId, Course)>> {
     let conn = get_connection();
 
-    conn.prepare(
-        "
+    let rows = conn
+        .prepare(
+            "
+        SELECT course_id, owner_id, structure, tasks, settings, description
+        FROM courses
+        WHERE json_extract(settings, '$.public') = 1
+        ORDER BY course_id DESC;
+        ",
+        )?
+        .query_map((), |row| {
+            Ok((CourseId(row.get("course_id")?), CourseRow::read(row)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.into_iter()
+        .map(|(course_id, row)| Ok((course_id, row.parse()?)))
+        .collect()
+}
+pub fn db_list_user_learned_courses(user_id: UserId) -> DbResult<Vec<CourseId>> {
+    let conn = get_connection();
+
+    let courses = conn
+        .prepare(
+            "
         SELECT course_id
         FROM user_progress
         WHERE user_id = ?;
         ",
-    )
-    .unwrap()
-    .query_map((user_id.0,), |row| Ok(CourseId(row.get("course_id")?)))
-    .unwrap()
-    .collect::<Result<_, _>>()
-    .unwrap()
+        )?
+        .query_map((user_id.0,), |row| Ok(CourseId(row.get("course_id")?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(courses)
 }
-/// Panics if user doesn't have progress for this course.
-pub fn db_get_progress(UserId(user_id): UserId, CourseId(course_id): CourseId) -> UserProgress {
+/// Error, if user doesn't have progress for this course.
+pub fn db_get_progress(
+    UserId(user_id): UserId,
+    CourseId(course_id): CourseId,
+) -> DbResult<UserProgress> {
     let conn = get_connection();
 
-    conn.query_one(
+    let progress: String = conn.query_one(
         "SELECT progress FROM user_progress WHERE user_id = ? AND course_id = ?",
         (user_id, course_id),
-        |row| {
-            let progress: String = row.get_unwrap("progress");
-            let progress = serde_json::from_str(&progress).unwrap();
-            Ok(progress)
-        },
-    )
-    .unwrap()
+        |row| row.get("progress"),
+    )?;
+    load_progress(&conn, user_id, course_id, &progress)
+}
+/// Progress, saved by older version, is migrated and saved back.
+fn load_progress(
+    conn: &Connection,
+    user_id: u64,
+    course_id: u64,
+    json: &str,
+) -> DbResult<UserProgress> {
+    let mut progress: UserProgress = serde_json::from_str(json)?;
+    if progress.migrate(SystemTime::now()) {
+        conn.execute(
+            "UPDATE user_progress SET progress = ? WHERE user_id = ? AND course_id = ?",
+            (serde_json::to_string(&progress)?, user_id, course_id),
+        )?;
+        log::info!("progress of user {user_id} in course {course_id} is migrated");
+    }
+    Ok(progress)
+}
+pub fn db_all_progress_for_course(
+    CourseId(course_id): CourseId,
+) -> DbResult<Vec<(UserId, UserProgress)>> {
+    let conn = get_connection();
+
+    let rows = conn
+        .prepare(
+            "
+        SELECT user_id, progress
+        FROM user_progress
+        WHERE course_id = ?;
+        ",
+        )?
+        .query_map((course_id,), |row| {
+            Ok((
+                row.get::<_, u64>("user_id")?,
+                row.get::<_, String>("progress")?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.into_iter()
+        .map(|(user_id, progress)| {
+            let progress = load_progress(&conn, user_id, course_id, &progress)?;
+            Ok((UserId(user_id), progress))
+        })
+        .collect()
 }
-pub fn db_add_course_to_user(user_id: UserId, course_id: CourseId) {
+pub fn db_add_course_to_user(user_id: UserId, course_id: CourseId) -> DbResult<()> {
     let mut conn = get_connection();
 
-    let tr = conn.transaction().unwrap();
+    let tr = write_transaction(&mut conn)?;
     let course = tr
         .query_one(
             "
-            SELECT owner_id, structure, tasks
+            SELECT owner_id, structure, tasks, settings, description
             FROM courses
             WHERE course_id = ?;
             ",
             (course_id.0,),
-            row_to_course,
-        )
-        .unwrap();
+            CourseRow::read,
+        )?
+        .parse()?;
 
     if course.owner_id != user_id {
-        let default_progress = serde_json::to_string(&course.default_user_progress()).unwrap();
+        let default_progress = serde_json::to_string(&course.default_user_progress())?;
         tr.execute(
             "INSERT OR IGNORE INTO user_progress (user_id, course_id, progress) VALUES (?, ?, ?)",
             (user_id.0, course_id.0, default_progress),
-        )
-        .unwrap();
+        )?;
         log::info!("initialized course {} for user ({})", course_id.0, user_id);
     }
-    tr.commit().unwrap();
+    tr.commit()?;
+    Ok(())
 }
-/// Returns None if this progress doesn't exists.
-pub fn db_set_course_progress(user_id: UserId, course_id: CourseId, progress: UserProgress) {
+/// Progress and counters of this course are deleted, so learner can join it again from scratch.
+pub fn db_remove_course_from_user(
+    UserId(user_id): UserId,
+    CourseId(course_id): CourseId,
+) -> DbResult<()> {
+    let mut conn = get_connection();
+    let tr = write_transaction(&mut conn)?;
+    for table in ["user_progress", "daily_reviews", "progress_checkpoints"] {
+        tr.execute(
+            &format!("DELETE FROM {table} WHERE user_id = ? AND course_id = ?"),
+            (user_id, course_id),
+        )?;
+    }
+    tr.commit()?;
+    Ok(())
+}
+/// Does nothing, if this progress doesn't exist.
+pub fn db_set_course_progress(
+    user_id: UserId,
+    course_id: CourseId,
+    progress: UserProgress,
+) -> DbResult<()> {
     let conn = get_connection();
-    let progress = serde_json::to_string(&progress).unwrap();
+    let progress = serde_json::to_string(&progress)?;
     conn.execute(
         "
         UPDATE user_progress
@@ -207,40 +527,916 @@ pub fn db_set_course_progress(user_id: UserId, course_id: CourseId, progress: Us
         WHERE user_id = ? AND course_id = ?
         ",
         (progress, user_id.0, course_id.0),
-    )
-    .unwrap();
+    )?;
+    Ok(())
+}
+pub fn db_get_user_settings(UserId(user_id): UserId) -> DbResult<UserSettings> {
+    let conn = get_connection();
+
+    let settings: Option<String> = conn
+        .query_one(
+            "SELECT settings FROM user_settings WHERE user_id = ?",
+            (user_id,),
+            |row| row.get("settings"),
+        )
+        .optional()?;
+    match settings {
+        Some(settings) => Ok(serde_json::from_str(&settings)?),
+        None => Ok(UserSettings::default()),
+    }
+}
+/// Only screen survives restart. Interactions are lost, learner just starts them again.
+pub fn db_save_user_screen(UserId(user_id): UserId, screen: Screen) -> DbResult<()> {
+    let (name, course_id) = match screen {
+        Screen::Main => ("main", None),
+        Screen::Settings => ("settings", None),
+        Screen::Course(CourseId(course_id)) => ("course", Some(course_id)),
+    };
+    let conn = get_connection();
+    conn.execute(
+        "INSERT OR REPLACE INTO user_state (user_id, screen, course_id) VALUES (?, ?, ?)",
+        (user_id, name, course_id),
+    )?;
+    Ok(())
+}
+/// Main screen, if nothing is saved. Screens of deleted courses are deleted with them.
+pub fn db_load_user_screen(UserId(user_id): UserId) -> DbResult<Screen> {
+    let conn = get_connection();
+    let saved = conn
+        .query_one(
+            "SELECT screen, course_id FROM user_state WHERE user_id = ?",
+            (user_id,),
+            |row| {
+                Ok((
+                    row.get::<_, String>("screen")?,
+                    row.get::<_, Option<u64>>("course_id")?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(match saved {
+        Some((screen, _)) if screen == "settings" => Screen::Settings,
+        Some((screen, Some(course_id))) if screen == "course" => {
+            Screen::Course(CourseId(course_id))
+        }
+        _ => Screen::Main,
+    })
+}
+pub fn db_set_user_settings(UserId(user_id): UserId, settings: &UserSettings) -> DbResult<()> {
+    let conn = get_connection();
+    let settings = serde_json::to_string(settings)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO user_settings (user_id, settings) VALUES (?, ?)",
+        (user_id, settings),
+    )?;
+    Ok(())
+}
+
+/// Number of reviews, learner did in course on `day`.
+pub fn db_get_daily_reviews(
+    UserId(user_id): UserId,
+    CourseId(course_id): CourseId,
+    day: NaiveDate,
+) -> DbResult<u32> {
+    let conn = get_connection();
+    let count = conn
+        .query_one(
+            "SELECT count FROM daily_reviews WHERE user_id = ? AND course_id = ? AND day = ?",
+            (user_id, course_id, day.to_string()),
+            |row| row.get("count"),
+        )
+        .optional()?;
+    Ok(count.unwrap_or(0))
+}
+/// Counters of previous days are removed.
+pub fn db_add_daily_review(
+    UserId(user_id): UserId,
+    CourseId(course_id): CourseId,
+    day: NaiveDate,
+) -> DbResult<()> {
+    let mut conn = get_connection();
+    let tr = write_transaction(&mut conn)?;
+    let day = day.to_string();
+    tr.execute(
+        "DELETE FROM daily_reviews WHERE user_id = ? AND course_id = ? AND day != ?",
+        (user_id, course_id, &day),
+    )?;
+    tr.execute(
+        "
+        INSERT INTO daily_reviews (user_id, course_id, day, count) VALUES (?, ?, ?, 1)
+        ON CONFLICT (user_id, course_id, day) DO UPDATE SET count = count + 1
+        ",
+        (user_id, course_id, &day),
+    )?;
+    tr.commit()?;
+    Ok(())
+}
+
+/// Card states, saved with `/checkpoint`. Replaces previous checkpoint.
+pub fn db_set_checkpoint(
+    UserId(user_id): UserId,
+    CourseId(course_id): CourseId,
+    states: &HashMap<String, TaskProgress>,
+) -> DbResult<()> {
+    let conn = get_connection();
+    let states = serde_json::to_string(states)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO progress_checkpoints (user_id, course_id, states) VALUES (?, ?, ?)",
+        (user_id, course_id, states),
+    )?;
+    Ok(())
+}
+pub fn db_get_checkpoint(
+    UserId(user_id): UserId,
+    CourseId(course_id): CourseId,
+) -> DbResult<Option<HashMap<String, TaskProgress>>> {
+    let conn = get_connection();
+    let states: Option<String> = conn
+        .query_one(
+            "SELECT states FROM progress_checkpoints WHERE user_id = ? AND course_id = ?",
+            (user_id, course_id),
+            |row| row.get("states"),
+        )
+        .optional()?;
+    match states {
+        Some(states) => Ok(Some(serde_json::from_str(&states)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn db_get_time_anchor() -> DbResult<Option<TimeAnchor>> {
+    let conn = get_connection();
+
+    let anchor = conn
+        .query_one(
+            "SELECT real_anchor, simulated_anchor, scale FROM clock WHERE id = 0",
+            (),
+            |row| {
+                Ok((
+                    row.get::<_, String>("real_anchor")?,
+                    row.get::<_, String>("simulated_anchor")?,
+                    row.get("scale")?,
+                ))
+            },
+        )
+        .optional()?;
+    let Some((real, simulated, scale)) = anchor else {
+        return Ok(None);
+    };
+    let parse = |time: &str| -> DbResult<_> {
+        Ok(DateTime::parse_from_rfc3339(time)?.with_timezone(&Local))
+    };
+    Ok(Some(TimeAnchor {
+        real: parse(&real)?,
+        simulated: parse(&simulated)?,
+        scale,
+    }))
+}
+pub fn db_set_time_anchor(anchor: &TimeAnchor) -> DbResult<()> {
+    let conn = get_connection();
+    conn.execute(
+        "INSERT OR REPLACE INTO clock (id, real_anchor, simulated_anchor, scale) VALUES (0, ?, ?, ?)",
+        (
+            anchor.real.to_rfc3339(),
+            anchor.simulated.to_rfc3339(),
+            anchor.scale,
+        ),
+    )?;
+    Ok(())
+}
+
+/// Progress, settings and daily reviews of user. Owned courses are kept.
+pub fn db_delete_user_data(UserId(user_id): UserId) -> DbResult<()> {
+    let mut conn = get_connection();
+    let tr = write_transaction(&mut conn)?;
+    for table in [
+        "user_progress",
+        "user_settings",
+        "daily_reviews",
+        "progress_checkpoints",
+        "user_state",
+    ] {
+        tr.execute(
+            &format!("DELETE FROM {table} WHERE user_id = ?"),
+            (user_id,),
+        )?;
+    }
+    tr.commit()?;
+    Ok(())
+}
+/// Progress of learners is deleted with course, so nobody is left with missing course.
+/// Foreign keys are not enforced, so rows are deleted explicitly.
+pub fn db_delete_course(CourseId(course_id): CourseId) -> DbResult<()> {
+    let mut conn = get_connection();
+    let tr = write_transaction(&mut conn)?;
+    for table in [
+        "user_progress",
+        "daily_reviews",
+        "progress_checkpoints",
+        "user_state",
+        "courses",
+    ] {
+        tr.execute(
+            &format!("DELETE FROM {table} WHERE course_id = ?"),
+            (course_id,),
+        )?;
+    }
+    tr.commit()?;
+    Ok(())
+}
+
+/// Everything stored about one user, for `/export_my_data`.
+#[derive(Serialize)]
+pub struct UserDataExport {
+    pub user_id: u64,
+    pub settings: UserSettings,
+    pub owned_courses: Vec<u64>,
+    pub learned_courses: Vec<u64>,
+    /// By course id.
+    pub progress: BTreeMap<u64, UserProgress>,
+}
+pub fn db_export_user_data(user_id: UserId) -> DbResult<UserDataExport> {
+    let learned_courses = db_list_user_learned_courses(user_id)?;
+    Ok(UserDataExport {
+        user_id: user_id.0,
+        settings: db_get_user_settings(user_id)?,
+        owned_courses: db_select_courses_by_owner(user_id)?
+            .into_iter()
+            .map(|CourseId(id)| id)
+            .collect(),
+        learned_courses: learned_courses.iter().map(|CourseId(id)| *id).collect(),
+        progress: learned_courses
+            .into_iter()
+            .map(|course_id| Ok((course_id.0, db_get_progress(user_id, course_id)?)))
+            .collect::<DbResult<_>>()?,
+    })
 }
 
+/// Sources of the only card of [`Course::empty`].
+const PLACEHOLDER_GRAPH: &str = "intro";
+const PLACEHOLDER_DEQUE: &str = "# Name
+intro
+## Task 1
+This is placeholder card. Replace it with /change_course_graph and /change_deque.
+
+* Got it
+- What?
+";
+
 impl Course {
+    /// Course with one placeholder card, so it's valid before owner adds own cards.
+    pub fn empty(owner_id: UserId) -> Self {
+        Self {
+            owner_id,
+            structure: CourseGraph::parse(PLACEHOLDER_GRAPH, false).unwrap(),
+            tasks: deque::from_str(PLACEHOLDER_DEQUE, true, false).unwrap(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        }
+    }
+    /// Course with default graph and deque, so tests can use "countries" and "capitals" cards.
+    #[cfg(test)]
+    pub fn with_default_cards(owner_id: UserId) -> Self {
+        Self {
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            ..Self::empty(owner_id)
+        }
+    }
+    /// Same course with other cards. Fails, if graph and deque have different cards.
+    pub fn with(self, structure: CourseGraph, tasks: Deque) -> Result<Self, ConsistencyReport> {
+        let report = tasks.validate_against(&structure);
+        if !report.is_consistent() {
+            return Err(report);
+        }
+        Ok(Self {
+            structure,
+            tasks,
+            ..self
+        })
+    }
     pub fn default_user_progress(&self) -> UserProgress {
         let mut user_progress = UserProgress::default();
         self.structure.init_store(&mut user_progress);
         user_progress
     }
+    /// Description, that `user_id` should see when entering the course.
+    /// Owner already knows it, so they see nothing.
+    pub fn entry_description(&self, user_id: UserId) -> Option<&str> {
+        (self.owner_id != user_id && !self.description.trim().is_empty())
+            .then_some(self.description.as_str())
+    }
+    /// First line of description, at most [`MAX_TITLE_LEN`] characters.
+    pub fn title(&self) -> Option<&str> {
+        let title = self
+            .description
+            .lines()
+            .map(|line| line.trim_start_matches('#').trim())
+            .find(|line| !line.is_empty())?;
+        let end = title
+            .char_indices()
+            .nth(MAX_TITLE_LEN)
+            .map_or(title.len(), |(ix, _)| ix);
+        Some(title[..end].trim_end())
+    }
     pub fn get_errors(&self) -> Option<Vec<String>> {
-        let deque = &self.tasks;
-        let course_graph = &self.structure;
-        let mut errors = Vec::new();
-
-        course_graph
-            .cards()
-            .keys()
-            .filter(|&id| !deque.tasks.contains_key(id))
-            .map(|id| format!("Graph has '{id}' card, but deque doesn't."))
-            .for_each(|item| errors.push(item));
-        deque
+        let report = self.tasks.validate_against(&self.structure);
+        (!report.is_consistent()).then(|| report.messages())
+    }
+    pub fn has_card(&self, card: &str) -> bool {
+        self.tasks.tasks.contains_key(card)
+    }
+    /// Task `## Task n` of card.
+    pub fn task_for(&self, card: &str, n: u16) -> Option<&Task> {
+        self.tasks.tasks.get(card)?.get(&n)
+    }
+    /// Order, in which learners start cards in linear mode. None if course isn't linear.
+    /// Cards, missing in explicit order, follow it in topological order.
+    pub fn card_sequence(&self) -> Option<Vec<&String>> {
+        let explicit = self.settings.linear_order.as_ref()?;
+        let mut sequence = explicit
+            .iter()
+            .filter(|&name| self.structure.cards().contains_key(name))
+            .collect::<Vec<_>>();
+        for name in self.structure.topological_order() {
+            if !sequence.contains(&name) {
+                sequence.push(name);
+            }
+        }
+        Some(sequence)
+    }
+    /// In linear mode, learner can start only the first startable card of [`Self::card_sequence`].
+    /// Started cards can be repeated in any order.
+    pub fn is_out_of_sequence(&self, progress: &UserProgress, card: &str) -> bool {
+        let Some(sequence) = self.card_sequence() else {
+            return false;
+        };
+        progress
             .tasks
-            .keys()
-            .filter(|x| !CourseGraph::default().cards().contains_key(*x))
-            .map(|err| format!("Deque has '{err}', but graph doesn't."))
-            .for_each(|item| {
-                errors.push(item);
-            });
+            .get(card)
+            .is_some_and(|task| matches!(task.progress, TaskProgress::NotStarted { .. }))
+            && progress.first_startable(&sequence).map(String::as_str) != Some(card)
+    }
+    /// Random task of card. None if there is no such card or it has no tasks.
+    pub fn any_task(&self, card: &str) -> Option<&Task> {
+        random_task(self.tasks.tasks.get(card)?, rand::rng())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn description_is_saved() {
+        db_create_tables().unwrap();
+        let owner_id = UserId(218_000);
+        let course_id = db_insert(Course {
+            owner_id,
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        let course = db_get_course(course_id).unwrap().unwrap();
+        assert_eq!(course.entry_description(UserId(218_001)), None);
+
+        let mut course = course;
+        course.description = "Learn *everything*\nabout countries.".into();
+        db_set_course(course_id, course).unwrap();
+
+        let course = db_get_course(course_id).unwrap().unwrap();
+        assert_eq!(course.description, "Learn *everything*\nabout countries.");
+        assert_eq!(
+            course.entry_description(UserId(218_001)),
+            Some("Learn *everything*\nabout countries.")
+        );
+        assert_eq!(course.entry_description(owner_id), None);
+    }
+
+    #[test]
+    fn checkpoint_is_saved() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course {
+            owner_id: UserId(260_100),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        let learner = UserId(260_101);
+        db_add_course_to_user(learner, course_id).unwrap();
+        assert_eq!(db_get_checkpoint(learner, course_id).unwrap(), None);
+
+        let states = db_get_progress(learner, course_id).unwrap().states();
+        db_set_checkpoint(learner, course_id, &states).unwrap();
+        assert_eq!(
+            db_get_checkpoint(learner, course_id).unwrap(),
+            Some(states.clone())
+        );
+
+        let mut newer = states;
+        newer.insert("countries".into(), TaskProgress::Good);
+        db_set_checkpoint(learner, course_id, &newer).unwrap();
+        assert_eq!(db_get_checkpoint(learner, course_id).unwrap(), Some(newer));
 
-        if errors.is_empty() {
-            None
-        } else {
-            Some(errors)
+        db_delete_user_data(learner).unwrap();
+        assert_eq!(db_get_checkpoint(learner, course_id).unwrap(), None);
+    }
+
+    #[test]
+    fn errors_use_course_graph() {
+        let card = |name: &str| format!("# Name\n{name}\n## Task 1\nQ\n\n* a\n- b");
+        let mut course = Course {
+            owner_id: UserId(260_000),
+            structure: CourseGraph::parse("rivers\nlakes: rivers", false).unwrap(),
+            tasks: deque::from_str(
+                &[card("rivers"), card("lakes")].join("\n-----\n"),
+                true,
+                false,
+            )
+            .unwrap(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        assert_eq!(course.get_errors(), None);
+
+        course.tasks = deque::from_str(
+            &[card("rivers"), card("seas")].join("\n-----\n"),
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            course.get_errors(),
+            Some(vec![
+                "Graph has 'lakes' card, but deque doesn't.".to_owned(),
+                "Deque has 'seas', but graph doesn't.".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn long_title_is_cut() {
+        let mut course = Course {
+            owner_id: UserId(256_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: format!("# {}\nrest", "ж".repeat(MAX_TITLE_LEN + 10)),
+        };
+        assert_eq!(course.title(), Some("ж".repeat(MAX_TITLE_LEN).as_str()));
+        course.description = "\n## *Short*  \nrest".into();
+        assert_eq!(course.title(), Some("*Short*"));
+    }
+
+    #[test]
+    fn tasks_are_looked_up_safely() {
+        let course = Course {
+            owner_id: UserId(267_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        assert!(course.has_card("capitals"));
+        assert!(!course.has_card("unknown"));
+        assert_eq!(
+            course.task_for("capitals", 2),
+            course.tasks.tasks["capitals"].get(&2)
+        );
+        assert!(course.task_for("capitals", 2).is_some());
+        assert!(course.task_for("capitals", 0).is_none());
+        assert!(course.task_for("capitals", 99).is_none());
+        assert!(course.task_for("unknown", 1).is_none());
+        assert!(course.any_task("capitals").is_some());
+        assert!(course.any_task("unknown").is_none());
+    }
+
+    #[test]
+    fn empty_course_is_valid() {
+        let course = Course::empty(UserId(275_000));
+        assert_eq!(course.get_errors(), None);
+        assert_eq!(course.structure.cards().len(), 1);
+        assert!(course.has_card("intro"));
+        assert!(course.any_task("intro").is_some());
+
+        db_create_tables().unwrap();
+        let course_id = db_insert(course).unwrap();
+        let course = db_get_course(course_id).unwrap().unwrap();
+        assert!(course.has_card("intro"));
+    }
+
+    #[test]
+    fn course_with_inconsistent_cards_is_rejected() {
+        let deque = |names: &[&str]| {
+            let cards = names
+                .iter()
+                .map(|name| format!("# Name\n{name}\n## Task 1\nQ\n\n* a\n- b"))
+                .collect::<Vec<_>>();
+            deque::from_str(&cards.join("\n-----\n"), true, false).unwrap()
+        };
+        let course = Course::empty(UserId(275_001));
+        let Err(report) = course
+            .clone()
+            .with(CourseGraph::parse("a\nb: a", false).unwrap(), deque(&["a"]))
+        else {
+            panic!("course without deque card should be rejected");
+        };
+        assert_eq!(report.missing_in_deque, ["b"]);
+        let Err(report) = course
+            .clone()
+            .with(CourseGraph::parse("a", false).unwrap(), deque(&["a", "c"]))
+        else {
+            panic!("course without graph card should be rejected");
+        };
+        assert_eq!(report.missing_in_graph, ["c"]);
+
+        let course = course
+            .with(
+                CourseGraph::parse("a\nb: a", false).unwrap(),
+                deque(&["a", "b"]),
+            )
+            .unwrap();
+        assert_eq!(course.owner_id, UserId(275_001));
+        assert!(course.has_card("b"));
+        assert!(!course.has_card("intro"));
+    }
+
+    #[test]
+    fn linear_course_refuses_later_cards() {
+        let mut course = Course {
+            owner_id: UserId(273_000),
+            structure: CourseGraph::parse("a\nb\nc", false).unwrap(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        let mut progress = course.default_user_progress();
+        course.structure.detect_recursive_fails(&mut progress);
+        let [a, b, c] = ["a", "b", "c"].map(String::from);
+        assert!(!course.is_out_of_sequence(&progress, &c));
+
+        course.settings.linear_order = Some(vec![c.clone(), a.clone()]);
+        assert_eq!(course.card_sequence().unwrap(), [&c, &a, &b]);
+        assert!(course.is_out_of_sequence(&progress, &a));
+        assert!(course.is_out_of_sequence(&progress, &b));
+        assert!(!course.is_out_of_sequence(&progress, &c));
+
+        progress.tasks.get_mut(&c).unwrap().progress = TaskProgress::Good;
+        assert!(!course.is_out_of_sequence(&progress, &a));
+        assert!(course.is_out_of_sequence(&progress, &b));
+        // Started cards can be repeated.
+        assert!(!course.is_out_of_sequence(&progress, &c));
+
+        course.settings.linear_order = Some(Vec::new());
+        assert_eq!(course.card_sequence().unwrap(), [&a, &b, &c]);
+        assert!(!course.is_out_of_sequence(&progress, &a));
+        assert!(course.is_out_of_sequence(&progress, &b));
+    }
+
+    #[test]
+    fn only_public_courses_are_listed() {
+        db_create_tables().unwrap();
+        let course = Course {
+            owner_id: UserId(219_001),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: "Private course".into(),
+        };
+        let private = db_insert(course.clone()).unwrap();
+        let public = db_insert(Course {
+            settings: CourseSettings {
+                public: true,
+                ..Default::default()
+            },
+            ..course
+        })
+        .unwrap();
+        let listed = db_public_courses()
+            .unwrap()
+            .into_iter()
+            .map(|(course_id, _)| course_id)
+            .collect::<Vec<_>>();
+        assert!(listed.contains(&public));
+        assert!(!listed.contains(&private));
+    }
+    #[test]
+    fn export_contains_only_own_data() {
+        db_create_tables().unwrap();
+        let learner = UserId(245_001);
+        let other = UserId(245_002);
+        let course = Course {
+            owner_id: other,
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        let learned = db_insert(course.clone()).unwrap();
+        let owned = db_insert(Course {
+            owner_id: learner,
+            ..course.clone()
+        })
+        .unwrap();
+        let others = db_insert(course).unwrap();
+        db_add_course_to_user(learner, learned).unwrap();
+        db_add_course_to_user(other, learned).unwrap();
+        db_add_course_to_user(other, others).unwrap();
+
+        let export = db_export_user_data(learner).unwrap();
+        assert_eq!(export.owned_courses, [owned.0]);
+        assert_eq!(export.learned_courses, [learned.0]);
+        assert_eq!(export.progress.keys().collect::<Vec<_>>(), [&learned.0]);
+        let json = serde_json::to_string(&export).unwrap();
+        assert!(!json.contains(&other.0.to_string()));
+    }
+    #[test]
+    fn user_data_is_deleted() {
+        db_create_tables().unwrap();
+        let user = UserId(246_001);
+        let other = UserId(246_002);
+        let course = Course {
+            owner_id: user,
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        let owned = db_insert(course.clone()).unwrap();
+        let learned = db_insert(Course {
+            owner_id: other,
+            ..course
+        })
+        .unwrap();
+        for course_id in [owned, learned] {
+            db_add_course_to_user(user, course_id).unwrap();
+            db_add_course_to_user(other, course_id).unwrap();
         }
+        db_set_user_settings(
+            user,
+            &UserSettings {
+                utc_offset: 3,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        db_delete_user_data(user).unwrap();
+        assert!(db_list_user_learned_courses(user).unwrap().is_empty());
+        assert_eq!(db_get_user_settings(user).unwrap(), UserSettings::default());
+        assert_eq!(db_list_user_learned_courses(other).unwrap().len(), 2);
+        assert_eq!(db_select_courses_by_owner(user).unwrap(), [owned]);
+
+        db_delete_course(owned).unwrap();
+        assert!(db_get_course(owned).unwrap().is_none());
+        assert_eq!(db_list_user_learned_courses(other).unwrap(), [learned]);
+    }
+    #[test]
+    fn course_deletion_removes_progress() {
+        db_create_tables().unwrap();
+        let owner = UserId(262_001);
+        let learner = UserId(262_002);
+        let course = Course {
+            owner_id: owner,
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        let deleted = db_insert(course.clone()).unwrap();
+        let kept = db_insert(course).unwrap();
+        let today = Local::now().date_naive();
+        for course_id in [deleted, kept] {
+            db_add_course_to_user(learner, course_id).unwrap();
+            db_add_daily_review(learner, course_id, today).unwrap();
+        }
+
+        db_delete_course(deleted).unwrap();
+        assert!(db_get_course(deleted).unwrap().is_none());
+        assert!(db_all_progress_for_course(deleted).unwrap().is_empty());
+        assert_eq!(db_get_daily_reviews(learner, deleted, today).unwrap(), 0);
+        assert_eq!(db_list_user_learned_courses(learner).unwrap(), [kept]);
+        assert_eq!(db_all_progress_for_course(kept).unwrap().len(), 1);
+        assert_eq!(db_get_daily_reviews(learner, kept, today).unwrap(), 1);
+    }
+    #[test]
+    fn deleted_course_row_cascades() {
+        db_create_tables().unwrap();
+        let learner = UserId(262_101);
+        let course_id = db_insert(Course::empty(UserId(262_100))).unwrap();
+        db_add_course_to_user(learner, course_id).unwrap();
+        db_add_daily_review(learner, course_id, Local::now().date_naive()).unwrap();
+
+        get_connection()
+            .execute("DELETE FROM courses WHERE course_id = ?", (course_id.0,))
+            .unwrap();
+        assert!(db_all_progress_for_course(course_id).unwrap().is_empty());
+        assert!(db_list_user_learned_courses(learner).unwrap().is_empty());
+        assert_eq!(
+            db_get_daily_reviews(learner, course_id, Local::now().date_naive()).unwrap(),
+            0
+        );
+    }
+    #[test]
+    fn names_case_change_renames_progress() {
+        db_create_tables().unwrap();
+        let learner = UserId(201_101);
+        let mut course = Course::empty(UserId(201_100));
+        course.settings.case_sensitive_names = true;
+        let course_id = db_insert(course.clone()).unwrap();
+        db_add_course_to_user(learner, course_id).unwrap();
+        // Progress, saved while names were case sensitive.
+        let mut progress = db_get_progress(learner, course_id).unwrap();
+        let mut task = progress.tasks.remove("intro").unwrap();
+        task.note = Some("my note".into());
+        progress.tasks.insert("Intro".into(), task);
+        progress.goals = vec!["Intro".into()];
+        db_set_course_progress(learner, course_id, progress).unwrap();
+        let states = HashMap::from([("Intro".to_owned(), TaskProgress::Good)]);
+        db_set_checkpoint(learner, course_id, &states).unwrap();
+
+        course.settings.case_sensitive_names = false;
+        db_set_course_with_names_case(course_id, course).unwrap();
+        assert!(
+            !db_get_existing_course(course_id)
+                .unwrap()
+                .settings
+                .case_sensitive_names
+        );
+        let progress = db_get_progress(learner, course_id).unwrap();
+        assert_eq!(progress.tasks.keys().collect::<Vec<_>>(), ["intro"]);
+        assert_eq!(progress.tasks["intro"].note.as_deref(), Some("my note"));
+        assert_eq!(progress.goals, ["intro"]);
+        let checkpoint = db_get_checkpoint(learner, course_id).unwrap().unwrap();
+        assert_eq!(checkpoint.keys().collect::<Vec<_>>(), ["intro"]);
+    }
+    #[test]
+    fn course_is_removed_from_user() {
+        db_create_tables().unwrap();
+        let learner = UserId(263_001);
+        let other = UserId(263_002);
+        let course_id = db_insert(Course {
+            owner_id: UserId(263_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        db_add_course_to_user(learner, course_id).unwrap();
+        db_add_course_to_user(other, course_id).unwrap();
+        assert_eq!(db_list_user_learned_courses(learner).unwrap(), [course_id]);
+
+        db_remove_course_from_user(learner, course_id).unwrap();
+        assert!(db_list_user_learned_courses(learner).unwrap().is_empty());
+        assert_eq!(db_list_user_learned_courses(other).unwrap(), [course_id]);
+
+        // Joining again starts from scratch.
+        db_add_course_to_user(learner, course_id).unwrap();
+        assert_eq!(db_list_user_learned_courses(learner).unwrap(), [course_id]);
+    }
+    #[test]
+    fn user_screen_is_persisted() {
+        db_create_tables().unwrap();
+        let user = UserId(264_001);
+        assert_eq!(db_load_user_screen(user).unwrap(), Screen::Main);
+        let course_id = db_insert(Course {
+            owner_id: UserId(264_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        db_save_user_screen(user, Screen::Course(course_id)).unwrap();
+        assert_eq!(
+            db_load_user_screen(user).unwrap(),
+            Screen::Course(course_id)
+        );
+        db_save_user_screen(user, Screen::Settings).unwrap();
+        assert_eq!(db_load_user_screen(user).unwrap(), Screen::Settings);
+
+        db_save_user_screen(user, Screen::Course(course_id)).unwrap();
+        db_delete_course(course_id).unwrap();
+        assert_eq!(db_load_user_screen(user).unwrap(), Screen::Main);
+
+        // Screen doesn't outlive course, even if it's deleted not by `db_delete_course`.
+        let course_id = db_insert(Course::empty(UserId(264_000))).unwrap();
+        db_save_user_screen(user, Screen::Course(course_id)).unwrap();
+        get_connection()
+            .execute("DELETE FROM courses WHERE course_id = ?", (course_id.0,))
+            .unwrap();
+        assert_eq!(db_load_user_screen(user).unwrap(), Screen::Main);
+    }
+    #[test]
+    fn time_anchor_is_persisted() {
+        db_create_tables().unwrap();
+        let real = Local::now();
+        let anchor = TimeAnchor {
+            real,
+            simulated: real + chrono::TimeDelta::days(3),
+            scale: 24,
+        };
+        db_set_time_anchor(&anchor).unwrap();
+        assert_eq!(db_get_time_anchor().unwrap(), Some(anchor));
+    }
+    #[test]
+    fn concurrent_reads_get_own_courses() {
+        db_create_tables().unwrap();
+        let courses = (0..5)
+            .map(|i| {
+                let description = format!("Course {i}");
+                let course_id = db_insert(Course {
+                    owner_id: UserId(267_100),
+                    structure: CourseGraph::default(),
+                    tasks: Deque::default(),
+                    settings: CourseSettings::default(),
+                    description: description.clone(),
+                })
+                .unwrap();
+                (course_id, description)
+            })
+            .collect::<Vec<_>>();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        for i in 0..50 {
+            let (course_id, description) = courses[i % courses.len()].clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                let course = db_get_course(course_id).unwrap().unwrap();
+                sender.send(course.description == description).unwrap();
+            });
+        }
+        for _ in 0..50 {
+            let correct = receiver
+                .recv_timeout(Duration::from_secs(30))
+                .expect("readers shouldn't deadlock");
+            assert!(correct);
+        }
+    }
+    #[test]
+    fn legacy_progress_is_saved_migrated() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course {
+            owner_id: UserId(270_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        let learner = UserId(270_001);
+        db_add_course_to_user(learner, course_id).unwrap();
+        let stored_progress = || -> serde_json::Value {
+            let progress: String = get_connection()
+                .query_one(
+                    "SELECT progress FROM user_progress WHERE user_id = ? AND course_id = ?",
+                    (learner.0, course_id.0),
+                    |row| row.get("progress"),
+                )
+                .unwrap();
+            serde_json::from_str(&progress).unwrap()
+        };
+        let mut legacy = stored_progress();
+        legacy.as_object_mut().unwrap().remove("version");
+        get_connection()
+            .execute(
+                "UPDATE user_progress SET progress = ? WHERE user_id = ? AND course_id = ?",
+                (legacy.to_string(), learner.0, course_id.0),
+            )
+            .unwrap();
+
+        db_get_progress(learner, course_id).unwrap();
+        assert_eq!(stored_progress()["version"], PROGRESS_VERSION);
+    }
+    #[test]
+    fn corrupted_course_is_error() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course {
+            owner_id: UserId(266_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        get_connection()
+            .execute(
+                "UPDATE courses SET structure = 'not json' WHERE course_id = ?",
+                (course_id.0,),
+            )
+            .unwrap();
+
+        assert!(matches!(db_get_course(course_id), Err(DbError::Json(_))));
+        assert!(db_get_existing_course(course_id).is_err());
+        assert!(db_add_course_to_user(UserId(266_001), course_id).is_err());
+        assert!(matches!(
+            db_get_existing_course(CourseId(266_999_999)),
+            Err(DbError::MissingCourse(_))
+        ));
     }
 }
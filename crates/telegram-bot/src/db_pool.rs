@@ -0,0 +1,116 @@
+//! Few sqlite connections, shared by all update handlers.
+//! One connection behind mutex makes every handler wait for all others.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use rusqlite::Connection;
+
+pub const DB_POOL_SIZE_VAR: &str = "DB_POOL_SIZE";
+pub const DEFAULT_DB_POOL_SIZE: usize = 4;
+
+pub struct ConnectionPool {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+}
+impl ConnectionPool {
+    pub fn new(connections: Vec<Connection>) -> Self {
+        assert!(
+            !connections.is_empty(),
+            "pool without connections would wait forever"
+        );
+        Self {
+            idle: Mutex::new(connections),
+            returned: Condvar::new(),
+        }
+    }
+    pub fn size_from_env() -> usize {
+        match std::env::var(DB_POOL_SIZE_VAR) {
+            Ok(size) => size
+                .parse()
+                .ok()
+                .filter(|size| *size > 0)
+                .unwrap_or_else(|| panic!("'{DB_POOL_SIZE_VAR}' should be positive integer")),
+            Err(_) => DEFAULT_DB_POOL_SIZE,
+        }
+    }
+    /// Waits, while all connections are taken.
+    pub fn get(&self) -> PooledConnection<'_> {
+        let mut idle = self
+            .returned
+            .wait_while(self.lock_idle(), |idle| idle.is_empty())
+            .unwrap_or_else(PoisonError::into_inner);
+        PooledConnection {
+            pool: self,
+            connection: idle.pop(),
+        }
+    }
+    fn lock_idle(&self) -> MutexGuard<'_, Vec<Connection>> {
+        // Connections aren't used under this lock, so they are fine after panic.
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns connection to pool on drop.
+pub struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    /// Taken only on drop.
+    connection: Option<Connection>,
+}
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().unwrap()
+    }
+}
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.lock_idle().push(connection);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn waiting_handler_gets_returned_connection() {
+        let pool = ConnectionPool::new(vec![Connection::open_in_memory().unwrap()]);
+        let conn = pool.get();
+        conn.execute("CREATE TABLE marker (id INTEGER)", ())
+            .unwrap();
+
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            scope.spawn(|| {
+                let conn = pool.get();
+                // Same connection, so in-memory table is here.
+                let exists = conn
+                    .prepare("SELECT 1 FROM sqlite_master WHERE name = 'marker'")
+                    .unwrap()
+                    .exists(())
+                    .unwrap();
+                sender.send(exists).unwrap();
+            });
+            assert!(
+                receiver.recv_timeout(Duration::from_millis(100)).is_err(),
+                "all connections are taken"
+            );
+            drop(conn);
+            assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+        });
+    }
+}
@@ -0,0 +1,249 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use course_graph::progress_store::TaskProgress;
+
+use super::progress_store::UserProgress;
+
+/// Outcomes of one card, summed over all learners of a course.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CardStats {
+    /// Meaningful repetitions.
+    pub attempts: u32,
+    pub failed_attempts: u32,
+    /// Answers, revealed without guessing. Not counted in attempts.
+    pub reveals: u32,
+    /// Learners, who can learn this card or already started it.
+    pub learners_reached: usize,
+    pub learners_mastered: usize,
+    /// Meaningful repetitions of learners, who mastered this card.
+    repetitions_to_mastery: u32,
+}
+impl CardStats {
+    pub fn failure_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            0.
+        } else {
+            self.failed_attempts as f64 / self.attempts as f64
+        }
+    }
+    /// None if nobody mastered this card.
+    pub fn average_repetitions_to_mastery(&self) -> Option<f64> {
+        (self.learners_mastered != 0)
+            .then(|| self.repetitions_to_mastery as f64 / self.learners_mastered as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CourseStats {
+    pub learners: usize,
+    pub cards: BTreeMap<String, CardStats>,
+}
+impl CourseStats {
+    pub fn aggregate<'a>(progresses: impl IntoIterator<Item = &'a UserProgress>) -> Self {
+        let mut stats = CourseStats::default();
+        for progress in progresses {
+            stats.learners += 1;
+            for (id, task) in &progress.tasks {
+                let card = stats.cards.entry(id.clone()).or_default();
+                card.attempts += task.meaningful_repetitions;
+                card.failed_attempts += task.failed_repetitions;
+                card.reveals += task.revealed_repetitions;
+                if !matches!(
+                    task.progress,
+                    TaskProgress::NotStarted {
+                        could_be_learned: false
+                    }
+                ) {
+                    card.learners_reached += 1;
+                }
+                if task.progress == TaskProgress::Good {
+                    card.learners_mastered += 1;
+                    card.repetitions_to_mastery += task.meaningful_repetitions;
+                }
+            }
+        }
+        stats
+    }
+    pub fn reached_ratio(&self, card: &CardStats) -> f64 {
+        if self.learners == 0 {
+            0.
+        } else {
+            card.learners_reached as f64 / self.learners as f64
+        }
+    }
+    /// Cards with the highest failure rate first.
+    pub fn hardest_cards(&self, count: usize) -> Vec<(&String, &CardStats)> {
+        let mut cards = self.cards.iter().collect::<Vec<_>>();
+        cards.sort_by(|(_, a), (_, b)| b.failure_rate().total_cmp(&a.failure_rate()));
+        cards.truncate(count);
+        cards
+    }
+    pub fn hardest_cards_message(&self, count: usize) -> String {
+        if self.learners == 0 {
+            return "Nobody learns this course yet.".into();
+        }
+        let mut message = format!("Learners: {}\nHardest cards:\n", self.learners);
+        for (ix, (name, card)) in self.hardest_cards(count).into_iter().enumerate() {
+            let repetitions = match card.average_repetitions_to_mastery() {
+                Some(repetitions) => format!("{repetitions:.1} repetitions to master"),
+                None => "nobody mastered".into(),
+            };
+            writeln!(
+                message,
+                "{}. {name} — {:.0}% failed attempts, {repetitions}, reached by {:.0}% of learners",
+                ix + 1,
+                card.failure_rate() * 100.,
+                self.reached_ratio(card) * 100.,
+            )
+            .unwrap();
+        }
+        message
+    }
+    /// One row per card: `card,attempts,correct,incorrect,failure_rate,learners_reached,revealed`.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "card,attempts,correct,incorrect,failure_rate,learners_reached,revealed\n",
+        );
+        for (name, card) in &self.cards {
+            writeln!(
+                csv,
+                "{},{},{},{},{:.3},{},{}",
+                escape_csv_field(name),
+                card.attempts,
+                card.attempts - card.failed_attempts,
+                card.failed_attempts,
+                card.failure_rate(),
+                card.learners_reached,
+                card.reveals,
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+/// Spreadsheets run cells, starting with these, as formulas.
+const CSV_FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+/// Card names come from course owner, so formula-like ones are prefixed with `'`.
+fn escape_csv_field(field: &str) -> String {
+    let field = if field.starts_with(CSV_FORMULA_PREFIXES) {
+        format!("'{field}")
+    } else {
+        field.to_owned()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use teloxide_core::types::UserId;
+
+    use super::*;
+    use crate::database::*;
+
+    fn set_card(
+        progress: &mut UserProgress,
+        card: &str,
+        state: TaskProgress,
+        attempts: u32,
+        failed_attempts: u32,
+    ) {
+        let task = progress.tasks.get_mut(card).unwrap();
+        task.progress = state;
+        task.meaningful_repetitions = attempts;
+        task.failed_repetitions = failed_attempts;
+    }
+
+    #[test]
+    fn analytics_csv() {
+        let mut stats = CourseStats {
+            learners: 4,
+            ..Default::default()
+        };
+        stats.cards.insert(
+            "plain".into(),
+            CardStats {
+                attempts: 8,
+                failed_attempts: 2,
+                learners_reached: 3,
+                reveals: 1,
+                ..Default::default()
+            },
+        );
+        stats
+            .cards
+            .insert("with, \"quotes\"".into(), CardStats::default());
+
+        let csv = stats.to_csv();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "card,attempts,correct,incorrect,failure_rate,learners_reached,revealed",
+                "plain,8,6,2,0.250,3,1",
+                "\"with, \"\"quotes\"\"\",0,0,0,0.000,0,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_formulas_are_not_run() {
+        assert_eq!(
+            escape_csv_field("=HYPERLINK(\"x\")"),
+            "\"'=HYPERLINK(\"\"x\"\")\""
+        );
+        assert_eq!(escape_csv_field("+1"), "'+1");
+        assert_eq!(escape_csv_field("-1"), "'-1");
+        assert_eq!(escape_csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(escape_csv_field("a=b"), "a=b");
+    }
+
+    #[test]
+    fn aggregate_learners_progress() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course::with_default_cards(UserId(202_000))).unwrap();
+        let learners = [UserId(202_001), UserId(202_002), UserId(202_003)];
+        for learner in learners {
+            db_add_course_to_user(learner, course_id).unwrap();
+        }
+
+        let mut progress = db_get_progress(learners[0], course_id).unwrap();
+        set_card(&mut progress, "countries", TaskProgress::Good, 2, 1);
+        set_card(&mut progress, "capitals", TaskProgress::Failed, 2, 2);
+        db_set_course_progress(learners[0], course_id, progress).unwrap();
+
+        let mut progress = db_get_progress(learners[1], course_id).unwrap();
+        set_card(&mut progress, "countries", TaskProgress::Good, 1, 0);
+        set_card(&mut progress, "capitals", TaskProgress::Good, 3, 1);
+        db_set_course_progress(learners[1], course_id, progress).unwrap();
+
+        // Third learner didn't start anything.
+
+        let progresses = db_all_progress_for_course(course_id).unwrap();
+        assert_eq!(progresses.len(), 3);
+        let stats = CourseStats::aggregate(progresses.iter().map(|(_, progress)| progress));
+        assert_eq!(stats.learners, 3);
+
+        let countries = &stats.cards["countries"];
+        assert_eq!(countries.attempts, 3);
+        assert_eq!(countries.failed_attempts, 1);
+        assert_eq!(countries.learners_reached, 2);
+        assert_eq!(countries.average_repetitions_to_mastery(), Some(1.5));
+
+        let capitals = &stats.cards["capitals"];
+        assert_eq!(capitals.attempts, 5);
+        assert_eq!(capitals.failed_attempts, 3);
+        assert_eq!(capitals.failure_rate(), 0.6);
+        assert_eq!(capitals.learners_reached, 2);
+        assert_eq!(capitals.average_repetitions_to_mastery(), Some(3.));
+
+        let hardest = stats.hardest_cards(1);
+        assert_eq!(hardest.len(), 1);
+        assert_eq!(hardest[0].0, "capitals");
+    }
+}
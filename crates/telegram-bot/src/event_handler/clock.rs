@@ -0,0 +1,85 @@
+//! Clock of spaced repetition. Schedules are computed in simulated time, which runs
+//! `SRS_TIME_SCALE` times faster than real time, to test long schedules quickly.
+//! Anchor is persisted, so after restart simulated time continues from where it was,
+//! instead of jumping back to real time.
+
+use chrono::{DateTime, Local};
+
+pub const TIME_SCALE_VAR: &str = "SRS_TIME_SCALE";
+
+/// `simulated = simulated_anchor + (real - real_anchor) * scale`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeAnchor {
+    pub real: DateTime<Local>,
+    pub simulated: DateTime<Local>,
+    pub scale: i32,
+}
+impl TimeAnchor {
+    pub fn new(real: DateTime<Local>, scale: i32) -> Self {
+        Self {
+            real,
+            simulated: real,
+            scale,
+        }
+    }
+    pub fn at(&self, real: DateTime<Local>) -> DateTime<Local> {
+        self.simulated + (real - self.real) * self.scale
+    }
+    /// Anchor after restart at `real`. Time, while bot was stopped, is counted with old scale.
+    /// If scale changed, it applies only from `real`.
+    pub fn restore(persisted: Option<TimeAnchor>, real: DateTime<Local>, scale: i32) -> Self {
+        match persisted {
+            Some(anchor) if anchor.scale == scale => anchor,
+            Some(anchor) => Self {
+                real,
+                simulated: anchor.at(real),
+                scale,
+            },
+            None => Self::new(real, scale),
+        }
+    }
+}
+
+/// 1, which means real time, if variable isn't set.
+pub fn time_scale_from_env() -> i32 {
+    match std::env::var(TIME_SCALE_VAR) {
+        Ok(scale) => scale
+            .parse()
+            .ok()
+            .filter(|scale| *scale >= 1)
+            .unwrap_or_else(|| panic!("'{TIME_SCALE_VAR}' should be positive integer")),
+        Err(_) => 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    #[test]
+    fn simulated_time_continues_after_restart() {
+        let start = Local::now();
+        let anchor = TimeAnchor::new(start, 10);
+        let restart = start + TimeDelta::hours(1);
+        let before_restart = anchor.at(restart);
+        assert_eq!(before_restart, start + TimeDelta::hours(10));
+
+        let restored = TimeAnchor::restore(Some(anchor), restart, 10);
+        assert_eq!(restored.at(restart), before_restart);
+        assert_eq!(
+            restored.at(restart + TimeDelta::hours(1)),
+            start + TimeDelta::hours(20)
+        );
+
+        let rescaled = TimeAnchor::restore(Some(anchor), restart, 1);
+        assert_eq!(rescaled.at(restart), before_restart);
+        assert_eq!(
+            rescaled.at(restart + TimeDelta::hours(1)),
+            before_restart + TimeDelta::hours(1)
+        );
+
+        assert_eq!(TimeAnchor::restore(None, restart, 1).at(restart), restart);
+    }
+}
@@ -1,33 +1,50 @@
-use std::{str::FromStr, sync::LazyLock};
+use std::{
+    collections::HashSet,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeDelta};
 use course_graph::graph::CourseGraph;
 use dashmap::DashMap;
-use rand::seq::SliceRandom;
-use ssr_algorithms::fsrs::level::{Quality, RepetitionContext};
-use teloxide_core::{
-    Bot,
-    payloads::SendMessageSetters,
-    prelude::Requester,
-    types::{ParseMode, UserId},
+use rand::{Rng, seq::SliceRandom};
+use ssr_algorithms::fsrs::{
+    level::{Quality, RepetitionContext},
+    weights::Weights,
 };
+use teloxide_core::{Bot, prelude::Requester, types::UserId};
 
+use self::{
+    clock::{TimeAnchor, time_scale_from_env},
+    progress_store::UserProgress,
+};
 use crate::{
     database::*,
-    handlers::{send_interactions, send_markdown, set_task_for_user},
-    interaction_types::{telegram_interaction::QuestionElement, *},
-    state::{MutUserState, UserState},
+    handlers::{compact_question, send_interactions, send_literal, set_task_for_user},
+    interaction_types::{
+        task::TaskKind,
+        telegram_interaction::{QuestionElement, option_labels},
+        *,
+    },
+    settings::{
+        CourseSettings, LatencyThresholds, MAX_EXPLANATION_LEN, SettingsAction, UserSettings,
+        effective_daily_limit,
+    },
+    state::{MutUserState, SessionFails, UserState},
+    update_limit::release_slot,
     utils::{Immutable, ResultExt},
 };
 
+pub mod analytics;
+pub mod clock;
 pub mod progress_store;
 
 async fn get_user_answer(
     bot: Bot,
     user_id: UserId,
-    interactions: impl IntoIterator<Item = QuestionElement>,
-    answers: Vec<String>,
+    interactions: impl IntoIterator<Item = impl Into<TelegramInteraction>>,
+    answers: Vec<QuestionElement>,
     user_state: MutUserState<'_>,
 ) -> anyhow::Result<Option<String>> {
     let answer = get_user_answer_raw(
@@ -54,6 +71,8 @@ async fn get_user_answer_raw(
     set_task_for_user(bot, user_id, interactions, tx, user_state)
         .await
         .context("failed to set task for user")?;
+    // Answer comes with another update, which may need this handler's slot.
+    release_slot();
     let Ok(answer) = rx.await else {
         return Ok(None);
     };
@@ -61,25 +80,398 @@ async fn get_user_answer_raw(
 }
 
 const I_DONT_KNOW_MESSAGE: &str = "I don't know";
+/// Unlike [`I_DONT_KNOW_MESSAGE`], learner studies answer without guessing.
+const REVEAL_ANSWER_MESSAGE: &str = "Reveal answer";
+
+const CONFIRM_YES: &str = "Yes";
+const CONFIRM_NO: &str = "No";
+
+/// Asks user yes/no question. Should be used before every destructive action.
+pub async fn confirm(
+    bot: Bot,
+    user_id: UserId,
+    prompt: &str,
+    user_state: MutUserState<'_>,
+) -> anyhow::Result<bool> {
+    let answer = get_user_answer(
+        bot,
+        user_id,
+        [QuestionElement::Text(prompt.to_owned())],
+        vec![CONFIRM_YES.into(), CONFIRM_NO.into()],
+        user_state,
+    )
+    .await
+    .context("failed to ask user for confirmation")?;
+    Ok(is_confirmed(answer.as_deref()))
+}
+/// Interrupted confirmation is not a confirmation.
+fn is_confirmed(answer: Option<&str>) -> bool {
+    answer == Some(CONFIRM_YES)
+}
+
+/// Buttons of difficulty prompt, from hardest to easiest.
+const DIFFICULTY_OPTIONS: [&str; 4] = ["Again", "Hard", "Good", "Easy"];
+
+/// Grade of correct answer. Interrupted prompt counts as `Good`, like without prompt.
+fn graded_repetition(difficulty: Option<&str>) -> RepetitionContext {
+    let quality = match difficulty {
+        Some("Again") => Quality::Again,
+        Some("Hard") => Quality::Hard,
+        Some("Easy") => Quality::Easy,
+        _ => Quality::Good,
+    };
+    RepetitionContext {
+        quality,
+        review_time: now(),
+    }
+}
+
+fn quality_from_latency(latency: Duration, thresholds: LatencyThresholds) -> Quality {
+    if latency <= Duration::from_secs(thresholds.easy_secs.into()) {
+        Quality::Easy
+    } else if latency >= Duration::from_secs(thresholds.hard_secs.into()) {
+        Quality::Hard
+    } else {
+        Quality::Good
+    }
+}
+
+/// Returns shuffled options and new index of `options[correct]`.
+/// Positions are tracked, so options with the same text are fine.
+fn shuffle_options<T>(
+    options: Vec<T>,
+    correct: &[usize],
+    rng: &mut impl Rng,
+) -> (Vec<T>, Vec<usize>) {
+    let mut options = options.into_iter().map(Some).collect::<Vec<_>>();
+    let mut order = (0..options.len()).collect::<Vec<_>>();
+    order.shuffle(rng);
+    let correct = order
+        .iter()
+        .enumerate()
+        .filter(|&(_, ix)| correct.contains(ix))
+        .map(|(position, _)| position)
+        .collect();
+    let options = order
+        .into_iter()
+        .map(|ix| options[ix].take().unwrap())
+        .collect();
+    (options, correct)
+}
+
+/// Text alternative to answer keyboard: every shown option, marked as correct or wrong.
+fn options_summary(options: &[String], correct: &[usize], chosen: &[usize]) -> String {
+    options
+        .iter()
+        .enumerate()
+        .map(|(ix, option)| {
+            let mark = if correct.contains(&ix) { "✅" } else { "❌" };
+            let chosen = if chosen.contains(&ix) {
+                " ← your answer"
+            } else {
+                ""
+            };
+            format!("{mark} {option}{chosen}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Indices of shown options, chosen by learner. Multi-select answer is indices itself,
+/// other answer is label of option. Empty for "I don't know" and revealed answer.
+fn chosen_options(labels: &[String], answer: &str, multi_select: bool) -> Vec<usize> {
+    if multi_select {
+        answer
+            .split_whitespace()
+            .filter_map(|ix| ix.parse().ok())
+            .collect()
+    } else {
+        labels
+            .iter()
+            .enumerate()
+            .filter(|(_, label)| *label == answer)
+            .map(|(ix, _)| ix)
+            .collect()
+    }
+}
+
+/// Options of one of task in the order, learner sees them, and indices of correct ones.
+/// `!noshuffle` tasks keep source order. Empty for other tasks.
+fn shown_options(task: &Task, rng: &mut impl Rng) -> (Vec<QuestionElement>, Vec<usize>) {
+    let TaskKind::OneOf { answers, .. } = &task.kind else {
+        return (Vec::new(), Vec::new());
+    };
+    // `sample_options` keeps correct options at `answers` indices.
+    let options = task.sample_options(rng);
+    if task.shuffle {
+        shuffle_options(options, answers, rng)
+    } else {
+        (options, answers.clone())
+    }
+}
 
+/// `answers` should be already shuffled, if task asks for it.
+/// "I don't know" and "reveal answer" options go after them.
 async fn get_card_answer(
     bot: Bot,
     user_id: UserId,
-    interactions: impl IntoIterator<Item = QuestionElement>,
-    mut answers: Vec<String>,
+    interactions: impl IntoIterator<Item = impl Into<TelegramInteraction>>,
+    mut answers: Vec<QuestionElement>,
     user_state: MutUserState<'_>,
 ) -> anyhow::Result<Option<String>> {
-    answers.shuffle(&mut rand::rng());
     answers.push(I_DONT_KNOW_MESSAGE.into());
+    answers.push(REVEAL_ANSWER_MESSAGE.into());
 
     get_user_answer(bot, user_id, interactions, answers, user_state).await
 }
 
+/// Telegram limits for quiz polls.
+const MAX_POLL_QUESTION_LEN: usize = 300;
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_OPTION_LEN: usize = 100;
+
+/// Compact question is sent as one message with all images.
+fn question_interactions(
+    question: Vec<QuestionElement>,
+    compact: bool,
+) -> Vec<TelegramInteraction> {
+    if compact {
+        compact_question(question)
+    } else {
+        question
+            .into_iter()
+            .map(TelegramInteraction::from)
+            .collect()
+    }
+}
+
+/// Explanation of task, learner was suggested to move on from, sent as after answer.
+pub fn move_on_explanation(
+    user_state: &UserState,
+) -> anyhow::Result<Option<Vec<TelegramInteraction>>> {
+    let Some((course_id, explanation)) = user_state.move_on_explanation.clone() else {
+        return Ok(None);
+    };
+    let max_len = db_get_existing_course(course_id)?
+        .settings
+        .max_explanation_len
+        .unwrap_or(MAX_EXPLANATION_LEN);
+    Ok(Some(explanation_interactions(explanation, max_len)))
+}
+
+/// Explanation with texts longer than `max_len` is cut at line boundary,
+/// and full text follows as a file. Images and audios are sent as is.
+fn explanation_interactions(
+    explanation: Vec<QuestionElement>,
+    max_len: usize,
+) -> Vec<TelegramInteraction> {
+    let full_text = explanation
+        .iter()
+        .filter_map(|element| match element {
+            QuestionElement::Text(text) => Some(text.as_str()),
+            QuestionElement::Image(_) | QuestionElement::Audio(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if full_text.chars().count() <= max_len {
+        return explanation
+            .into_iter()
+            .map(TelegramInteraction::from)
+            .collect();
+    }
+    let mut interactions = vec![TelegramInteraction::Text(format!(
+        "{}…\n\nFull explanation is in the file.",
+        truncate_at_line(&full_text, max_len)
+    ))];
+    interactions.extend(
+        explanation
+            .into_iter()
+            .filter(|element| !matches!(element, QuestionElement::Text(_)))
+            .map(TelegramInteraction::from),
+    );
+    interactions.push(TelegramInteraction::Document {
+        name: "explanation.txt".into(),
+        contents: full_text,
+    });
+    interactions
+}
+
+/// At most `max_len` characters. Falls back to cutting inside line, if first line is too long.
+fn truncate_at_line(text: &str, max_len: usize) -> &str {
+    let end = text
+        .char_indices()
+        .nth(max_len)
+        .map_or(text.len(), |(ix, _)| ix);
+    let cut = &text[..end];
+    match cut.rfind('\n') {
+        Some(ix) if ix > 0 && end < text.len() => cut[..ix].trim_end(),
+        _ => cut,
+    }
+}
+
+/// Last question text becomes poll question, if it fits.
+/// Returns None if options don't fit in poll, so buttons should be used.
+fn poll_interactions(
+    mut question: Vec<QuestionElement>,
+    options: Vec<String>,
+    correct: usize,
+    compact: bool,
+) -> Option<Vec<TelegramInteraction>> {
+    if options.len() > MAX_POLL_OPTIONS
+        || options
+            .iter()
+            .any(|option| option.chars().count() > MAX_POLL_OPTION_LEN)
+    {
+        return None;
+    }
+    let poll_question = match question.last() {
+        Some(QuestionElement::Text(text)) if text.chars().count() <= MAX_POLL_QUESTION_LEN => {
+            let Some(QuestionElement::Text(text)) = question.pop() else {
+                unreachable!()
+            };
+            text
+        }
+        _ => "choose answer".into(),
+    };
+    Some(
+        question_interactions(question, compact)
+            .into_iter()
+            .chain([TelegramInteraction::Poll {
+                question: poll_question,
+                options,
+                correct,
+            }])
+            .collect(),
+    )
+}
+
+/// Same as [`get_card_answer`], but asks with quiz poll when possible.
+/// `correct` is index of correct answer in shuffled `answers`.
+async fn get_poll_answer(
+    bot: Bot,
+    user_id: UserId,
+    question: Vec<QuestionElement>,
+    mut answers: Vec<String>,
+    correct: usize,
+    compact: bool,
+    user_state: MutUserState<'_>,
+) -> anyhow::Result<Option<String>> {
+    answers.push(I_DONT_KNOW_MESSAGE.into());
+    answers.push(REVEAL_ANSWER_MESSAGE.into());
+
+    let Some(interactions) = poll_interactions(question.clone(), answers.clone(), correct, compact)
+    else {
+        let question = question_interactions(question, compact);
+        let answers = answers.into_iter().map(QuestionElement::from).collect();
+        return get_user_answer(bot, user_id, question, answers, user_state).await;
+    };
+    let answer = get_user_answer_raw(bot, user_id, interactions, user_state)
+        .await
+        .context("failed to get user answer raw")?;
+    Ok(answer.map(|mut answer| answers[answer.pop().unwrap().parse::<usize>().unwrap()].clone()))
+}
+
+/// `options` should be already shuffled, if task asks for it.
+/// Returns indices of chosen `options`, or "I don't know" or "reveal answer" message.
+async fn get_multi_select_answer(
+    bot: Bot,
+    user_id: UserId,
+    interactions: impl IntoIterator<Item = impl Into<TelegramInteraction>>,
+    options: Vec<QuestionElement>,
+    user_state: MutUserState<'_>,
+) -> anyhow::Result<Option<String>> {
+    let answer = get_user_answer_raw(
+        bot,
+        user_id,
+        interactions
+            .into_iter()
+            .map(|x| x.into())
+            .chain([TelegramInteraction::MultiSelect {
+                options,
+                extra: vec![I_DONT_KNOW_MESSAGE.into(), REVEAL_ANSWER_MESSAGE.into()],
+            }]),
+        user_state,
+    )
+    .await
+    .context("failed to get user answer raw")?;
+    Ok(answer.map(|mut answer| answer.pop().unwrap()))
+}
+
+/// Items are shown in random order.
+/// Returns indices of `items` in order, chosen by user.
+async fn get_ordering_answer(
+    bot: Bot,
+    user_id: UserId,
+    interactions: impl IntoIterator<Item = impl Into<TelegramInteraction>>,
+    items: &[String],
+    user_state: MutUserState<'_>,
+) -> anyhow::Result<Option<Vec<usize>>> {
+    let mut shown = (0..items.len()).collect::<Vec<_>>();
+    shown.shuffle(&mut rand::rng());
+    let labels = shown.iter().map(|&ix| items[ix].clone()).collect();
+    let answer = get_user_answer_raw(
+        bot,
+        user_id,
+        interactions
+            .into_iter()
+            .map(|x| x.into())
+            .chain([TelegramInteraction::Ordering(labels)]),
+        user_state,
+    )
+    .await
+    .context("failed to get user answer raw")?;
+    Ok(answer.map(|mut answer| {
+        answer
+            .pop()
+            .unwrap()
+            .split_whitespace()
+            .map(|ix| shown[ix.parse::<usize>().unwrap()])
+            .collect()
+    }))
+}
+
+/// Asks user input for each blank.
+async fn get_blanks_answer(
+    bot: Bot,
+    user_id: UserId,
+    interactions: impl IntoIterator<Item = impl Into<TelegramInteraction>>,
+    blanks: usize,
+    user_state: MutUserState<'_>,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let mut interactions = interactions.into_iter().map(Into::into).collect::<Vec<_>>();
+    for ix in 0..blanks {
+        if blanks > 1 {
+            interactions.push(format!("Blank {}:", ix + 1).into());
+        }
+        interactions.push(TelegramInteraction::UserInput);
+    }
+    let answer = get_user_answer_raw(bot, user_id, interactions.clone(), user_state)
+        .await
+        .context("failed to get user answer raw")?;
+    Ok(answer.map(|answer| {
+        interactions
+            .iter()
+            .zip(answer)
+            .filter(|(interaction, _)| matches!(interaction, TelegramInteraction::UserInput))
+            .map(|(_, answer)| answer)
+            .collect()
+    }))
+}
+
+/// Simulated time, see [`clock`].
 fn now() -> DateTime<Local> {
-    static START_TIME: LazyLock<Immutable<DateTime<Local>>> = LazyLock::new(|| Local::now().into());
-    let now = Local::now();
-    let diff = now - **START_TIME;
-    **START_TIME + diff * 1 // No speedup
+    static ANCHOR: LazyLock<Immutable<TimeAnchor>> = LazyLock::new(|| {
+        let scale = time_scale_from_env();
+        // Tests share temporary database, where tables may be not created yet.
+        if cfg!(test) {
+            return TimeAnchor::new(Local::now(), scale).into();
+        }
+        let persisted = db_get_time_anchor().log_err().flatten();
+        let anchor = TimeAnchor::restore(persisted, Local::now(), scale);
+        db_set_time_anchor(&anchor).log_err();
+        anchor.into()
+    });
+    ANCHOR.at(Local::now())
 }
 
 pub async fn handle_changing_course_graph(
@@ -88,8 +480,8 @@ pub async fn handle_changing_course_graph(
     user_id: UserId,
     course_id: CourseId,
 ) -> anyhow::Result<()> {
-    let (source, printed_graph) = {
-        let Some(course) = db_get_course(course_id) else {
+    let (source, printed_graph, case_sensitive_names) = {
+        let Some(course) = db_get_course(course_id)? else {
             bot.send_message(
                 user_id,
                 format!("Course with id {} not found.", course_id.0),
@@ -106,7 +498,8 @@ pub async fn handle_changing_course_graph(
         }
         let course_graph = &course.structure;
         let source = course_graph.get_source().to_owned();
-        let graph = course_graph.generate_structure_graph();
+        let case_sensitive_names = course.settings.case_sensitive_names;
+        let graph = course_graph.generate_structure_graph(course.settings.layered_graph);
         let printed_graph = tokio::task::spawn_blocking(move || {
             graphviz_rust::exec(
                 graph,
@@ -117,7 +510,7 @@ pub async fn handle_changing_course_graph(
         })
         .await
         .unwrap();
-        (source, printed_graph)
+        (source, printed_graph, case_sensitive_names)
     };
 
     if let Some(answer) = get_user_answer_raw(
@@ -143,30 +536,30 @@ pub async fn handle_changing_course_graph(
         }
         let answer = answer.last().unwrap();
 
-        match CourseGraph::from_str(answer) {
+        match CourseGraph::parse(answer, case_sensitive_names) {
             Ok(new_course_graph) => {
-                let mut new_course = db_get_course(course_id).unwrap();
+                let mut new_course = db_get_existing_course(course_id)?;
                 new_course.structure = new_course_graph;
-                db_set_course(course_id, new_course);
+                db_set_course(course_id, new_course)?;
                 bot.send_message(user_id, "Course graph changed.")
                     .await
                     .context("failed to confirm course graph change")?;
             }
             Err(err) => {
                 let err = strip_ansi_escapes::strip_str(err);
-                bot.send_message(
+                send_literal(
+                    &bot,
                     user_id,
-                    format!("Your course graph has this errors:\n```\n{err}\n```"),
+                    &format!("Your course graph has this errors:\n{err}"),
                 )
-                .parse_mode(ParseMode::MarkdownV2)
                 .await
                 .context("failed to notify that course graph has errors")?;
             }
         }
     }
-    if let Some(msgs) = super::generate_message_about_course_errors(course_id) {
+    if let Some(msgs) = super::generate_message_about_course_errors(course_id)? {
         for msg in msgs {
-            send_markdown(&bot, user_id, &msg)
+            send_literal(&bot, user_id, &msg)
                 .await
                 .context("failed to send course errors")?;
         }
@@ -178,8 +571,9 @@ pub async fn handle_changing_deque(
     user_state: MutUserState<'_>,
     user_id: UserId,
     course_id: CourseId,
+    user_states: &DashMap<UserId, UserState>,
 ) -> anyhow::Result<()> {
-    let Some(course) = db_get_course(course_id) else {
+    let Some(course) = db_get_course(course_id)? else {
         bot.send_message(
             user_id,
             format!("Course with id {} not found.", course_id.0),
@@ -217,28 +611,47 @@ pub async fn handle_changing_deque(
         }
         let answer = answer.last().unwrap();
 
-        match deque::from_str(answer, true) {
+        let new_deque = deque::from_str(answer, true, course.settings.case_sensitive_names)
+            .and_then(|deque| deque.check_links().map(|()| deque));
+        match new_deque {
             Ok(new_deque) => {
-                let mut new_course = course;
-                new_course.tasks = new_deque;
-                db_set_course(course_id, new_course);
-                bot.send_message(user_id, "Deque changed.")
-                    .await
-                    .context("failed to confirm, that deque is changed")?;
+                let diff = course.tasks.diff(&new_deque);
+                // Nothing to review, if only formatting changed.
+                let saved = diff.is_empty()
+                    || confirm(
+                        bot.clone(),
+                        user_id,
+                        &format!("{}\n\nSave new deque?", diff.message()),
+                        user_states.get_mut(&user_id).unwrap(),
+                    )
+                    .await?;
+                if saved {
+                    let mut new_course = course;
+                    new_course.tasks = new_deque;
+                    db_set_course(course_id, new_course)?;
+                    bot.send_message(user_id, "Deque changed.")
+                        .await
+                        .context("failed to confirm, that deque is changed")?;
+                } else {
+                    bot.send_message(user_id, "Deque is kept.")
+                        .await
+                        .context("failed to notify user, that deque is kept")?;
+                }
             }
             Err(err) => {
-                bot.send_message(
+                send_literal(
+                    &bot,
                     user_id,
-                    format!("Your deque has this errors:\n```\n{err}\n```"),
+                    &format!("Your deque has this errors:\n{err}"),
                 )
                 .await
                 .context("failed to notify user, that deque has errors")?;
             }
         }
     }
-    if let Some(msgs) = super::generate_message_about_course_errors(course_id) {
+    if let Some(msgs) = super::generate_message_about_course_errors(course_id)? {
         for msg in msgs {
-            send_markdown(&bot, user_id, &msg)
+            send_literal(&bot, user_id, &msg)
                 .await
                 .context("failed to send course errors")?;
         }
@@ -246,77 +659,1052 @@ pub async fn handle_changing_deque(
     Ok(())
 }
 
-pub fn synchronize(user_id: UserId, course_id: CourseId) {
-    let mut progress = db_get_progress(user_id, course_id);
+pub fn synchronize(user_id: UserId, course_id: CourseId) -> DbResult<()> {
+    let mut progress = db_get_progress(user_id, course_id)?;
+    progress.set_desired_retention(db_get_user_settings(user_id)?.desired_retention as f32 / 100.);
     progress.synchronize(now().into());
-    db_get_course(course_id)
-        .unwrap()
-        .structure
-        .detect_recursive_fails(&mut progress);
-    db_set_course_progress(user_id, course_id, progress);
+    let course = db_get_existing_course(course_id)?;
+    // Course could be changed after goal was set.
+    progress
+        .goals
+        .retain(|goal| course.structure.cards().contains_key(goal));
+    let missing = if course.settings.cascade_fails {
+        course.structure.detect_recursive_fails_with_depth(
+            &mut progress,
+            course.settings.fail_propagation_depth,
+        )
+    } else {
+        course.structure.detect_fails_without_cascade(&mut progress)
+    };
+    if !missing.is_empty() {
+        log::warn!(
+            "progress of user {user_id} in course {} doesn't have cards {missing:?}",
+            course_id.0
+        );
+    }
+    db_set_course_progress(user_id, course_id, progress)
 }
 
-pub async fn complete_card(
-    bot: Bot,
+/// Card, learner should complete now. Cards, learner moved on from, are skipped.
+pub fn next_card(
+    user_id: UserId,
+    course_id: CourseId,
+    session_fails: &SessionFails,
+) -> DbResult<Option<String>> {
+    synchronize(user_id, course_id)?;
+    let course = db_get_existing_course(course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    Ok(progress
+        .recommended_next(now().into(), |id| {
+            session_fails.is_moved_on(course_id, id) || course.is_out_of_sequence(&progress, id)
+        })
+        .cloned())
+}
+
+/// See [`UserProgress::preview_intervals`].
+pub fn preview_intervals(
+    user_id: UserId,
+    course_id: CourseId,
+    card: &String,
+) -> DbResult<Option<[(Quality, Duration); 4]>> {
+    synchronize(user_id, course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    Ok(progress.preview_intervals(card, now().into()))
+}
+/// Card for `/next`. Unlike review session, cards, learner moved on from, aren't skipped.
+pub fn best_next_card(user_id: UserId, course_id: CourseId) -> DbResult<Option<String>> {
+    synchronize(user_id, course_id)?;
+    let course = db_get_existing_course(course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    Ok(progress
+        .recommended_next(now().into(), |id| course.is_out_of_sequence(&progress, id))
+        .cloned())
+}
+/// All cards, which are due now, for owner's `/revise`.
+pub fn revision_cards(user_id: UserId, course_id: CourseId) -> DbResult<Vec<String>> {
+    synchronize(user_id, course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    Ok(progress
+        .due_cards(now().into())
+        .into_iter()
+        .cloned()
+        .collect())
+}
+
+/// Like [`next_card`], but due cards are taken in random order. `served` cards are skipped.
+pub fn next_random_card(
+    user_id: UserId,
+    course_id: CourseId,
+    session_fails: &SessionFails,
+    served: &HashSet<String>,
+    rng: &mut impl Rng,
+) -> DbResult<Option<String>> {
+    synchronize(user_id, course_id)?;
+    let course = db_get_existing_course(course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    Ok(progress
+        .shuffled_due_cards(now().into(), rng)
+        .into_iter()
+        .find(|&id| {
+            !served.contains(id)
+                && !session_fails.is_moved_on(course_id, id)
+                && !course.is_out_of_sequence(&progress, id)
+        })
+        .cloned())
+}
+
+/// Like [`next_card`], but due cards are taken dependencies first. `served` cards are skipped.
+pub fn next_foundation_card(
+    user_id: UserId,
+    course_id: CourseId,
+    session_fails: &SessionFails,
+    served: &HashSet<String>,
+) -> DbResult<Option<String>> {
+    synchronize(user_id, course_id)?;
+    let course = db_get_existing_course(course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    Ok(progress
+        .due_cards_in_order(now().into(), &course.structure.topological_order())
+        .into_iter()
+        .find(|&id| {
+            !served.contains(id)
+                && !session_fails.is_moved_on(course_id, id)
+                && !course.is_out_of_sequence(&progress, id)
+        })
+        .cloned())
+}
+
+/// Cards to repeat and new cards, learner can start now.
+pub fn review_counts(user_id: UserId, course_id: CourseId) -> DbResult<(usize, usize)> {
+    synchronize(user_id, course_id)?;
+    Ok(db_get_progress(user_id, course_id)?.review_counts(now().into()))
+}
+
+/// Copies progress of cards with the same names. Returns number of copied cards.
+pub fn copy_progress(user_id: UserId, from: CourseId, to: CourseId) -> DbResult<usize> {
+    let from_graph = db_get_existing_course(from)?.structure;
+    let to_graph = db_get_existing_course(to)?.structure;
+    let shared = to_graph
+        .cards()
+        .keys()
+        .filter(|&name| from_graph.cards().contains_key(name));
+    let mut progress = db_get_progress(user_id, to)?;
+    let copied = progress.merge_matching(&db_get_progress(user_id, from)?, shared);
+    db_set_course_progress(user_id, to, progress)?;
+    synchronize(user_id, to)?;
+    Ok(copied)
+}
+
+/// Replaces weights of learner's scheduler in this course.
+pub fn set_weights(user_id: UserId, course_id: CourseId, weights: Weights) -> DbResult<()> {
+    let mut progress = db_get_progress(user_id, course_id)?;
+    progress.set_weights(weights);
+    db_set_course_progress(user_id, course_id, progress)?;
+    synchronize(user_id, course_id)
+}
+
+/// Learner's date at `time`. Day changes at learner's midnight.
+fn local_day(time: DateTime<Local>, utc_offset: i8) -> NaiveDate {
+    (time.naive_utc() + TimeDelta::hours(utc_offset.into())).date()
+}
+
+pub fn today(user_id: UserId) -> DbResult<NaiveDate> {
+    Ok(local_day(now(), db_get_user_settings(user_id)?.utc_offset))
+}
+
+/// Learner already did as many reviews on `day`, as course and his own limits allow.
+pub fn daily_limit_reached(user_id: UserId, course_id: CourseId, day: NaiveDate) -> DbResult<bool> {
+    let course_limit = db_get_existing_course(course_id)?
+        .settings
+        .daily_review_limit;
+    let user_limit = db_get_user_settings(user_id)?.daily_review_limit;
+    Ok(match effective_daily_limit(course_limit, user_limit) {
+        Some(limit) => db_get_daily_reviews(user_id, course_id, day)? >= limit,
+        None => false,
+    })
+}
+
+/// Returns new settings, or None if action didn't change them.
+pub fn apply_settings_action(
     user_id: UserId,
-    Task {
-        question,
-        options,
-        answer,
-        explanation,
-    }: Task,
+    action: SettingsAction,
+) -> DbResult<Option<UserSettings>> {
+    let mut settings = db_get_user_settings(user_id)?;
+    let old = settings.clone();
+    settings.apply(action);
+    if settings == old {
+        return Ok(None);
+    }
+    db_set_user_settings(user_id, &settings)?;
+    Ok(Some(settings))
+}
+
+/// Keeps progress, but makes all cards due. Returns number of due cards.
+pub fn reset_schedule(user_id: UserId, course_id: CourseId) -> DbResult<usize> {
+    synchronize(user_id, course_id)?;
+    let mut progress = db_get_progress(user_id, course_id)?;
+    progress.make_all_due(now().into());
+    let due = progress.due_cards(now().into()).len();
+    db_set_course_progress(user_id, course_id, progress)?;
+    Ok(due)
+}
+
+/// Reviews per learner's day, if every review is answered correctly.
+pub fn project_workload(
+    user_id: UserId,
+    course_id: CourseId,
+    days: u32,
+) -> DbResult<Vec<(NaiveDate, usize)>> {
+    synchronize(user_id, course_id)?;
+    let utc_offset = db_get_user_settings(user_id)?.utc_offset;
+    let offset = FixedOffset::east_opt(i32::from(utc_offset) * 60 * 60).unwrap();
+    Ok(db_get_progress(user_id, course_id)?.project_workload(days, now().with_timezone(&offset)))
+}
+
+/// Like "Again → 10m, Hard → 1d, Good → 4d, Easy → 9d".
+pub fn intervals_message(intervals: &[(Quality, Duration); 4]) -> String {
+    DIFFICULTY_OPTIONS
+        .iter()
+        .zip(intervals)
+        .map(|(name, (_, interval))| format!("{name} → {}", short_duration(*interval)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+fn short_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match minutes {
+        ..60 => format!("{minutes}m"),
+        ..1440 => format!("{}h", minutes / 60),
+        _ => format!("{}d", minutes / 1440),
+    }
+}
+/// Bar is limited, so busy days don't break lines.
+pub fn workload_message(workload: &[(NaiveDate, usize)]) -> String {
+    const MAX_BAR_LEN: usize = 20;
+    let mut message = String::from("Expected reviews, if you answer correctly:\n");
+    for (day, count) in workload {
+        message.push_str(&format!(
+            "\n{} {} {count}",
+            day.format("%a %d.%m"),
+            "█".repeat((*count).min(MAX_BAR_LEN))
+        ));
+    }
+    message
+}
+
+pub fn goals_message(graph: &CourseGraph, progress: &UserProgress) -> String {
+    if progress.goals.is_empty() {
+        return "You have no goals. Set one with /set_goal CARD_NAME".into();
+    }
+    let mut message = String::from("Your goals:");
+    for goal in &progress.goals {
+        let Some(goal_progress) = graph.goal_progress(goal, progress) else {
+            continue;
+        };
+        message.push_str(&format!(
+            "\n{goal} — {:.0}% of prerequisites mastered, ",
+            goal_progress.percentage()
+        ));
+        match goal_progress.next {
+            Some(next) if &next == goal => message.push_str("learn the goal itself"),
+            Some(next) => message.push_str(&format!("next: {next}")),
+            None => message.push_str("reached"),
+        }
+    }
+    message
+}
+
+/// Learner's note is shown before the question.
+fn with_note(question: Vec<QuestionElement>, note: Option<String>) -> Vec<QuestionElement> {
+    note.map(|note| QuestionElement::Text(format!("Your note: {note}")))
+        .into_iter()
+        .chain(question)
+        .collect()
+}
+
+pub async fn handle_note(
+    bot: Bot,
     user_state: MutUserState<'_>,
-    user_states: &DashMap<UserId, UserState>,
-) -> (RepetitionContext, bool) {
-    let Some(user_answer) = get_card_answer(
-        bot.clone(),
-        user_id,
-        question.clone(),
-        options.clone(),
-        user_state,
-    )
-    .await
-    .log_err()
-    .unwrap() else {
-        return (
-            RepetitionContext {
+    user_id: UserId,
+    course_id: CourseId,
+    card_name: String,
+) -> anyhow::Result<()> {
+    let mut interactions = Vec::new();
+    if let Some(note) = db_get_progress(user_id, course_id)?.note(&card_name) {
+        interactions.push(format!("Current note: {note}").into());
+    }
+    interactions.push("Print new note, or '-' to remove it:".into());
+    interactions.push(TelegramInteraction::UserInput);
+
+    if let Some(answer) = get_user_answer_raw(bot.clone(), user_id, interactions, user_state)
+        .await
+        .context("failed to ask user for note")?
+    {
+        let answer = answer.last().unwrap().trim();
+        let note = (answer != "-").then(|| answer.to_owned());
+        let mut progress = db_get_progress(user_id, course_id)?;
+        progress.set_note(&card_name, note);
+        db_set_course_progress(user_id, course_id, progress)?;
+        bot.send_message(user_id, "Note saved.")
+            .await
+            .context("failed to confirm, that note is saved")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CardResponse {
+    Correct,
+    Wrong,
+    DontKnow,
+    Revealed,
+}
+impl CardResponse {
+    /// Any of `correct` options is correct answer.
+    fn from_option(answer: &str, correct: &[&str]) -> Self {
+        if correct.contains(&answer) {
+            return Self::Correct;
+        }
+        match answer {
+            I_DONT_KNOW_MESSAGE => Self::DontKnow,
+            REVEAL_ANSWER_MESSAGE => Self::Revealed,
+            _ => Self::Wrong,
+        }
+    }
+    /// Only exactly `correct` options is correct answer.
+    fn from_selection(answer: &str, chosen: &[usize], correct: &[usize]) -> Self {
+        match answer {
+            I_DONT_KNOW_MESSAGE => Self::DontKnow,
+            REVEAL_ANSWER_MESSAGE => Self::Revealed,
+            _ => {
+                Self::from_check(chosen.iter().collect::<HashSet<_>>() == correct.iter().collect())
+            }
+        }
+    }
+    fn from_check(is_correct: bool) -> Self {
+        if is_correct {
+            Self::Correct
+        } else {
+            Self::Wrong
+        }
+    }
+}
+
+/// Outcome of [`complete_card`].
+pub struct CardCompletion {
+    pub repetition: RepetitionContext,
+    /// Interrupted and revealed answers don't count as attempts.
+    pub meaningful: bool,
+    /// Learner asked for answer instead of guessing.
+    pub revealed: bool,
+    /// Owner's preview. Isn't recorded anywhere.
+    pub preview: bool,
+    /// Learner answered "I don't know".
+    pub gave_up: bool,
+}
+impl CardCompletion {
+    fn again(meaningful: bool, revealed: bool) -> Self {
+        Self {
+            repetition: RepetitionContext {
                 quality: Quality::Again,
                 review_time: now(),
             },
-            false,
+            meaningful,
+            revealed,
+            preview: false,
+            gave_up: false,
+        }
+    }
+    fn answered(repetition: RepetitionContext) -> Self {
+        Self {
+            repetition,
+            meaningful: true,
+            revealed: false,
+            preview: false,
+            gave_up: false,
+        }
+    }
+    fn into_preview(self) -> Self {
+        Self {
+            meaningful: false,
+            preview: true,
+            ..self
+        }
+    }
+    pub fn is_failed(&self) -> bool {
+        self.meaningful && matches!(self.repetition.quality, Quality::Again)
+    }
+    /// Does nothing for preview.
+    pub fn record(self, progress: &mut UserProgress, card: &String) {
+        if self.preview {
+            return;
+        }
+        if self.revealed {
+            progress.reveal(card, self.repetition.review_time);
+        } else {
+            progress.repetition(card, self.repetition, self.meaningful);
+        }
+    }
+}
+
+pub enum CardAttempt {
+    /// `note` is learner's note for this card.
+    Review { note: Option<String> },
+    /// Owner checks, how card looks. Nothing is recorded.
+    Preview,
+}
+
+/// Course card, task is asked from.
+#[derive(Clone, Copy)]
+pub struct CardContext<'a> {
+    pub course_id: CourseId,
+    pub card_name: &'a str,
+    pub settings: &'a CourseSettings,
+}
+
+pub async fn complete_card(
+    bot: Bot,
+    user_id: UserId,
+    task: Task,
+    attempt: CardAttempt,
+    card: CardContext<'_>,
+    user_state: MutUserState<'_>,
+    user_states: &DashMap<UserId, UserState>,
+) -> CardCompletion {
+    match attempt {
+        CardAttempt::Review { note } => {
+            answer_card(bot, user_id, task, note, card, user_state, user_states).await
+        }
+        CardAttempt::Preview => {
+            answer_card(bot, user_id, task, None, card, user_state, user_states)
+                .await
+                .into_preview()
+        }
+    }
+}
+
+async fn answer_card(
+    bot: Bot,
+    user_id: UserId,
+    task: Task,
+    note: Option<String>,
+    card: CardContext<'_>,
+    user_state: MutUserState<'_>,
+    user_states: &DashMap<UserId, UserState>,
+) -> CardCompletion {
+    let settings = card.settings;
+    if let Err(err) = task.check_answer_index() {
+        // Task text can be private, so only its place is logged.
+        log::error!(
+            "broken task of card '{}' in course {}: {err}",
+            card.card_name,
+            card.course_id.0
         );
+        bot.send_message(user_id, "This task is broken, please try other card.")
+            .await
+            .log_err();
+        return CardCompletion::again(false, false);
+    }
+    let question = with_note(task.question.clone(), note);
+    let compact = settings.compact_questions;
+    let asked_at = Instant::now();
+    let user_answer = match &task.kind {
+        TaskKind::OneOf { .. } => {
+            let (shown_options, correct) = shown_options(&task, &mut rand::rng());
+            // Learner answers with label, even if option is image.
+            let labels = option_labels(&shown_options);
+            let summary_options = db_get_user_settings(user_id)
+                .log_err()
+                .is_some_and(|settings| settings.options_summary)
+                .then(|| labels.clone());
+            let only_texts = shown_options
+                .iter()
+                .all(|option| matches!(option, QuestionElement::Text(_)));
+            let multi_select = correct.len() > 1;
+            let user_answer = match (settings.native_polls && only_texts, correct.as_slice()) {
+                _ if multi_select => {
+                    let question = question_interactions(question, compact);
+                    get_multi_select_answer(
+                        bot.clone(),
+                        user_id,
+                        question,
+                        shown_options,
+                        user_state,
+                    )
+                    .await
+                }
+                // Quiz poll can have only one correct option and can't show images.
+                (true, &[correct]) => {
+                    get_poll_answer(
+                        bot.clone(),
+                        user_id,
+                        question,
+                        labels.clone(),
+                        correct,
+                        compact,
+                        user_state,
+                    )
+                    .await
+                }
+                _ => {
+                    let question = question_interactions(question, compact);
+                    get_card_answer(bot.clone(), user_id, question, shown_options, user_state).await
+                }
+            };
+            let user_answer = user_answer.log_err().unwrap();
+            let chosen = user_answer
+                .as_deref()
+                .map(|user_answer| chosen_options(&labels, user_answer, multi_select));
+            if let (Some(shown_options), Some(chosen)) = (summary_options, &chosen) {
+                bot.send_message(user_id, options_summary(&shown_options, &correct, chosen))
+                    .await
+                    .log_err();
+            }
+            let correct_options = correct
+                .iter()
+                .map(|&ix| labels[ix].as_str())
+                .collect::<Vec<_>>();
+            user_answer.zip(chosen).map(|(user_answer, chosen)| {
+                if multi_select {
+                    CardResponse::from_selection(&user_answer, &chosen, &correct)
+                } else {
+                    CardResponse::from_option(&user_answer, &correct_options)
+                }
+            })
+        }
+        TaskKind::Ordering { items, .. } => {
+            let question = question_interactions(question, compact);
+            get_ordering_answer(bot.clone(), user_id, question, items, user_state)
+                .await
+                .log_err()
+                .unwrap()
+                .map(|order| CardResponse::from_check(task.is_correct_order(&order)))
+        }
+        TaskKind::Cloze { answers } => {
+            let question = question_interactions(question, compact);
+            get_blanks_answer(bot.clone(), user_id, question, answers.len(), user_state)
+                .await
+                .log_err()
+                .unwrap()
+                .map(|given| {
+                    CardResponse::from_check(task.check_blanks(&given).into_iter().all(|x| x))
+                })
+        }
+        TaskKind::FreeText { .. } => {
+            let question = question_interactions(question, compact);
+            get_blanks_answer(bot.clone(), user_id, question, 1, user_state)
+                .await
+                .log_err()
+                .unwrap()
+                .map(|given| {
+                    CardResponse::from_check(
+                        given
+                            .last()
+                            .is_some_and(|given| task.check_free_text(given)),
+                    )
+                })
+        }
+    };
+    let Some(response) = user_answer else {
+        return CardCompletion::again(false, false);
     };
-    if user_answer == options[answer] {
+    let latency = asked_at.elapsed();
+    if response == CardResponse::Correct {
         bot.send_message(user_id, "Correct!").await.log_err();
-        (
-            RepetitionContext {
-                quality: Quality::Good,
+        if !settings.ask_difficulty {
+            let quality = match settings.difficulty_by_time {
+                Some(thresholds) => quality_from_latency(latency, thresholds),
+                None => Quality::Good,
+            };
+            return CardCompletion::answered(RepetitionContext {
+                quality,
                 review_time: now(),
-            },
-            true,
+            });
+        }
+        let user_state = user_states.get_mut(&user_id).unwrap();
+        let difficulty = get_user_answer(
+            bot.clone(),
+            user_id,
+            ["How hard was that?"],
+            DIFFICULTY_OPTIONS.map(QuestionElement::from).to_vec(),
+            user_state,
         )
+        .await
+        .log_err()
+        .flatten();
+        CardCompletion::answered(graded_repetition(difficulty.as_deref()))
     } else {
         let mut messages = Vec::new();
         messages.push(TelegramInteraction::Text(
-            if user_answer == I_DONT_KNOW_MESSAGE {
-                format!("Answer is {}", options[answer])
+            if response == CardResponse::Wrong {
+                format!("Wrong. Answer is {}", task.correct_answer())
             } else {
-                format!("Wrong. Answer is {}", options[answer])
+                format!("Answer is {}", task.correct_answer())
             },
         ));
-        if let Some(explanation) = explanation {
-            messages.extend(explanation.iter().cloned().map(TelegramInteraction::from));
+        if let TaskKind::OneOf {
+            options, answers, ..
+        } = &task.kind
+        {
+            // Link alone doesn't show, which image is correct.
+            messages.extend(
+                answers
+                    .iter()
+                    .map(|&ix| &options[ix])
+                    .filter(|option| !matches!(option, QuestionElement::Text(_)))
+                    .cloned()
+                    .map(TelegramInteraction::from),
+            );
+        }
+        if let Some(explanation) = task.explanation {
+            let max_len = settings.max_explanation_len.unwrap_or(MAX_EXPLANATION_LEN);
+            messages.extend(explanation_interactions(explanation, max_len));
         }
         let user_state = user_states.get_mut(&user_id).unwrap();
         send_interactions(bot.clone(), user_id, messages, user_state)
             .await
             .log_err();
-        (
+        let revealed = response == CardResponse::Revealed;
+        CardCompletion {
+            gave_up: response == CardResponse::DontKnow,
+            ..CardCompletion::again(!revealed, revealed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn note_is_saved() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course::with_default_cards(UserId(207_000))).unwrap();
+        let learner = UserId(207_001);
+        db_add_course_to_user(learner, course_id).unwrap();
+
+        let card = "countries".to_owned();
+        let mut progress = db_get_progress(learner, course_id).unwrap();
+        assert_eq!(progress.note(&card), None);
+        progress.set_note(&card, Some("think of a map".into()));
+        db_set_course_progress(learner, course_id, progress).unwrap();
+
+        let progress = db_get_progress(learner, course_id).unwrap();
+        assert_eq!(progress.note(&card), Some("think of a map"));
+        // notes are private
+        let other = UserId(207_002);
+        db_add_course_to_user(other, course_id).unwrap();
+        assert_eq!(db_get_progress(other, course_id).unwrap().note(&card), None);
+    }
+
+    #[test]
+    fn options_summary_marks_correct_option() {
+        let options = ["Rome", "Paris", "Oslo"].map(String::from);
+        assert_eq!(
+            options_summary(&options, &[1], &[0]),
+            "❌ Rome ← your answer\n✅ Paris\n❌ Oslo"
+        );
+        assert_eq!(
+            options_summary(&options, &[1], &[]),
+            "❌ Rome\n✅ Paris\n❌ Oslo"
+        );
+        assert_eq!(
+            options_summary(&options, &[0, 1], &[1, 2]),
+            "✅ Rome\n✅ Paris ← your answer\n❌ Oslo ← your answer"
+        );
+        assert_eq!(chosen_options(&options, "Paris", false), [1]);
+        assert!(chosen_options(&options, I_DONT_KNOW_MESSAGE, false).is_empty());
+        assert_eq!(chosen_options(&options, "0 2", true), [0, 2]);
+        assert!(chosen_options(&options, I_DONT_KNOW_MESSAGE, true).is_empty());
+    }
+
+    #[test]
+    fn multi_select_needs_exactly_correct_options() {
+        let correct = [0, 2];
+        for (chosen, expected) in [
+            (vec![2, 0], CardResponse::Correct),
+            (vec![0], CardResponse::Wrong),
+            (vec![0, 1, 2], CardResponse::Wrong),
+            (vec![1], CardResponse::Wrong),
+        ] {
+            assert_eq!(
+                CardResponse::from_selection("", &chosen, &correct),
+                expected,
+                "{chosen:?}"
+            );
+        }
+        assert_eq!(
+            CardResponse::from_selection(I_DONT_KNOW_MESSAGE, &[], &correct),
+            CardResponse::DontKnow
+        );
+        assert_eq!(
+            CardResponse::from_selection(REVEAL_ANSWER_MESSAGE, &[], &correct),
+            CardResponse::Revealed
+        );
+    }
+
+    #[test]
+    fn revealed_answer_is_not_meaningful() {
+        assert_eq!(
+            CardResponse::from_option(REVEAL_ANSWER_MESSAGE, &["Paris"]),
+            CardResponse::Revealed
+        );
+        assert_eq!(
+            CardResponse::from_option(I_DONT_KNOW_MESSAGE, &["Paris"]),
+            CardResponse::DontKnow
+        );
+        let graph = CourseGraph::default();
+        let mut progress = UserProgress::default();
+        graph.init_store(&mut progress);
+        graph.detect_recursive_fails(&mut progress);
+        let card = "countries".to_owned();
+
+        let revealed = CardCompletion::again(false, true);
+        assert!(matches!(revealed.repetition.quality, Quality::Again));
+        assert!(!revealed.is_failed());
+        revealed.record(&mut progress, &card);
+        let guessed = CardCompletion::again(true, false);
+        assert!(guessed.is_failed());
+        guessed.record(&mut progress, &card);
+
+        let task = &progress.tasks[&card];
+        assert_eq!(task.meaningful_repetitions, 1);
+        assert_eq!(task.failed_repetitions, 1);
+        assert_eq!(task.revealed_repetitions, 1);
+        let stats = analytics::CourseStats::aggregate([&progress]);
+        assert_eq!(stats.cards[&card].attempts, 1);
+        assert_eq!(stats.cards[&card].reveals, 1);
+    }
+
+    #[test]
+    fn preview_records_nothing() {
+        let graph = CourseGraph::default();
+        let mut progress = UserProgress::default();
+        graph.init_store(&mut progress);
+        graph.detect_recursive_fails(&mut progress);
+        let before = serde_json::to_string(&progress).unwrap();
+        let card = "countries".to_owned();
+
+        for completion in [
+            CardCompletion::again(true, false),
+            CardCompletion::again(false, true),
+            CardCompletion::answered(RepetitionContext {
+                quality: Quality::Good,
+                review_time: now(),
+            }),
+        ] {
+            let preview = completion.into_preview();
+            assert!(preview.preview && !preview.meaningful && !preview.is_failed());
+            preview.record(&mut progress, &card);
+        }
+        assert_eq!(serde_json::to_string(&progress).unwrap(), before);
+        let stats = analytics::CourseStats::aggregate([&progress]);
+        let stats = &stats.cards[&card];
+        assert_eq!(
+            (stats.attempts, stats.failed_attempts, stats.reveals),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn review_counts_split_due_and_new() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course::with_default_cards(UserId(231_100))).unwrap();
+        let learner = UserId(231_101);
+        db_add_course_to_user(learner, course_id).unwrap();
+        assert_eq!(review_counts(learner, course_id).unwrap(), (0, 1));
+
+        let mut progress = db_get_progress(learner, course_id).unwrap();
+        progress.repetition(
+            &"countries".to_owned(),
             RepetitionContext {
-                quality: Quality::Again,
+                quality: Quality::Good,
                 review_time: now(),
             },
             true,
-        )
+        );
+        db_set_course_progress(learner, course_id, progress).unwrap();
+        assert_eq!(review_counts(learner, course_id).unwrap(), (0, 1));
+
+        reset_schedule(learner, course_id).unwrap();
+        assert_eq!(review_counts(learner, course_id).unwrap(), (1, 1));
+    }
+
+    #[test]
+    fn daily_limit_resets_next_day() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course {
+            owner_id: UserId(232_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings {
+                daily_review_limit: Some(2),
+                ..Default::default()
+            },
+            description: String::new(),
+        })
+        .unwrap();
+        let learner = UserId(232_001);
+        db_add_course_to_user(learner, course_id).unwrap();
+        let day = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap();
+
+        for _ in 0..2 {
+            assert!(!daily_limit_reached(learner, course_id, day).unwrap());
+            db_add_daily_review(learner, course_id, day).unwrap();
+        }
+        // Third review is deferred.
+        assert!(daily_limit_reached(learner, course_id, day).unwrap());
+        let next_day = day.succ_opt().unwrap();
+        assert!(!daily_limit_reached(learner, course_id, next_day).unwrap());
+
+        let mut settings = db_get_user_settings(learner).unwrap();
+        settings.daily_review_limit = Some(1);
+        db_set_user_settings(learner, &settings).unwrap();
+        db_add_daily_review(learner, course_id, next_day).unwrap();
+        assert!(daily_limit_reached(learner, course_id, next_day).unwrap());
+        assert_eq!(db_get_daily_reviews(learner, course_id, day).unwrap(), 0);
+    }
+
+    #[test]
+    fn local_day_uses_utc_offset() {
+        let time = DateTime::parse_from_rfc3339("2026-01-01T23:30:00Z")
+            .unwrap()
+            .with_timezone(&Local);
+        assert_eq!(
+            local_day(time, 0),
+            NaiveDate::from_ymd_opt(2026, 1, 1).unwrap()
+        );
+        assert_eq!(
+            local_day(time, 1),
+            NaiveDate::from_ymd_opt(2026, 1, 2).unwrap()
+        );
+        assert_eq!(
+            local_day(time, -12),
+            NaiveDate::from_ymd_opt(2026, 1, 1).unwrap()
+        );
+    }
+
+    #[test]
+    fn compact_mode_sends_fewer_messages() {
+        let image: url::Url = "https://example.com/map.png".parse().unwrap();
+        let question = vec![
+            QuestionElement::Text("Look at the map.".into()),
+            QuestionElement::Image(image),
+            QuestionElement::Text("Which country is it?".into()),
+        ];
+        // Every interaction is one message, options keyboard is one more.
+        let messages = |compact| question_interactions(question.clone(), compact).len() + 1;
+        assert_eq!(messages(false), 4);
+        assert_eq!(messages(true), 2);
+    }
+
+    #[test]
+    fn difficulty_maps_to_quality() {
+        let quality = |difficulty| graded_repetition(difficulty).quality;
+        assert!(matches!(quality(Some("Again")), Quality::Again));
+        assert!(matches!(quality(Some("Hard")), Quality::Hard));
+        assert!(matches!(quality(Some("Good")), Quality::Good));
+        assert!(matches!(quality(Some("Easy")), Quality::Easy));
+        assert!(matches!(quality(None), Quality::Good));
+    }
+
+    #[test]
+    fn fast_answer_is_easy() {
+        let thresholds = LatencyThresholds::default();
+        let quality = |secs| quality_from_latency(Duration::from_secs(secs), thresholds);
+        assert!(matches!(quality(2), Quality::Easy));
+        assert!(matches!(quality(10), Quality::Good));
+        assert!(matches!(quality(60), Quality::Hard));
+    }
+
+    #[test]
+    fn long_explanation_is_attached_as_file() {
+        let short = vec![QuestionElement::Text("Because.".into())];
+        assert!(matches!(
+            &explanation_interactions(short, 100)[..],
+            [TelegramInteraction::Text(text)] if text == "Because."
+        ));
+
+        let line = "Paris is the capital of France.";
+        let long_text = vec![line; 10].join("\n");
+        let image: url::Url = "https://example.com/map.png".parse().unwrap();
+        let long = vec![
+            QuestionElement::Text(long_text.clone()),
+            QuestionElement::Image(image),
+        ];
+        let interactions = explanation_interactions(long, 100);
+        let [
+            TelegramInteraction::Text(text),
+            TelegramInteraction::Image(_),
+            TelegramInteraction::Document { contents, .. },
+        ] = &interactions[..]
+        else {
+            panic!("unexpected interactions: {interactions:?}");
+        };
+        assert!(text.starts_with(&format!("{line}\n{line}\n{line}…")));
+        assert!(text.chars().count() < long_text.chars().count());
+        assert_eq!(contents, &long_text);
+    }
+
+    #[test]
+    fn move_on_explanation_follows_course_limit() {
+        db_create_tables().unwrap();
+        let mut course = Course::empty(UserId(223_000));
+        course.settings.max_explanation_len = Some(10);
+        let course_id = db_insert(course).unwrap();
+        let mut user_state = UserState::default();
+        assert!(move_on_explanation(&user_state).unwrap().is_none());
+
+        let explanation = vec![QuestionElement::Text("Long enough explanation.".into())];
+        user_state.move_on_explanation = Some((course_id, explanation));
+        let interactions = move_on_explanation(&user_state).unwrap().unwrap();
+        assert!(matches!(
+            &interactions[..],
+            [
+                TelegramInteraction::Text(_),
+                TelegramInteraction::Document { contents, .. },
+            ] if contents == "Long enough explanation."
+        ));
+    }
+
+    #[test]
+    fn next_card_is_recommended() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course {
+            owner_id: UserId(229_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        })
+        .unwrap();
+        let learner = UserId(229_001);
+        db_add_course_to_user(learner, course_id).unwrap();
+        let mut session_fails = SessionFails::default();
+
+        let card = next_card(learner, course_id, &session_fails)
+            .unwrap()
+            .unwrap();
+        assert_eq!(card, "countries");
+        let mut progress = db_get_progress(learner, course_id).unwrap();
+        progress.repetition(
+            &card,
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time: now(),
+            },
+            true,
+        );
+        db_set_course_progress(learner, course_id, progress).unwrap();
+
+        // Dependency is learned, so dependent card is next.
+        assert_eq!(
+            next_card(learner, course_id, &session_fails)
+                .unwrap()
+                .as_deref(),
+            Some("capitals")
+        );
+        assert_eq!(
+            db_get_progress(learner, course_id).unwrap().tasks["countries"].meaningful_repetitions,
+            1
+        );
+
+        assert!(session_fails.record(course_id, "capitals", true, Some(1)));
+        assert_eq!(next_card(learner, course_id, &session_fails).unwrap(), None);
+    }
+
+    #[test]
+    fn settings_toggle_is_persisted() {
+        db_create_tables().unwrap();
+        let user_id = UserId(217_001);
+        assert!(db_get_user_settings(user_id).unwrap().session_greeting);
+
+        let settings = apply_settings_action(user_id, SettingsAction::ToggleGreeting)
+            .unwrap()
+            .unwrap();
+        assert!(!settings.session_greeting);
+        assert_eq!(db_get_user_settings(user_id).unwrap(), settings);
+
+        assert_eq!(
+            apply_settings_action(user_id, SettingsAction::Done).unwrap(),
+            None
+        );
+        assert!(!db_get_user_settings(user_id).unwrap().session_greeting);
+    }
+
+    #[test]
+    fn question_text_becomes_poll_question() {
+        let image = QuestionElement::Image("https://example.com/map.png".parse().unwrap());
+        let question = vec![image, QuestionElement::Text("Capital of France?".into())];
+        let options = vec!["Paris".to_owned(), "Rome".to_owned()];
+
+        let interactions = poll_interactions(question.clone(), options.clone(), 0, false).unwrap();
+        assert_eq!(interactions.len(), 2);
+        assert!(matches!(interactions[0], TelegramInteraction::Image(_)));
+        assert!(matches!(
+            &interactions[1],
+            TelegramInteraction::Poll { question, options, correct: 0 }
+                if question == "Capital of France?" && options.len() == 2
+        ));
+
+        let too_many = (0..=MAX_POLL_OPTIONS).map(|ix| ix.to_string()).collect();
+        assert!(poll_interactions(question, too_many, 0, false).is_none());
+    }
+
+    #[test]
+    fn shuffled_index_points_to_correct_option() {
+        use rand::{SeedableRng, rngs::StdRng};
+
+        let options = ["Paris", "Rome", "Berlin", "Madrid", "Lisbon"].map(String::from);
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let correct = seed as usize % options.len();
+            let (shuffled, new_correct) = shuffle_options(options.to_vec(), &[correct], &mut rng);
+            assert_eq!(shuffled[new_correct[0]], options[correct], "seed {seed}");
+            let mut sorted = shuffled;
+            sorted.sort();
+            let mut expected = options.to_vec();
+            expected.sort();
+            assert_eq!(sorted, expected);
+        }
+    }
+
+    #[test]
+    fn unshuffled_options_keep_correct_index() {
+        let task =
+            Task::from_str("!noshuffle\nWhich comes first?\n\n- b\n* a\n- c", false).unwrap();
+        for _ in 0..20 {
+            let (shown, correct) = shown_options(&task, &mut rand::rng());
+            let labels = option_labels(&shown);
+            assert_eq!(labels, ["b", "a", "c"]);
+            assert_eq!(correct, [1]);
+            assert_eq!(
+                CardResponse::from_option(&labels[correct[0]], &["a"]),
+                CardResponse::Correct
+            );
+        }
+        let task = Task::from_str("Which comes first?\n\n* a\n- b\n- c", false).unwrap();
+        let (shown, correct) = shown_options(&task, &mut rand::rng());
+        assert_eq!(shown[correct[0]], QuestionElement::from("a"));
+    }
+
+    #[test]
+    fn confirmation_outcomes() {
+        assert!(is_confirmed(Some(CONFIRM_YES)));
+        assert!(!is_confirmed(Some(CONFIRM_NO)));
+        assert!(!is_confirmed(None));
+    }
+
+    #[test]
+    fn note_is_shown_before_question() {
+        let question = vec![QuestionElement::Text("Question".into())];
+        assert_eq!(with_note(question.clone(), None), question);
+        assert_eq!(
+            with_note(question, Some("mnemonic".into())),
+            vec![
+                QuestionElement::Text("Your note: mnemonic".into()),
+                QuestionElement::Text("Question".into()),
+            ]
+        );
     }
 }
@@ -1,23 +1,160 @@
-use std::{collections::HashMap, time::SystemTime};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 
-use course_graph::progress_store::{TaskProgress, TaskProgressStore};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeDelta};
+use course_graph::{
+    parsing::normalize_card_name,
+    progress_store::{TaskProgress, TaskProgressStore},
+};
 use fsrs::FSRS;
+use rand::{Rng, seq::SliceRandom};
 use serde::{Deserialize, Serialize};
-use ssr_algorithms::fsrs::{level::RepetitionContext, weights::Weights};
+use ssr_algorithms::fsrs::{
+    level::{Quality, RepetitionContext},
+    weights::Weights,
+};
 
 type Level = ssr_algorithms::fsrs::level::Level;
 
 type Id = String;
 
+/// FSRS weights are non-negative and way smaller than this.
+const MAX_WEIGHT: f32 = 100.;
+
+/// Card, answered right, isn't shown again sooner. Otherwise broken schedule(like after
+/// weird weights) could show it again and again.
+const MIN_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Way longer, than FSRS schedules with sane weights.
+const MAX_INTERVAL: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// Progress, saved by older version, is migrated on load. See [`UserProgress::migrate`].
+pub const PROGRESS_VERSION: u32 = 2;
+
+/// Schedule of `level`, clamped to [`MIN_INTERVAL`]..=[`MAX_INTERVAL`] after `last_review`.
+/// Failed card should be repeated right away, so only too late schedule is clamped for it.
+fn bounded_next_repetition(
+    level: &Level,
+    last_review: Option<SystemTime>,
+    fsrs: &FSRS,
+    retrievability_goal: f32,
+) -> SystemTime {
+    let scheduled = level.next_repetition(fsrs, retrievability_goal as f64);
+    let Some(last_review) = last_review else {
+        return scheduled;
+    };
+    let min = match level.failed() {
+        true => scheduled.min(last_review),
+        false => last_review + MIN_INTERVAL,
+    };
+    scheduled.clamp(min, last_review + MAX_INTERVAL)
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum WeightsParseError {
+    #[error("'{0}' is not a number")]
+    InvalidNumber(String),
+    #[error("expected {expected} weights, found {found}")]
+    WrongCount { expected: usize, found: usize },
+    #[error("weight {index} should be from 0 to {MAX_WEIGHT}, found {value}")]
+    OutOfRange { index: usize, value: f32 },
+}
+
+fn weights_values(weights: &Weights) -> Vec<f32> {
+    serde_json::from_value(serde_json::to_value(weights).unwrap())
+        .expect("weights should be serialized as list of numbers")
+}
+
+/// Comma separated numbers, accepted by [`parse_weights`].
+pub fn weights_to_string(weights: &Weights) -> String {
+    weights_values(weights)
+        .iter()
+        .map(f32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Numbers can be separated by commas and/or whitespace.
+/// Count should match default weights.
+pub fn parse_weights(input: &str) -> Result<Weights, WeightsParseError> {
+    let values = input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|number| !number.is_empty())
+        .map(|number| {
+            number
+                .parse::<f32>()
+                .map_err(|_| WeightsParseError::InvalidNumber(number.to_owned()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let expected = weights_values(&Weights::default()).len();
+    if values.len() != expected {
+        return Err(WeightsParseError::WrongCount {
+            expected,
+            found: values.len(),
+        });
+    }
+    if let Some((index, &value)) = values
+        .iter()
+        .enumerate()
+        .find(|(_, value)| !(0. ..=MAX_WEIGHT).contains(*value))
+    {
+        return Err(WeightsParseError::OutOfRange { index, value });
+    }
+    Ok(serde_json::from_value(serde_json::to_value(values).unwrap()).unwrap())
+}
+
+/// Card names are renamed with [`normalize_card_name`]. If several names become the same,
+/// already normalized one keeps its value.
+pub fn normalize_keys<T>(map: HashMap<Id, T>, case_sensitive: bool) -> HashMap<Id, T> {
+    let normalize = |id: &Id| normalize_card_name(id, case_sensitive);
+    let (normalized, other): (HashMap<_, _>, HashMap<_, _>) =
+        map.into_iter().partition(|(id, _)| *id == normalize(id));
+    let mut map = normalized;
+    for (id, value) in other {
+        map.entry(normalize(&id)).or_insert(value);
+    }
+    map
+}
+
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct Task {
-    progress: TaskProgress,
+    pub(crate) progress: TaskProgress,
     level: Level,
     pub(crate) meaningful_repetitions: u32,
+    /// Meaningful repetitions, answered wrong.
+    #[serde(default)]
+    pub(crate) failed_repetitions: u32,
+    /// Repetitions, where learner revealed answer instead of guessing. Not meaningful.
+    #[serde(default)]
+    pub(crate) revealed_repetitions: u32,
+    /// Private note of learner, shown with this card.
+    #[serde(default)]
+    pub(crate) note: Option<String>,
+    /// Makes card due for review before `level` schedule. Doesn't change progress.
+    /// Cleared on next repetition.
+    #[serde(default)]
+    due_override: Option<SystemTime>,
+    /// None for progress, saved before it was tracked. Its schedule isn't clamped until next review.
+    #[serde(default)]
+    last_review: Option<SystemTime>,
+    /// Reviews in a row, after which `level` schedule was clamped.
+    #[serde(default)]
+    clamped_schedules: u32,
 }
 impl Task {
+    fn scheduled_repetition(&self, fsrs: &FSRS, retrievability_goal: f32) -> SystemTime {
+        bounded_next_repetition(&self.level, self.last_review, fsrs, retrievability_goal)
+    }
+    fn next_repetition(&self, fsrs: &FSRS, retrievability_goal: f32) -> SystemTime {
+        let scheduled = self.scheduled_repetition(fsrs, retrievability_goal);
+        match self.due_override {
+            Some(due) => due.min(scheduled),
+            None => scheduled,
+        }
+    }
     fn synchronize(&mut self, fsrs: &FSRS, retrievability_goal: f32, now: SystemTime) {
-        let next_repetition = self.level.next_repetition(fsrs, retrievability_goal as f64);
+        let next_repetition = self.scheduled_repetition(fsrs, retrievability_goal);
         let time_to_repeat = next_repetition < now;
         match self.progress {
             TaskProgress::NotStarted {
@@ -38,6 +175,24 @@ impl Task {
             }
         }
     }
+    /// Interval until next repetition, if card is reviewed at `now` with each quality,
+    /// from `Again` to `Easy`. Nothing is recorded.
+    pub fn preview_intervals(
+        &self,
+        fsrs: &FSRS,
+        retrievability_goal: f32,
+        now: SystemTime,
+    ) -> [(Quality, Duration); 4] {
+        [Quality::Again, Quality::Hard, Quality::Good, Quality::Easy].map(|quality| {
+            let mut level = self.level.clone();
+            level.add_repetition(RepetitionContext {
+                quality,
+                review_time: now.into(),
+            });
+            let next = bounded_next_repetition(&level, Some(now), fsrs, retrievability_goal);
+            (quality, next.duration_since(now).unwrap_or_default())
+        })
+    }
     fn update_parents_info(&mut self, is_all_parents_correct: bool) {
         match self.progress {
             TaskProgress::NotStarted {
@@ -64,15 +219,30 @@ impl Task {
         &mut self,
         repetition: RepetitionContext,
         meaningful_repetition: bool,
+        fsrs: &FSRS,
+        retrievability_goal: f32,
     ) -> Result<(), ()> {
         match self.progress {
             TaskProgress::NotStarted {
                 could_be_learned: false,
             } => Err(()),
             _ => {
+                let failed = matches!(repetition.quality, Quality::Again);
+                let review_time = repetition.review_time.into();
                 self.level.add_repetition(repetition);
+                self.due_override = None;
+                self.last_review = Some(review_time);
+                let scheduled = self.level.next_repetition(fsrs, retrievability_goal as f64);
+                if self.scheduled_repetition(fsrs, retrievability_goal) == scheduled {
+                    self.clamped_schedules = 0;
+                } else {
+                    self.clamped_schedules += 1;
+                }
                 if meaningful_repetition {
                     self.meaningful_repetitions += 1;
+                    if failed {
+                        self.failed_repetitions += 1;
+                    }
                 }
                 Ok(())
             }
@@ -80,11 +250,36 @@ impl Task {
     }
 }
 
+/// Number of cards in every state, for `/stats`.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct ProgressSummary {
+    pub learned: usize,
+    pub failed: usize,
+    /// Learned, but some dependency is failed.
+    pub blocked: usize,
+    pub available: usize,
+    pub locked: usize,
+}
+impl ProgressSummary {
+    pub fn message(&self) -> String {
+        format!(
+            "Learned: {}, Failed: {}, Blocked: {}, Available: {}, Locked: {}",
+            self.learned, self.failed, self.blocked, self.available, self.locked
+        )
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UserProgress {
     weights: Weights,
     desired_retention: f32,
     pub(crate) tasks: HashMap<Id, Task>,
+    /// Cards, learner wants to reach.
+    #[serde(default)]
+    pub(crate) goals: Vec<Id>,
+    /// 0 for progress, saved before versions.
+    #[serde(default)]
+    version: u32,
 }
 impl Default for UserProgress {
     fn default() -> Self {
@@ -92,10 +287,86 @@ impl Default for UserProgress {
             weights: Weights::default(),
             desired_retention: 0.85,
             tasks: HashMap::new(),
+            goals: Vec::new(),
+            version: PROGRESS_VERSION,
         }
     }
 }
 impl UserProgress {
+    /// Upgrades progress, saved by older version, to [`PROGRESS_VERSION`].
+    /// Returns false, if it is already up to date.
+    pub fn migrate(&mut self, now: SystemTime) -> bool {
+        if self.version >= PROGRESS_VERSION {
+            return false;
+        }
+        if self.version < 1 {
+            // Version 1: last review is tracked, so broken schedules are clamped.
+            let fsrs = self.weights.fsrs();
+            for task in self.tasks.values_mut() {
+                if task.last_review.is_some()
+                    || matches!(task.progress, TaskProgress::NotStarted { .. })
+                {
+                    continue;
+                }
+                // Real review time is unknown. This one keeps current schedule, unless it is too late.
+                let scheduled = task
+                    .level
+                    .next_repetition(&fsrs, self.desired_retention as f64);
+                let last_review = scheduled.checked_sub(MIN_INTERVAL).unwrap_or(scheduled);
+                task.last_review = Some(last_review.min(now));
+            }
+        }
+        if self.version < 2 {
+            // Version 2: whitespace in card names is collapsed, like courses do.
+            // Case is kept, because it depends on course.
+            self.normalize_names(true);
+        }
+        self.version = PROGRESS_VERSION;
+        self.synchronize(now);
+        true
+    }
+    /// Renames cards and goals with [`normalize_card_name`], like after course changed
+    /// case sensitivity of names.
+    pub fn normalize_names(&mut self, case_sensitive: bool) {
+        self.tasks = normalize_keys(std::mem::take(&mut self.tasks), case_sensitive);
+        let mut goals = Vec::new();
+        for goal in self.goals.drain(..) {
+            let goal = normalize_card_name(&goal, case_sensitive);
+            if !goals.contains(&goal) {
+                goals.push(goal);
+            }
+        }
+        self.goals = goals;
+    }
+    pub fn set_desired_retention(&mut self, desired_retention: f32) {
+        self.desired_retention = desired_retention;
+    }
+    pub fn weights(&self) -> &Weights {
+        &self.weights
+    }
+    pub fn set_weights(&mut self, weights: Weights) {
+        self.weights = weights;
+    }
+    /// Card states without schedule, for `/checkpoint`.
+    pub fn states(&self) -> HashMap<Id, TaskProgress> {
+        self.tasks
+            .iter()
+            .map(|(id, task)| (id.clone(), task.progress))
+            .collect()
+    }
+    /// See [`Task::preview_intervals`]. None for unknown card and card, that can't be learned yet.
+    pub fn preview_intervals(&self, id: &Id, now: SystemTime) -> Option<[(Quality, Duration); 4]> {
+        let task = self.tasks.get(id)?;
+        let locked = TaskProgress::NotStarted {
+            could_be_learned: false,
+        };
+        (task.progress != locked)
+            .then(|| task.preview_intervals(&self.weights.fsrs(), self.desired_retention, now))
+    }
+    /// When card should be repeated. Started `Good` card becomes `Failed` after this time.
+    pub fn next_repetition(&self, id: &Id) -> SystemTime {
+        self.tasks[id].next_repetition(&self.weights.fsrs(), self.desired_retention)
+    }
     pub fn synchronize(&mut self, now: SystemTime) {
         let fsrs = self.weights.fsrs();
         self.tasks.values_mut().for_each(|t| {
@@ -108,11 +379,215 @@ impl UserProgress {
         repetition: RepetitionContext,
         meaningful_repetition: bool,
     ) {
-        self.tasks
-            .get_mut(id)
-            .unwrap()
-            .add_repetition(repetition, meaningful_repetition)
-            .expect("HINT: you cant revice card that not started and have bad known(for user) dependencies")
+        let fsrs = self.weights.fsrs();
+        let task = self.tasks.get_mut(id).unwrap();
+        task.add_repetition(
+            repetition,
+            meaningful_repetition,
+            &fsrs,
+            self.desired_retention,
+        )
+        .expect(
+            "HINT: you cant revice card that not started and have bad known(for user) dependencies",
+        );
+        if task.clamped_schedules > 0 {
+            log::warn!(
+                "FSRS schedule of card '{id}' is out of sane range after {} reviews in a row, it is clamped",
+                task.clamped_schedules
+            );
+        }
+    }
+    /// Card is repeated again soon, but revealed answer isn't counted as failed attempt.
+    pub fn reveal(&mut self, id: &Id, review_time: DateTime<Local>) {
+        self.repetition(
+            id,
+            RepetitionContext {
+                quality: Quality::Again,
+                review_time,
+            },
+            false,
+        );
+        self.tasks.get_mut(id).unwrap().revealed_repetitions += 1;
+    }
+    /// Learnable cards, that should be repeated before `now`, most overdue first.
+    pub fn due_cards(&self, now: SystemTime) -> Vec<&Id> {
+        let fsrs = self.weights.fsrs();
+        let mut due = self
+            .tasks
+            .iter()
+            .filter(|(_, task)| {
+                task.progress
+                    != TaskProgress::NotStarted {
+                        could_be_learned: false,
+                    }
+            })
+            .map(|(id, task)| (task.next_repetition(&fsrs, self.desired_retention), id))
+            .filter(|(next_repetition, _)| *next_repetition <= now)
+            .collect::<Vec<_>>();
+        due.sort();
+        due.into_iter().map(|(_, id)| id).collect()
+    }
+    /// Best card to study now: failed, then new, then learned one past its schedule.
+    /// Most overdue first in every group. Cards, for which `skip` returns true, are not recommended.
+    pub fn recommended_next(&self, now: SystemTime, skip: impl Fn(&Id) -> bool) -> Option<&Id> {
+        self.due_cards(now)
+            .into_iter()
+            .filter(|&id| !skip(id))
+            .min_by_key(|&id| match self[id] {
+                TaskProgress::Failed => 0,
+                TaskProgress::NotStarted { .. } => 1,
+                TaskProgress::Good | TaskProgress::RecursiveFailed => 2,
+            })
+    }
+    /// Same cards, as [`Self::due_cards`], in random order. Started cards still go before new.
+    pub fn shuffled_due_cards(&self, now: SystemTime, rng: &mut impl Rng) -> Vec<&Id> {
+        let (mut new, mut started): (Vec<_>, Vec<_>) = self
+            .due_cards(now)
+            .into_iter()
+            .partition(|&id| matches!(self[id], TaskProgress::NotStarted { .. }));
+        // Shuffle doesn't depend on order of `HashMap`, so seed is enough to reproduce it.
+        started.sort();
+        new.sort();
+        started.shuffle(rng);
+        new.shuffle(rng);
+        started.extend(new);
+        started
+    }
+    /// Same cards, as [`Self::due_cards`], in the same order as in `order`.
+    /// Cards missing in `order` go last, most overdue first.
+    pub fn due_cards_in_order(&self, now: SystemTime, order: &[&Id]) -> Vec<&Id> {
+        let positions = order
+            .iter()
+            .enumerate()
+            .map(|(position, &id)| (id, position))
+            .collect::<HashMap<_, _>>();
+        let mut due = self.due_cards(now);
+        due.sort_by_key(|&id| positions.get(id).copied().unwrap_or(usize::MAX));
+        due
+    }
+    /// First card of `order`, that learner can start now.
+    pub fn first_startable<'a>(&self, order: &[&'a Id]) -> Option<&'a Id> {
+        order.iter().copied().find(|&id| {
+            self.tasks.get(id).is_some_and(|task| {
+                task.progress
+                    == TaskProgress::NotStarted {
+                        could_be_learned: true,
+                    }
+            })
+        })
+    }
+    pub fn summary(&self) -> ProgressSummary {
+        let mut summary = ProgressSummary::default();
+        for task in self.tasks.values() {
+            let count = match task.progress {
+                TaskProgress::Good => &mut summary.learned,
+                TaskProgress::Failed => &mut summary.failed,
+                TaskProgress::RecursiveFailed => &mut summary.blocked,
+                TaskProgress::NotStarted {
+                    could_be_learned: true,
+                } => &mut summary.available,
+                TaskProgress::NotStarted {
+                    could_be_learned: false,
+                } => &mut summary.locked,
+            };
+            *count += 1;
+        }
+        summary
+    }
+    /// Number of started cards to repeat and new cards, that could be learned.
+    pub fn review_counts(&self, now: SystemTime) -> (usize, usize) {
+        let due = self.due_cards(now);
+        let new = due
+            .iter()
+            .filter(|&&id| matches!(self[id], TaskProgress::NotStarted { .. }))
+            .count();
+        (due.len() - new, new)
+    }
+    /// Reviews per day for `days` days, starting from day of `now` in its timezone.
+    /// Every review is assumed to be answered `Good`, overdue cards are counted on the first day.
+    /// Progress is not changed.
+    pub fn project_workload(
+        &self,
+        days: u32,
+        now: DateTime<FixedOffset>,
+    ) -> Vec<(NaiveDate, usize)> {
+        let fsrs = self.weights.fsrs();
+        let first_day = now.date_naive();
+        let mut counts = vec![0; days as usize];
+        let learnable = self.tasks.values().filter(|task| {
+            task.progress
+                != TaskProgress::NotStarted {
+                    could_be_learned: false,
+                }
+        });
+        for task in learnable {
+            let mut level = task.level.clone();
+            let mut due =
+                DateTime::<Local>::from(task.next_repetition(&fsrs, self.desired_retention))
+                    .with_timezone(now.offset())
+                    .max(now);
+            loop {
+                let day = (due.date_naive() - first_day).num_days();
+                let Some(count) = usize::try_from(day)
+                    .ok()
+                    .and_then(|day| counts.get_mut(day))
+                else {
+                    break;
+                };
+                *count += 1;
+                level.add_repetition(RepetitionContext {
+                    quality: Quality::Good,
+                    review_time: due.with_timezone(&Local),
+                });
+                let next = DateTime::<Local>::from(bounded_next_repetition(
+                    &level,
+                    Some(due.into()),
+                    &fsrs,
+                    self.desired_retention,
+                ))
+                .with_timezone(now.offset());
+                // Schedule should move forward, otherwise projection never ends.
+                if next <= due {
+                    break;
+                }
+                due = next;
+            }
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(day, count)| (first_day + TimeDelta::days(day as i64), count))
+            .collect()
+    }
+    /// Every card becomes due at `now`, but progress and repetitions are kept.
+    pub fn make_all_due(&mut self, now: SystemTime) {
+        self.tasks.values_mut().for_each(|task| {
+            task.due_override = Some(now);
+        });
+    }
+    /// Copies progress of `shared_names` cards from `other`. Cards, missing in any of progresses,
+    /// are skipped. Returns number of copied cards.
+    pub fn merge_matching<'a>(
+        &mut self,
+        other: &UserProgress,
+        shared_names: impl IntoIterator<Item = &'a Id>,
+    ) -> usize {
+        let mut copied = 0;
+        for name in shared_names {
+            if let (Some(task), Some(other_task)) =
+                (self.tasks.get_mut(name), other.tasks.get(name))
+            {
+                *task = other_task.clone();
+                copied += 1;
+            }
+        }
+        copied
+    }
+    pub fn note(&self, id: &Id) -> Option<&str> {
+        self.tasks.get(id)?.note.as_deref()
+    }
+    pub fn set_note(&mut self, id: &Id, note: Option<String>) {
+        self.tasks.get_mut(id).unwrap().note = note;
     }
 }
 impl<'a> std::ops::Index<&'a Id> for UserProgress {
@@ -146,3 +621,513 @@ impl TaskProgressStore for UserProgress {
         self.tasks.iter().map(|(id, t)| (id, t.progress))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use course_graph::graph::CourseGraph;
+
+    use super::*;
+
+    /// Progress with one learnable card `id`, repeated at given times.
+    fn progress_with_card(id: &str, repetitions: &[(DateTime<Local>, Quality)]) -> UserProgress {
+        let mut progress = UserProgress::default();
+        progress.init(&id.to_owned());
+        progress.update_no_recursive_failed(&id.to_owned());
+        for &(review_time, quality) in repetitions {
+            progress.repetition(
+                &id.to_owned(),
+                RepetitionContext {
+                    quality,
+                    review_time,
+                },
+                true,
+            );
+        }
+        progress
+    }
+
+    #[test]
+    fn summary_counts_cards_by_state() {
+        let mut progress = UserProgress::default();
+        let states = [
+            ("a", TaskProgress::Good),
+            ("b", TaskProgress::Good),
+            ("c", TaskProgress::Failed),
+            ("d", TaskProgress::RecursiveFailed),
+            (
+                "e",
+                TaskProgress::NotStarted {
+                    could_be_learned: true,
+                },
+            ),
+            (
+                "f",
+                TaskProgress::NotStarted {
+                    could_be_learned: false,
+                },
+            ),
+            (
+                "g",
+                TaskProgress::NotStarted {
+                    could_be_learned: false,
+                },
+            ),
+        ];
+        for (id, state) in states {
+            progress.tasks.insert(
+                id.to_owned(),
+                Task {
+                    progress: state,
+                    ..Default::default()
+                },
+            );
+        }
+        let summary = progress.summary();
+        assert_eq!(
+            summary,
+            ProgressSummary {
+                learned: 2,
+                failed: 1,
+                blocked: 1,
+                available: 1,
+                locked: 2,
+            }
+        );
+        assert_eq!(
+            summary.message(),
+            "Learned: 2, Failed: 1, Blocked: 1, Available: 1, Locked: 2"
+        );
+        assert_eq!(
+            UserProgress::default().summary(),
+            ProgressSummary::default()
+        );
+    }
+
+    #[test]
+    fn recommended_next_prefers_failed_then_new() {
+        let review_time = Local::now();
+        let mut progress = progress_with_card("overdue", &[(review_time, Quality::Good)]);
+        let locked = Task {
+            progress: TaskProgress::NotStarted {
+                could_be_learned: false,
+            },
+            ..Default::default()
+        };
+        progress.tasks.insert("locked".to_owned(), locked);
+        let now = progress.next_repetition(&"overdue".to_owned()) + Duration::from_secs(1);
+        assert_eq!(
+            progress.recommended_next(review_time.into(), |_| false),
+            None
+        );
+        assert_eq!(
+            progress.recommended_next(now, |_| false).unwrap(),
+            "overdue"
+        );
+
+        let new = Task {
+            progress: TaskProgress::NotStarted {
+                could_be_learned: true,
+            },
+            ..Default::default()
+        };
+        progress.tasks.insert("new".to_owned(), new);
+        assert_eq!(progress.recommended_next(now, |_| false).unwrap(), "new");
+
+        let failed = Task {
+            progress: TaskProgress::Failed,
+            ..Default::default()
+        };
+        progress.tasks.insert("failed".to_owned(), failed);
+        assert_eq!(progress.recommended_next(now, |_| false).unwrap(), "failed");
+    }
+
+    #[test]
+    fn shuffled_due_cards_are_reproducible() {
+        use rand::{SeedableRng, rngs::StdRng};
+
+        let graph = CourseGraph::parse("a\nb\nc\nd\ne\nf: a", false).unwrap();
+        let mut progress = UserProgress::default();
+        graph.init_store(&mut progress);
+        graph.detect_recursive_fails(&mut progress);
+        let a = "a".to_owned();
+        let review_time = Local::now();
+        progress.repetition(
+            &a,
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time,
+            },
+            true,
+        );
+        progress.synchronize(review_time.into());
+        let now = progress.next_repetition(&a) + Duration::from_secs(1);
+        progress.synchronize(now);
+        graph.detect_recursive_fails(&mut progress);
+        assert_eq!(progress[&a], TaskProgress::Failed);
+        let mut due = progress.due_cards(now);
+        due.sort();
+        assert_eq!(due, ["a", "b", "c", "d", "e"]);
+
+        let shuffled = progress.shuffled_due_cards(now, &mut StdRng::seed_from_u64(7));
+        assert_eq!(shuffled[0], "a");
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, due);
+        for _ in 0..10 {
+            assert_eq!(
+                progress.shuffled_due_cards(now, &mut StdRng::seed_from_u64(7)),
+                shuffled
+            );
+        }
+    }
+
+    #[test]
+    fn dependencies_are_due_first_in_topological_order() {
+        // Dependency is last alphabetically, so overdue order starts with dependent.
+        let graph = CourseGraph::parse("a: z\nz", false).unwrap();
+        let mut progress = UserProgress::default();
+        graph.init_store(&mut progress);
+        graph.detect_recursive_fails(&mut progress);
+        let review_time = Local::now();
+        for card in ["z", "a"] {
+            progress.repetition(
+                &card.to_owned(),
+                RepetitionContext {
+                    quality: Quality::Good,
+                    review_time,
+                },
+                true,
+            );
+            progress.synchronize(review_time.into());
+            graph.detect_recursive_fails(&mut progress);
+        }
+        let now = progress
+            .next_repetition(&"a".to_owned())
+            .max(progress.next_repetition(&"z".to_owned()))
+            + Duration::from_secs(1);
+        progress.synchronize(now);
+        graph.detect_recursive_fails(&mut progress);
+        assert_eq!(progress.due_cards(now), ["a", "z"]);
+
+        let order = graph.topological_order();
+        assert_eq!(progress.due_cards_in_order(now, &order), ["z", "a"]);
+    }
+
+    #[test]
+    fn good_card_fails_after_next_repetition() {
+        let card = "card".to_owned();
+        let review_time = Local::now();
+        let mut progress = progress_with_card(&card, &[(review_time, Quality::Good)]);
+        let next_repetition = progress.next_repetition(&card);
+        assert!(next_repetition > review_time.into());
+
+        let second = Duration::from_secs(1);
+        progress.synchronize(next_repetition - second);
+        assert_eq!(progress[&card], TaskProgress::Good);
+        progress.synchronize(next_repetition);
+        assert_eq!(progress[&card], TaskProgress::Good);
+        progress.synchronize(next_repetition + second);
+        assert_eq!(progress[&card], TaskProgress::Failed);
+    }
+
+    #[test]
+    fn preview_intervals_grow_with_quality() {
+        let card = "card".to_owned();
+        let review_time = Local::now();
+        let mut progress = UserProgress::default();
+        progress.init(&card);
+        progress.update_no_recursive_failed(&card);
+        let started = progress_with_card(&card, &[(review_time, Quality::Good)]);
+        let later = SystemTime::from(review_time) + Duration::from_secs(3 * 24 * 60 * 60);
+
+        // New and started card.
+        for (progress, now) in [(&progress, review_time.into()), (&started, later)] {
+            let intervals = progress.preview_intervals(&card, now).unwrap();
+            assert!(matches!(
+                intervals.map(|(quality, _)| quality),
+                [Quality::Again, Quality::Hard, Quality::Good, Quality::Easy]
+            ));
+            let intervals = intervals.map(|(_, interval)| interval);
+            assert!(intervals.is_sorted(), "{intervals:?}");
+        }
+
+        let mut locked = UserProgress::default();
+        locked.init(&card);
+        assert!(
+            locked
+                .preview_intervals(&card, review_time.into())
+                .is_none()
+        );
+        assert!(
+            progress
+                .preview_intervals(&"unknown".to_owned(), review_time.into())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn legacy_progress_is_migrated() {
+        let card = "card".to_owned();
+        let review_time = Local::now();
+        let progress = progress_with_card(&card, &[(review_time, Quality::Good)]);
+        let scheduled = progress.next_repetition(&card);
+
+        let mut legacy = serde_json::to_value(&progress).unwrap();
+        legacy.as_object_mut().unwrap().remove("version");
+        let task = &mut legacy["tasks"][&card];
+        task.as_object_mut().unwrap().remove("last_review");
+        let mut legacy: UserProgress = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.version, 0);
+        assert_eq!(legacy.tasks[&card].last_review, None);
+
+        let now = SystemTime::from(review_time) + Duration::from_secs(1);
+        assert!(legacy.migrate(now));
+        assert!(!legacy.migrate(now));
+        assert_eq!(legacy.version, PROGRESS_VERSION);
+        assert!(
+            legacy.tasks[&card]
+                .last_review
+                .is_some_and(|last| last <= now)
+        );
+        // Schedule is kept.
+        assert_eq!(legacy.next_repetition(&card), scheduled);
+        assert_eq!(legacy[&card], TaskProgress::Good);
+        assert!(legacy.due_cards(now).is_empty());
+        assert_eq!(
+            legacy.due_cards(scheduled + Duration::from_secs(1)),
+            [&card]
+        );
+
+        assert!(!UserProgress::default().migrate(now));
+    }
+
+    #[test]
+    fn spaced_card_names_are_migrated() {
+        let review_time = Local::now();
+        let task = |name: &str, quality| {
+            progress_with_card(name, &[(review_time, quality)])
+                .tasks
+                .remove(name)
+                .unwrap()
+        };
+        let mut progress = UserProgress::default();
+        progress
+            .tasks
+            .insert("a  b".into(), task("a  b", Quality::Good));
+        progress
+            .tasks
+            .insert("c d".into(), task("c d", Quality::Good));
+        progress
+            .tasks
+            .insert("c   d".into(), task("c   d", Quality::Again));
+        progress.goals = vec!["a  b".into(), "a b".into()];
+        progress.version = 1;
+
+        let now = SystemTime::from(review_time) + Duration::from_secs(1);
+        assert!(progress.migrate(now));
+        let mut names = progress.tasks.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a b", "c d"]);
+        assert_eq!(progress[&"a b".to_owned()], TaskProgress::Good);
+        assert_eq!(progress[&"c d".to_owned()], TaskProgress::Good);
+        assert_eq!(progress.goals, ["a b"]);
+    }
+
+    #[test]
+    fn degenerate_schedule_is_clamped() {
+        let card = "card".to_owned();
+        let review_time = Local::now();
+        let mut progress = progress_with_card(&card, &[(review_time, Quality::Good)]);
+        let scheduled = progress.next_repetition(&card);
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        // Level schedules card before its last review, so it would be due again right away.
+        let last_review = scheduled + day;
+        progress.tasks.get_mut(&card).unwrap().last_review = Some(last_review);
+        assert_eq!(progress.next_repetition(&card), last_review + MIN_INTERVAL);
+        progress.synchronize(last_review + Duration::from_secs(1));
+        assert_eq!(progress[&card], TaskProgress::Good);
+        assert!(
+            progress
+                .due_cards(last_review + Duration::from_secs(1))
+                .is_empty()
+        );
+
+        let last_review = scheduled - MAX_INTERVAL - day;
+        progress.tasks.get_mut(&card).unwrap().last_review = Some(last_review);
+        assert_eq!(progress.next_repetition(&card), last_review + MAX_INTERVAL);
+
+        // Real review makes schedule consistent again.
+        progress.repetition(
+            &card,
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time: (scheduled + day).into(),
+            },
+            true,
+        );
+        assert_eq!(progress.tasks[&card].clamped_schedules, 0);
+        assert!(progress.next_repetition(&card) > scheduled + day);
+    }
+
+    #[test]
+    fn failed_card_is_good_again_after_repetition() {
+        let card = "card".to_owned();
+        let review_time = Local::now();
+        let mut progress = progress_with_card(&card, &[(review_time, Quality::Good)]);
+        let late = progress.next_repetition(&card) + Duration::from_secs(1);
+        progress.synchronize(late);
+        assert_eq!(progress[&card], TaskProgress::Failed);
+
+        progress.repetition(
+            &card,
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time: late.into(),
+            },
+            true,
+        );
+        progress.synchronize(late);
+        assert_eq!(progress[&card], TaskProgress::Good);
+        assert!(progress.next_repetition(&card) > late);
+    }
+
+    #[test]
+    fn all_learnable_cards_are_due_after_reset() {
+        let graph = CourseGraph::default();
+        let mut progress = UserProgress::default();
+        graph.init_store(&mut progress);
+        graph.detect_recursive_fails(&mut progress);
+
+        let countries = "countries".to_owned();
+        let review_time = Local::now();
+        progress.repetition(
+            &countries,
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time,
+            },
+            true,
+        );
+        let now = review_time.into();
+        progress.synchronize(now);
+        graph.detect_recursive_fails(&mut progress);
+        assert_eq!(progress[&countries], TaskProgress::Good);
+        assert_eq!(progress.due_cards(now), vec!["capitals"]);
+
+        progress.make_all_due(now);
+        let mut due = progress.due_cards(now);
+        due.sort();
+        assert_eq!(due, vec!["capitals", "countries"]);
+        assert_eq!(progress[&countries], TaskProgress::Good);
+        assert_eq!(progress.tasks[&countries].meaningful_repetitions, 1);
+    }
+
+    #[test]
+    fn workload_projection_counts_scheduled_cards() {
+        let graph = CourseGraph::default();
+        let mut progress = UserProgress::default();
+        graph.init_store(&mut progress);
+        graph.detect_recursive_fails(&mut progress);
+        let review_time = Local::now();
+        progress.repetition(
+            &"countries".to_owned(),
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time,
+            },
+            true,
+        );
+        progress.synchronize(review_time.into());
+        graph.detect_recursive_fails(&mut progress);
+        let before = serde_json::to_value(&progress).unwrap();
+
+        let now = review_time.fixed_offset();
+        let workload = progress.project_workload(30, now);
+        assert_eq!(workload.len(), 30);
+        assert_eq!(workload[0].0, now.date_naive());
+        assert_eq!(workload[1].0, now.date_naive().succ_opt().unwrap());
+        // Only capitals is due now, countries was just repeated.
+        assert_eq!(workload[0].1, progress.due_cards(review_time.into()).len());
+        // Both cards are scheduled at least once in a month.
+        let total = workload.iter().map(|(_, count)| count).sum::<usize>();
+        assert!(total >= progress.tasks.len());
+        assert_eq!(serde_json::to_value(&progress).unwrap(), before);
+        assert!(progress.project_workload(0, now).is_empty());
+    }
+
+    #[test]
+    fn shared_cards_progress_is_copied() {
+        let old_graph = CourseGraph::parse("a\nb: a", false).unwrap();
+        let new_graph = CourseGraph::parse("a\nc: a", false).unwrap();
+        let mut old = UserProgress::default();
+        old_graph.init_store(&mut old);
+        old_graph.detect_recursive_fails(&mut old);
+        let mut new = UserProgress::default();
+        new_graph.init_store(&mut new);
+        new_graph.detect_recursive_fails(&mut new);
+        old.repetition(
+            &"a".to_owned(),
+            RepetitionContext {
+                quality: Quality::Good,
+                review_time: Local::now(),
+            },
+            true,
+        );
+
+        let shared = ["a".to_owned(), "b".to_owned()];
+        assert_eq!(new.merge_matching(&old, &shared), 1);
+        let json = |task: &Task| serde_json::to_value(task).unwrap();
+        assert_eq!(json(&new.tasks["a"]), json(&old.tasks["a"]));
+        assert_eq!(new.tasks["a"].meaningful_repetitions, 1);
+        assert_eq!(new.tasks["c"].meaningful_repetitions, 0);
+        assert_eq!(
+            new[&"c".to_owned()],
+            TaskProgress::NotStarted {
+                could_be_learned: false
+            }
+        );
+        assert!(!new.tasks.contains_key("b"));
+    }
+
+    #[test]
+    fn weights_roundtrip() {
+        let weights = Weights::default();
+        let string = weights_to_string(&weights);
+        let parsed = parse_weights(&string).unwrap();
+        assert_eq!(weights_to_string(&parsed), string);
+        // whitespace separated numbers are also accepted
+        let parsed = parse_weights(&string.replace(',', " \n")).unwrap();
+        assert_eq!(weights_to_string(&parsed), string);
+    }
+
+    #[test]
+    fn malformed_weights_are_rejected() {
+        let count = weights_values(&Weights::default()).len();
+        assert_eq!(
+            parse_weights("0.4, abc"),
+            Err(WeightsParseError::InvalidNumber("abc".into()))
+        );
+        assert_eq!(
+            parse_weights("0.4, 1.2"),
+            Err(WeightsParseError::WrongCount {
+                expected: count,
+                found: 2
+            })
+        );
+        let mut values = vec!["1"; count];
+        values[3] = "-1";
+        assert_eq!(
+            parse_weights(&values.join(",")),
+            Err(WeightsParseError::OutOfRange {
+                index: 3,
+                value: -1.
+            })
+        );
+        values[3] = "NaN";
+        assert!(matches!(
+            parse_weights(&values.join(",")),
+            Err(WeightsParseError::OutOfRange { index: 3, .. })
+        ));
+    }
+}
@@ -1,26 +1,109 @@
-use teloxide_core::types::{CallbackQuery, InputFile, ParseMode};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use teloxide_core::{
+    payloads::{
+        EditMessageReplyMarkupSetters, EditMessageTextSetters, SendPhotoSetters, SendPollSetters,
+    },
+    types::{
+        CallbackQuery, InputFile, InputMedia, InputMediaPhoto, MaybeAnonymousUser, MessageId,
+        ParseMode, PollAnswer, PollType,
+    },
+};
 use tokio::sync::oneshot;
+use url::Url;
 
 use super::*;
 use crate::{
-    interaction_types::TelegramInteraction,
-    state::{MutUserState, UserInteraction},
+    event_handler::apply_settings_action,
+    interaction_types::{
+        TelegramInteraction,
+        telegram_interaction::{QuestionElement, option_labels},
+    },
+    media_cache::{MEDIA_CACHE, MediaSource},
+    menu::MenuAction,
+    settings::{SettingsAction, UserSettings},
+    state::{MutUserState, TransientMessage, UserInteraction},
 };
 
+/// Telegram allows bots to delete messages only within 48 hours, margin covers slow handling.
+const DELETE_WINDOW: Duration = Duration::from_secs(47 * 60 * 60);
+/// Text of prompt, which can't be deleted anymore.
+const COLLAPSED_PROMPT: &str = "…";
+
+/// Telegram limit of message length.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Joins consecutive texts, if joined message fits in Telegram limit.
+fn batch_texts(
+    interactions: impl IntoIterator<Item = TelegramInteraction>,
+) -> Vec<TelegramInteraction> {
+    let mut batched = Vec::new();
+    for interaction in interactions {
+        if let (TelegramInteraction::Text(new), Some(TelegramInteraction::Text(prev))) =
+            (&interaction, batched.last_mut())
+        {
+            let joined = format!("{prev}\n\n{new}");
+            if escape_telegram_message(&joined).chars().count() <= MAX_MESSAGE_LEN {
+                *prev = joined;
+                continue;
+            }
+        }
+        batched.push(interaction);
+    }
+    batched
+}
+
+/// Telegram limits of media groups.
+const MAX_CAPTION_LEN: usize = 1024;
+const MAX_ALBUM_LEN: usize = 10;
+
+/// All texts in one message with all images. Audios can't be in album, so they are sent after it.
+/// If texts don't fit in caption, they are sent before images.
+pub fn compact_question(question: Vec<QuestionElement>) -> Vec<TelegramInteraction> {
+    let mut texts = Vec::new();
+    let mut images = Vec::new();
+    let mut audios = Vec::new();
+    for element in question {
+        match element {
+            QuestionElement::Text(text) => texts.push(text),
+            QuestionElement::Image(image) => images.push(image),
+            QuestionElement::Audio(audio) => audios.push(TelegramInteraction::Audio(audio)),
+        }
+    }
+    let text = texts.join("\n\n");
+    let mut interactions = Vec::new();
+    if images.is_empty() {
+        if !text.is_empty() {
+            interactions.push(text.into());
+        }
+    } else if escape_telegram_message(&text).chars().count() <= MAX_CAPTION_LEN {
+        interactions.push(TelegramInteraction::Album {
+            images,
+            caption: text,
+        });
+    } else {
+        interactions.push(text.into());
+        interactions.push(TelegramInteraction::Album {
+            images,
+            caption: String::new(),
+        });
+    }
+    interactions.extend(audios);
+    interactions
+}
+
+/// Consecutive texts are sent as one message, when possible.
 pub async fn send_interactions(
     bot: Bot,
     user_id: UserId,
     interactions: impl IntoIterator<Item = TelegramInteraction>,
     mut user_state: MutUserState<'_>,
 ) -> anyhow::Result<()> {
-    user_state.current_interaction = Some(UserInteraction {
-        interactions: interactions.into_iter().collect(),
-        current: 0,
-        current_id: rand::random(),
-        current_message: None,
-        answers: Vec::new(),
-        channel: None,
-    });
+    let cleanup = db_get_user_settings(user_id)?.cleanup_prompts;
+    user_state.current_interaction = Some(plain_interaction(interactions, cleanup));
 
     progress_on_user_event(bot, user_id, &mut user_state.current_interaction)
         .await
@@ -28,6 +111,38 @@ pub async fn send_interactions(
     Ok(())
 }
 
+/// Like [`send_interactions`], but current interaction of user, if any, is left as is.
+/// So `interactions` shouldn't wait for answer.
+pub async fn send_detached(
+    bot: Bot,
+    user_id: UserId,
+    interactions: impl IntoIterator<Item = TelegramInteraction>,
+) -> anyhow::Result<()> {
+    let mut interaction = Some(plain_interaction(interactions, false));
+    progress_on_user_event(bot, user_id, &mut interaction)
+        .await
+        .context("failed to send detached interactions")?;
+    Ok(())
+}
+
+fn plain_interaction(
+    interactions: impl IntoIterator<Item = TelegramInteraction>,
+    cleanup: bool,
+) -> UserInteraction {
+    UserInteraction {
+        interactions: batch_texts(interactions),
+        current: 0,
+        current_id: rand::random(),
+        current_message: None,
+        answers: Vec::new(),
+        chosen_items: Vec::new(),
+        current_poll: None,
+        channel: None,
+        transient_messages: Vec::new(),
+        cleanup,
+    }
+}
+
 pub async fn set_task_for_user(
     bot: Bot,
     user_id: UserId,
@@ -41,7 +156,11 @@ pub async fn set_task_for_user(
         current_id: rand::random(),
         current_message: None,
         answers: Vec::new(),
+        chosen_items: Vec::new(),
+        current_poll: None,
         channel: Some(channel),
+        transient_messages: Vec::new(),
+        cleanup: db_get_user_settings(user_id)?.cleanup_prompts,
     });
 
     progress_on_user_event(bot, user_id, &mut user_state.current_interaction)
@@ -50,6 +169,84 @@ pub async fn set_task_for_user(
     Ok(())
 }
 
+pub fn settings_keyboard(settings: &UserSettings) -> InlineKeyboardMarkup {
+    let button = |text: &str, action: SettingsAction| {
+        InlineKeyboardButton::callback(text, action.callback_data())
+    };
+    let greeting = if settings.session_greeting {
+        "Turn course greeting off"
+    } else {
+        "Turn course greeting on"
+    };
+    let cleanup = if settings.cleanup_prompts {
+        "Keep answered prompts"
+    } else {
+        "Delete answered prompts"
+    };
+    let options_summary = if settings.options_summary {
+        "Don't list options after answer"
+    } else {
+        "List options after answer"
+    };
+    InlineKeyboardMarkup::new([
+        vec![
+            button("Retention −", SettingsAction::RetentionDown),
+            button("Retention +", SettingsAction::RetentionUp),
+        ],
+        vec![
+            button("UTC −1", SettingsAction::UtcOffsetDown),
+            button("UTC +1", SettingsAction::UtcOffsetUp),
+        ],
+        vec![button(greeting, SettingsAction::ToggleGreeting)],
+        vec![button(cleanup, SettingsAction::ToggleCleanup)],
+        vec![button("Next graph view", SettingsAction::NextGraphView)],
+        vec![button(
+            options_summary,
+            SettingsAction::ToggleOptionsSummary,
+        )],
+        vec![button("Done", SettingsAction::Done)],
+    ])
+}
+
+async fn settings_callback_handler(
+    bot: Bot,
+    user_id: UserId,
+    message: Option<MessageId>,
+    action: SettingsAction,
+    users_state: &DashMap<UserId, UserState>,
+) -> anyhow::Result<()> {
+    let changed = apply_settings_action(user_id, action)?;
+    let Some(message) = message else {
+        log::warn!("settings message of user {user_id} is inaccessible");
+        return Ok(());
+    };
+    if action == SettingsAction::Done {
+        if let Some(mut user_state) = users_state
+            .get_mut(&user_id)
+            .filter(|user_state| matches!(user_state.current_screen, Screen::Settings))
+        {
+            user_state.current_screen = Screen::Main;
+            db_save_user_screen(user_id, Screen::Main)?;
+        }
+        bot.edit_message_text(
+            user_id,
+            message,
+            format!(
+                "{}\n\nSaved. You are now in main menu.",
+                db_get_user_settings(user_id)?.message()
+            ),
+        )
+        .await
+        .context("failed to close settings")?;
+    } else if let Some(settings) = changed {
+        bot.edit_message_text(user_id, message, settings.message())
+            .reply_markup(settings_keyboard(&settings))
+            .await
+            .context("failed to update settings message")?;
+    }
+    Ok(())
+}
+
 pub async fn callback_handler(
     bot: Bot,
     q: CallbackQuery,
@@ -67,18 +264,35 @@ pub async fn callback_handler(
 
     let _ = bot.answer_callback_query(q.id).await;
 
+    if let Some(action) = SettingsAction::from_callback_data(&response) {
+        let message = q.message.as_ref().map(|message| message.id());
+        return settings_callback_handler(bot, user_id, message, action, users_state).await;
+    }
+    if let Some(course_id) = parse_review_callback(&response) {
+        return review_session(bot, &q.from, course_id, ReviewOrder::Overdue, users_state).await;
+    }
+    if response == MOVE_ON_EXPLANATION_CALLBACK {
+        let explanation = match users_state.get(&user_id) {
+            Some(user_state) => move_on_explanation(&user_state)?,
+            None => None,
+        };
+        let interactions = explanation.unwrap_or_else(|| {
+            vec![TelegramInteraction::Text(
+                "Explanation is no longer available.".into(),
+            )]
+        });
+        return send_detached(bot, user_id, interactions).await;
+    }
+    if let Some(action) = MenuAction::from_callback_data(&response) {
+        handle_text_message(bot, &q.from, action.command(), users_state).await;
+        return Ok(());
+    }
+
     let Some(mut user_state) = users_state.get_mut(&user_id) else {
         log::debug!("user {user_id} not in dialogue");
         return Ok(());
     };
-    let Some(UserInteraction {
-        current,
-        current_id,
-        current_message,
-        answers,
-        ..
-    }) = &mut user_state.current_interaction
-    else {
+    let Some(interaction) = &mut user_state.current_interaction else {
         log::warn!("user {:?} in different state", q.from);
         bot.send_message(user_id, "You can answer only to current question")
             .await
@@ -90,30 +304,234 @@ pub async fn callback_handler(
     let (rand_id, response) = response.split_at(whitespace);
     let response = &response[1..];
 
-    if rand_id != current_id.to_string() {
-        log::info!("user {:?} answer to previous question", q.from);
-        // TODO: maybe delete this message
-        bot.send_message(user_id, "You can answer only to current question")
+    let current_message = interaction.current_message;
+    match record_callback_answer(interaction, rand_id, response) {
+        CallbackAnswer::Outdated => {
+            log::info!("user {:?} answer to previous question", q.from);
+            // TODO: maybe delete this message
+            bot.send_message(user_id, "You can answer only to current question")
+                .await
+                .context("failed to warn user, that he can only answer to current question")?;
+            return Ok(());
+        }
+        CallbackAnswer::UnknownItem => {
+            log::warn!("user {:?} taps unknown item '{response}'", q.from);
+            return Ok(());
+        }
+        CallbackAnswer::OrderingItem => (),
+        CallbackAnswer::OrderingDone(order) => {
+            bot.edit_message_text(user_id, current_message.unwrap(), order)
+                .await
+                .context("failed to send user his order")?;
+        }
+        CallbackAnswer::SelectionChanged => {
+            if let TelegramInteraction::MultiSelect { options, extra } =
+                &interaction.interactions[interaction.current]
+            {
+                let keyboard = multi_select_keyboard(
+                    interaction.current_id,
+                    options,
+                    extra,
+                    &interaction.chosen_items,
+                );
+                bot.edit_message_reply_markup(user_id, current_message.unwrap())
+                    .reply_markup(keyboard)
+                    .await
+                    .context("failed to update multi-select reply markup")?;
+            }
+            // Question isn't answered yet.
+            return Ok(());
+        }
+        CallbackAnswer::SelectionDone(selection) => {
+            bot.edit_message_text(
+                user_id,
+                current_message.unwrap(),
+                format!("You answer: {selection}"),
+            )
             .await
-            .context("failed to warn user, that he can only answer to current question")?;
-        return Ok(());
+            .context("failed to send user his selection")?;
+        }
+        CallbackAnswer::Answer => {
+            bot.edit_message_text(
+                user_id,
+                current_message.unwrap(),
+                format!("You answer: {response}"),
+            )
+            .await
+            .context("failed to send user his answer")?;
+        }
     }
 
-    bot.edit_message_text(
-        user_id,
-        current_message.unwrap(),
-        format!("You answer: {response}"),
-    )
-    .await
-    .context("failed to send user his answer")?;
+    progress_on_user_event(bot, user_id, &mut user_state.current_interaction)
+        .await
+        .context("failed to progress on user event")?;
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum CallbackAnswer {
+    /// Button of already answered question.
+    Outdated,
+    UnknownItem,
+    /// Not all items of ordering are tapped yet.
+    OrderingItem,
+    /// Message text with chosen order.
+    OrderingDone(String),
+    /// Option of multi-select is toggled.
+    SelectionChanged,
+    /// Labels of chosen options.
+    SelectionDone(String),
+    Answer,
+}
 
+/// Callback data of button, that confirms multi-select choice.
+const SELECTION_DONE: &str = "done";
+/// Prefix of callback data of multi-select `extra` buttons, followed by button index.
+const SELECTION_EXTRA: &str = "x";
+
+/// Tapped keyboard becomes outdated before anything is sent,
+/// so double tap can't record answer twice.
+fn record_callback_answer(
+    interaction: &mut UserInteraction,
+    rand_id: &str,
+    response: &str,
+) -> CallbackAnswer {
+    if rand_id != interaction.current_id.to_string() {
+        return CallbackAnswer::Outdated;
+    }
+    let UserInteraction {
+        interactions,
+        current,
+        current_id,
+        answers,
+        chosen_items,
+        ..
+    } = interaction;
+    if let TelegramInteraction::Ordering(items) = &interactions[*current] {
+        let Some(ix) = response
+            .parse::<usize>()
+            .ok()
+            .filter(|ix| *ix < items.len() && !chosen_items.contains(ix))
+        else {
+            return CallbackAnswer::UnknownItem;
+        };
+        *current_id = rand::random();
+        chosen_items.push(ix);
+        if chosen_items.len() < items.len() {
+            return CallbackAnswer::OrderingItem;
+        }
+        let order = ordering_message(items, chosen_items);
+        answers.push(
+            chosen_items
+                .drain(..)
+                .map(|ix| ix.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        *current += 1;
+        return CallbackAnswer::OrderingDone(order);
+    }
+    if let TelegramInteraction::MultiSelect { options, extra } = &interactions[*current] {
+        let (answer, selection) = if response == SELECTION_DONE {
+            if chosen_items.is_empty() {
+                return CallbackAnswer::UnknownItem;
+            }
+            chosen_items.sort();
+            let labels = option_labels(options);
+            let selection = chosen_items
+                .iter()
+                .map(|&ix| labels[ix].as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let answer = chosen_items
+                .drain(..)
+                .map(|ix| ix.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            (answer, selection)
+        } else if let Some(button) = response
+            .strip_prefix(SELECTION_EXTRA)
+            .and_then(|ix| ix.parse::<usize>().ok())
+            .and_then(|ix| extra.get(ix))
+        {
+            chosen_items.clear();
+            (button.clone(), button.clone())
+        } else {
+            let Some(ix) = response
+                .parse::<usize>()
+                .ok()
+                .filter(|ix| *ix < options.len())
+            else {
+                return CallbackAnswer::UnknownItem;
+            };
+            match chosen_items.iter().position(|&chosen| chosen == ix) {
+                Some(position) => {
+                    chosen_items.remove(position);
+                }
+                None => chosen_items.push(ix),
+            }
+            return CallbackAnswer::SelectionChanged;
+        };
+        *current_id = rand::random();
+        answers.push(answer);
+        *current += 1;
+        return CallbackAnswer::SelectionDone(selection);
+    }
+    *current_id = rand::random();
     answers.push(response.to_owned());
     *current += 1;
+    CallbackAnswer::Answer
+}
 
+/// Returns false if answer is not for current poll.
+fn record_poll_answer(interaction: &mut UserInteraction, poll_id: &str, option_ids: &[u8]) -> bool {
+    let TelegramInteraction::Poll { options, .. } = &interaction.interactions[interaction.current]
+    else {
+        return false;
+    };
+    if interaction.current_poll.as_deref() != Some(poll_id) {
+        return false;
+    }
+    // Quiz poll allows exactly one option.
+    let [option] = option_ids else {
+        return false;
+    };
+    if usize::from(*option) >= options.len() {
+        return false;
+    }
+    interaction.answers.push(option.to_string());
+    interaction.current_poll = None;
+    interaction.current += 1;
+    true
+}
+
+pub async fn poll_answer_handler(
+    bot: Bot,
+    answer: PollAnswer,
+    users_state: &DashMap<UserId, UserState>,
+) -> anyhow::Result<()> {
+    log::debug!("get poll answer {answer:?}");
+    let MaybeAnonymousUser::User(user) = &answer.voter else {
+        log::warn!("poll {} is answered anonymously", answer.poll_id);
+        return Ok(());
+    };
+    let user_id = user.id;
+    let Some(mut user_state) = users_state.get_mut(&user_id) else {
+        log::debug!("user {user_id} not in dialogue");
+        return Ok(());
+    };
+    let Some(interaction) = &mut user_state.current_interaction else {
+        log::info!("user {user_id} answers poll outside of dialogue");
+        return Ok(());
+    };
+    if !record_poll_answer(interaction, &answer.poll_id, &answer.option_ids) {
+        log::info!("user {user_id} answers to previous poll");
+        return Ok(());
+    }
     progress_on_user_event(bot, user_id, &mut user_state.current_interaction)
         .await
-        .context("failed to progress on user event")?;
-
+        .context("failed to progress on poll answer")?;
     Ok(())
 }
 
@@ -128,7 +546,11 @@ pub async fn progress_on_user_event(
         current_id,
         current_message,
         answers,
+        chosen_items,
+        current_poll,
         channel,
+        transient_messages,
+        cleanup,
     }) = current_user_interaction
     else {
         log::error!("unexpected idle state");
@@ -136,6 +558,9 @@ pub async fn progress_on_user_event(
     };
     loop {
         if *current >= interactions.len() {
+            if *cleanup {
+                clean_up_prompts(&bot, user_id, transient_messages).await;
+            }
             if let Some(channel) = channel.take() {
                 channel.send(answers.clone()).unwrap();
             }
@@ -143,10 +568,14 @@ pub async fn progress_on_user_event(
             break;
         }
         match &interactions[*current] {
-            TelegramInteraction::OneOf(vec) => {
+            TelegramInteraction::OneOf(options) => {
                 *current_id = rand::random();
 
-                let keyboard = InlineKeyboardMarkup::new(vec.iter().map(|label| {
+                let labels = option_labels(options);
+                send_option_previews(&bot, user_id, options, &labels)
+                    .await
+                    .context("failed to send option previews")?;
+                let keyboard = InlineKeyboardMarkup::new(labels.iter().map(|label| {
                     [InlineKeyboardButton::callback(
                         label,
                         format!("{current_id} {label}"),
@@ -158,6 +587,88 @@ pub async fn progress_on_user_event(
                     .await
                     .context("failed to send reply markup")?;
 
+                *current_message = Some(message.id);
+                transient_messages.push(TransientMessage {
+                    id: message.id,
+                    sent_at: Instant::now(),
+                });
+                break;
+            }
+            TelegramInteraction::MultiSelect { options, extra } => {
+                *current_id = rand::random();
+                chosen_items.clear();
+
+                let labels = option_labels(options);
+                send_option_previews(&bot, user_id, options, &labels)
+                    .await
+                    .context("failed to send option previews")?;
+                let message = bot
+                    .send_message(user_id, "choose all correct answers, then tap Done")
+                    .reply_markup(multi_select_keyboard(
+                        *current_id,
+                        options,
+                        extra,
+                        chosen_items,
+                    ))
+                    .await
+                    .context("failed to send multi-select reply markup")?;
+
+                *current_message = Some(message.id);
+                transient_messages.push(TransientMessage {
+                    id: message.id,
+                    sent_at: Instant::now(),
+                });
+                break;
+            }
+            TelegramInteraction::Ordering(items) => {
+                *current_id = rand::random();
+
+                let keyboard = InlineKeyboardMarkup::new(
+                    items
+                        .iter()
+                        .enumerate()
+                        .filter(|(ix, _)| !chosen_items.contains(ix))
+                        .map(|(ix, label)| {
+                            [InlineKeyboardButton::callback(
+                                label,
+                                format!("{current_id} {ix}"),
+                            )]
+                        }),
+                );
+                let text = ordering_message(items, chosen_items);
+                if chosen_items.is_empty() {
+                    let message = bot
+                        .send_message(user_id, text)
+                        .reply_markup(keyboard)
+                        .await
+                        .context("failed to send ordering reply markup")?;
+                    *current_message = Some(message.id);
+                    transient_messages.push(TransientMessage {
+                        id: message.id,
+                        sent_at: Instant::now(),
+                    });
+                } else {
+                    bot.edit_message_text(user_id, current_message.unwrap(), text)
+                        .reply_markup(keyboard)
+                        .await
+                        .context("failed to update ordering reply markup")?;
+                }
+                break;
+            }
+            TelegramInteraction::Poll {
+                question,
+                options,
+                correct,
+            } => {
+                let message = bot
+                    .send_poll(user_id, question, options.iter().cloned())
+                    .type_(PollType::Quiz)
+                    .correct_option_id(u8::try_from(*correct).unwrap())
+                    // Answers of anonymous polls are not sent to bot.
+                    .is_anonymous(false)
+                    .await
+                    .context("failed to send quiz poll")?;
+                *current_poll = message.poll().map(|poll| poll.id.clone());
                 *current_message = Some(message.id);
                 break;
             }
@@ -179,9 +690,41 @@ pub async fn progress_on_user_event(
                 break;
             }
             TelegramInteraction::Image(link) => {
-                bot.send_photo(user_id, InputFile::url(link.clone()))
+                let bot = &bot;
+                let sent = MEDIA_CACHE
+                    .send_or_upload(
+                        link,
+                        move |source| async move {
+                            let message = bot.send_photo(user_id, source.into()).await?;
+                            Ok(message
+                                .photo()
+                                .and_then(|sizes| sizes.last())
+                                .map(|size| size.file.id.clone()))
+                        },
+                        download_media,
+                    )
+                    .await;
+                if let Err(err) = sent {
+                    // Link is better than stuck interaction.
+                    log::error!("failed to send photo '{link}': {err:?}");
+                    bot.send_message(user_id, format!("Image: {link}"))
+                        .await
+                        .context("failed to send link of photo")?;
+                }
+                *current += 1;
+                answers.push(String::new());
+            }
+            TelegramInteraction::Album { images, caption } => {
+                send_album(&bot, user_id, images, caption)
+                    .await
+                    .context("failed to send album")?;
+                *current += 1;
+                answers.push(String::new());
+            }
+            TelegramInteraction::Audio(link) => {
+                send_audio(&bot, user_id, link)
                     .await
-                    .context("failed to send photo")?;
+                    .context("failed to send audio")?;
                 *current += 1;
                 answers.push(String::new());
             }
@@ -193,20 +736,747 @@ pub async fn progress_on_user_event(
                 *current += 1;
                 answers.push(String::new());
             }
+            TelegramInteraction::Document { name, contents } => {
+                bot.send_document(
+                    user_id,
+                    InputFile::memory(contents.clone().into_bytes()).file_name(name.clone()),
+                )
+                .await
+                .context("failed to send document")?;
+                *current += 1;
+                answers.push(String::new());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PromptCleanup {
+    Delete,
+    /// Message is too old to be deleted, so it's edited instead.
+    Collapse,
+}
+
+fn prompt_cleanup(messages: &[TransientMessage], now: Instant) -> Vec<(MessageId, PromptCleanup)> {
+    messages
+        .iter()
+        .map(|message| {
+            let cleanup = if now.saturating_duration_since(message.sent_at) < DELETE_WINDOW {
+                PromptCleanup::Delete
+            } else {
+                PromptCleanup::Collapse
+            };
+            (message.id, cleanup)
+        })
+        .collect()
+}
+
+/// Failed deletion falls back to editing.
+async fn clean_up_prompts(bot: &Bot, user_id: UserId, messages: &mut Vec<TransientMessage>) {
+    for (id, cleanup) in prompt_cleanup(messages, Instant::now()) {
+        let deleted =
+            cleanup == PromptCleanup::Delete && bot.delete_message(user_id, id).await.is_ok();
+        if !deleted {
+            bot.edit_message_text(user_id, id, COLLAPSED_PROMPT)
+                .await
+                .log_err();
+        }
+    }
+    messages.clear();
+}
+
+async fn send_audio(bot: &Bot, user_id: UserId, link: &Url) -> anyhow::Result<()> {
+    let voice = is_voice(link);
+    MEDIA_CACHE
+        .send(link, move |source| async move {
+            Ok(if voice {
+                let message = bot.send_voice(user_id, source.into()).await?;
+                message.voice().map(|voice| voice.file.id.clone())
+            } else {
+                let message = bot.send_audio(user_id, source.into()).await?;
+                message.audio().map(|audio| audio.file.id.clone())
+            })
+        })
+        .await
+}
+
+/// Every media option is sent with its label, so learner knows, which button chooses it.
+/// Text options are only buttons.
+async fn send_option_previews(
+    bot: &Bot,
+    user_id: UserId,
+    options: &[QuestionElement],
+    labels: &[String],
+) -> anyhow::Result<()> {
+    for (option, label) in options.iter().zip(labels) {
+        match option {
+            QuestionElement::Text(_) => (),
+            QuestionElement::Image(link) => {
+                send_album(bot, user_id, std::slice::from_ref(link), label).await?
+            }
+            QuestionElement::Audio(link) => {
+                send_markdown(bot, user_id, label).await?;
+                send_audio(bot, user_id, link).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Caption is shown under the first image.
+async fn send_album(
+    bot: &Bot,
+    user_id: UserId,
+    images: &[Url],
+    caption: &str,
+) -> anyhow::Result<()> {
+    let caption = (!caption.is_empty()).then(|| escape_telegram_message(caption));
+    for (chunk_ix, chunk) in images.chunks(MAX_ALBUM_LEN).enumerate() {
+        let caption = caption.clone().filter(|_| chunk_ix == 0);
+        // Media group should have at least 2 items.
+        if let [image] = chunk {
+            MEDIA_CACHE
+                .send(image, |source| {
+                    let caption = caption.clone();
+                    async move {
+                        let mut request = bot.send_photo(user_id, source.into());
+                        if let Some(caption) = caption {
+                            request = request.caption(caption).parse_mode(ParseMode::MarkdownV2);
+                        }
+                        let message = request.await?;
+                        Ok(message
+                            .photo()
+                            .and_then(|sizes| sizes.last())
+                            .map(|size| size.file.id.clone()))
+                    }
+                })
+                .await?;
+            continue;
+        }
+        let cached = chunk.iter().map(|image| MEDIA_CACHE.source(image));
+        let messages = match bot
+            .send_media_group(user_id, album_media(cached, caption.as_deref()))
+            .await
+        {
+            Ok(messages) => messages,
+            Err(err) => {
+                log::warn!("album with cached file ids is rejected: {err:?}");
+                chunk.iter().for_each(|image| MEDIA_CACHE.forget(image));
+                let urls = chunk.iter().cloned().map(MediaSource::Url);
+                bot.send_media_group(user_id, album_media(urls, caption.as_deref()))
+                    .await?
+            }
+        };
+        for (image, message) in chunk.iter().zip(&messages) {
+            if let Some(size) = message.photo().and_then(|sizes| sizes.last()) {
+                MEDIA_CACHE.remember(image, size.file.id.clone());
+            }
         }
     }
     Ok(())
 }
 
+fn album_media(
+    sources: impl Iterator<Item = MediaSource>,
+    caption: Option<&str>,
+) -> Vec<InputMedia> {
+    sources
+        .enumerate()
+        .map(|(ix, source)| {
+            let photo = InputMediaPhoto::new(source.into());
+            InputMedia::Photo(match caption.filter(|_| ix == 0) {
+                Some(caption) => photo.caption(caption).parse_mode(ParseMode::MarkdownV2),
+                None => photo,
+            })
+        })
+        .collect()
+}
+
+/// Telegram limit of photo, uploaded by bot.
+const MAX_DOWNLOAD_SIZE: usize = 10 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Link is written by course author, so only public hosts are fetched. Client is pinned to
+/// checked address and doesn't follow redirects, so neither DNS nor redirect can lead it
+/// to local network.
+async fn download_media(url: Url) -> anyhow::Result<Vec<u8>> {
+    let port = url.port_or_known_default().context("link has no port")?;
+    let addresses = match url.host().context("link has no host")? {
+        url::Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+            .await
+            .with_context(|| format!("failed to resolve '{domain}'"))?
+            .collect::<Vec<_>>(),
+        url::Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+        url::Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+    };
+    let Some(&address) = addresses.first() else {
+        anyhow::bail!("host of '{url}' has no addresses");
+    };
+    if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+        anyhow::bail!("host of '{url}' is not public: {address}");
+    }
+
+    let mut client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(DOWNLOAD_TIMEOUT);
+    if let Some(domain) = url.domain() {
+        client = client.resolve(domain, address);
+    }
+    let mut response = client.build()?.get(url).send().await?;
+    let status = response.status();
+    anyhow::ensure!(status.is_success(), "server responded with {status}");
+    if let Some(len) = response.content_length() {
+        anyhow::ensure!(
+            len <= MAX_DOWNLOAD_SIZE as u64,
+            "file is too big: {len} bytes"
+        );
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        anyhow::ensure!(
+            bytes.len() + chunk.len() <= MAX_DOWNLOAD_SIZE,
+            "file is bigger than {MAX_DOWNLOAD_SIZE} bytes"
+        );
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Address, that isn't loopback, private, link-local or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            let shared = first == 100 && (64..128).contains(&second);
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || shared
+                || first == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Telegram shows only OGG with OPUS as voice message.
+fn is_voice(link: &Url) -> bool {
+    let path = link.path().to_lowercase();
+    path.ends_with(".ogg") || path.ends_with(".oga")
+}
+
+/// Chosen options are marked, "Done" and `extra` buttons go after options.
+fn multi_select_keyboard(
+    current_id: u64,
+    options: &[QuestionElement],
+    extra: &[String],
+    chosen_items: &[usize],
+) -> InlineKeyboardMarkup {
+    let options = option_labels(options)
+        .into_iter()
+        .enumerate()
+        .map(|(ix, label)| {
+            let text = if chosen_items.contains(&ix) {
+                format!("✅ {label}")
+            } else {
+                label
+            };
+            [InlineKeyboardButton::callback(
+                text,
+                format!("{current_id} {ix}"),
+            )]
+        });
+    let done = [InlineKeyboardButton::callback(
+        "Done",
+        format!("{current_id} {SELECTION_DONE}"),
+    )];
+    let extra = extra.iter().enumerate().map(|(ix, text)| {
+        [InlineKeyboardButton::callback(
+            text,
+            format!("{current_id} {SELECTION_EXTRA}{ix}"),
+        )]
+    });
+    InlineKeyboardMarkup::new(options.chain([done]).chain(extra))
+}
+
+fn ordering_message(items: &[String], chosen_items: &[usize]) -> String {
+    if chosen_items.is_empty() {
+        return "tap items in the right order".into();
+    }
+    let chosen = chosen_items
+        .iter()
+        .map(|&ix| items[ix].as_str())
+        .collect::<Vec<_>>();
+    format!("Your order: {}", chosen.join(" → "))
+}
+
 pub fn escape_telegram_message(text: &str) -> String {
     text.replace('.', r#"\."#)
         .replace('!', r#"\!"#)
         .replace("(", r#"\("#)
         .replace(")", r#"\)"#)
 }
+/// Place in text, that Telegram would reject as MarkdownV2 after [`escape_telegram_message`].
+#[derive(Debug, PartialEq)]
+pub struct MarkdownProblem {
+    /// Source line with problem.
+    pub line: String,
+    pub message: String,
+}
+
+/// Characters, that should be escaped outside of entities.
+const MARKDOWN_RESERVED: &[char] = &['-', '#', '+', '=', '|', '{', '}', '>', '[', ']'];
+/// Longer markers first, so `__` isn't read as two `_`.
+const MARKDOWN_MARKERS: &[&str] = &["```", "`", "||", "__", "*", "_", "~"];
+
+fn starts_with_at(chars: &[char], ix: usize, marker: &str) -> bool {
+    chars[ix..]
+        .iter()
+        .copied()
+        .take(marker.len())
+        .eq(marker.chars())
+}
+
+/// Finds reserved characters and entities without closing marker.
+pub fn lint_markdown(text: &str) -> Vec<MarkdownProblem> {
+    let source_lines = text.lines().collect::<Vec<_>>();
+    let chars = escape_telegram_message(text).chars().collect::<Vec<_>>();
+    let mut problems = Vec::new();
+    let mut push = |line: usize, message: String| {
+        let line = source_lines
+            .get(line)
+            .copied()
+            .unwrap_or_default()
+            .to_owned();
+        problems.push(MarkdownProblem { line, message });
+    };
+    let mut code: Option<(&str, usize)> = None;
+    let mut open: Vec<(&str, usize)> = Vec::new();
+    let mut line = 0;
+    let mut ix = 0;
+    while ix < chars.len() {
+        let c = chars[ix];
+        if c == '\\' {
+            if chars.get(ix + 1) == Some(&'\n') {
+                line += 1;
+            }
+            ix += 2;
+            continue;
+        }
+        if c == '\n' {
+            line += 1;
+            ix += 1;
+            continue;
+        }
+        if let Some((marker, _)) = code {
+            if starts_with_at(&chars, ix, marker) {
+                code = None;
+                ix += marker.len();
+            } else {
+                ix += 1;
+            }
+            continue;
+        }
+        match MARKDOWN_MARKERS
+            .iter()
+            .find(|marker| starts_with_at(&chars, ix, marker))
+        {
+            Some(&marker @ ("```" | "`")) => {
+                code = Some((marker, line));
+                ix += marker.len();
+            }
+            Some(&marker) => {
+                match open.iter().position(|(opened, _)| *opened == marker) {
+                    Some(pos) => {
+                        open.remove(pos);
+                    }
+                    None => open.push((marker, line)),
+                }
+                ix += marker.len();
+            }
+            None => {
+                if MARKDOWN_RESERVED.contains(&c) {
+                    push(line, format!("'{c}' should be escaped as '\\{c}'"));
+                }
+                ix += 1;
+            }
+        }
+    }
+    for (marker, line) in code.into_iter().chain(open) {
+        push(line, format!("'{marker}' is not closed"));
+    }
+    problems.dedup();
+    problems
+}
+
 pub async fn send_markdown(bot: &Bot, user_id: UserId, text: &str) -> anyhow::Result<()> {
     bot.send_message(user_id, escape_telegram_message(text))
         .parse_mode(ParseMode::MarkdownV2)
         .await?;
     Ok(())
 }
+
+/// All characters, MarkdownV2 gives special meaning to.
+const MARKDOWN_SPECIAL: &[char] = &[
+    '\\', '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.', '!',
+];
+
+/// Unlike [`escape_telegram_message`], escapes everything, so text can't add formatting.
+pub fn escape_markdown_literal(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_SPECIAL.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// For texts, set by course owners. They are shown exactly as written.
+pub async fn send_literal(bot: &Bot, user_id: UserId, text: &str) -> anyhow::Result<()> {
+    bot.send_message(user_id, escape_markdown_literal(text))
+        .parse_mode(ParseMode::MarkdownV2)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interaction_types::task::BLANK;
+
+    #[test]
+    fn only_public_hosts_are_downloaded() {
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn local_links_are_not_downloaded() {
+        for link in [
+            "http://127.0.0.1/image.png",
+            "http://[::1]:8080/image.png",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://localhost/image.png",
+        ] {
+            let err = download_media(link.parse().unwrap()).await.unwrap_err();
+            assert!(err.to_string().contains("not public"), "{link}: {err}");
+        }
+    }
+
+    #[test]
+    fn title_is_rendered_literally() {
+        let title = r"*Free* [prize](http://x.y) _now_ `code` \ ||spoiler|| ~a~ -> #1!";
+        let escaped = escape_markdown_literal(title);
+        assert_eq!(
+            escaped,
+            r"\*Free\* \[prize\]\(http://x\.y\) \_now\_ \`code\` \\ \|\|spoiler\|\| \~a\~ \-\> \#1\!"
+        );
+        // Every special character is escaped, so nothing is left for Telegram to format.
+        let mut chars = escaped.chars();
+        let mut unescaped = String::new();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                unescaped.extend(chars.next());
+            } else {
+                assert!(!MARKDOWN_SPECIAL.contains(&c), "'{c}' is not escaped");
+                unescaped.push(c);
+            }
+        }
+        assert_eq!(unescaped, title);
+    }
+
+    #[test]
+    fn unescaped_markdown_is_flagged() {
+        assert_eq!(
+            lint_markdown("Capital of France?\nFrance - Paris"),
+            vec![MarkdownProblem {
+                line: "France - Paris".into(),
+                message: r"'-' should be escaped as '\-'".into(),
+            }]
+        );
+        assert_eq!(
+            lint_markdown("Is it *bold?"),
+            vec![MarkdownProblem {
+                line: "Is it *bold?".into(),
+                message: "'*' is not closed".into(),
+            }]
+        );
+        assert!(
+            lint_markdown(r"Clean *bold*, __underline__ and `a - b` (really)\-fine. ||spoiler||")
+                .is_empty()
+        );
+        assert!(lint_markdown(&format!("Fill {BLANK}!")).is_empty());
+    }
+
+    #[test]
+    fn short_texts_are_batched() {
+        let image = TelegramInteraction::Image("https://example.com/image.png".parse().unwrap());
+        let interactions: Vec<TelegramInteraction> = vec![
+            "first".into(),
+            "second".into(),
+            "third".into(),
+            image,
+            "fourth".into(),
+        ];
+        let batched = batch_texts(interactions);
+        assert_eq!(batched.len(), 3);
+        assert!(
+            matches!(&batched[0], TelegramInteraction::Text(text) if text == "first\n\nsecond\n\nthird")
+        );
+        assert!(matches!(&batched[1], TelegramInteraction::Image(_)));
+        assert!(matches!(&batched[2], TelegramInteraction::Text(text) if text == "fourth"));
+    }
+
+    #[test]
+    fn poll_answer_is_recorded() {
+        let mut interaction = UserInteraction {
+            interactions: vec![
+                "question".into(),
+                TelegramInteraction::Poll {
+                    question: "2 + 2".into(),
+                    options: vec!["3".into(), "4".into()],
+                    correct: 1,
+                },
+            ],
+            current: 1,
+            current_id: 0,
+            current_message: None,
+            answers: vec![String::new()],
+            chosen_items: Vec::new(),
+            current_poll: Some("poll".into()),
+            channel: None,
+            transient_messages: Vec::new(),
+            cleanup: false,
+        };
+        assert!(!record_poll_answer(&mut interaction, "old poll", &[1]));
+        assert!(!record_poll_answer(&mut interaction, "poll", &[2]));
+        assert!(!record_poll_answer(&mut interaction, "poll", &[]));
+        assert_eq!(interaction.current, 1);
+
+        assert!(record_poll_answer(&mut interaction, "poll", &[1]));
+        assert_eq!(interaction.answers, vec![String::new(), "1".into()]);
+        assert_eq!(interaction.current, 2);
+        assert_eq!(interaction.current_poll, None);
+    }
+
+    #[test]
+    fn double_tap_is_recorded_once() {
+        let mut interaction = UserInteraction {
+            interactions: vec![
+                TelegramInteraction::OneOf(vec!["yes".into(), "no".into()]),
+                TelegramInteraction::Ordering(vec!["a".into(), "b".into()]),
+            ],
+            current: 0,
+            current_id: 7,
+            current_message: None,
+            answers: Vec::new(),
+            chosen_items: Vec::new(),
+            current_poll: None,
+            channel: None,
+            transient_messages: Vec::new(),
+            cleanup: false,
+        };
+        assert_eq!(
+            record_callback_answer(&mut interaction, "7", "yes"),
+            CallbackAnswer::Answer
+        );
+        assert_eq!(
+            record_callback_answer(&mut interaction, "7", "yes"),
+            CallbackAnswer::Outdated
+        );
+        assert_eq!(interaction.answers, vec!["yes".to_owned()]);
+        assert_eq!(interaction.current, 1);
+
+        let id = interaction.current_id.to_string();
+        assert_eq!(
+            record_callback_answer(&mut interaction, &id, "1"),
+            CallbackAnswer::OrderingItem
+        );
+        assert_eq!(
+            record_callback_answer(&mut interaction, &id, "0"),
+            CallbackAnswer::Outdated
+        );
+        assert_eq!(interaction.chosen_items, vec![1]);
+    }
+
+    #[test]
+    fn multi_select_toggles_options() {
+        let mut interaction = UserInteraction {
+            interactions: vec![
+                TelegramInteraction::MultiSelect {
+                    options: vec!["2".into(), "4".into(), "3".into()],
+                    extra: vec!["I don't know".into()],
+                },
+                TelegramInteraction::MultiSelect {
+                    options: vec!["a".into(), "b".into()],
+                    extra: vec!["I don't know".into()],
+                },
+            ],
+            current: 0,
+            current_id: 7,
+            current_message: None,
+            answers: Vec::new(),
+            chosen_items: Vec::new(),
+            current_poll: None,
+            channel: None,
+            transient_messages: Vec::new(),
+            cleanup: false,
+        };
+        assert_eq!(
+            record_callback_answer(&mut interaction, "7", SELECTION_DONE),
+            CallbackAnswer::UnknownItem
+        );
+        for tap in ["2", "1", "0", "1", "3"] {
+            let expected = if tap == "3" {
+                CallbackAnswer::UnknownItem
+            } else {
+                CallbackAnswer::SelectionChanged
+            };
+            assert_eq!(record_callback_answer(&mut interaction, "7", tap), expected);
+        }
+        assert_eq!(interaction.chosen_items, vec![2, 0]);
+        let options = ["2", "4", "3"].map(QuestionElement::from);
+        let keyboard = multi_select_keyboard(7, &options, &[], &[2, 0]);
+        let texts = keyboard
+            .inline_keyboard
+            .iter()
+            .map(|row| row[0].text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["✅ 2", "4", "✅ 3", "Done"]);
+
+        assert_eq!(
+            record_callback_answer(&mut interaction, "7", SELECTION_DONE),
+            CallbackAnswer::SelectionDone("2, 3".into())
+        );
+        assert_eq!(interaction.answers, vec!["0 2".to_owned()]);
+        assert!(interaction.chosen_items.is_empty());
+
+        let id = interaction.current_id.to_string();
+        assert_eq!(
+            record_callback_answer(&mut interaction, &id, "0"),
+            CallbackAnswer::SelectionChanged
+        );
+        assert_eq!(
+            record_callback_answer(&mut interaction, &id, "x0"),
+            CallbackAnswer::SelectionDone("I don't know".into())
+        );
+        assert_eq!(interaction.answers[1], "I don't know");
+        assert!(interaction.chosen_items.is_empty());
+        assert_eq!(interaction.current, 2);
+    }
+
+    #[test]
+    fn old_prompts_are_collapsed() {
+        let now = Instant::now();
+        let messages = [
+            TransientMessage {
+                id: MessageId(1),
+                sent_at: now,
+            },
+            TransientMessage {
+                id: MessageId(2),
+                sent_at: now.checked_sub(DELETE_WINDOW).unwrap(),
+            },
+        ];
+        assert_eq!(
+            prompt_cleanup(&messages, now),
+            vec![
+                (MessageId(1), PromptCleanup::Delete),
+                (MessageId(2), PromptCleanup::Collapse),
+            ]
+        );
+    }
+
+    #[test]
+    fn ogg_is_sent_as_voice() {
+        assert!(is_voice(&"https://example.com/word.OGG".parse().unwrap()));
+        assert!(is_voice(
+            &"https://example.com/word.oga?v=2".parse().unwrap()
+        ));
+        assert!(!is_voice(&"https://example.com/word.mp3".parse().unwrap()));
+    }
+
+    #[test]
+    fn parsed_audio_is_sent_by_extension() {
+        for (element, voice) in [
+            ("!a[https://example.com/word.ogg]", true),
+            ("!a[https://example.com/word.mp3]", false),
+        ] {
+            let element = element.parse::<QuestionElement>().unwrap();
+            let TelegramInteraction::Audio(link) = TelegramInteraction::from(element) else {
+                panic!("audio should be sent as audio");
+            };
+            assert_eq!(is_voice(&link), voice, "{link}");
+        }
+    }
+
+    #[test]
+    fn compact_question_is_one_message() {
+        let image =
+            |name: &str| -> Url { format!("https://example.com/{name}.png").parse().unwrap() };
+        let question = vec![
+            QuestionElement::Text("first".into()),
+            QuestionElement::Image(image("a")),
+            QuestionElement::Text("second".into()),
+            QuestionElement::Image(image("b")),
+        ];
+        let compact = compact_question(question.clone());
+        assert_eq!(compact.len(), 1);
+        assert!(matches!(
+            &compact[0],
+            TelegramInteraction::Album { images, caption }
+                if images == &vec![image("a"), image("b")] && caption == "first\n\nsecond"
+        ));
+
+        let long = vec![
+            QuestionElement::Text("a".repeat(MAX_CAPTION_LEN + 1)),
+            QuestionElement::Image(image("a")),
+        ];
+        let compact = compact_question(long);
+        assert_eq!(compact.len(), 2);
+        assert!(matches!(&compact[0], TelegramInteraction::Text(_)));
+        assert!(
+            matches!(&compact[1], TelegramInteraction::Album { caption, .. } if caption.is_empty())
+        );
+    }
+
+    #[test]
+    fn long_texts_are_not_batched() {
+        let long = "a".repeat(MAX_MESSAGE_LEN / 2 + 1);
+        let batched = batch_texts([
+            TelegramInteraction::Text(long.clone()),
+            TelegramInteraction::Text(long),
+            "short".into(),
+        ]);
+        assert_eq!(batched.len(), 2);
+    }
+}
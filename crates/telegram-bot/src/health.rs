@@ -0,0 +1,156 @@
+//! Liveness and readiness probes for deployment monitoring.
+//! Enabled by `HEALTH_PORT` variable.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use teloxide_core::{Bot, prelude::*};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use crate::database::db_ping;
+
+/// How often Telegram is checked with `get_me`.
+const TELEGRAM_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Telegram is considered reachable, if `get_me` succeeded this recently.
+const TELEGRAM_MAX_AGE: Duration = Duration::from_secs(120);
+/// Database, that is busy this long, is considered unreachable.
+const DB_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+static LAST_GET_ME: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, PartialEq, Eq)]
+struct Response {
+    status: u16,
+    body: &'static str,
+}
+
+/// `/healthz` is ok while process is alive.
+/// `/readyz` is ok if database is reachable and Telegram answered recently.
+fn route(path: &str, db_ok: bool, last_get_me: Option<Instant>, now: Instant) -> Response {
+    match path {
+        "/healthz" => Response {
+            status: 200,
+            body: "ok",
+        },
+        "/readyz" => {
+            let telegram_ok = last_get_me
+                .is_some_and(|last| now.saturating_duration_since(last) <= TELEGRAM_MAX_AGE);
+            match (db_ok, telegram_ok) {
+                (true, true) => Response {
+                    status: 200,
+                    body: "ready",
+                },
+                (false, _) => Response {
+                    status: 503,
+                    body: "database is unreachable",
+                },
+                (true, false) => Response {
+                    status: 503,
+                    body: "telegram is unreachable",
+                },
+            }
+        }
+        _ => Response {
+            status: 404,
+            body: "not found",
+        },
+    }
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Service Unavailable",
+    }
+}
+
+/// Getting connection blocks, while pool is exhausted, so it's done off async workers.
+async fn db_reachable() -> bool {
+    tokio::time::timeout(DB_PING_TIMEOUT, tokio::task::spawn_blocking(db_ping))
+        .await
+        .is_ok_and(|ping| ping.unwrap_or(false))
+}
+
+async fn check_telegram(bot: Bot) {
+    let mut interval = tokio::time::interval(TELEGRAM_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match bot.get_me().await {
+            Ok(_) => *LAST_GET_ME.lock().unwrap() = Some(Instant::now()),
+            Err(err) => log::warn!("health check: get_me failed: {err}"),
+        }
+    }
+}
+
+/// Serves probes forever. Only request line is read, everything else is ignored.
+pub async fn serve(port: u16, bot: Bot) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    log::info!("Health checks are served on port {port}");
+    tokio::spawn(check_telegram(bot));
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut buffer = [0; 1024];
+            let Ok(read) = stream.read(&mut buffer).await else {
+                return;
+            };
+            let request = String::from_utf8_lossy(&buffer[..read]);
+            // Request line is `GET /path HTTP/1.1`.
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let db_ok = path == "/readyz" && db_reachable().await;
+            let last_get_me = *LAST_GET_ME.lock().unwrap();
+            let Response { status, body } = route(path, db_ok, last_get_me, Instant::now());
+            let response = format!(
+                "HTTP/1.1 {status} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                status_text(status),
+                body.len(),
+            );
+            if let Err(err) = stream.write_all(response.as_bytes()).await {
+                log::debug!("health check: failed to respond: {err}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn readiness_depends_on_db_and_telegram() {
+        let now = Instant::now();
+        let recent = Some(now);
+        let stale = now.checked_sub(TELEGRAM_MAX_AGE * 2);
+
+        assert_eq!(route("/healthz", false, None, now).status, 200);
+        assert_eq!(route("/readyz", true, recent, now).status, 200);
+        assert_eq!(
+            route("/readyz", false, recent, now),
+            Response {
+                status: 503,
+                body: "database is unreachable"
+            }
+        );
+        assert_eq!(
+            route("/readyz", true, stale, now),
+            Response {
+                status: 503,
+                body: "telegram is unreachable"
+            }
+        );
+        assert_eq!(route("/readyz", true, None, now).status, 503);
+        assert_eq!(route("/metrics", true, recent, now).status, 404);
+    }
+
+    #[tokio::test]
+    async fn readyz_ok_when_db_reachable() {
+        assert!(db_ping());
+        assert!(db_reachable().await);
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use teloxide_core::{
+    prelude::*,
+    types::{
+        InlineKeyboardButton, InlineKeyboardMarkup, InlineQuery, InlineQueryResult,
+        InlineQueryResultArticle, InputMessageContent, InputMessageContentText,
+    },
+};
+use url::Url;
+
+use crate::database::{Course, CourseId, db_public_courses};
+
+/// Telegram doesn't accept more results for one query.
+const MAX_RESULTS: usize = 50;
+
+static BOT_USERNAME: OnceLock<String> = OnceLock::new();
+
+async fn bot_username(bot: &Bot) -> anyhow::Result<&'static str> {
+    if let Some(username) = BOT_USERNAME.get() {
+        return Ok(username);
+    }
+    let me = bot.get_me().await.context("failed to get bot info")?;
+    Ok(BOT_USERNAME.get_or_init(|| me.username().to_owned()))
+}
+
+pub fn course_link(bot_username: &str, CourseId(course_id): CourseId) -> Url {
+    format!("https://t.me/{bot_username}?start=course_{course_id}")
+        .parse()
+        .unwrap()
+}
+
+/// Public courses, whose title contains `query`. Empty query shows the most recent courses.
+fn inline_results(
+    courses: &[(CourseId, Course)],
+    query: &str,
+    bot_username: &str,
+) -> Vec<InlineQueryResult> {
+    let query = query.trim().to_lowercase();
+    courses
+        .iter()
+        .filter_map(|(course_id, course)| Some((course_id, course.title()?)))
+        .filter(|(_, title)| title.to_lowercase().contains(&query))
+        .take(MAX_RESULTS)
+        .map(|(&course_id, title)| {
+            let text = format!(
+                "{title}\n\nJoin with /course {} in @{bot_username}",
+                course_id.0
+            );
+            let article = InlineQueryResultArticle::new(
+                course_id.0.to_string(),
+                title,
+                InputMessageContent::Text(InputMessageContentText::new(text)),
+            )
+            .description(format!("Course {}", course_id.0))
+            .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::url(
+                "Open course",
+                course_link(bot_username, course_id),
+            )]]));
+            InlineQueryResult::Article(article)
+        })
+        .collect()
+}
+
+pub async fn inline_query_handler(bot: Bot, q: InlineQuery) -> anyhow::Result<()> {
+    log::debug!("get inline query '{}' from user {}", q.query, q.from.id);
+    let bot_username = bot_username(&bot).await?;
+    let results = inline_results(&db_public_courses()?, &q.query, bot_username);
+    bot.answer_inline_query(q.id, results)
+        .await
+        .context("failed to answer inline query")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use course_graph::graph::CourseGraph;
+
+    use super::*;
+    use crate::{interaction_types::deque::Deque, settings::CourseSettings};
+
+    fn course(description: &str) -> Course {
+        Course {
+            owner_id: UserId(219_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings {
+                public: true,
+                ..Default::default()
+            },
+            description: description.into(),
+        }
+    }
+
+    #[test]
+    fn inline_results_match_title() {
+        let courses = [
+            (
+                CourseId(2),
+                course("# World capitals\nCountries and their capitals."),
+            ),
+            (CourseId(1), course("Rust basics")),
+            (CourseId(3), course("")),
+        ];
+
+        let results = inline_results(&courses, "CAPITALS", "course_bot");
+        assert_eq!(results.len(), 1);
+        let InlineQueryResult::Article(article) = &results[0] else {
+            panic!("result should be article");
+        };
+        assert_eq!(article.id, "2");
+        assert_eq!(article.title, "World capitals");
+        let InputMessageContent::Text(content) = &article.input_message_content else {
+            panic!("article should contain text");
+        };
+        assert!(content.message_text.contains("/course 2"));
+
+        // Courses without title can't be found.
+        assert_eq!(inline_results(&courses, "", "course_bot").len(), 2);
+    }
+}
@@ -2,12 +2,18 @@ use std::collections::BTreeMap;
 
 use rand::Rng;
 
-use super::{Task, task::TaskParseError};
+use super::{
+    Task,
+    task::{OptionMarkers, TaskParseError},
+};
 use crate::check;
 
 const USAGE: &str = "Card should follow this syntax:
 # Name
 name
+# Distractors       <- optional
+- wrong option      <- used by tasks with only correct option
+...
 ## Task 1
 task syntax
 ## Task 2
@@ -26,6 +32,10 @@ pub enum CardParseError {
     TaskParseError(#[from] TaskParseError),
     #[error("{USAGE}. Card should have at least 1 task")]
     NoTasks,
+    #[error(
+        "{USAGE}. Distractors section should have at least 1 option, starting with incorrect marker('- ' by default)"
+    )]
+    EmptyDistractors,
     #[error(
         "{USAGE}. Task token should have '## Task ID' syntax, where ID is unique(for card) number. Line {line_ix}"
     )]
@@ -36,17 +46,21 @@ pub enum CardParseError {
 #[derive(Debug)]
 pub struct Card {
     pub name: String,
+    pub distractors: Vec<String>,
     pub tasks: BTreeMap<u16, Task>,
 }
 
-pub fn random_task(tasks: &BTreeMap<u16, Task>, mut rng: impl Rng) -> &Task {
-    let len = tasks.len();
-    tasks.values().nth(rng.random_range(0..len)).unwrap()
+/// None if card has no tasks.
+pub fn random_task(tasks: &BTreeMap<u16, Task>, mut rng: impl Rng) -> Option<&Task> {
+    if tasks.is_empty() {
+        return None;
+    }
+    tasks.values().nth(rng.random_range(0..tasks.len()))
 }
 
 impl Card {
     #[allow(dead_code)]
-    pub fn random_task(&self, rng: impl Rng) -> &Task {
+    pub fn random_task(&self, rng: impl Rng) -> Option<&Task> {
         random_task(&self.tasks, rng)
     }
 }
@@ -55,6 +69,14 @@ impl Card {
     pub fn from_str(
         input: impl AsRef<str>,
         multiline_messages: bool,
+    ) -> Result<Self, CardParseError> {
+        Self::parse(input, multiline_messages, &OptionMarkers::default())
+    }
+    /// Distractors also use incorrect option marker.
+    pub fn parse(
+        input: impl AsRef<str>,
+        multiline_messages: bool,
+        markers: &OptionMarkers,
     ) -> Result<Self, CardParseError> {
         let input = input.as_ref().trim();
         check!(!input.is_empty(), CardParseError::EmptyInput);
@@ -79,6 +101,30 @@ impl Card {
             .collect::<Vec<_>>()
             .into_iter();
 
+        let mut distractors = Vec::new();
+        if lines
+            .as_slice()
+            .first()
+            .is_some_and(|line| line.to_lowercase() == "# distractors")
+        {
+            lines.next();
+            line_ix += 1;
+            while let Some(line) = lines
+                .as_slice()
+                .first()
+                .and_then(|line| markers.strip_incorrect(line))
+            {
+                distractors.push(line.trim().to_owned());
+                lines.next();
+                line_ix += 1;
+            }
+            check!(!distractors.is_empty(), CardParseError::EmptyDistractors);
+            while lines.as_slice().first().is_some_and(|line| line.is_empty()) {
+                lines.next();
+                line_ix += 1;
+            }
+        }
+
         let mut tasks = BTreeMap::new();
 
         line_ix += 1;
@@ -99,7 +145,10 @@ impl Card {
             };
             match new_number {
                 Some(nmbr) => {
-                    let prev = tasks.insert(number, Task::from_str(task_text, multiline_messages)?);
+                    let prev = tasks.insert(
+                        number,
+                        Task::parse(task_text, multiline_messages, &distractors, markers)?,
+                    );
                     check!(
                         prev.is_none(),
                         CardParseError::IncorrectTaskToken { line_ix }
@@ -114,14 +163,21 @@ impl Card {
             }
         }
         {
-            let prev = tasks.insert(number, Task::from_str(task_text, multiline_messages)?);
+            let prev = tasks.insert(
+                number,
+                Task::parse(task_text, multiline_messages, &distractors, markers)?,
+            );
             check!(
                 prev.is_none(),
                 CardParseError::IncorrectTaskToken { line_ix }
             );
         }
         check!(!tasks.is_empty(), CardParseError::NoTasks);
-        Ok(Self { name, tasks })
+        Ok(Self {
+            name,
+            distractors,
+            tasks,
+        })
     }
 }
 
@@ -134,3 +190,81 @@ fn parse_task_token(input: &str) -> Option<Option<u16>> {
         .strip_prefix("## task ")
         .map(|tail| tail.trim().parse::<u16>().ok())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interaction_types::{
+        task::{POOL_DISTRACTORS, TaskKind},
+        telegram_interaction::{QuestionElement, option_labels},
+    };
+
+    const WITH_DISTRACTORS: &str = "# Name
+capitals
+# Distractors
+- Paris
+- Berlin
+- berlin
+- Madrid
+- Rome
+
+## Task 1
+Capital of Italy?
+
+* Rome
+## Task 2
+Capital of Spain?
+
+* Madrid
+- Lisbon
+";
+
+    #[test]
+    fn parse_distractors() {
+        let card = Card::from_str(WITH_DISTRACTORS, false).unwrap();
+        assert_eq!(
+            card.distractors,
+            vec!["Paris", "Berlin", "berlin", "Madrid", "Rome"]
+        );
+        let TaskKind::OneOf { options, pool, .. } = &card.tasks[&1].kind else {
+            panic!("should be one of task");
+        };
+        assert_eq!(options, &vec![QuestionElement::from("Rome")]);
+        assert_eq!(pool, &card.distractors);
+        assert_eq!(
+            option_labels(&card.tasks[&2].sample_options(&mut rand::rng())),
+            vec!["Madrid", "Lisbon"]
+        );
+    }
+
+    #[test]
+    fn task_without_distractors_needs_wrong_option() {
+        let input = "# Name\ncapitals\n## Task 1\nCapital of Italy?\n\n* Rome";
+        assert!(matches!(
+            Card::from_str(input, false),
+            Err(CardParseError::TaskParseError(
+                TaskParseError::NoIncorrectOption
+            ))
+        ));
+        let input = "# Name\ncapitals\n# Distractors\n\n## Task 1\nQ\n\n* Rome";
+        assert!(matches!(
+            Card::from_str(input, false),
+            Err(CardParseError::EmptyDistractors)
+        ));
+    }
+
+    #[test]
+    fn sampled_distractors_are_distinct() {
+        let card = Card::from_str(WITH_DISTRACTORS, false).unwrap();
+        let task = &card.tasks[&1];
+        for _ in 0..100 {
+            let options = option_labels(&task.sample_options(&mut rand::rng()));
+            assert_eq!(options[0], "Rome");
+            assert_eq!(options.len(), 1 + POOL_DISTRACTORS);
+            let mut normalized = options.iter().map(|x| x.to_lowercase()).collect::<Vec<_>>();
+            normalized.sort();
+            normalized.dedup();
+            assert_eq!(normalized.len(), options.len());
+        }
+    }
+}
@@ -1,12 +1,15 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
-use course_graph::graph::CourseGraph;
+use course_graph::{
+    graph::{CardDiff, CourseGraph},
+    parsing::normalize_card_name,
+};
 use serde::{
     Deserialize, Serialize,
     de::{Error, Visitor},
 };
 
-use super::{Card, Task};
+use super::{Card, Task, task::OptionMarkers};
 use crate::check;
 
 #[derive(Debug, Clone)]
@@ -16,6 +19,7 @@ pub struct Deque {
 }
 
 const USAGE: &str = "Deque should follow this syntax:
+# Option markers: * -    <- optional, changes option prefixes of all cards
 card syntax
 -----
 other card
@@ -28,53 +32,279 @@ pub enum DequeParseError {
     NoCards,
     #[error(transparent)]
     CardParseError(#[from] super::card::CardParseError),
-    #[error("{USAGE}. Each card should have unique name")]
-    CardNameRepeated,
+    /// Names are compared after [`normalize_card_name`].
+    #[error("{USAGE}. Each card should have unique name, but '{0}' repeats")]
+    CardNameRepeated(String),
+    #[error(
+        "{USAGE}. Option markers header should contain 2 different markers without spaces, like '# Option markers: + ~'"
+    )]
+    InvalidOptionMarkers,
+    #[error("Card '{card}': {error}")]
+    InvalidLink {
+        card: String,
+        error: super::task::TaskParseError,
+    },
+}
+
+const OPTION_MARKERS_HEADER: &str = "# option markers:";
+
+/// Parses optional option markers header. Returns markers and input without header.
+fn parse_option_markers(input: &str) -> Result<(OptionMarkers, &str), DequeParseError> {
+    let trimmed = input.trim_start();
+    let (first_line, rest) = trimmed.split_once('\n').unwrap_or((trimmed, ""));
+    let first_line = first_line.trim();
+    let Some(markers) = first_line
+        .get(..OPTION_MARKERS_HEADER.len())
+        .filter(|header| header.eq_ignore_ascii_case(OPTION_MARKERS_HEADER))
+        .map(|_| &first_line[OPTION_MARKERS_HEADER.len()..])
+    else {
+        return Ok((OptionMarkers::default(), input));
+    };
+    let markers = markers.split_whitespace().collect::<Vec<_>>();
+    let [correct, incorrect] = markers[..] else {
+        return Err(DequeParseError::InvalidOptionMarkers);
+    };
+    let markers =
+        OptionMarkers::new(correct, incorrect).ok_or(DequeParseError::InvalidOptionMarkers)?;
+    Ok((markers, rest))
 }
 
-pub fn from_str(input: &str, multiline_messages: bool) -> Result<Deque, DequeParseError> {
-    let lines = input.lines().collect::<Vec<_>>();
+pub fn from_str(
+    input: &str,
+    multiline_messages: bool,
+    case_sensitive_names: bool,
+) -> Result<Deque, DequeParseError> {
+    parse(input, multiline_messages, case_sensitive_names, false)
+}
+
+/// Like [`from_str`], but for deque, saved by older version. If names of cards became equal,
+/// when [`normalize_card_name`] started to collapse whitespace, first card is kept.
+pub fn from_stored(input: &str, case_sensitive_names: bool) -> Result<Deque, DequeParseError> {
+    parse(input, true, case_sensitive_names, true)
+}
+
+fn parse(
+    input: &str,
+    multiline_messages: bool,
+    case_sensitive_names: bool,
+    keep_first_repeated: bool,
+) -> Result<Deque, DequeParseError> {
+    let (markers, cards_source) = parse_option_markers(input)?;
+    let lines = cards_source.lines().collect::<Vec<_>>();
+    // Blank chunks come from leading, trailing or repeated separators.
     let cards_input = lines
-        .split(|line| line.starts_with("-----"))
+        .split(|line| line.trim_end().starts_with("-----"))
+        .filter(|input| !input.iter().all(|line| line.trim().is_empty()))
         .map(|input| input.join("\n"));
-    let cards = cards_input.map(|x| Card::from_str(x, multiline_messages));
+    let cards = cards_input.map(|x| Card::parse(x, multiline_messages, &markers));
     let mut deque = Deque {
         source: input.to_owned(),
         tasks: BTreeMap::new(),
     };
     for card in cards {
-        let Card { name, tasks } = card?;
-        let prev = deque.tasks.insert(name.to_lowercase(), tasks);
-        check!(prev.is_none(), DequeParseError::CardNameRepeated);
+        let Card { name, tasks, .. } = card?;
+        // Card parser already rejects such cards, but empty card can't be served.
+        check!(
+            !tasks.is_empty(),
+            DequeParseError::CardParseError(super::card::CardParseError::NoTasks)
+        );
+        let name = normalize_card_name(&name, case_sensitive_names);
+        if deque.tasks.contains_key(&name) {
+            check!(keep_first_repeated, DequeParseError::CardNameRepeated(name));
+            log::warn!("saved deque repeats card '{name}', only first one is kept");
+            continue;
+        }
+        deque.tasks.insert(name, tasks);
     }
     check!(!deque.tasks.is_empty(), DequeParseError::NoCards);
     Ok(deque)
 }
 
-impl Default for Deque {
-    fn default() -> Self {
-        let deque = from_str(include_str!("../../../../cards.md"), true).unwrap();
-        let mut errors = Vec::new();
-        CourseGraph::default()
-            .cards()
+/// Cards, that are only in graph or only in deque. Both lists are sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    pub missing_in_deque: Vec<String>,
+    pub missing_in_graph: Vec<String>,
+}
+impl From<CardDiff> for ConsistencyReport {
+    fn from(diff: CardDiff) -> Self {
+        Self {
+            missing_in_deque: diff.missing_in_deque,
+            missing_in_graph: diff.extra_in_deque,
+        }
+    }
+}
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_in_deque.is_empty() && self.missing_in_graph.is_empty()
+    }
+    /// One human readable message per mismatched card.
+    pub fn messages(&self) -> Vec<String> {
+        let missing_in_deque = self
+            .missing_in_deque
+            .iter()
+            .map(|id| format!("Graph has '{id}' card, but deque doesn't."));
+        let missing_in_graph = self
+            .missing_in_graph
+            .iter()
+            .map(|id| format!("Deque has '{id}', but graph doesn't."));
+        missing_in_deque.chain(missing_in_graph).collect()
+    }
+}
+
+/// Card names, that changed between two deques. All lists are sorted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DequeDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Same name, but different tasks.
+    pub modified: Vec<String>,
+}
+impl DequeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+    pub fn message(&self) -> String {
+        if self.is_empty() {
+            return "No cards changed.".into();
+        }
+        [
+            ("Added", &self.added),
+            ("Removed", &self.removed),
+            ("Modified", &self.modified),
+        ]
+        .into_iter()
+        .filter(|(_, cards)| !cards.is_empty())
+        .map(|(kind, cards)| format!("{kind}: {}", cards.join(", ")))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+impl Deque {
+    /// Links of all tasks, see [`Task::check_links`]. Only new sources are checked.
+    pub fn check_links(&self) -> Result<(), DequeParseError> {
+        for (card, tasks) in &self.tasks {
+            for task in tasks.values() {
+                task.check_links()
+                    .map_err(|error| DequeParseError::InvalidLink {
+                        card: card.clone(),
+                        error,
+                    })?;
+            }
+        }
+        Ok(())
+    }
+    /// Deque with cards of both deques. Same rules, as for [`CourseGraph::merge`].
+    /// Both deques should use the same option markers.
+    pub fn merge(&self, other: &Deque, case_sensitive_names: bool) -> Result<Deque, String> {
+        let shared = self
+            .tasks
             .keys()
-            .filter(|&id| !deque.tasks.contains_key(id))
-            .map(|id| format!("Graph has '{id}' card, but deque(cards.md) doesn't."))
-            .for_each(|item| {
-                errors.push(item);
-            });
-        deque
+            .filter(|&name| other.tasks.contains_key(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !shared.is_empty() {
+            return Err(format!("both deques have cards: {}", shared.join(", ")));
+        }
+        let (markers, _) = parse_option_markers(&self.source).map_err(|err| err.to_string())?;
+        let (other_markers, other_cards) =
+            parse_option_markers(&other.source).map_err(|err| err.to_string())?;
+        if markers != other_markers {
+            return Err("deques use different option markers".into());
+        }
+        let source = format!(
+            "{}\n-----\n{}",
+            self.source.trim_end(),
+            other_cards.trim_start()
+        );
+        from_str(&source, true, case_sensitive_names).map_err(|err| err.to_string())
+    }
+    /// Pairs of cards, that have identical tasks(see [`Task::content_hash`]).
+    /// Pairs are sorted, first card of pair is less than second.
+    pub fn find_duplicate_tasks(&self) -> Vec<(String, String)> {
+        let mut cards_by_task = HashMap::<u64, Vec<&String>>::new();
+        for (name, tasks) in &self.tasks {
+            for task in tasks.values() {
+                let cards = cards_by_task.entry(task.content_hash()).or_default();
+                if !cards.contains(&name) {
+                    cards.push(name);
+                }
+            }
+        }
+        let mut duplicates = Vec::new();
+        for cards in cards_by_task.values() {
+            for (ix, first) in cards.iter().enumerate() {
+                for second in &cards[ix + 1..] {
+                    duplicates.push(((*first).clone(), (*second).clone()));
+                }
+            }
+        }
+        duplicates.sort();
+        duplicates.dedup();
+        duplicates
+    }
+    /// Pairs of cards, which names differ only in case or whitespace, like `mycard` and `my card`.
+    /// Learners can easily confuse them. Pairs are sorted, first card of pair is less than second.
+    pub fn find_similar_names(&self) -> Vec<(String, String)> {
+        let mut cards_by_key = BTreeMap::<String, Vec<&String>>::new();
+        for name in self.tasks.keys() {
+            let key = name
+                .chars()
+                .filter(|ch| !ch.is_whitespace())
+                .collect::<String>()
+                .to_lowercase();
+            cards_by_key.entry(key).or_default().push(name);
+        }
+        let mut similar = Vec::new();
+        for cards in cards_by_key.values() {
+            for (ix, first) in cards.iter().enumerate() {
+                for second in &cards[ix + 1..] {
+                    similar.push(((*first).clone(), (*second).clone()));
+                }
+            }
+        }
+        similar.sort();
+        similar
+    }
+    /// What changes, if `self` is replaced with `new`.
+    pub fn diff(&self, new: &Deque) -> DequeDiff {
+        let added = new
             .tasks
             .keys()
-            .filter(|x| !CourseGraph::default().cards().contains_key(*x))
-            .map(|err| format!("Deque(cards.md) has '{err}', but graph doesn't."))
-            .for_each(|item| {
-                errors.push(item);
-            });
-        if !errors.is_empty() {
+            .filter(|&name| !self.tasks.contains_key(name))
+            .cloned()
+            .collect();
+        let mut removed = Vec::new();
+        let mut modified = Vec::new();
+        for (name, tasks) in &self.tasks {
+            match new.tasks.get(name) {
+                None => removed.push(name.clone()),
+                Some(new_tasks) if new_tasks != tasks => modified.push(name.clone()),
+                Some(_) => (),
+            }
+        }
+        DequeDiff {
+            added,
+            removed,
+            modified,
+        }
+    }
+    pub fn validate_against(&self, graph: &CourseGraph) -> ConsistencyReport {
+        graph
+            .diff_cards(self.tasks.keys().map(String::as_str))
+            .into()
+    }
+}
+
+impl Default for Deque {
+    fn default() -> Self {
+        let deque = from_str(include_str!("../../../../cards.md"), true, false).unwrap();
+        let report = deque.validate_against(&CourseGraph::default());
+        if !report.is_consistent() {
             panic!(
                 "Cards in deque(cards.md) and graph(graph) are different.\n{}",
-                errors.join("\n")
+                report.messages().join("\n")
             );
         }
         deque
@@ -100,7 +330,7 @@ impl Visitor<'_> for DequeVisitor {
     where
         E: Error,
     {
-        from_str(v, true).map_err(Error::custom)
+        from_str(v, true, false).map_err(Error::custom)
     }
 }
 impl<'de> Deserialize<'de> for Deque {
@@ -111,3 +341,254 @@ impl<'de> Deserialize<'de> for Deque {
         deserializer.deserialize_str(DequeVisitor)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interaction_types::task::TaskKind;
+
+    const CASE_VARIANTS: &str = "
+# Name
+Foo
+## Task 1
+Question
+
+* a
+- b
+-----
+# Name
+foo
+## Task 1
+Question
+
+* a
+- b
+";
+
+    #[test]
+    fn case_insensitive_names() {
+        assert!(matches!(
+            from_str(CASE_VARIANTS, true, false),
+            Err(DequeParseError::CardNameRepeated(name)) if name == "foo"
+        ));
+    }
+
+    #[test]
+    fn deque_option_markers() {
+        let input = "# Option markers: + ~
+# Name
+capitals
+# Distractors
+~ Berlin
+## Task 1
+Capital of France?
+
++ Paris
+~ Rome
+";
+        let deque = from_str(input, true, false).unwrap();
+        let task = &deque.tasks["capitals"][&1];
+        assert_eq!(
+            task.kind,
+            TaskKind::OneOf {
+                options: vec!["Paris".into(), "Rome".into()],
+                answers: vec![0],
+                pool: vec!["Berlin".into()],
+            }
+        );
+        assert!(matches!(
+            from_str(&input.replace("+ ~", "+"), true, false),
+            Err(DequeParseError::InvalidOptionMarkers)
+        ));
+    }
+
+    #[test]
+    fn consistency_report() {
+        let graph = CourseGraph::parse("capitals: countries\ncountries\nrivers", false).unwrap();
+        let deque = from_str(
+            "# Name\ncountries\n## Task 1\nQ\n\n* a\n- b\n-----\n# Name\nlakes\n## Task 1\nQ\n\n* a\n- b",
+            true,
+            false,
+        )
+        .unwrap();
+        let report = deque.validate_against(&graph);
+        assert_eq!(
+            report,
+            ConsistencyReport {
+                missing_in_deque: vec!["capitals".into(), "rivers".into()],
+                missing_in_graph: vec!["lakes".into()],
+            }
+        );
+        assert!(!report.is_consistent());
+        assert_eq!(
+            report.messages(),
+            vec![
+                "Graph has 'capitals' card, but deque doesn't.",
+                "Graph has 'rivers' card, but deque doesn't.",
+                "Deque has 'lakes', but graph doesn't.",
+            ]
+        );
+        assert!(
+            Deque::default()
+                .validate_against(&CourseGraph::default())
+                .is_consistent()
+        );
+    }
+
+    #[test]
+    fn deque_diff() {
+        let card = |name: &str, answer: &str| {
+            format!("# Name\n{name}\n## Task 1\nQ\n\n* {answer}\n- wrong")
+        };
+        let old = from_str(
+            &[card("kept", "a"), card("changed", "a"), card("gone", "a")].join("\n-----\n"),
+            true,
+            false,
+        )
+        .unwrap();
+        let new = from_str(
+            &[card("kept", "a"), card("changed", "b"), card("new", "a")].join("\n-----\n"),
+            true,
+            false,
+        )
+        .unwrap();
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            DequeDiff {
+                added: vec!["new".into()],
+                removed: vec!["gone".into()],
+                modified: vec!["changed".into()],
+            }
+        );
+        assert_eq!(
+            diff.message(),
+            "Added: new\nRemoved: gone\nModified: changed"
+        );
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(old.diff(&old).message(), "No cards changed.");
+    }
+
+    #[test]
+    fn merge_deques() {
+        let card = |name: &str| format!("# Name\n{name}\n## Task 1\nQ\n\n* a\n- b");
+        let first = from_str(&card("a"), true, false).unwrap();
+        let second = from_str(&[card("b"), card("c")].join("\n-----\n"), true, false).unwrap();
+        let merged = first.merge(&second, false).unwrap();
+        assert_eq!(merged.tasks.keys().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        let shared = from_str(&card("a"), true, false).unwrap();
+        assert_eq!(
+            first.merge(&shared, false).unwrap_err(),
+            "both deques have cards: a"
+        );
+
+        let custom_markers = from_str(
+            "# Option markers: + ~\n# Name\nd\n## Task 1\nQ\n\n+ a\n~ b",
+            true,
+            false,
+        )
+        .unwrap();
+        assert!(first.merge(&custom_markers, false).is_err());
+    }
+
+    #[test]
+    fn duplicate_tasks() {
+        let deque = from_str(
+            "# Name\na\n## Task 1\nCapital of France?\n\n* Paris\n- Rome
+-----
+# Name\nb\n## Task 1\nOther question\n\n* x\n- y\n## Task 2\ncapital  of FRANCE?\n\n- rome\n* paris
+-----
+# Name\nc\n## Task 1\nCapital of France?\n\n* Rome\n- Paris",
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            deque.find_duplicate_tasks(),
+            vec![("a".to_owned(), "b".to_owned())]
+        );
+    }
+
+    #[test]
+    fn blank_cards_are_skipped() {
+        let card = |name: &str| format!("# Name\n{name}\n## Task 1\nQ\n\n* a\n- b");
+        let names = |input: &str| {
+            from_str(input, true, false)
+                .unwrap()
+                .tasks
+                .into_keys()
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = (card("a"), card("b"));
+        assert_eq!(names(&format!("{a}\n-----\n{b}\n-----\n")), ["a", "b"]);
+        assert_eq!(names(&format!("-----  \n{a}\n-----\t\n{b}")), ["a", "b"]);
+        assert_eq!(names(&format!("{a}\n-----\n  \n-----\n\n{b}")), ["a", "b"]);
+        assert!(matches!(
+            from_str(&format!("{a}\n-----\n\n-----\n{a}"), true, false),
+            Err(DequeParseError::CardNameRepeated(_))
+        ));
+        assert!(matches!(
+            from_str("-----\n \n-----", true, false),
+            Err(DequeParseError::NoCards)
+        ));
+    }
+
+    #[test]
+    fn case_sensitive_names() {
+        let deque = from_str(CASE_VARIANTS, true, true).unwrap();
+        assert_eq!(deque.tasks.keys().collect::<Vec<_>>(), vec!["Foo", "foo"]);
+        assert_eq!(
+            deque.find_similar_names(),
+            vec![("Foo".to_owned(), "foo".to_owned())]
+        );
+    }
+
+    #[test]
+    fn whitespace_variant_names() {
+        let card = |name: &str| format!("# Name\n{name}\n## Task 1\nQ\n\n* a\n- b");
+        assert!(matches!(
+            from_str(&[card("My Card"), card("my   card ")].join("\n-----\n"), true, false),
+            Err(DequeParseError::CardNameRepeated(name)) if name == "my card"
+        ));
+        assert!(matches!(
+            from_str(&[card("my card"), card("my\tcard")].join("\n-----\n"), true, true),
+            Err(DequeParseError::CardNameRepeated(name)) if name == "my card"
+        ));
+
+        let deque = from_str(
+            &[card("mycard"), card("my card"), card("other")].join("\n-----\n"),
+            true,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            deque.find_similar_names(),
+            vec![("my card".to_owned(), "mycard".to_owned())]
+        );
+    }
+
+    #[test]
+    fn saved_deque_loads_despite_newer_checks() {
+        let card =
+            |name: &str, option: &str| format!("# Name\n{name}\n## Task 1\nQ\n\n* {option}\n- b");
+        let source = [
+            card("my  card", "a"),
+            card("my card", "c"),
+            card("other", "![ftp://example.com/a.png]"),
+        ]
+        .join("\n-----\n");
+        assert!(matches!(
+            from_str(&source, true, false),
+            Err(DequeParseError::CardNameRepeated(_))
+        ));
+
+        let deque = from_stored(&source, false).unwrap();
+        assert_eq!(deque.tasks.len(), 2);
+        assert_eq!(deque.tasks["my card"][&1].correct_answer(), "a");
+        assert!(matches!(
+            deque.check_links(),
+            Err(DequeParseError::InvalidLink { card, .. }) if card == "other"
+        ));
+    }
+}
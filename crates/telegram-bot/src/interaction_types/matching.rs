@@ -0,0 +1,65 @@
+//! Grading of typed answers.
+
+/// Lowercase, trim and collapse whitespace, so `" New  York"` equals `"new york"`.
+pub fn normalize_answer(answer: &str) -> String {
+    answer
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Answers shorter than this should match exactly(after normalization).
+const MIN_LEN_FOR_TYPO: usize = 5;
+
+/// Normalized answers are equal, or differ by one typo in long enough answers.
+pub fn answers_match(expected: &str, given: &str) -> bool {
+    let expected = normalize_answer(expected);
+    let given = normalize_answer(given);
+    if expected == given {
+        return true;
+    }
+    expected.chars().count() >= MIN_LEN_FOR_TYPO && edit_distance(&expected, &given) <= 1
+}
+
+/// Levenshtein distance in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize_answer("  New   York "), "new york");
+        assert!(answers_match("Paris", " paris"));
+    }
+
+    #[test]
+    fn typos() {
+        assert!(answers_match("Berlin", "Berlim"));
+        assert!(answers_match("Berlin", "Berln"));
+        assert!(!answers_match("Berlin", "Brlim"));
+        // short answers should be exact
+        assert!(!answers_match("cat", "car"));
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+}
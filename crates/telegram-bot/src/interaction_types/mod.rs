@@ -8,3 +8,5 @@ pub mod card;
 pub use card::Card;
 
 pub mod deque;
+
+pub mod matching;
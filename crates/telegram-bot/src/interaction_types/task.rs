@@ -1,18 +1,238 @@
-use super::telegram_interaction::{QuestionElement, TelegramInteraction};
+use std::{collections::HashSet, iter};
+
+use rand::{Rng, seq::IndexedRandom};
+
+use super::{
+    matching::{answers_match, normalize_answer},
+    telegram_interaction::{QuestionElement, TelegramInteraction},
+};
 use crate::check;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Task {
     pub question: Vec<QuestionElement>,
-    pub options: Vec<String>,
-    pub answer: usize,
+    pub kind: TaskKind,
     pub explanation: Option<Vec<QuestionElement>>,
+    /// Options of one of task are shown in random order. Otherwise in source order.
+    pub shuffle: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskKind {
+    OneOf {
+        /// Texts or images. Learner chooses image by its label, see
+        /// [`option_labels`](super::telegram_interaction::option_labels).
+        options: Vec<QuestionElement>,
+        /// Indices of correct `options`. If there are several, learner should choose
+        /// exactly all of them.
+        answers: Vec<usize>,
+        /// Shared distractors of card. Used, if task has only correct option.
+        pool: Vec<String>,
+    },
+    /// Learner should put `items` in the right sequence.
+    Ordering {
+        /// In the same order, as in source.
+        items: Vec<String>,
+        /// Indices of `items` in the right sequence.
+        correct_order: Vec<usize>,
+    },
+    /// Question has blanks, learner types missing text for each of them.
+    Cloze {
+        /// Expected text of each blank, in order of appearance.
+        answers: Vec<String>,
+    },
+    /// Learner types answer, it should equal `answer` up to case and whitespace.
+    FreeText { answer: String },
+}
+
+/// How many wrong options are drawn from card distractors.
+pub const POOL_DISTRACTORS: usize = 3;
+
+/// First line of task, that keeps its options in source order.
+pub const NO_SHUFFLE_DIRECTIVE: &str = "!noshuffle";
+
+/// How blank looks in question. Escaped, because questions are sent as MarkdownV2.
+pub const BLANK: &str = r"\_\_\_\_";
+
+/// FNV-1a, so hash doesn't change between runs and Rust versions.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
 }
 
 impl Task {
-    #[allow(unused)]
-    pub fn correct_answer(&self) -> &str {
-        &self.options[self.answer]
+    /// Same for tasks, that differ only in case, whitespace or order of options.
+    /// Card distractors and explanation are ignored.
+    pub fn content_hash(&self) -> u64 {
+        let mut content = self
+            .question
+            .iter()
+            .map(|element| match element {
+                QuestionElement::Text(text) => format!("text:{}", normalize_answer(text)),
+                QuestionElement::Image(image) => format!("image:{image}"),
+                QuestionElement::Audio(audio) => format!("audio:{audio}"),
+            })
+            .collect::<Vec<_>>();
+        match &self.kind {
+            TaskKind::OneOf {
+                options, answers, ..
+            } => {
+                let mut options = options
+                    .iter()
+                    .enumerate()
+                    .map(|(ix, option)| {
+                        let kind = if answers.contains(&ix) {
+                            "correct"
+                        } else {
+                            "wrong"
+                        };
+                        let option = match option {
+                            QuestionElement::Text(text) => normalize_answer(text),
+                            QuestionElement::Image(image) => format!("image:{image}"),
+                            QuestionElement::Audio(audio) => format!("audio:{audio}"),
+                        };
+                        format!("{kind}:{option}")
+                    })
+                    .collect::<Vec<_>>();
+                options.sort();
+                content.push("one of".into());
+                content.extend(options);
+            }
+            TaskKind::Ordering { .. } => {
+                content.push("ordering".into());
+                content.extend(self.correct_sequence().into_iter().map(normalize_answer));
+            }
+            TaskKind::Cloze { answers } => {
+                content.push("cloze".into());
+                content.extend(answers.iter().map(|answer| normalize_answer(answer)));
+            }
+            TaskKind::FreeText { answer } => {
+                content.push("free text".into());
+                content.push(normalize_answer(answer));
+            }
+        }
+        stable_hash(content.join("\n").as_bytes())
+    }
+    /// Correct options of one of task should exist, otherwise indexing by them panics.
+    pub fn check_answer_index(&self) -> Result<(), TaskParseError> {
+        let TaskKind::OneOf {
+            options, answers, ..
+        } = &self.kind
+        else {
+            return Ok(());
+        };
+        check!(!answers.is_empty(), TaskParseError::NoCorrectOption);
+        match answers.iter().find(|&&answer| answer >= options.len()) {
+            Some(&answer) => Err(TaskParseError::AnswerOutOfRange {
+                answer,
+                options: options.len(),
+            }),
+            None => Ok(()),
+        }
+    }
+    /// See [`QuestionElement::check_link`].
+    pub fn check_links(&self) -> Result<(), TaskParseError> {
+        let options = match &self.kind {
+            TaskKind::OneOf { options, .. } => options.as_slice(),
+            TaskKind::Ordering { .. } | TaskKind::Cloze { .. } | TaskKind::FreeText { .. } => &[],
+        };
+        self.question
+            .iter()
+            .chain(options)
+            .chain(self.explanation.iter().flatten())
+            .try_for_each(QuestionElement::check_link)
+    }
+    /// Correct options of one of task are joined with comma. Images are shown as links.
+    pub fn correct_answer(&self) -> String {
+        match &self.kind {
+            TaskKind::OneOf {
+                options, answers, ..
+            } => answers
+                .iter()
+                .map(|&ix| match &options[ix] {
+                    QuestionElement::Text(text) => text.clone(),
+                    QuestionElement::Image(link) | QuestionElement::Audio(link) => link.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            TaskKind::Ordering { .. } => self.correct_sequence().join(" → "),
+            TaskKind::Cloze { answers } => answers.join(", "),
+            TaskKind::FreeText { answer } => answer.clone(),
+        }
+    }
+    /// Options of one of task, with distractors drawn from pool, if needed.
+    /// Correct options keep their indices. Empty for other tasks.
+    pub fn sample_options(&self, rng: &mut impl Rng) -> Vec<QuestionElement> {
+        let TaskKind::OneOf { options, pool, .. } = &self.kind else {
+            return Vec::new();
+        };
+        if options.len() > 1 {
+            return options.clone();
+        }
+        let mut seen = options
+            .iter()
+            .filter_map(|option| match option {
+                QuestionElement::Text(text) => Some(normalize_answer(text)),
+                QuestionElement::Image(_) | QuestionElement::Audio(_) => None,
+            })
+            .collect::<HashSet<_>>();
+        let candidates = pool
+            .iter()
+            .filter(|distractor| seen.insert(normalize_answer(distractor)))
+            .collect::<Vec<_>>();
+        let mut options = options.clone();
+        options.extend(
+            candidates
+                .choose_multiple(rng, POOL_DISTRACTORS)
+                .map(|distractor| QuestionElement::Text((*distractor).clone())),
+        );
+        options
+    }
+    /// Items of ordering task in the right sequence. Empty for other tasks.
+    pub fn correct_sequence(&self) -> Vec<&str> {
+        match &self.kind {
+            TaskKind::OneOf { .. } | TaskKind::Cloze { .. } | TaskKind::FreeText { .. } => {
+                Vec::new()
+            }
+            TaskKind::Ordering {
+                items,
+                correct_order,
+            } => correct_order.iter().map(|&ix| items[ix].as_str()).collect(),
+        }
+    }
+    /// `order` is indices of `items` in sequence chosen by learner.
+    pub fn is_correct_order(&self, order: &[usize]) -> bool {
+        match &self.kind {
+            TaskKind::OneOf { .. } | TaskKind::Cloze { .. } | TaskKind::FreeText { .. } => false,
+            TaskKind::Ordering { correct_order, .. } => correct_order == order,
+        }
+    }
+    /// Unlike blanks, typos aren't forgiven. False for other tasks.
+    pub fn check_free_text(&self, given: &str) -> bool {
+        match &self.kind {
+            TaskKind::FreeText { answer } => normalize_answer(answer) == normalize_answer(given),
+            TaskKind::OneOf { .. } | TaskKind::Ordering { .. } | TaskKind::Cloze { .. } => false,
+        }
+    }
+    /// Grades each blank independently. Empty for other tasks.
+    pub fn check_blanks(&self, given: &[String]) -> Vec<bool> {
+        match &self.kind {
+            TaskKind::OneOf { .. } | TaskKind::Ordering { .. } | TaskKind::FreeText { .. } => {
+                Vec::new()
+            }
+            TaskKind::Cloze { answers } => answers
+                .iter()
+                .enumerate()
+                .map(|(ix, expected)| {
+                    given
+                        .get(ix)
+                        .is_some_and(|given| answers_match(expected, given))
+                })
+                .collect(),
+        }
     }
     #[allow(unused)]
     pub fn interactions(&self) -> Vec<TelegramInteraction> {
@@ -20,7 +240,24 @@ impl Task {
         for element in &self.question {
             interactions.push(element.clone().into());
         }
-        interactions.push(TelegramInteraction::OneOf(self.options.clone()));
+        match &self.kind {
+            TaskKind::OneOf { answers, .. } if answers.len() > 1 => {
+                interactions.push(TelegramInteraction::MultiSelect {
+                    options: self.sample_options(&mut rand::rng()),
+                    extra: Vec::new(),
+                })
+            }
+            TaskKind::OneOf { .. } => interactions.push(TelegramInteraction::OneOf(
+                self.sample_options(&mut rand::rng()),
+            )),
+            TaskKind::Ordering { items, .. } => {
+                interactions.push(TelegramInteraction::Ordering(items.clone()))
+            }
+            TaskKind::Cloze { answers } => {
+                interactions.extend(answers.iter().map(|_| TelegramInteraction::UserInput))
+            }
+            TaskKind::FreeText { .. } => interactions.push(TelegramInteraction::UserInput),
+        }
         interactions
     }
 }
@@ -30,64 +267,279 @@ pub(crate) const ERROR_MSG: &str = "Task should follow this syntax:
 'question':
 text
 ![link_to_image]
+!a[link_to_audio]
 ...
             <- empty line
 * correct 'option'
 - options
+* more correct 'options'     <- learner should choose all of them
+- ![link_to_image]    <- 'option' can be image
 ...
             <- empty line
 'explanation'
 formatted same as 'question'
 ...
+
+Instead of 'options' task can have 'items' to put in order:
+2. second item
+1. first item
+3. third item
+...
+
+Or 'question' can have blanks instead of 'options':
+The capital of France is {{Paris}}.
+
+Or learner can type answer instead of choosing 'option':
+= expected answer
+
+'options' are shuffled, unless task starts with '!noshuffle' line.
+Then they are shown as is and any of them can be correct.
 ";
 
+/// Line prefixes of correct and incorrect options, followed by space.
+/// Deque can change them with `# Option markers: CORRECT INCORRECT` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionMarkers {
+    correct: String,
+    incorrect: String,
+}
+impl Default for OptionMarkers {
+    fn default() -> Self {
+        Self {
+            correct: "*".into(),
+            incorrect: "-".into(),
+        }
+    }
+}
+impl OptionMarkers {
+    /// None if markers are empty, equal or contain whitespace.
+    pub fn new(correct: &str, incorrect: &str) -> Option<Self> {
+        let valid = |marker: &str| !marker.is_empty() && !marker.contains(char::is_whitespace);
+        (valid(correct) && valid(incorrect) && correct != incorrect).then(|| Self {
+            correct: correct.to_owned(),
+            incorrect: incorrect.to_owned(),
+        })
+    }
+    pub fn strip_correct<'a>(&self, line: &'a str) -> Option<&'a str> {
+        line.strip_prefix(self.correct.as_str())?.strip_prefix(' ')
+    }
+    pub fn strip_incorrect<'a>(&self, line: &'a str) -> Option<&'a str> {
+        line.strip_prefix(self.incorrect.as_str())?
+            .strip_prefix(' ')
+    }
+}
+
 #[derive(Debug, thiserror::Error, PartialEq)]
 pub enum TaskParseError {
     #[error("{ERROR_MSG}. Input shouldn't be empty")]
     EmptyInput,
-    // NoQuestion,
+    #[error("{ERROR_MSG}. Task should start with 'question', but it starts with 'option'")]
+    NoQuestion,
     #[error("{ERROR_MSG}. No 'options' provided")]
     NoOptions,
-    #[error("{ERROR_MSG}. First 'option' should be correct and line should start with '* '")]
+    #[error(
+        "{ERROR_MSG}. First 'option' should be correct(any one for '!noshuffle' task) and line should start with correct marker('* ' by default)"
+    )]
     NoCorrectOption,
     #[error(
-        "{ERROR_MSG}. After correct 'option' required at least one incorrect, so line should start with '- '"
+        "{ERROR_MSG}. At least one 'option' should be incorrect, so its line should start with incorrect marker('- ' by default)"
     )]
     NoIncorrectOption,
-    #[error("{ERROR_MSG}. Correct option should start with '* ' and incorrect with '- '")]
+    #[error(
+        "{ERROR_MSG}. Correct option should start with correct marker('* ' by default) and incorrect with incorrect marker('- ' by default)"
+    )]
     InvalidOptionPrefix,
     #[error("{ERROR_MSG}. Each option should contain non empty text")]
     EmptyOptionText,
     #[error("Image should have this syntax: ![link_to_image]")]
     InvalidImageSyntax,
+    #[error("Audio should have this syntax: !a[link_to_audio]")]
+    InvalidAudioSyntax,
     #[error("Image should be valid link. Error: {0}")]
-    ImageShouldBeLink(#[from] url::ParseError),
+    ImageShouldBeLink(url::ParseError),
+    #[error("Audio should be valid link. Error: {0}")]
+    AudioShouldBeLink(url::ParseError),
+    #[error("Image or audio link should start with 'http://' or 'https://', but it uses '{0}'")]
+    UnsupportedImageScheme(String),
     #[error("{ERROR_MSG}. Task should not have anything after explanation")]
     ContentAfterExplanation,
+    #[error("{ERROR_MSG}. Ordering task should have at least 2 items")]
+    NotEnoughOrderingItems,
+    #[error("{ERROR_MSG}. Each item should start with 'N. ', where N is its position from 1")]
+    InvalidItemPrefix,
+    #[error("{ERROR_MSG}. Item positions should go from 1 to number of items without repeats")]
+    InvalidItemPositions,
+    #[error("{ERROR_MSG}. Each '{{{{' should be closed with '}}}}'")]
+    UnclosedBlank,
+    #[error("{ERROR_MSG}. Blank should contain answer, like '{{{{answer}}}}'")]
+    EmptyBlank,
+    #[error("{ERROR_MSG}. Free text answer should be one line like '= answer'")]
+    InvalidFreeTextAnswer,
+    #[error("Correct option {answer} is out of {options} options")]
+    AnswerOutOfRange { answer: usize, options: usize },
 }
 
 impl Task {
     pub fn from_str(
         input: impl AsRef<str>,
         multiline_messages: bool,
+    ) -> Result<Self, TaskParseError> {
+        Self::parse(input, multiline_messages, &[], &OptionMarkers::default())
+    }
+    /// With non empty `pool` task could have only correct option.
+    /// Question is required, but it can contain only images.
+    pub fn parse(
+        input: impl AsRef<str>,
+        multiline_messages: bool,
+        pool: &[String],
+        markers: &OptionMarkers,
     ) -> Result<Self, TaskParseError> {
         let input = input.as_ref().trim();
+        let (shuffle, input) = match input.lines().next() {
+            Some(first_line) if first_line.trim() == NO_SHUFFLE_DIRECTIVE => {
+                (false, input[first_line.len()..].trim())
+            }
+            _ => (true, input),
+        };
         check!(!input.is_empty(), TaskParseError::EmptyInput);
         let lines = input.lines().map(|x| x.trim());
+        // Otherwise options become question text and error says, that there are no options.
+        check!(
+            !input
+                .lines()
+                .next()
+                .is_some_and(|line| is_option_string_prefix_valid(line.trim(), markers)),
+            TaskParseError::NoQuestion
+        );
 
         let (question, remainder) = parse_messages(lines, multiline_messages)?;
-        let (options, remainder) = parse_options(remainder)?;
+        let mut remainder = remainder.peekable();
+        let (question, blanks) = parse_blanks(question)?;
+        let kind = if !blanks.is_empty() {
+            TaskKind::Cloze { answers: blanks }
+        } else if remainder
+            .peek()
+            .is_some_and(|line| parse_ordering_item(line).is_some())
+        {
+            parse_ordering(&mut remainder)?
+        } else if remainder
+            .peek()
+            .is_some_and(|line| parse_free_text_answer(line).is_some())
+        {
+            parse_free_text(&mut remainder)?
+        } else {
+            let (options, answers, _) =
+                parse_options(&mut remainder, !pool.is_empty(), shuffle, markers)?;
+            TaskKind::OneOf {
+                options,
+                answers,
+                pool: pool.to_vec(),
+            }
+        };
         let explanation = parse_explanation(multiline_messages, remainder)?;
 
-        Ok(Task {
+        let task = Task {
             question,
-            options,
-            answer: 0,
+            kind,
             explanation,
-        })
+            shuffle,
+        };
+        task.check_answer_index()?;
+        Ok(task)
     }
 }
 
+/// Replaces `{{answer}}` markers in question text with [`BLANK`].
+/// Returns new question and answers of blanks.
+fn parse_blanks(
+    question: Vec<QuestionElement>,
+) -> Result<(Vec<QuestionElement>, Vec<String>), TaskParseError> {
+    let mut answers = Vec::new();
+    let mut new_question = Vec::new();
+    for element in question {
+        let QuestionElement::Text(mut text) = element else {
+            new_question.push(element);
+            continue;
+        };
+        let mut new_text = String::new();
+        while let Some(start) = text.find("{{") {
+            let (end, _) = text[start..]
+                .match_indices("}}")
+                .next()
+                .ok_or(TaskParseError::UnclosedBlank)?;
+            let answer = text[start + 2..start + end].trim();
+            check!(!answer.is_empty(), TaskParseError::EmptyBlank);
+            answers.push(answer.to_owned());
+            new_text.push_str(&text[..start]);
+            new_text.push_str(BLANK);
+            text = text[start + end + 2..].to_owned();
+        }
+        new_text.push_str(&text);
+        new_question.push(QuestionElement::Text(new_text));
+    }
+    Ok((new_question, answers))
+}
+
+/// Position(from 1) and text of an ordering item, if line looks like one.
+fn parse_ordering_item(line: &str) -> Option<(usize, &str)> {
+    let (position, text) = line.split_once(". ")?;
+    Some((position.parse().ok()?, text.trim()))
+}
+
+pub(crate) fn parse_ordering<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<TaskKind, TaskParseError> {
+    let mut items = Vec::new();
+    let mut positions = Vec::new();
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        let (position, text) =
+            parse_ordering_item(line).ok_or(TaskParseError::InvalidItemPrefix)?;
+        check!(!text.is_empty(), TaskParseError::EmptyOptionText);
+        items.push(text.to_owned());
+        positions.push(position);
+    }
+    check!(items.len() > 1, TaskParseError::NotEnoughOrderingItems);
+    let mut correct_order = vec![None; items.len()];
+    for (ix, position) in positions.into_iter().enumerate() {
+        let slot = position
+            .checked_sub(1)
+            .and_then(|position| correct_order.get_mut(position))
+            .ok_or(TaskParseError::InvalidItemPositions)?;
+        check!(slot.is_none(), TaskParseError::InvalidItemPositions);
+        *slot = Some(ix);
+    }
+    Ok(TaskKind::Ordering {
+        items,
+        correct_order: correct_order.into_iter().map(Option::unwrap).collect(),
+    })
+}
+
+/// Expected answer, if line looks like `= answer`.
+fn parse_free_text_answer(line: &str) -> Option<&str> {
+    line.strip_prefix('=').map(str::trim)
+}
+
+/// Single `= answer` line, followed by empty line or end of task.
+fn parse_free_text<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<TaskKind, TaskParseError> {
+    let answer = lines
+        .next()
+        .and_then(parse_free_text_answer)
+        .ok_or(TaskParseError::InvalidFreeTextAnswer)?;
+    check!(!answer.is_empty(), TaskParseError::EmptyOptionText);
+    check!(
+        lines.next().is_none_or(str::is_empty),
+        TaskParseError::InvalidFreeTextAnswer
+    );
+    Ok(TaskKind::FreeText {
+        answer: answer.to_owned(),
+    })
+}
+
 pub(crate) fn parse_explanation<'a>(
     multiline_messages: bool,
     remainder: impl Iterator<Item = &'a str>,
@@ -101,47 +553,70 @@ pub(crate) fn parse_explanation<'a>(
     }
 }
 
+/// `allow_single` allows only correct options.
+/// `correct_first` requires first option to be correct.
+/// Option like `![link]` is image, any other is text.
+/// Returns options, indices of correct ones and remaining lines.
 pub(crate) fn parse_options<'a>(
     mut lines: impl Iterator<Item = &'a str>,
-) -> Result<(Vec<String>, impl Iterator<Item = &'a str>), TaskParseError> {
+    allow_single: bool,
+    correct_first: bool,
+    markers: &OptionMarkers,
+) -> Result<
+    (
+        Vec<QuestionElement>,
+        Vec<usize>,
+        impl Iterator<Item = &'a str>,
+    ),
+    TaskParseError,
+> {
     let mut options = Vec::new();
+    let mut answers = Vec::new();
     let Some(first_line) = lines.next() else {
         return Err(TaskParseError::NoOptions);
     };
     check!(
-        is_option_string_prefix_valid(first_line),
+        is_option_string_prefix_valid(first_line, markers),
         TaskParseError::InvalidOptionPrefix
     );
-    let first_line = first_line
-        .strip_prefix("* ")
-        .ok_or(TaskParseError::NoCorrectOption)?
-        .trim();
-    check!(!first_line.is_empty(), TaskParseError::EmptyOptionText);
-    options.push(first_line.to_owned());
-    for line in &mut lines {
+    check!(
+        !correct_first || markers.strip_correct(first_line).is_some(),
+        TaskParseError::NoCorrectOption
+    );
+    for line in iter::once(first_line).chain(&mut lines) {
         if line.is_empty() {
-            check!(options.len() > 1, TaskParseError::NoIncorrectOption);
-            return Ok((options, lines));
+            break;
         }
-        check!(
-            is_option_string_prefix_valid(line),
-            TaskParseError::InvalidOptionPrefix
-        );
-        let line = line
-            .strip_prefix("- ")
-            .ok_or(TaskParseError::NoIncorrectOption)?
-            .trim();
-        check!(!line.is_empty(), TaskParseError::EmptyOptionText);
-        options.push(line.to_owned());
+        let text = match markers.strip_correct(line) {
+            Some(text) => {
+                answers.push(options.len());
+                text
+            }
+            None => markers
+                .strip_incorrect(line)
+                .ok_or(TaskParseError::InvalidOptionPrefix)?,
+        }
+        .trim();
+        check!(!text.is_empty(), TaskParseError::EmptyOptionText);
+        options.push(if text.starts_with("![") {
+            QuestionElement::from_str(text)?
+        } else {
+            QuestionElement::Text(text.to_owned())
+        });
     }
-    check!(options.len() > 1, TaskParseError::NoIncorrectOption);
-    Ok((options, lines))
+    check!(!answers.is_empty(), TaskParseError::NoCorrectOption);
+    check!(
+        allow_single || options.len() > answers.len(),
+        TaskParseError::NoIncorrectOption
+    );
+    Ok((options, answers, lines))
 }
 
-pub(crate) fn is_option_string_prefix_valid(line: &str) -> bool {
-    line.starts_with("* ") || line.starts_with("- ")
+pub(crate) fn is_option_string_prefix_valid(line: &str, markers: &OptionMarkers) -> bool {
+    markers.strip_correct(line).is_some() || markers.strip_incorrect(line).is_some()
 }
 
+/// Joins consecutive texts with newline. Every image and audio stays separate element.
 pub(crate) fn merge_messages(question: Vec<QuestionElement>) -> Vec<QuestionElement> {
     let mut new_question = Vec::new();
     let mut prev: Option<String> = None;
@@ -155,7 +630,7 @@ pub(crate) fn merge_messages(question: Vec<QuestionElement>) -> Vec<QuestionElem
                     prev = Some(text);
                 }
             }
-            QuestionElement::Image(_) => {
+            QuestionElement::Image(_) | QuestionElement::Audio(_) => {
                 if let Some(prev) = prev.take() {
                     new_question.push(QuestionElement::Text(prev));
                 }
@@ -169,13 +644,23 @@ pub(crate) fn merge_messages(question: Vec<QuestionElement>) -> Vec<QuestionElem
     new_question
 }
 
+fn is_media_line(line: &str) -> bool {
+    line.starts_with("![") || line.starts_with("!a[")
+}
+
+/// Messages end with empty line. Single empty line before image or audio is skipped,
+/// because options and explanation can't start with them.
 pub(crate) fn parse_messages<'a>(
-    mut lines: impl Iterator<Item = &'a str>,
+    lines: impl Iterator<Item = &'a str>,
     multiline_messages: bool,
 ) -> Result<(Vec<QuestionElement>, impl Iterator<Item = &'a str>), TaskParseError> {
+    let mut lines = lines.peekable();
     let mut question = Vec::new();
-    for line in &mut lines {
+    while let Some(line) = lines.next() {
         if line.is_empty() {
+            if !question.is_empty() && lines.peek().is_some_and(|next| is_media_line(next)) {
+                continue;
+            }
             break;
         }
         question.push(QuestionElement::from_str(line)?);
@@ -185,3 +670,469 @@ pub(crate) fn parse_messages<'a>(
     }
     Ok((question, lines))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interaction_types::telegram_interaction::option_labels;
+
+    const ORDERING: &str = "Order the steps
+
+2. Boil water
+1. Fill kettle
+3. Pour tea
+
+Explanation";
+
+    #[test]
+    fn answer_out_of_range() {
+        let mut task = Task::from_str("Q\n\n* a\n- b", false).unwrap();
+        assert_eq!(task.check_answer_index(), Ok(()));
+        let TaskKind::OneOf { answers, .. } = &mut task.kind else {
+            panic!("should be one of task");
+        };
+        answers.push(5);
+        assert_eq!(
+            task.check_answer_index(),
+            Err(TaskParseError::AnswerOutOfRange {
+                answer: 5,
+                options: 2
+            })
+        );
+    }
+
+    #[test]
+    fn parse_ordering_task() {
+        let task = Task::from_str(ORDERING, false).unwrap();
+        assert_eq!(
+            task.kind,
+            TaskKind::Ordering {
+                items: vec!["Boil water".into(), "Fill kettle".into(), "Pour tea".into()],
+                correct_order: vec![1, 0, 2],
+            }
+        );
+        assert_eq!(
+            task.correct_sequence(),
+            vec!["Fill kettle", "Boil water", "Pour tea"]
+        );
+        assert!(task.explanation.is_some());
+    }
+
+    #[test]
+    fn parse_audio() {
+        let task = Task::from_str(
+            "!a[https://example.com/word.ogg]\nWhat word is it?\n\n* hello\n- bye",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            task.question,
+            vec![
+                QuestionElement::Audio("https://example.com/word.ogg".parse().unwrap()),
+                QuestionElement::Text("What word is it?".into()),
+            ]
+        );
+        // images still parse as before
+        assert!(matches!(
+            QuestionElement::from_str("![https://example.com/a.png]"),
+            Ok(QuestionElement::Image(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_audio() {
+        assert_eq!(
+            QuestionElement::from_str("!a[https://example.com/word.ogg"),
+            Err(TaskParseError::InvalidAudioSyntax)
+        );
+        assert!(matches!(
+            QuestionElement::from_str("!a[not a link]"),
+            Err(TaskParseError::AudioShouldBeLink(_))
+        ));
+        assert!(matches!(
+            Task::from_str("Q\n\n* !a[not a link]\n- b", false),
+            Err(TaskParseError::AudioShouldBeLink(_))
+        ));
+    }
+
+    #[test]
+    fn custom_option_markers() {
+        let markers = OptionMarkers::new("✓", "✗").unwrap();
+        let task = Task::parse("Столица Франции?\n\n✓ Париж\n✗ Рим", false, &[], &markers).unwrap();
+        assert_eq!(
+            task.kind,
+            TaskKind::OneOf {
+                options: vec!["Париж".into(), "Рим".into()],
+                answers: vec![0],
+                pool: Vec::new(),
+            }
+        );
+        // Default markers are not options anymore.
+        assert_eq!(
+            Task::parse("Q\n\n* yes\n- no", false, &[], &markers).unwrap_err(),
+            TaskParseError::InvalidOptionPrefix
+        );
+        assert_eq!(
+            Task::parse("Q\n\n✗ no\n✓ yes", false, &[], &markers).unwrap_err(),
+            TaskParseError::NoCorrectOption
+        );
+        assert_eq!(OptionMarkers::new("+", "+"), None);
+        assert_eq!(OptionMarkers::new("", "-"), None);
+    }
+
+    #[test]
+    fn multiple_images_question() {
+        let images = [
+            "https://example.com/1.png",
+            "https://example.com/2.png",
+            "https://example.com/3.png",
+        ]
+        .map(|link| QuestionElement::Image(link.parse().unwrap()));
+        for input in [
+            "![https://example.com/1.png]\n![https://example.com/2.png]\n![https://example.com/3.png]\n\n* a\n- b",
+            "![https://example.com/1.png]\n\n![https://example.com/2.png]\n\n![https://example.com/3.png]\n\n* a\n- b",
+        ] {
+            for multiline in [false, true] {
+                let task = Task::from_str(input, multiline).unwrap();
+                assert_eq!(task.question, images, "{input:?}");
+                assert!(
+                    matches!(task.kind, TaskKind::OneOf { ref options, .. } if options.len() == 2)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn text_between_images() {
+        let input = "Look\n![https://example.com/1.png]\nand\ncompare\n\n![https://example.com/2.png]\n\n* a\n- b";
+        let task = Task::from_str(input, true).unwrap();
+        assert_eq!(
+            task.question,
+            vec![
+                QuestionElement::Text("Look".into()),
+                QuestionElement::Image("https://example.com/1.png".parse().unwrap()),
+                QuestionElement::Text("and\ncompare".into()),
+                QuestionElement::Image("https://example.com/2.png".parse().unwrap()),
+            ]
+        );
+        // without merging, each line is separate message
+        assert_eq!(Task::from_str(input, false).unwrap().question.len(), 5);
+    }
+
+    #[test]
+    fn options_only_task() {
+        assert_eq!(
+            Task::from_str("* yes\n- no", false).unwrap_err(),
+            TaskParseError::NoQuestion
+        );
+        assert_eq!(
+            Task::from_str("\n* yes\n- no\n\nExplanation", true).unwrap_err(),
+            TaskParseError::NoQuestion
+        );
+    }
+
+    #[test]
+    fn image_only_question() {
+        let task = Task::from_str(
+            "![https://example.com/flag.png]\n\n* France\n- Italy",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            task.question,
+            vec![QuestionElement::Image(
+                "https://example.com/flag.png".parse().unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_one_of_task() {
+        let task = Task::from_str("Question\n\n* yes\n- no", false).unwrap();
+        assert_eq!(
+            task.kind,
+            TaskKind::OneOf {
+                options: vec!["yes".into(), "no".into()],
+                answers: vec![0],
+                pool: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_image_options() {
+        let task = Task::from_str(
+            "Which diagram is correct?\n\n* ![https://example.com/a.png]\n- ![https://example.com/b.png]\n- none",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            task.kind,
+            TaskKind::OneOf {
+                options: vec![
+                    QuestionElement::Image("https://example.com/a.png".parse().unwrap()),
+                    QuestionElement::Image("https://example.com/b.png".parse().unwrap()),
+                    "none".into(),
+                ],
+                answers: vec![0],
+                pool: Vec::new(),
+            }
+        );
+        assert_eq!(task.correct_answer(), "https://example.com/a.png");
+        assert!(matches!(
+            Task::from_str("Q\n\n* ![not a link]\n- b", false),
+            Err(TaskParseError::ImageShouldBeLink(_))
+        ));
+        assert_eq!(
+            Task::from_str("Q\n\n* ![https://example.com/a.png\n- b", false),
+            Err(TaskParseError::InvalidImageSyntax)
+        );
+    }
+
+    #[test]
+    fn image_links_should_be_http() {
+        let image = QuestionElement::from_str("![https://example.com/a.png]").unwrap();
+        assert_eq!(
+            image,
+            QuestionElement::Image("https://example.com/a.png".parse().unwrap())
+        );
+        assert_eq!(image.check_link(), Ok(()));
+        assert_eq!(
+            QuestionElement::from_str("![ftp://example.com/a.png]")
+                .unwrap()
+                .check_link(),
+            Err(TaskParseError::UnsupportedImageScheme("ftp".into()))
+        );
+        assert_eq!(
+            Task::from_str("Q\n\n* ![file:///etc/passwd]\n- b", false)
+                .unwrap()
+                .check_links(),
+            Err(TaskParseError::UnsupportedImageScheme("file".into()))
+        );
+        assert_eq!(
+            Task::from_str("Q\n\n* a\n- b\n\n!a[ftp://example.com/a.ogg]", false)
+                .unwrap()
+                .check_links(),
+            Err(TaskParseError::UnsupportedImageScheme("ftp".into()))
+        );
+        assert_eq!(
+            QuestionElement::from_str("!a[images/a.png]"),
+            Err(TaskParseError::AudioShouldBeLink(
+                url::ParseError::RelativeUrlWithoutBase
+            ))
+        );
+    }
+
+    #[test]
+    fn image_options_are_labeled_by_position() {
+        let TaskKind::OneOf { options, .. } = Task::from_str(
+            "Q\n\n* ![https://example.com/a.png]\n- b\n- ![https://example.com/c.png]",
+            false,
+        )
+        .unwrap()
+        .kind
+        else {
+            panic!("should be one of task");
+        };
+        assert_eq!(option_labels(&options), ["🖼 1", "b", "🖼 3"]);
+        // Labels are positions among shown options, so they follow shuffle.
+        let shuffled = [options[2].clone(), options[0].clone()];
+        assert_eq!(option_labels(&shuffled), ["🖼 1", "🖼 2"]);
+    }
+
+    #[test]
+    fn parse_several_correct_options() {
+        let task = Task::from_str("Prime number?\n\n* 2\n- 4\n* 3", false).unwrap();
+        assert_eq!(
+            task.kind,
+            TaskKind::OneOf {
+                options: vec!["2".into(), "4".into(), "3".into()],
+                answers: vec![0, 2],
+                pool: Vec::new(),
+            }
+        );
+        assert_eq!(task.correct_answer(), "2, 3");
+        assert_eq!(
+            Task::from_str("Prime number?\n\n* 2\n* 3", false),
+            Err(TaskParseError::NoIncorrectOption)
+        );
+    }
+
+    #[test]
+    fn parse_no_shuffle_directive() {
+        let task =
+            Task::from_str("!noshuffle\nWhich comes first?\n\n- b\n* a\n- c", false).unwrap();
+        assert!(!task.shuffle);
+        assert_eq!(
+            task.question,
+            vec![QuestionElement::Text("Which comes first?".into())]
+        );
+        assert_eq!(
+            task.kind,
+            TaskKind::OneOf {
+                options: vec!["b".into(), "a".into(), "c".into()],
+                answers: vec![1],
+                pool: Vec::new(),
+            }
+        );
+        assert!(
+            Task::from_str("Question\n\n* yes\n- no", false)
+                .unwrap()
+                .shuffle
+        );
+        // Shuffled task still should start with correct option.
+        assert_eq!(
+            Task::from_str("Which comes first?\n\n- b\n* a", false),
+            Err(TaskParseError::NoCorrectOption)
+        );
+        assert_eq!(
+            Task::from_str("!noshuffle\nQ\n\n- b\n- a", false),
+            Err(TaskParseError::NoCorrectOption)
+        );
+        assert_eq!(
+            Task::from_str("!noshuffle", false),
+            Err(TaskParseError::EmptyInput)
+        );
+    }
+
+    #[test]
+    fn options_without_correct_one() {
+        assert_eq!(
+            Task::from_str("Prime number?\n\n- 4\n- 6", false),
+            Err(TaskParseError::NoCorrectOption)
+        );
+    }
+
+    #[test]
+    fn invalid_ordering_tasks() {
+        assert_eq!(
+            Task::from_str("Question\n\n1. only", false),
+            Err(TaskParseError::NotEnoughOrderingItems)
+        );
+        assert_eq!(
+            Task::from_str("Question\n\n1. first\n1. second", false),
+            Err(TaskParseError::InvalidItemPositions)
+        );
+        assert_eq!(
+            Task::from_str("Question\n\n1. first\n3. third", false),
+            Err(TaskParseError::InvalidItemPositions)
+        );
+        assert_eq!(
+            Task::from_str("Question\n\n1. first\n- second", false),
+            Err(TaskParseError::InvalidItemPrefix)
+        );
+    }
+
+    #[test]
+    fn parse_single_blank() {
+        let task = Task::from_str(
+            "The capital of France is {{ Paris }}.\n\nExplanation",
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            task.question,
+            vec![QuestionElement::Text(format!(
+                "The capital of France is {BLANK}."
+            ))]
+        );
+        assert_eq!(
+            task.kind,
+            TaskKind::Cloze {
+                answers: vec!["Paris".into()]
+            }
+        );
+        assert!(task.explanation.is_some());
+    }
+
+    #[test]
+    fn parse_multiple_blanks() {
+        let task = Task::from_str("{{Rome}} is\nthe capital of {{Italy}}", true).unwrap();
+        assert_eq!(
+            task.question,
+            vec![QuestionElement::Text(format!(
+                "{BLANK} is\nthe capital of {BLANK}"
+            ))]
+        );
+        assert_eq!(
+            task.kind,
+            TaskKind::Cloze {
+                answers: vec!["Rome".into(), "Italy".into()]
+            }
+        );
+        assert_eq!(
+            Task::from_str("Broken {{blank", false),
+            Err(TaskParseError::UnclosedBlank)
+        );
+        assert_eq!(
+            Task::from_str("Empty {{ }} blank", false),
+            Err(TaskParseError::EmptyBlank)
+        );
+    }
+
+    #[test]
+    fn grade_blanks() {
+        let task = Task::from_str("{{Rome}} is the capital of {{Italy}}", false).unwrap();
+        assert_eq!(
+            task.check_blanks(&[" rome".into(), "Itali".into()]),
+            vec![true, true]
+        );
+        assert_eq!(
+            task.check_blanks(&["Paris".into(), "italy".into()]),
+            vec![false, true]
+        );
+        assert_eq!(task.check_blanks(&["Rome".into()]), vec![true, false]);
+    }
+
+    #[test]
+    fn parse_free_text_task() {
+        let task = Task::from_str("Capital of France?\n\n= Paris\n\nExplanation", false).unwrap();
+        assert_eq!(
+            task.kind,
+            TaskKind::FreeText {
+                answer: "Paris".into()
+            }
+        );
+        assert!(task.explanation.is_some());
+        assert!(matches!(
+            task.interactions().last(),
+            Some(TelegramInteraction::UserInput)
+        ));
+        assert_eq!(
+            Task::from_str("Capital of France?\n\n= Paris\n= Lyon", false),
+            Err(TaskParseError::InvalidFreeTextAnswer)
+        );
+        assert_eq!(
+            Task::from_str("Capital of France?\n\n=", false),
+            Err(TaskParseError::EmptyOptionText)
+        );
+    }
+
+    #[test]
+    fn free_text_exact_match() {
+        let task = Task::from_str("Capital of France?\n\n= Paris", false).unwrap();
+        assert!(task.check_free_text("Paris"));
+    }
+
+    #[test]
+    fn free_text_ignores_case_and_whitespace() {
+        let task = Task::from_str("Largest city?\n\n= New York", false).unwrap();
+        assert!(task.check_free_text("  new   YORK "));
+    }
+
+    #[test]
+    fn free_text_wrong_answer() {
+        let task = Task::from_str("Capital of France?\n\n= Paris", false).unwrap();
+        assert!(!task.check_free_text("Lyon"));
+        // Typos are forgiven only in blanks.
+        assert!(!task.check_free_text("Pariss"));
+    }
+
+    #[test]
+    fn grade_ordering() {
+        let task = Task::from_str(ORDERING, false).unwrap();
+        assert!(task.is_correct_order(&[1, 0, 2]));
+        assert!(!task.is_correct_order(&[0, 1, 2]));
+        assert!(!task.is_correct_order(&[1, 0]));
+    }
+}
@@ -4,11 +4,40 @@ use super::task::TaskParseError;
 
 #[derive(Debug, Clone)]
 pub enum TelegramInteraction {
-    OneOf(Vec<String>),
+    /// Buttons with [`option_labels`]. Images are sent before buttons, captioned with labels.
+    /// Answer is label of chosen option.
+    OneOf(Vec<QuestionElement>),
+    /// Learner taps all items one by one. Answer is indices of items in tapped order.
+    Ordering(Vec<String>),
+    /// Learner toggles options with [`option_labels`] and confirms choice.
+    /// Answer is indices of chosen options, ascending, or text of tapped `extra` button.
+    MultiSelect {
+        options: Vec<QuestionElement>,
+        /// Buttons, that answer at once, like "I don't know".
+        extra: Vec<String>,
+    },
+    /// Telegram quiz poll. Answer is index of chosen option.
+    Poll {
+        question: String,
+        options: Vec<String>,
+        correct: usize,
+    },
     Text(String),
     UserInput,
     Image(Url),
+    /// Images in one message. Empty caption isn't sent.
+    Album {
+        images: Vec<Url>,
+        caption: String,
+    },
+    /// OGG files are sent as voice messages, other as audio files.
+    Audio(Url),
     PersonalImage(Vec<u8>),
+    /// Text file.
+    Document {
+        name: String,
+        contents: String,
+    },
 }
 impl<T> From<T> for TelegramInteraction
 where
@@ -24,6 +53,31 @@ where
 pub enum QuestionElement {
     Text(String),
     Image(Url),
+    Audio(Url),
+}
+
+impl<T> From<T> for QuestionElement
+where
+    T: Into<String>,
+{
+    fn from(value: T) -> Self {
+        QuestionElement::Text(value.into())
+    }
+}
+
+/// Button labels of options. Text option is labeled with its text, and media option with its
+/// position, because links don't fit in callback data. So options with equal labels are
+/// the same answer, like text options with the same text.
+pub fn option_labels(options: &[QuestionElement]) -> Vec<String> {
+    options
+        .iter()
+        .enumerate()
+        .map(|(ix, option)| match option {
+            QuestionElement::Text(text) => text.clone(),
+            QuestionElement::Image(_) => format!("🖼 {}", ix + 1),
+            QuestionElement::Audio(_) => format!("🔊 {}", ix + 1),
+        })
+        .collect()
 }
 
 impl From<QuestionElement> for TelegramInteraction {
@@ -31,6 +85,7 @@ impl From<QuestionElement> for TelegramInteraction {
         match element {
             QuestionElement::Text(text) => text.into(),
             QuestionElement::Image(image) => TelegramInteraction::Image(image),
+            QuestionElement::Audio(audio) => TelegramInteraction::Audio(audio),
         }
     }
 }
@@ -42,15 +97,38 @@ impl QuestionElement {
         assert!(!input.is_empty());
 
         match input.as_bytes()[0] {
+            b'!' if input.starts_with("!a[") => {
+                let link = input
+                    .strip_prefix("!a[")
+                    .unwrap()
+                    .strip_suffix("]")
+                    .ok_or(TaskParseError::InvalidAudioSyntax)?;
+                Ok(QuestionElement::Audio(
+                    link.parse().map_err(TaskParseError::AudioShouldBeLink)?,
+                ))
+            }
             b'!' => {
                 let link = input
                     .strip_prefix("![")
                     .ok_or(TaskParseError::InvalidImageSyntax)?
                     .strip_suffix("]")
                     .ok_or(TaskParseError::InvalidImageSyntax)?;
-                Ok(QuestionElement::Image(link.parse()?))
+                Ok(QuestionElement::Image(
+                    link.parse().map_err(TaskParseError::ImageShouldBeLink)?,
+                ))
             }
             _ => Ok(QuestionElement::Text(input.to_string())),
         }
     }
+    /// Telegram downloads media only by http(s) links, so other links fail only when sent.
+    /// Isn't part of parsing, so saved courses with such links still load.
+    pub fn check_link(&self) -> Result<(), TaskParseError> {
+        match self {
+            QuestionElement::Text(_) => Ok(()),
+            QuestionElement::Image(link) | QuestionElement::Audio(link) => match link.scheme() {
+                "http" | "https" => Ok(()),
+                scheme => Err(TaskParseError::UnsupportedImageScheme(scheme.to_owned())),
+            },
+        }
+    }
 }
@@ -1,8 +1,12 @@
-use std::cmp::max;
+use std::{
+    cmp::max,
+    collections::{BTreeMap, HashSet},
+};
 
 use anyhow::Context;
 use course_graph::{
     graph::CourseGraph,
+    parsing::normalize_card_name,
     progress_store::{TaskProgress, TaskProgressStoreExt},
 };
 use dashmap::DashMap;
@@ -10,28 +14,57 @@ use graphviz_rust::{
     cmd::Format,
     printer::{DotPrinter, PrinterContext},
 };
+use rand::{SeedableRng, rngs::StdRng};
 use teloxide_core::{
     RequestError,
     payloads::SendMessageSetters,
     prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup, Update, UpdateKind, User},
+    types::{
+        InlineKeyboardButton, InlineKeyboardMarkup, InputFile, ParseMode, Update, UpdateKind, User,
+    },
 };
 
+mod db_pool;
 mod event_handler;
 mod handlers;
+mod health;
+mod inline_query;
 mod interaction_types;
+mod media_cache;
+mod menu;
+mod settings;
 mod state;
+mod templates;
+mod update_limit;
 mod utils;
 
 use database::*;
 
 use crate::{
     event_handler::{
-        complete_card, handle_changing_course_graph, handle_changing_deque, synchronize,
+        CardAttempt, CardContext,
+        analytics::CourseStats,
+        best_next_card, complete_card, confirm, copy_progress, daily_limit_reached, goals_message,
+        handle_changing_course_graph, handle_changing_deque, handle_note, intervals_message,
+        move_on_explanation, next_card, next_foundation_card, next_random_card, preview_intervals,
+        progress_store::{parse_weights, weights_to_string},
+        project_workload, reset_schedule, review_counts, revision_cards, set_weights, synchronize,
+        today, workload_message,
+    },
+    handlers::{
+        callback_handler, lint_markdown, poll_answer_handler, progress_on_user_event,
+        send_interactions, send_literal, settings_keyboard,
+    },
+    inline_query::inline_query_handler,
+    interaction_types::{Task, TelegramInteraction, deque, telegram_interaction::QuestionElement},
+    menu::{MenuScreen, menu_keyboard},
+    settings::{
+        GraphView, LatencyThresholds, MAX_DAILY_REVIEW_LIMIT, MAX_EXPLANATION_LEN,
+        MAX_LATENCY_THRESHOLD_SECS, MIN_EXPLANATION_LEN, UserSettings,
     },
-    handlers::{callback_handler, progress_on_user_event, send_interactions, send_markdown},
-    interaction_types::{TelegramInteraction, deque::Deque},
     state::*,
+    templates::{find_template, templates_message},
+    update_limit::UpdateLimiter,
     utils::ResultExt,
 };
 mod database;
@@ -42,7 +75,14 @@ async fn main() {
     pretty_env_logger::init();
     let bot = Bot::from_env();
     let users_state: &DashMap<UserId, UserState> = Box::leak(Box::new(DashMap::new()));
-    db_create_tables();
+    db_create_tables().expect("failed to create database tables");
+    if let Ok(port) = std::env::var("HEALTH_PORT") {
+        let port = port.parse().expect("'HEALTH_PORT' should be port number");
+        let bot = bot.clone();
+        tokio::spawn(async move { health::serve(port, bot).await.log_err() });
+    }
+
+    let limiter = UpdateLimiter::from_env();
 
     log::info!("Bot started");
 
@@ -73,7 +113,9 @@ async fn main() {
             offset = max(offset, update.id.0);
 
             let bot = bot.clone();
-            tokio::spawn(update_handler(bot, update, users_state));
+            limiter
+                .spawn(update_handler(bot, update, users_state))
+                .await;
         }
     }
 }
@@ -97,52 +139,90 @@ async fn update_handler(bot: Bot, update: Update, user_states: &DashMap<UserId,
             };
             assert!(!text.is_empty());
             log::trace!("user {user:?} sends message '{text}'.");
-            let user_state = user_states.entry(user.id).or_default();
-            match user_state.current_screen {
-                Screen::Main => {
-                    handle_main_menu_interaction(bot, user, text, user_state)
-                        .await
-                        .log_err();
-                }
-                Screen::Course(course_id) => {
-                    match db_get_course(course_id).unwrap().owner_id == user.id {
-                        true => {
-                            handle_owned_course_interaction(
-                                bot,
-                                user,
-                                text,
-                                course_id,
-                                user_state,
-                                user_states,
-                            )
-                            .await
-                            .log_err();
-                        }
-                        false => {
-                            handle_learned_course_interaction(
-                                bot,
-                                user,
-                                text,
-                                course_id,
-                                user_state,
-                                user_states,
-                            )
-                            .await
-                            .log_err();
-                        }
-                    };
-                }
-            }
+            handle_text_message(bot, user, text, user_states).await;
         }
         UpdateKind::CallbackQuery(callback_query) => {
             callback_handler(bot, callback_query, user_states)
                 .await
                 .log_err();
         }
+        UpdateKind::PollAnswer(poll_answer) => {
+            poll_answer_handler(bot, poll_answer, user_states)
+                .await
+                .log_err();
+        }
+        UpdateKind::InlineQuery(inline_query) => {
+            inline_query_handler(bot, inline_query).await.log_err();
+        }
         _ => todo!(),
     };
 }
 
+/// Typed command, or command of `/menu` button.
+async fn handle_text_message(
+    bot: Bot,
+    user: &User,
+    text: &str,
+    user_states: &DashMap<UserId, UserState>,
+) {
+    // Screen is saved, so learner stays in course after restart.
+    let user_state = user_states.entry(user.id).or_insert_with(|| UserState {
+        current_screen: db_load_user_screen(user.id).log_err().unwrap_or_default(),
+        ..Default::default()
+    });
+    let screen = user_state.current_screen;
+    match screen {
+        Screen::Main => {
+            handle_main_menu_interaction(bot, user, text, user_state, user_states)
+                .await
+                .log_err();
+        }
+        Screen::Settings => {
+            handle_settings_interaction(bot, user, text, user_state)
+                .await
+                .log_err();
+        }
+        Screen::Course(course_id) => {
+            let owned = db_get_existing_course(course_id)
+                .log_err()
+                .map(|course| course.owner_id == user.id);
+            match owned {
+                Some(true) => {
+                    handle_owned_course_interaction(
+                        bot,
+                        user,
+                        text,
+                        course_id,
+                        user_state,
+                        user_states,
+                    )
+                    .await
+                    .log_err();
+                }
+                Some(false) => {
+                    handle_learned_course_interaction(
+                        bot,
+                        user,
+                        text,
+                        course_id,
+                        user_state,
+                        user_states,
+                    )
+                    .await
+                    .log_err();
+                }
+                None => {}
+            };
+        }
+    }
+    let new_screen = user_states
+        .get(&user.id)
+        .map(|user_state| user_state.current_screen);
+    if let Some(new_screen) = new_screen.filter(|&new_screen| new_screen != screen) {
+        db_save_user_screen(user.id, new_screen).log_err();
+    }
+}
+
 async fn send_help_message(
     bot: Bot,
     user: &User,
@@ -150,37 +230,90 @@ async fn send_help_message(
 ) -> anyhow::Result<()> {
     let main_menu_help_message = "
 /help - Display all commands
+/menu - Show buttons for common commands
 
-/create_course - Create new course and get it's ID
+/create_course [template=NAME] - Create new course and get it's ID
+/templates - List starter courses
 /list - List all your courses
 /course COURSE_ID - Go to course menu
+/settings - Change your preferences
+/export_my_data - Download everything the bot stores about you
+/delete_my_data - Delete your progress and settings, and optionally your courses
+";
+    let settings_help_message = "
+/help - Display all commands
+/menu - Show buttons for common commands
+/exit - Go to main menu
+
+Use buttons under settings message to change them.
 ";
     let owned_course_help_message = "
 /help — Display all commands
+/menu — Show buttons for common commands
 /exit - Go to main menu
 
-/preview CARD_NAME — Try to complete card
+/preview CARD_NAME [N] — Try to complete card, or its task number N
 /graph — View course structure
 /change_course_graph
 /change_deque
 /view_course_graph_source
 /view_deque_source
 /view_course_errors
+/revise — Complete all due cards of this course, until you give up on one
+/merge_course COURSE_ID — Add cards of your other course to this one. Card names should be unique
+/delete_course — Delete this course with progress of all its learners
+/lint_markdown — Find task texts, Telegram can't send as markdown
+/preview_graph_state CARD=STATE,... — View graph for learner with these card states(good, failed, not_started)
+/stats_global [COUNT] — View cards, learners struggle with most
+/export_analytics — Download per card stats as CSV
+/set_case_sensitive_names on|off — Treat 'Foo' and 'foo' as different cards
+/set_fail_propagation_depth N|unlimited — How many levels of dependents become yellow after a fail
+/set_cascade_fails on|off — Whether a fail makes dependents yellow at all
+/set_linear off|topological|CARD, ... — Make learners start cards strictly one after another, in topological or given order
+/set_description [TEXT] — Shown to learners as is, when they enter the course. Without TEXT removes description
+/set_move_on_after N|never — Suggest learner to move on after N consecutive fails of one card
+/set_daily_limit N|none — Allow learners at most N reviews per day
+/set_max_explanation_len N|default — Longer explanations are truncated and sent as file
+/set_native_polls on|off — Ask multiple choice questions as Telegram quiz polls
+/set_ask_difficulty on|off — Ask learner how hard correct answer was, for more accurate schedule
+/set_difficulty_by_time EASY_SECS HARD_SECS|off — Grade correct answers faster than EASY_SECS as easy and slower than HARD_SECS as hard
+/set_compact_questions on|off — Send question texts and images as one message
+/set_layered_graph on|off — Draw cards with the same depth of dependencies in one row
+/set_public on|off — List course in inline search by title(first line of description)
 ";
     let learned_course_help_message = "
 /help — Display all commands
+/menu — Show buttons for common commands
 /exit - Go to main menu
+/leave — Remove this course from your list, with all progress
 
-/card CARD_NAME — Try to complete card
-/graph — View course structure
+/card CARD_NAME[, CARD_NAME...] — Try to complete cards one by one
+/next — Complete best card to study now: failed, then new, then overdue
+/review [random|foundations] — Complete due cards one by one, most overdue first, in random order or dependencies first
+/intervals CARD_NAME — View when card would be repeated after each answer: Again, Hard, Good or Easy
+/blocked_by CARD_NAME — View cards, you can't learn until you repeat this failed card
+/set_my_daily_limit N|default — Do at most N reviews per day
+/copy_progress COURSE_ID — Copy progress of cards with the same names from other course, you learn
+/weights — View FSRS weights, used to schedule your reviews
+/set_weights W1, W2, ... — Use FSRS weights, optimized elsewhere
+/note CARD_NAME — Save private note, shown with this card
+/set_goal CARD_NAME — Track progress toward this card
+/remove_goal CARD_NAME
+/goal — View progress toward your goals
+/stats — Count your cards by state: learned, failed, available and so on
+/simulate [DAYS] — View expected number of reviews per day for next DAYS days(7 by default)
+/reset_course_schedule — Make all cards due for review, keeping your progress
+/checkpoint — Remember current progress, to compare with it later
+/graph [image|text|list|diff] — View your progress. Without argument uses view from /settings. With diff shows cards, that improved since /checkpoint
 ";
 
     bot.send_message(
         user.id,
         match user_state.current_screen {
             Screen::Main => main_menu_help_message,
+            Screen::Settings => settings_help_message,
             Screen::Course(course_id) => {
-                match db_get_course(course_id).unwrap().owner_id == user.id {
+                match db_get_existing_course(course_id)?.owner_id == user.id {
                     true => owned_course_help_message,
                     false => learned_course_help_message,
                 }
@@ -192,6 +325,36 @@ async fn send_help_message(
     Ok(())
 }
 
+async fn send_menu(bot: Bot, user: &User, user_state: &MutUserState<'_>) -> anyhow::Result<()> {
+    let screen = match user_state.current_screen {
+        Screen::Main => MenuScreen::Main,
+        Screen::Settings => MenuScreen::Settings,
+        Screen::Course(course_id) => match db_get_existing_course(course_id)?.owner_id == user.id {
+            true => MenuScreen::OwnedCourse,
+            false => MenuScreen::LearnedCourse,
+        },
+    };
+    bot.send_message(user.id, "What do you want to do?")
+        .reply_markup(menu_keyboard(screen))
+        .await
+        .context("failed to send menu")?;
+    Ok(())
+}
+
+/// Learners can't stay in deleted course.
+fn leave_deleted_courses(user_states: &DashMap<UserId, UserState>, deleted: &[CourseId]) {
+    for mut state in user_states.iter_mut() {
+        let in_deleted = match state.current_screen {
+            Screen::Course(course_id) => deleted.contains(&course_id),
+            Screen::Main | Screen::Settings => false,
+        };
+        if in_deleted {
+            state.current_screen = Screen::Main;
+            state.current_interaction = None;
+        }
+    }
+}
+
 fn log_user_command(user: &User, command_name: &str) {
     log::info!(
         "user {}({}) sends {command_name} command",
@@ -205,6 +368,7 @@ async fn handle_main_menu_interaction(
     user: &User,
     message: &str,
     mut user_state: MutUserState<'_>,
+    user_states: &DashMap<UserId, UserState>,
 ) -> anyhow::Result<()> {
     let (first_word, tail) = message.trim().split_once(" ").unwrap_or((message, ""));
     match first_word {
@@ -212,6 +376,33 @@ async fn handle_main_menu_interaction(
             log_user_command(user, "help");
             send_help_message(bot, user, &user_state).await?;
         }
+        "/menu" => {
+            log_user_command(user, "menu");
+            send_menu(bot, user, &user_state).await?;
+        }
+        "/start" if !tail.is_empty() => {
+            log_user_command(user, "start with payload");
+            let Some(course_id) = parse_start_payload(tail) else {
+                log::warn!("user {} sends unknown start payload '{tail}'", user.id);
+                bot.send_message(user.id, "This link is broken.")
+                    .await
+                    .context("failed to notify user, that start link is broken")?;
+                send_help_message(bot, user, &user_state).await?;
+                return Ok(());
+            };
+            if !is_reachable_by_link(user.id, course_id)? {
+                log::warn!(
+                    "user {} follows link to hidden course {}",
+                    user.id,
+                    course_id.0
+                );
+                bot.send_message(user.id, "Course not found.")
+                    .await
+                    .context("failed to notify user, that course from start link is not found")?;
+                return Ok(());
+            }
+            enter_course(bot, user, course_id, user_state).await?;
+        }
         "/start" => {
             log_user_command(user, "start");
             // TODO: onboarding
@@ -221,11 +412,39 @@ async fn handle_main_menu_interaction(
         }
         "/create_course" => {
             log_user_command(user, "create_course");
-            let course_id = db_insert(Course {
-                owner_id: user.id,
-                structure: CourseGraph::default(),
-                tasks: Deque::default(),
-            });
+            let course = if tail.is_empty() {
+                Course::empty(user.id)
+            } else {
+                let Some(name) = tail.strip_prefix("template=") else {
+                    bot.send_message(
+                        user.id,
+                        "create_course command expects nothing or 'template=NAME'.",
+                    )
+                    .await
+                    .context("failed to notify user, that create_course argument is invalid")?;
+                    return Ok(());
+                };
+                let Some(template) = find_template(name.trim()) else {
+                    bot.send_message(
+                        user.id,
+                        format!("There is no template '{}'. See /templates.", name.trim()),
+                    )
+                    .await
+                    .context("failed to notify user, that template doesn't exist")?;
+                    return Ok(());
+                };
+                let (structure, tasks) = template.build();
+                Course::empty(user.id)
+                    .with(structure, tasks)
+                    .map_err(|report| {
+                        anyhow::anyhow!(
+                            "template {} is inconsistent: {}",
+                            template.name,
+                            report.messages().join(" ")
+                        )
+                    })?
+            };
+            let course_id = db_insert(course)?;
             bot.send_message(user.id, format!("Course created with id {}.", course_id.0))
                 .await
                 .context("failed to confirm, that course created")
@@ -236,6 +455,20 @@ async fn handle_main_menu_interaction(
                 .context("failed to notify user, that he is now in course menu")?;
             send_help_message(bot, user, &user_state).await?;
         }
+        "/templates" => {
+            log_user_command(user, "templates");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "templates command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that templates command doesn't expect arguments",
+                    )?;
+                return Ok(());
+            }
+            bot.send_message(user.id, templates_message())
+                .await
+                .context("failed to send templates")?;
+        }
         "/course" => {
             let Ok(course_id) = tail.parse() else {
                 bot.send_message(
@@ -251,24 +484,109 @@ async fn handle_main_menu_interaction(
                 user.username.clone().unwrap_or("unknown".into()),
                 user.id
             );
-            let course_id = CourseId(course_id);
-            if db_get_course(course_id).is_none() {
-                bot.send_message(user.id, "Can't find course with this id.")
+            enter_course(bot, user, CourseId(course_id), user_state).await?;
+        }
+        "/settings" => {
+            log_user_command(user, "settings");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "settings command doesn't expect any arguments.")
                     .await
-                    .context("failed to notify user, that course with this id doesn't exists")?;
+                    .context(
+                        "failed to notify user, that settings command doesn't expect any arguments",
+                    )?;
                 return Ok(());
             }
-            user_state.current_screen = Screen::Course(course_id);
-            db_add_course_to_user(user.id, course_id);
-            bot.send_message(user.id, "You are now in course menu.")
+            let settings = db_get_user_settings(user.id)?;
+            bot.send_message(user.id, settings.message())
+                .reply_markup(settings_keyboard(&settings))
                 .await
-                .context("failed to notify user, that he is now in course menu")?;
-            send_help_message(bot, user, &user_state).await?;
+                .context("failed to send settings")?;
+            user_state.current_screen = Screen::Settings;
+        }
+        "/export_my_data" => {
+            log_user_command(user, "export_my_data");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "export_my_data command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that export_my_data command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            let export = serde_json::to_string_pretty(&db_export_user_data(user.id)?)
+                .context("failed to serialize user data")?;
+            bot.send_document(
+                user.id,
+                InputFile::memory(export.into_bytes()).file_name("my_data.json"),
+            )
+            .await
+            .context("failed to send user data")?;
+        }
+        "/delete_my_data" => {
+            log_user_command(user, "delete_my_data");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "delete_my_data command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that delete_my_data command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            if !confirm(
+                bot.clone(),
+                user.id,
+                "All your progress, notes and settings will be deleted. This can't be undone. Continue?",
+                user_state,
+            )
+            .await?
+            {
+                bot.send_message(user.id, "Your data is kept.")
+                    .await
+                    .context("failed to notify user, that data deletion is cancelled")?;
+                return Ok(());
+            }
+            let owned_courses = db_select_courses_by_owner(user.id)?;
+            let mut delete_courses = false;
+            if !owned_courses.is_empty() {
+                let learners = owned_courses
+                    .iter()
+                    .map(|&course_id| db_all_progress_for_course(course_id).map(|all| all.len()))
+                    .sum::<DbResult<usize>>()?;
+                let user_state = user_states.get_mut(&user.id).unwrap();
+                delete_courses = confirm(
+                    bot.clone(),
+                    user.id,
+                    &format!(
+                        "Also delete your {} courses? Their learners will lose {learners} enrollments with progress.",
+                        owned_courses.len()
+                    ),
+                    user_state,
+                )
+                .await?;
+            }
+            db_delete_user_data(user.id)?;
+            if delete_courses {
+                for &course_id in &owned_courses {
+                    db_delete_course(course_id)?;
+                }
+                leave_deleted_courses(user_states, &owned_courses);
+            }
+            user_states.remove(&user.id);
+            bot.send_message(
+                user.id,
+                if delete_courses {
+                    "Your data and courses are deleted."
+                } else {
+                    "Your data is deleted. Your courses are kept."
+                },
+            )
+            .await
+            .context("failed to confirm data deletion")?;
         }
         "/list" => {
             log_user_command(user, "list");
-            let owned_courses = db_select_courses_by_owner(user.id);
-            let learned_courses = db_list_user_learned_courses(user.id);
+            let owned_courses = db_select_courses_by_owner(user.id)?;
+            let learned_courses = db_list_user_learned_courses(user.id)?;
             let mut message = String::new();
             message.push_str("# Owned\n");
             for course in owned_courses {
@@ -293,158 +611,541 @@ async fn handle_main_menu_interaction(
     Ok(())
 }
 
-async fn handle_learned_course_interaction(
+/// Payload of `t.me/BOT?start=PAYLOAD` links. Only `course_N` is supported.
+fn parse_start_payload(payload: &str) -> Option<CourseId> {
+    let course_id = payload.strip_prefix("course_")?;
+    if course_id.is_empty() || !course_id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    course_id.parse().ok().map(CourseId)
+}
+
+/// Start links are shared outside the bot, so they lead only to public, own
+/// or already learned courses.
+fn is_reachable_by_link(user_id: UserId, course_id: CourseId) -> DbResult<bool> {
+    let Some(course) = db_get_course(course_id)? else {
+        return Ok(false);
+    };
+    Ok(course.owner_id == user_id
+        || course.settings.public
+        || db_list_user_learned_courses(user_id)?.contains(&course_id))
+}
+
+/// Callback data of "Review now" button is `review:<course id>`.
+const REVIEW_CALLBACK_PREFIX: &str = "review:";
+
+/// Callback data of "Show explanation" button, sent with move on suggestion.
+const MOVE_ON_EXPLANATION_CALLBACK: &str = "move_on_explanation";
+
+fn parse_review_callback(data: &str) -> Option<CourseId> {
+    data.strip_prefix(REVIEW_CALLBACK_PREFIX)?
+        .parse()
+        .ok()
+        .map(CourseId)
+}
+
+/// Summary for learner, entering the course, with "Review now" button, if there is something to review.
+/// None for course owner or if learner turned greeting off.
+fn session_greeting(
+    user_id: UserId,
+    course_id: CourseId,
+    course: &Course,
+) -> DbResult<Option<(String, Option<InlineKeyboardMarkup>)>> {
+    if course.owner_id == user_id || !db_get_user_settings(user_id)?.session_greeting {
+        return Ok(None);
+    }
+    let (due, new) = review_counts(user_id, course_id)?;
+    if due == 0 && new == 0 {
+        return Ok(Some((
+            "Welcome back! Nothing to review right now.".into(),
+            None,
+        )));
+    }
+    let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        "Review now",
+        format!("{REVIEW_CALLBACK_PREFIX}{}", course_id.0),
+    )]]);
+    Ok(Some((
+        format!("Welcome back! Cards to review: {due}. New cards available: {new}."),
+        Some(keyboard),
+    )))
+}
+
+/// Switches screen to the course and adds it to learned ones.
+/// Returns None if course doesn't exist.
+fn switch_to_course(
+    user_id: UserId,
+    course_id: CourseId,
+    user_state: &mut UserState,
+) -> DbResult<Option<Course>> {
+    let Some(course) = db_get_course(course_id)? else {
+        return Ok(None);
+    };
+    user_state.current_screen = Screen::Course(course_id);
+    db_add_course_to_user(user_id, course_id)?;
+    Ok(Some(course))
+}
+
+async fn enter_course(
     bot: Bot,
     user: &User,
-    message: &str,
     course_id: CourseId,
     mut user_state: MutUserState<'_>,
+) -> anyhow::Result<()> {
+    let Some(course) = switch_to_course(user.id, course_id, &mut user_state)? else {
+        bot.send_message(user.id, "Can't find course with this id.")
+            .await
+            .context("failed to notify user, that course with this id doesn't exists")?;
+        return Ok(());
+    };
+    bot.send_message(user.id, "You are now in course menu.")
+        .await
+        .context("failed to notify user, that he is now in course menu")?;
+    if let Some(description) = course.entry_description(user.id) {
+        send_literal(&bot, user.id, description)
+            .await
+            .context("failed to send course description")?;
+    }
+    if let Some((greeting, keyboard)) = session_greeting(user.id, course_id, &course)? {
+        let mut request = bot.send_message(user.id, greeting);
+        if let Some(keyboard) = keyboard {
+            request = request.reply_markup(keyboard);
+        }
+        request.await.context("failed to send session greeting")?;
+    }
+    send_help_message(bot, user, &user_state).await?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReviewOrder {
+    /// Most overdue cards first.
+    Overdue,
+    /// Seed makes order reproducible.
+    Random { seed: u64 },
+    /// Dependencies before dependents, cards with the same dependencies by name.
+    Foundations,
+}
+
+/// Learns recommended cards, until nothing is left or learner starts something else.
+/// Every card is served at most once per session.
+async fn review_session(
+    bot: Bot,
+    user: &User,
+    course_id: CourseId,
+    order: ReviewOrder,
     user_states: &DashMap<UserId, UserState>,
 ) -> anyhow::Result<()> {
-    let (first_word, tail) = message.trim().split_once(" ").unwrap_or((message, ""));
-    match first_word {
-        "/help" => {
-            log_user_command(user, "help");
-            send_help_message(bot, user, &user_state).await?;
+    let mut served = HashSet::new();
+    let mut rng = match order {
+        ReviewOrder::Overdue | ReviewOrder::Foundations => None,
+        ReviewOrder::Random { seed } => Some(StdRng::seed_from_u64(seed)),
+    };
+    loop {
+        let Some(user_state) = user_states.get_mut(&user.id).filter(
+            |user_state| matches!(user_state.current_screen, Screen::Course(id) if id == course_id),
+        ) else {
+            bot.send_message(user.id, "Enter this course to review it.")
+                .await
+                .context("failed to notify user, that he should enter course to review it")?;
+            return Ok(());
+        };
+        let card_name = match &mut rng {
+            None if order == ReviewOrder::Foundations => {
+                next_foundation_card(user.id, course_id, &user_state.session_fails, &served)?
+            }
+            None => next_card(user.id, course_id, &user_state.session_fails)?
+                .filter(|card_name| !served.contains(card_name)),
+            Some(rng) => {
+                next_random_card(user.id, course_id, &user_state.session_fails, &served, rng)?
+            }
+        };
+        let Some(card_name) = card_name else {
+            let text = if served.is_empty() {
+                "Nothing to review right now."
+            } else {
+                "Review finished."
+            };
+            bot.send_message(user.id, text)
+                .await
+                .context("failed to notify user, that review is finished")?;
+            return Ok(());
+        };
+        served.insert(card_name.clone());
+        let outcome = learn_card(
+            bot.clone(),
+            user,
+            course_id,
+            card_name,
+            user_state,
+            user_states,
+        )
+        .await?;
+        if outcome != CardOutcome::Answered {
+            return Ok(());
         }
-        "/exit" => {
-            log_user_command(user, "exit");
-            user_state.current_screen = Screen::Main;
-            bot.send_message(user.id, "You are now in main menu.")
+    }
+}
+
+/// Owner goes through all due cards of own course, until they answer "I don't know".
+/// Unlike review session, there is no daily limit and cards aren't skipped.
+async fn revise_owned_course(
+    bot: Bot,
+    user: &User,
+    course_id: CourseId,
+    user_states: &DashMap<UserId, UserState>,
+) -> anyhow::Result<()> {
+    let cards = revision_cards(user.id, course_id)?;
+    if cards.is_empty() {
+        bot.send_message(user.id, "Nothing to revise right now.")
+            .await
+            .context("failed to notify user, that nothing to revise")?;
+        return Ok(());
+    }
+    let course = db_get_existing_course(course_id)?;
+    for card_name in cards {
+        let Some(user_state) = user_states.get_mut(&user.id).filter(
+            |user_state| matches!(user_state.current_screen, Screen::Course(id) if id == course_id),
+        ) else {
+            bot.send_message(user.id, "Enter this course to revise it.")
                 .await
-                .context("failed to notify user, that he is now in main menu")?;
-            send_help_message(bot, user, &user_state).await?;
+                .context("failed to notify user, that he should enter course to revise it")?;
+            return Ok(());
+        };
+        let card_progress = db_get_progress(user.id, course_id)?.tasks[&card_name].clone();
+        let Some(task) =
+            course.tasks.tasks.get(&card_name).and_then(|tasks| {
+                choose_task(tasks, card_progress.meaningful_repetitions, user.id)
+            })
+        else {
+            continue;
+        };
+        let completion = complete_card(
+            bot.clone(),
+            user.id,
+            task,
+            CardAttempt::Review {
+                note: card_progress.note,
+            },
+            CardContext {
+                course_id,
+                card_name: &card_name,
+                settings: &course.settings,
+            },
+            user_state,
+            user_states,
+        )
+        .await;
+        let interrupted = !completion.meaningful && !completion.revealed;
+        let gave_up = completion.gave_up;
+        let mut progress = db_get_progress(user.id, course_id)?;
+        completion.record(&mut progress, &card_name);
+        db_set_course_progress(user.id, course_id, progress)?;
+        if interrupted {
+            return Ok(());
         }
-        "/card" => {
-            log_user_command(user, "card");
-            if tail.contains(" ") {
-                bot.send_message(user.id, "Error: Card name should not contain spaces.")
-                    .await
-                    .context("failed to send user, that card name should not contain spaces")?;
-                return Ok(());
-            }
-            if tail.is_empty() {
-                bot.send_message(
-                    user.id,
-                    "Error: You should provide card name, you want to learn.",
-                )
+        if gave_up {
+            bot.send_message(user.id, "Revision stopped. Use /revise to continue.")
                 .await
-                .context("failed to notify user, that card command should contain card name")?;
-                return Ok(());
-            }
-            let card_name = tail;
-            log::info!(
-                "user {}({}) sends card '{card_name}' command",
-                user.username.clone().unwrap_or("unknown".into()),
-                user.id
-            );
+                .context("failed to notify user, that revision is stopped")?;
+            return Ok(());
+        }
+    }
+    bot.send_message(user.id, "Revision finished.")
+        .await
+        .context("failed to notify user, that revision is finished")?;
+    Ok(())
+}
 
-            synchronize(user.id, course_id);
-            let task = {
-                let course = db_get_course(course_id).unwrap();
-                let Some(tasks) = course.tasks.tasks.get(card_name) else {
-                    send_interactions(
-                        bot,
-                        user.id,
-                        vec!["Card with this name not found".into()],
-                        user_state,
+/// Explicit argument overrides learner's preference.
+fn graph_view_for(argument: &str, settings: &UserSettings) -> Option<GraphView> {
+    match argument {
+        "" => Some(settings.graph_view),
+        argument => GraphView::from_name(argument),
+    }
+}
+
+const DEFAULT_SIMULATION_DAYS: u32 = 7;
+const MAX_SIMULATION_DAYS: u32 = 30;
+
+/// Number in allowed bounds, or `unlimited` keyword, which means no limit.
+fn parse_daily_limit(input: &str, unlimited: &str) -> Option<Option<u32>> {
+    if input == unlimited {
+        return Some(None);
+    }
+    input
+        .parse()
+        .ok()
+        .filter(|limit| (1..=MAX_DAILY_REVIEW_LIMIT).contains(limit))
+        .map(Some)
+}
+
+/// `EASY HARD` seconds, easy should be faster, or `off`.
+fn parse_latency_thresholds(input: &str) -> Option<Option<LatencyThresholds>> {
+    if input == "off" {
+        return Some(None);
+    }
+    let (easy, hard) = input.split_once(' ')?;
+    let thresholds = LatencyThresholds {
+        easy_secs: easy.trim().parse().ok()?,
+        hard_secs: hard.trim().parse().ok()?,
+    };
+    (thresholds.easy_secs < thresholds.hard_secs
+        && thresholds.hard_secs <= MAX_LATENCY_THRESHOLD_SECS)
+        .then_some(Some(thresholds))
+}
+
+/// MarkdownV2 problems in texts of all tasks, with card and task, where they are.
+fn course_markdown_problems(course: &Course) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (card_name, tasks) in &course.tasks.tasks {
+        for (task_id, task) in tasks {
+            let texts = task
+                .question
+                .iter()
+                .chain(task.explanation.iter().flatten())
+                .filter_map(|element| match element {
+                    QuestionElement::Text(text) => Some(text),
+                    _ => None,
+                });
+            for text in texts {
+                problems.extend(lint_markdown(text).into_iter().map(|problem| {
+                    format!(
+                        "Card '{card_name}', task {task_id}: {} in '{}'",
+                        problem.message, problem.line
                     )
-                    .await
-                    .context("failed to notify user, that card with this name not found")?;
-                    return Ok(());
-                };
-                let tasks_list = tasks.values().collect::<Vec<_>>();
-                let meaningful_repetitions = db_get_progress(user.id, course_id).tasks
-                    [&card_name.to_owned()]
-                    .meaningful_repetitions;
-                if (meaningful_repetitions as usize) < tasks_list.len() {
-                    tasks_list[((meaningful_repetitions as usize)
-                        + usize::try_from(user.id.0).unwrap() % tasks_list.len())
-                        % tasks_list.len()]
-                    .clone()
-                } else {
-                    interaction_types::card::random_task(tasks, rand::rng()).clone()
-                }
-            };
-            if matches!(
-                db_get_progress(user.id, course_id)[&card_name.to_owned()],
-                TaskProgress::NotStarted {
-                    could_be_learned: false
-                }
-            ) {
-                bot.send_message(
-                    user.id,
-                    "You should learn all dependencies before learning this card.",
-                )
-                .await.context("failed to notify user, that he should learn all dependencies before learning this card")?;
-                return Ok(());
+                }));
             }
-            let (rcx, is_meaningful) =
-                complete_card(bot, user.id, task, user_state, user_states).await;
-            let mut progress = db_get_progress(user.id, course_id);
-            progress.repetition(&card_name.to_owned(), rcx, is_meaningful);
-            db_set_course_progress(user.id, course_id, progress);
         }
-        "/graph" => {
-            log_user_command(user, "graph");
-            if !tail.is_empty() {
-                bot.send_message(user.id, "graph command doesn't expect any arguments.")
-                    .await
-                    .context(
-                        "failed to notify user, that graph command doesn't expect any arguments",
-                    )?;
-                return Ok(());
-            }
-            synchronize(user.id, course_id);
+    }
+    problems
+}
 
-            let Some(course) = db_get_course(course_id) else {
-                bot.send_message(
-                    user.id,
-                    format!("Course with id {} not found.", course_id.0),
-                )
-                .await
-                .context("failed to notify user, that there is not course with this id")?;
-                return Ok(());
-            };
-            let mut graph = course.structure.generate_structure_graph();
+/// `/card a, b, c` arguments. Returns known cards in order and unknown names.
+fn parse_card_list(list: &str, course: &Course) -> (Vec<String>, Vec<String>) {
+    let mut cards = Vec::new();
+    let mut unknown = Vec::new();
+    for name in list
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let card_name = normalize_card_name(name, course.settings.case_sensitive_names);
+        if course.has_card(&card_name) {
+            cards.push(card_name);
+        } else {
+            unknown.push(name.to_owned());
+        }
+    }
+    (cards, unknown)
+}
 
-            db_get_progress(user.id, course_id)
-                .generate_stmts()
-                .into_iter()
-                .for_each(|stmt| {
-                    graph.add_stmt(stmt);
-                });
+const NO_TASKS_MESSAGE: &str = "This card has no tasks yet.";
 
-            send_interactions(
-                bot,
-                user.id,
-                [TelegramInteraction::PersonalImage(
-                    tokio::task::spawn_blocking(move || {
-                        graphviz_rust::exec(
-                            graph,
-                            &mut PrinterContext::default(),
-                            Vec::from([Format::Jpeg.into()]),
-                        )
-                        .expect("Failed to run 'dot'")
-                    })
-                    .await
-                    .unwrap(),
-                )],
-                user_state,
-            )
+/// First repetitions go through all tasks, starting from one, that depends on learner.
+/// Then tasks are random. None if card has no tasks.
+fn choose_task(
+    tasks: &BTreeMap<u16, Task>,
+    meaningful_repetitions: u32,
+    user_id: UserId,
+) -> Option<Task> {
+    let tasks_list = tasks.values().collect::<Vec<_>>();
+    if (meaningful_repetitions as usize) < tasks_list.len() {
+        Some(
+            tasks_list[((meaningful_repetitions as usize)
+                + usize::try_from(user_id.0).unwrap() % tasks_list.len())
+                % tasks_list.len()]
+            .clone(),
+        )
+    } else {
+        interaction_types::card::random_task(tasks, rand::rng()).cloned()
+    }
+}
+
+/// Task of card to learn now, or message, why card can't be learned now.
+fn prepare_card(
+    user_id: UserId,
+    course_id: CourseId,
+    card_name: &str,
+) -> anyhow::Result<Result<Task, String>> {
+    let course = db_get_existing_course(course_id)?;
+    let progress = db_get_progress(user_id, course_id)?;
+    let Some(tasks) = course.tasks.tasks.get(card_name) else {
+        return Ok(Err("Card with this name not found".into()));
+    };
+    let meaningful_repetitions = progress.tasks[card_name].meaningful_repetitions;
+    let Some(task) = choose_task(tasks, meaningful_repetitions, user_id) else {
+        return Ok(Err(NO_TASKS_MESSAGE.into()));
+    };
+    if matches!(
+        progress.tasks[card_name].progress,
+        TaskProgress::NotStarted {
+            could_be_learned: false
+        }
+    ) {
+        return Ok(Err(
+            "You should learn all dependencies before learning this card.".into(),
+        ));
+    }
+    if course.is_out_of_sequence(&progress, card_name) {
+        let next = course
+            .card_sequence()
+            .and_then(|sequence| progress.first_startable(&sequence).cloned());
+        return Ok(Err(match next {
+            Some(next) => format!("This course is linear, next card to learn is '{next}'."),
+            None => "This course is linear, learn previous cards first.".into(),
+        }));
+    }
+    Ok(Ok(task))
+}
+
+/// Outcome of [`learn_card`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CardOutcome {
+    /// Revealed answer is answered too: learner has seen the card.
+    Answered,
+    /// Card can't be learned now: it's unknown, without tasks, locked or out of sequence.
+    Skipped,
+    /// Learner started something else.
+    Interrupted,
+    LimitReached,
+}
+
+/// Completes one task of card and records result.
+async fn learn_card(
+    bot: Bot,
+    user: &User,
+    course_id: CourseId,
+    card_name: String,
+    user_state: MutUserState<'_>,
+    user_states: &DashMap<UserId, UserState>,
+) -> anyhow::Result<CardOutcome> {
+    let day = today(user.id)?;
+    if daily_limit_reached(user.id, course_id, day)? {
+        bot.send_message(user.id, "You've hit today's limit, come back tomorrow.")
             .await
-            .context("failed to send graph image")?;
+            .context("failed to notify user, that he hit daily review limit")?;
+        return Ok(CardOutcome::LimitReached);
+    }
+    synchronize(user.id, course_id)?;
+    let task = match prepare_card(user.id, course_id, &card_name)? {
+        Ok(task) => task,
+        Err(reason) => {
+            send_interactions(bot, user.id, vec![reason.into()], user_state)
+                .await
+                .context("failed to notify user, that card can't be learned now")?;
+            return Ok(CardOutcome::Skipped);
+        }
+    };
+    let note = db_get_progress(user.id, course_id)?.tasks[&card_name]
+        .note
+        .clone();
+    let settings = db_get_existing_course(course_id)?.settings;
+    let explanation = task.explanation.clone();
+    let completion = complete_card(
+        bot.clone(),
+        user.id,
+        task,
+        CardAttempt::Review { note },
+        CardContext {
+            course_id,
+            card_name: &card_name,
+            settings: &settings,
+        },
+        user_state,
+        user_states,
+    )
+    .await;
+    let failed = completion.is_failed();
+    let is_meaningful = completion.meaningful;
+    let outcome = if is_meaningful || completion.revealed {
+        CardOutcome::Answered
+    } else {
+        CardOutcome::Interrupted
+    };
+    let mut progress = db_get_progress(user.id, course_id)?;
+    completion.record(&mut progress, &card_name);
+    db_set_course_progress(user.id, course_id, progress)?;
+    if is_meaningful {
+        db_add_daily_review(user.id, course_id, day)?;
+    }
+
+    let limit = db_get_existing_course(course_id)?.settings.move_on_after;
+    let mut user_state = user_states.get_mut(&user.id).unwrap();
+    let move_on = user_state
+        .session_fails
+        .record(course_id, &card_name, failed, limit);
+    if move_on {
+        let has_explanation = explanation.is_some();
+        user_state.move_on_explanation = explanation.map(|explanation| (course_id, explanation));
+        drop(user_state);
+        let message = bot.send_message(
+            user.id,
+            "This card is hard right now. Let's move on to other cards, it will come back later.",
+        );
+        let message = if has_explanation {
+            message.reply_markup(InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback("Show explanation", MOVE_ON_EXPLANATION_CALLBACK),
+            ]]))
+        } else {
+            message
+        };
+        message.await.context("failed to suggest user to move on")?;
+    }
+    Ok(outcome)
+}
+
+/// Learns `cards` one by one. Card, that can't be learned now, doesn't stop others,
+/// only interruption or daily limit does. Returns skipped cards.
+async fn learn_queue<F, Fut>(cards: Vec<String>, mut learn: F) -> anyhow::Result<Vec<String>>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = anyhow::Result<CardOutcome>>,
+{
+    let mut skipped = Vec::new();
+    for card_name in cards {
+        match learn(card_name.clone()).await? {
+            CardOutcome::Answered => {}
+            CardOutcome::Skipped => skipped.push(card_name),
+            CardOutcome::Interrupted | CardOutcome::LimitReached => break,
+        }
+    }
+    Ok(skipped)
+}
+
+async fn handle_settings_interaction(
+    bot: Bot,
+    user: &User,
+    message: &str,
+    mut user_state: MutUserState<'_>,
+) -> anyhow::Result<()> {
+    match message.trim() {
+        "/help" => {
+            log_user_command(user, "help");
+            send_help_message(bot, user, &user_state).await?;
+        }
+        "/menu" => {
+            log_user_command(user, "menu");
+            send_menu(bot, user, &user_state).await?;
+        }
+        "/exit" => {
+            log_user_command(user, "exit");
+            user_state.current_screen = Screen::Main;
+            bot.send_message(user.id, "You are now in main menu.")
+                .await
+                .context("failed to notify user, that he is now in main menu")?;
+            send_help_message(bot, user, &user_state).await?;
         }
         _ => {
-            handle_no_command(bot, user, message, user_state)
+            bot.send_message(user.id, "Use buttons under settings message or /exit.")
                 .await
-                .context("failed to handle 'no command'")?;
+                .context("failed to send settings hint")?;
         }
     }
     Ok(())
 }
 
-async fn handle_owned_course_interaction(
+async fn handle_learned_course_interaction(
     bot: Bot,
     user: &User,
     message: &str,
@@ -458,6 +1159,10 @@ async fn handle_owned_course_interaction(
             log_user_command(user, "help");
             send_help_message(bot, user, &user_state).await?;
         }
+        "/menu" => {
+            log_user_command(user, "menu");
+            send_menu(bot, user, &user_state).await?;
+        }
         "/exit" => {
             log_user_command(user, "exit");
             user_state.current_screen = Screen::Main;
@@ -466,190 +1171,1484 @@ async fn handle_owned_course_interaction(
                 .context("failed to notify user, that he is now in main menu")?;
             send_help_message(bot, user, &user_state).await?;
         }
-        "/preview" => {
-            log_user_command(user, "preview");
-            if tail.contains(" ") {
-                bot.send_message(user.id, "Error: Card name should not contain spaces.")
-                    .await
-                    .context("failed to notify user, that card name should not contain spaces")?;
-                return Ok(());
-            }
+        "/card" => {
+            log_user_command(user, "card");
             if tail.is_empty() {
                 bot.send_message(
                     user.id,
                     "Error: You should provide card name, you want to learn.",
                 )
                 .await
-                .context(
-                    "failed to notify user, that he should provide card name to preview command",
-                )?;
+                .context("failed to notify user, that card command should contain card name")?;
                 return Ok(());
             }
+            let course = db_get_existing_course(course_id)?;
+            let (cards, unknown) = parse_card_list(tail, &course);
             log::info!(
-                "user {}({}) sends preview '{tail}' command",
+                "user {}({}) sends card '{cards:?}' command",
                 user.username.clone().unwrap_or("unknown".into()),
                 user.id
             );
-            let task = {
-                let course = db_get_course(course_id).unwrap();
-                let Some(tasks) = course.tasks.tasks.get(tail) else {
-                    send_interactions(
-                        bot,
+            if !unknown.is_empty() {
+                bot.send_message(
+                    user.id,
+                    format!("Cards with these names not found: {}", unknown.join(", ")),
+                )
+                .await
+                .context("failed to notify user about unknown cards")?;
+            }
+
+            let mut user_state = Some(user_state);
+            let skipped = learn_queue(cards, |card_name| {
+                let user_state = match user_state.take() {
+                    Some(user_state) => user_state,
+                    None => user_states.get_mut(&user.id).unwrap(),
+                };
+                learn_card(
+                    bot.clone(),
+                    user,
+                    course_id,
+                    card_name,
+                    user_state,
+                    user_states,
+                )
+            })
+            .await?;
+            if !skipped.is_empty() {
+                bot.send_message(user.id, format!("Skipped cards: {}", skipped.join(", ")))
+                    .await
+                    .context("failed to notify user about skipped cards")?;
+            }
+        }
+        "/copy_progress" => {
+            log_user_command(user, "copy_progress");
+            let learned_courses = db_list_user_learned_courses(user.id)?;
+            let Some(from) = tail
+                .parse()
+                .ok()
+                .map(CourseId)
+                .filter(|&from| from != course_id && learned_courses.contains(&from))
+            else {
+                bot.send_message(
+                    user.id,
+                    "copy_progress command expects id of other course, you learn.",
+                )
+                .await
+                .context("failed to notify user, that copy_progress argument is invalid")?;
+                return Ok(());
+            };
+            if !confirm(
+                bot.clone(),
+                user.id,
+                &format!(
+                    "Progress of cards, that course {} also has, will be replaced. Continue?",
+                    from.0
+                ),
+                user_state,
+            )
+            .await?
+            {
+                bot.send_message(user.id, "Progress is kept.")
+                    .await
+                    .context("failed to notify user, that progress copy is cancelled")?;
+                return Ok(());
+            }
+            let copied = copy_progress(user.id, from, course_id)?;
+            bot.send_message(user.id, format!("Progress of {copied} cards is copied."))
+                .await
+                .context("failed to confirm progress copy")?;
+        }
+        "/weights" => {
+            log_user_command(user, "weights");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "weights command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that weights command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            let weights = weights_to_string(db_get_progress(user.id, course_id)?.weights());
+            bot.send_message(user.id, format!("`{weights}`"))
+                .parse_mode(ParseMode::MarkdownV2)
+                .await
+                .context("failed to send weights")?;
+        }
+        "/set_weights" => {
+            log_user_command(user, "set_weights");
+            let weights = match parse_weights(tail) {
+                Ok(weights) => weights,
+                Err(err) => {
+                    bot.send_message(user.id, format!("Invalid weights: {err}."))
+                        .await
+                        .context("failed to notify user, that weights are invalid")?;
+                    return Ok(());
+                }
+            };
+            set_weights(user.id, course_id, weights)?;
+            bot.send_message(user.id, "Weights are updated.")
+                .await
+                .context("failed to confirm weights change")?;
+        }
+        "/set_my_daily_limit" => {
+            log_user_command(user, "set_my_daily_limit");
+            let Some(limit) = parse_daily_limit(tail, "default") else {
+                bot.send_message(
+                    user.id,
+                    format!(
+                        "set_my_daily_limit command expects number from 1 to {MAX_DAILY_REVIEW_LIMIT} or 'default'."
+                    ),
+                )
+                .await
+                .context("failed to notify user, that set_my_daily_limit argument is invalid")?;
+                return Ok(());
+            };
+            let mut settings = db_get_user_settings(user.id)?;
+            settings.daily_review_limit = limit;
+            db_set_user_settings(user.id, &settings)?;
+            bot.send_message(
+                user.id,
+                match limit {
+                    Some(limit) => format!(
+                        "You will do at most {limit} reviews per day, unless course limit is lower."
+                    ),
+                    None => "You will follow course daily limit.".into(),
+                },
+            )
+            .await
+            .context("failed to confirm own daily limit change")?;
+        }
+        "/review" => {
+            log_user_command(user, "review");
+            let order = match tail {
+                "" => ReviewOrder::Overdue,
+                "random" => ReviewOrder::Random {
+                    seed: rand::random(),
+                },
+                "foundations" => ReviewOrder::Foundations,
+                _ => {
+                    bot.send_message(
                         user.id,
-                        vec!["Card with this name not found".into()],
-                        user_state,
+                        "review command expects nothing, 'random' or 'foundations'.",
                     )
                     .await
-                    .context("failed to notify user, that there is no card with this name")?;
+                    .context("failed to notify user, that review command argument is invalid")?;
+                    return Ok(());
+                }
+            };
+            drop(user_state);
+            review_session(bot, user, course_id, order, user_states).await?;
+        }
+        "/next" => {
+            log_user_command(user, "next");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "next command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that next command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            let Some(card_name) = best_next_card(user.id, course_id)? else {
+                bot.send_message(user.id, "Nothing to review right now.")
+                    .await
+                    .context("failed to notify user, that nothing to review")?;
+                return Ok(());
+            };
+            learn_card(bot, user, course_id, card_name, user_state, user_states).await?;
+        }
+        "/note" => {
+            log_user_command(user, "note");
+            if tail.is_empty() || tail.contains(" ") {
+                bot.send_message(user.id, "note command expects card name without spaces.")
+                    .await
+                    .context("failed to notify user, that note command expects card name")?;
+                return Ok(());
+            }
+            let card_name = normalize_card_name(
+                tail,
+                db_get_existing_course(course_id)?
+                    .settings
+                    .case_sensitive_names,
+            );
+            if !db_get_progress(user.id, course_id)?
+                .tasks
+                .contains_key(&card_name)
+            {
+                bot.send_message(user.id, "Card with this name not found")
+                    .await
+                    .context("failed to notify user, that card with this name not found")?;
+                return Ok(());
+            }
+            handle_note(bot, user_state, user.id, course_id, card_name).await?;
+        }
+        command @ ("/set_goal" | "/remove_goal") => {
+            let command = &command[1..];
+            log_user_command(user, command);
+            if tail.is_empty() || tail.contains(" ") {
+                bot.send_message(
+                    user.id,
+                    format!("{command} command expects card name without spaces."),
+                )
+                .await
+                .context("failed to notify user, that goal command expects card name")?;
+                return Ok(());
+            }
+            let card_name = normalize_card_name(
+                tail,
+                db_get_existing_course(course_id)?
+                    .settings
+                    .case_sensitive_names,
+            );
+            let mut progress = db_get_progress(user.id, course_id)?;
+            if !progress.tasks.contains_key(&card_name) {
+                bot.send_message(user.id, "Card with this name not found")
+                    .await
+                    .context("failed to notify user, that card with this name not found")?;
+                return Ok(());
+            }
+            let is_goal = progress.goals.contains(&card_name);
+            let response = match (command, is_goal) {
+                ("set_goal", false) => {
+                    progress.goals.push(card_name);
+                    "Goal set."
+                }
+                ("set_goal", true) => "This card is already your goal.",
+                (_, true) => {
+                    progress.goals.retain(|goal| goal != &card_name);
+                    "Goal removed."
+                }
+                (_, false) => "This card is not your goal.",
+            };
+            db_set_course_progress(user.id, course_id, progress)?;
+            bot.send_message(user.id, response)
+                .await
+                .context("failed to confirm goal change")?;
+        }
+        "/reset_course_schedule" => {
+            log_user_command(user, "reset_course_schedule");
+            if !tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "reset_course_schedule command doesn't expect any arguments.",
+                )
+                .await
+                .context(
+                    "failed to notify user, that reset_course_schedule command doesn't expect any arguments",
+                )?;
+                return Ok(());
+            }
+            if !confirm(
+                bot.clone(),
+                user.id,
+                "All cards will be due for review now. Continue?",
+                user_state,
+            )
+            .await?
+            {
+                bot.send_message(user.id, "Schedule is kept.")
+                    .await
+                    .context("failed to notify user, that schedule reset is cancelled")?;
+                return Ok(());
+            }
+            let due = reset_schedule(user.id, course_id)?;
+            bot.send_message(
+                user.id,
+                format!("{due} cards are due now. Your progress is kept."),
+            )
+            .await
+            .context("failed to confirm course schedule reset")?;
+        }
+        "/simulate" => {
+            log_user_command(user, "simulate");
+            let days = if tail.is_empty() {
+                Some(DEFAULT_SIMULATION_DAYS)
+            } else {
+                tail.parse()
+                    .ok()
+                    .filter(|days| (1..=MAX_SIMULATION_DAYS).contains(days))
+            };
+            let Some(days) = days else {
+                bot.send_message(
+                    user.id,
+                    format!(
+                        "simulate command expects number of days from 1 to {MAX_SIMULATION_DAYS}."
+                    ),
+                )
+                .await
+                .context("failed to notify user, that simulate argument is invalid")?;
+                return Ok(());
+            };
+            let workload = project_workload(user.id, course_id, days)?;
+            bot.send_message(user.id, workload_message(&workload))
+                .await
+                .context("failed to send projected workload")?;
+        }
+        "/goal" => {
+            log_user_command(user, "goal");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "goal command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that goal command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            synchronize(user.id, course_id)?;
+            let course = db_get_existing_course(course_id)?;
+            let progress = db_get_progress(user.id, course_id)?;
+            bot.send_message(user.id, goals_message(&course.structure, &progress))
+                .await
+                .context("failed to send goals progress")?;
+        }
+        "/stats" => {
+            log_user_command(user, "stats");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "stats command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that stats command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            synchronize(user.id, course_id)?;
+            let summary = db_get_progress(user.id, course_id)?.summary();
+            bot.send_message(user.id, summary.message())
+                .await
+                .context("failed to send progress summary")?;
+        }
+        "/intervals" => {
+            log_user_command(user, "intervals");
+            if tail.is_empty() || tail.contains(" ") {
+                bot.send_message(
+                    user.id,
+                    "intervals command expects card name without spaces.",
+                )
+                .await
+                .context("failed to notify user, that intervals command expects card name")?;
+                return Ok(());
+            }
+            let course = db_get_existing_course(course_id)?;
+            let card_name = normalize_card_name(tail, course.settings.case_sensitive_names);
+            if !course.has_card(&card_name) {
+                bot.send_message(user.id, "Card with this name not found")
+                    .await
+                    .context("failed to notify user, that card with this name not found")?;
+                return Ok(());
+            }
+            let message = match preview_intervals(user.id, course_id, &card_name)? {
+                Some(intervals) => intervals_message(&intervals),
+                None => {
+                    format!("Card {card_name} can't be learned yet, learn its dependencies first.")
+                }
+            };
+            bot.send_message(user.id, message)
+                .await
+                .context("failed to send intervals of card")?;
+        }
+        "/blocked_by" => {
+            log_user_command(user, "blocked_by");
+            if tail.is_empty() || tail.contains(" ") {
+                bot.send_message(
+                    user.id,
+                    "blocked_by command expects card name without spaces.",
+                )
+                .await
+                .context("failed to notify user, that blocked_by command expects card name")?;
+                return Ok(());
+            }
+            synchronize(user.id, course_id)?;
+            let course = db_get_existing_course(course_id)?;
+            let card_name = normalize_card_name(tail, course.settings.case_sensitive_names);
+            let progress = db_get_progress(user.id, course_id)?;
+            if !progress.tasks.contains_key(&card_name) {
+                bot.send_message(user.id, "Card with this name not found")
+                    .await
+                    .context("failed to notify user, that card with this name not found")?;
+                return Ok(());
+            }
+            let blocked = course.structure.blocked_by(&card_name, &progress);
+            let message = if progress[&card_name] != TaskProgress::Failed {
+                format!("Card {card_name} isn't failed, so it blocks nothing.")
+            } else if blocked.is_empty() {
+                format!("Nothing is blocked by {card_name}.")
+            } else {
+                let blocked = blocked.into_iter().map(String::as_str).collect::<Vec<_>>();
+                format!("Repeat {card_name} to unblock: {}.", blocked.join(", "))
+            };
+            bot.send_message(user.id, message)
+                .await
+                .context("failed to send cards, blocked by failed card")?;
+        }
+        "/checkpoint" => {
+            log_user_command(user, "checkpoint");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "checkpoint command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that checkpoint command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            synchronize(user.id, course_id)?;
+            let states = db_get_progress(user.id, course_id)?.states();
+            db_set_checkpoint(user.id, course_id, &states)?;
+            bot.send_message(
+                user.id,
+                "Checkpoint saved. Use /graph diff later to see, what improved since now.",
+            )
+            .await
+            .context("failed to confirm checkpoint")?;
+        }
+        "/graph" => {
+            log_user_command(user, "graph");
+            let (view, diff) = match tail {
+                "diff" => (GraphView::Image, true),
+                tail => match graph_view_for(tail, &db_get_user_settings(user.id)?) {
+                    Some(view) => (view, false),
+                    None => {
+                        bot.send_message(
+                            user.id,
+                            "graph command expects nothing, 'image', 'text', 'list' or 'diff'.",
+                        )
+                        .await
+                        .context("failed to notify user, that graph command argument is invalid")?;
+                        return Ok(());
+                    }
+                },
+            };
+            let checkpoint = if diff {
+                let Some(checkpoint) = db_get_checkpoint(user.id, course_id)? else {
+                    bot.send_message(
+                        user.id,
+                        "You have no checkpoint. Save one with /checkpoint.",
+                    )
+                    .await
+                    .context("failed to notify user, that he has no checkpoint")?;
+                    return Ok(());
+                };
+                Some(checkpoint)
+            } else {
+                None
+            };
+            synchronize(user.id, course_id)?;
+
+            let Some(course) = db_get_course(course_id)? else {
+                bot.send_message(
+                    user.id,
+                    format!("Course with id {} not found.", course_id.0),
+                )
+                .await
+                .context("failed to notify user, that there is not course with this id")?;
+                return Ok(());
+            };
+            let progress = db_get_progress(user.id, course_id)?;
+            let text = match view {
+                GraphView::Image => None,
+                GraphView::Text => Some(course.structure.progress_tree(&progress)),
+                GraphView::List => Some(course.structure.progress_list(&progress)),
+            };
+            if let Some(text) = text {
+                bot.send_message(user.id, text)
+                    .await
+                    .context("failed to send progress as text")?;
+                return Ok(());
+            }
+            let mut graph = course
+                .structure
+                .generate_structure_graph(course.settings.layered_graph);
+
+            let stmts = match checkpoint {
+                Some(checkpoint) => progress.generate_diff_stmts(&checkpoint),
+                None => progress
+                    .generate_stmts()
+                    .into_iter()
+                    .chain(
+                        course
+                            .structure
+                            .generate_goal_stmts(&progress.goals, &progress),
+                    )
+                    .collect(),
+            };
+            stmts.into_iter().for_each(|stmt| {
+                graph.add_stmt(stmt);
+            });
+
+            send_interactions(
+                bot,
+                user.id,
+                [TelegramInteraction::PersonalImage(
+                    tokio::task::spawn_blocking(move || {
+                        graphviz_rust::exec(
+                            graph,
+                            &mut PrinterContext::default(),
+                            Vec::from([Format::Jpeg.into()]),
+                        )
+                        .expect("Failed to run 'dot'")
+                    })
+                    .await
+                    .unwrap(),
+                )],
+                user_state,
+            )
+            .await
+            .context("failed to send graph image")?;
+        }
+        "/delete_course" => {
+            log_user_command(user, "delete_course");
+            bot.send_message(user.id, "It's not your course.")
+                .await
+                .context("failed to notify user, that he can delete only his courses")?;
+        }
+        "/leave" => {
+            log_user_command(user, "leave");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "leave command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that leave command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            if !confirm(
+                bot.clone(),
+                user.id,
+                "Your progress in this course will be deleted. Continue?",
+                user_state,
+            )
+            .await?
+            {
+                bot.send_message(user.id, "You are still learning this course.")
+                    .await
+                    .context("failed to notify user, that leaving course is cancelled")?;
+                return Ok(());
+            }
+            db_remove_course_from_user(user.id, course_id)?;
+            if let Some(mut user_state) = user_states.get_mut(&user.id) {
+                user_state.current_screen = Screen::Main;
+                user_state.current_interaction = None;
+            }
+            bot.send_message(
+                user.id,
+                "Course is removed from your list. You are now in main menu.",
+            )
+            .await
+            .context("failed to confirm leaving course")?;
+        }
+        _ => {
+            handle_no_command(bot, user, message, user_state)
+                .await
+                .context("failed to handle 'no command'")?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_owned_course_interaction(
+    bot: Bot,
+    user: &User,
+    message: &str,
+    course_id: CourseId,
+    mut user_state: MutUserState<'_>,
+    user_states: &DashMap<UserId, UserState>,
+) -> anyhow::Result<()> {
+    let (first_word, tail) = message.trim().split_once(" ").unwrap_or((message, ""));
+    match first_word {
+        "/help" => {
+            log_user_command(user, "help");
+            send_help_message(bot, user, &user_state).await?;
+        }
+        "/menu" => {
+            log_user_command(user, "menu");
+            send_menu(bot, user, &user_state).await?;
+        }
+        "/exit" => {
+            log_user_command(user, "exit");
+            user_state.current_screen = Screen::Main;
+            bot.send_message(user.id, "You are now in main menu.")
+                .await
+                .context("failed to notify user, that he is now in main menu")?;
+            send_help_message(bot, user, &user_state).await?;
+        }
+        "/preview" => {
+            log_user_command(user, "preview");
+            let (card_name, number) = match tail.split_once(' ') {
+                Some((card_name, number)) => match number.parse::<u16>() {
+                    Ok(number) => (card_name, Some(number)),
+                    Err(_) => {
+                        bot.send_message(
+                            user.id,
+                            "Error: Card name should not contain spaces, and task number should be a number.",
+                        )
+                        .await
+                        .context("failed to notify user, that preview arguments are invalid")?;
+                        return Ok(());
+                    }
+                },
+                None => (tail, None),
+            };
+            if tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "Error: You should provide card name, you want to learn.",
+                )
+                .await
+                .context(
+                    "failed to notify user, that he should provide card name to preview command",
+                )?;
+                return Ok(());
+            }
+            log::info!(
+                "user {}({}) sends preview '{tail}' command",
+                user.username.clone().unwrap_or("unknown".into()),
+                user.id
+            );
+            let (card_name, task) = {
+                let course = db_get_existing_course(course_id)?;
+                let card_name =
+                    normalize_card_name(card_name, course.settings.case_sensitive_names);
+                if !course.has_card(&card_name) {
+                    send_interactions(
+                        bot,
+                        user.id,
+                        vec!["Card with this name not found".into()],
+                        user_state,
+                    )
+                    .await
+                    .context("failed to notify user, that there is no card with this name")?;
+                    return Ok(());
+                }
+                let task = match number {
+                    Some(number) => course.task_for(&card_name, number),
+                    None => course.any_task(&card_name),
+                };
+                let Some(task) = task else {
+                    let message = match number {
+                        Some(number) => format!("Card {card_name} has no task {number}."),
+                        None => NO_TASKS_MESSAGE.to_owned(),
+                    };
+                    bot.send_message(user.id, message)
+                        .await
+                        .context("failed to notify user, that card has no such tasks")?;
+                    return Ok(());
+                };
+                (card_name, task.clone())
+            };
+            let settings = db_get_existing_course(course_id)?.settings;
+            complete_card(
+                bot,
+                user.id,
+                task,
+                CardAttempt::Preview,
+                CardContext {
+                    course_id,
+                    card_name: &card_name,
+                    settings: &settings,
+                },
+                user_state,
+                user_states,
+            )
+            .await;
+        }
+        "/graph" => {
+            log_user_command(user, "graph");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "graph command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that graph command doesn't have any arguments",
+                    )?;
+                return Ok(());
+            }
+
+            let Some(course) = db_get_course(course_id)? else {
+                bot.send_message(
+                    user.id,
+                    format!("Course with id {} not found.", course_id.0),
+                )
+                .await
+                .context("failed to notify user, that there is no course with this id")?;
+                return Ok(());
+            };
+            let graph = course
+                .structure
+                .generate_structure_graph(course.settings.layered_graph);
+
+            send_interactions(
+                bot,
+                user.id,
+                [TelegramInteraction::PersonalImage(
+                    tokio::task::spawn_blocking(move || {
+                        graphviz_rust::exec(
+                            graph.clone(),
+                            &mut PrinterContext::default(),
+                            Vec::from([Format::Jpeg.into()]),
+                        )
+                        .unwrap_or_else(|err| {
+                            log::error!(
+                                "Failed to run dot with this source: \n`{}`\n, because of this error: {err}",
+                                graph.print(&mut PrinterContext::default())
+                                // course.structure.get_source()
+                            );
+                            panic!("Failed to run 'dot'");
+                        })
+                    })
+                    .await
+                    .unwrap(),
+                )],
+                user_state,
+            )
+            .await
+            .context("failed to send graph image")?;
+        }
+        "/preview_graph_state" => {
+            log_user_command(user, "preview_graph_state");
+            if tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "preview_graph_state command expects card states, like 'CARD=failed,CARD2=good'.",
+                )
+                .await
+                .context("failed to notify user, that preview_graph_state expects card states")?;
+                return Ok(());
+            }
+            let course = db_get_existing_course(course_id)?;
+            let store = match course.structure.synthetic_progress(
+                tail,
+                course.settings.case_sensitive_names,
+                course.settings.cascade_fails,
+                course.settings.fail_propagation_depth,
+            ) {
+                Ok(store) => store,
+                Err(err) => {
+                    bot.send_message(user.id, format!("Error: {err}."))
+                        .await
+                        .context("failed to notify user, that card states are invalid")?;
+                    return Ok(());
+                }
+            };
+            let mut graph = course
+                .structure
+                .generate_structure_graph(course.settings.layered_graph);
+            store.generate_stmts().into_iter().for_each(|stmt| {
+                graph.add_stmt(stmt);
+            });
+
+            send_interactions(
+                bot,
+                user.id,
+                [TelegramInteraction::PersonalImage(
+                    tokio::task::spawn_blocking(move || {
+                        graphviz_rust::exec(
+                            graph,
+                            &mut PrinterContext::default(),
+                            Vec::from([Format::Jpeg.into()]),
+                        )
+                        .expect("Failed to run 'dot'")
+                    })
+                    .await
+                    .unwrap(),
+                )],
+                user_state,
+            )
+            .await
+            .context("failed to send graph image")?;
+        }
+        "/revise" => {
+            log_user_command(user, "revise");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "revise command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that revise command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            drop(user_state);
+            revise_owned_course(bot, user, course_id, user_states).await?;
+        }
+        "/change_course_graph" => {
+            log_user_command(user, "change_course_graph");
+            if !tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "change_course_graph command doesn't expect any arguments.",
+                )
+                .await
+                .context(
+                    "failed to notify user, that change_course_graph command doesn't arguments",
+                )?;
+                return Ok(());
+            }
+            handle_changing_course_graph(bot, user_state, user.id, course_id)
+                .await
+                .context("failed to change course graph")?;
+        }
+        "/change_deque" => {
+            log_user_command(user, "change_deque");
+            if !tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "change_deque command doesn't expect any arguments.",
+                )
+                .await
+                .context(
+                    "failed to notify user, that change_deque command doesn't have arguments",
+                )?;
+                return Ok(());
+            }
+            handle_changing_deque(bot, user_state, user.id, course_id, user_states)
+                .await
+                .context("failed to change deque")?;
+        }
+        "/view_course_graph_source" => {
+            log_user_command(user, "view_course_graph_source");
+            if !tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "view_course_graph_source command doesn't expect any arguments.",
+                )
+                .await.context("failed to notify user, that view_course_graph_source command doesn't have arguments")?;
+                return Ok(());
+            }
+            send_interactions(
+                bot,
+                user.id,
+                vec![
+                    "Course graph source:".into(),
+                    format!(
+                        "```\n{}\n```",
+                        db_get_existing_course(course_id)?.structure.get_source()
+                    )
+                    .into(),
+                ],
+                user_state,
+            )
+            .await
+            .context("failed to send course graph source")?;
+        }
+        "/view_deque_source" => {
+            log_user_command(user, "view_deque_source");
+            if !tail.is_empty() {
+                bot.send_message(
+                    user.id,
+                    "view_deque_source command doesn't expect any arguments.",
+                )
+                .await
+                .context(
+                    "failed to notify user, that view_deque_source command doesn't have arguments",
+                )?;
+                return Ok(());
+            }
+            send_interactions(
+                bot,
+                user.id,
+                vec![
+                    "Deque source:".into(),
+                    format!(
+                        "```\n{}\n```",
+                        db_get_existing_course(course_id)?.tasks.source.to_owned()
+                    )
+                    .into(),
+                ],
+                user_state,
+            )
+            .await
+            .context("failed to send deque source")?;
+        }
+        "/set_case_sensitive_names" => {
+            log_user_command(user, "set_case_sensitive_names");
+            let case_sensitive_names = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(
+                        user.id,
+                        "set_case_sensitive_names command expects 'on' or 'off'.",
+                    )
+                    .await
+                    .context(
+                        "failed to notify user, that set_case_sensitive_names command expects 'on' or 'off'",
+                    )?;
+                    return Ok(());
+                }
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            // Card names are normalized while parsing, so both sources should be parsed again.
+            let structure = CourseGraph::parse(course.structure.get_source(), case_sensitive_names);
+            let tasks = deque::from_str(&course.tasks.source, true, case_sensitive_names);
+            match (structure, tasks) {
+                (Ok(structure), Ok(tasks)) => {
+                    course.structure = structure;
+                    course.tasks = tasks;
+                    course.settings.case_sensitive_names = case_sensitive_names;
+                    db_set_course_with_names_case(course_id, course)?;
+                    bot.send_message(
+                        user.id,
+                        if case_sensitive_names {
+                            "Card names are now case sensitive."
+                        } else {
+                            "Card names are now case insensitive."
+                        },
+                    )
+                    .await
+                    .context("failed to confirm card names case sensitivity change")?;
+                }
+                (Err(err), _) => {
+                    let err = strip_ansi_escapes::strip_str(err);
+                    send_literal(
+                        &bot,
+                        user.id,
+                        &format!("Your course graph has this errors in new mode:\n{err}"),
+                    )
+                    .await
+                    .context("failed to notify that course graph has errors in new mode")?;
+                }
+                (_, Err(err)) => {
+                    send_literal(
+                        &bot,
+                        user.id,
+                        &format!("Your deque has this errors in new mode:\n{err}"),
+                    )
+                    .await
+                    .context("failed to notify that deque has errors in new mode")?;
+                }
+            }
+        }
+        "/set_fail_propagation_depth" => {
+            log_user_command(user, "set_fail_propagation_depth");
+            let depth = match tail {
+                "unlimited" => None,
+                tail => match tail.parse::<usize>() {
+                    Ok(depth) => Some(depth),
+                    Err(_) => {
+                        bot.send_message(
+                            user.id,
+                            "set_fail_propagation_depth command expects number or 'unlimited'.",
+                        )
+                        .await
+                        .context(
+                            "failed to notify user, that set_fail_propagation_depth argument is invalid",
+                        )?;
+                        return Ok(());
+                    }
+                },
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.fail_propagation_depth = depth;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                match depth {
+                    Some(depth) => format!("Fails now propagate to {depth} levels of dependents."),
+                    None => "Fails now propagate to all dependents.".into(),
+                },
+            )
+            .await
+            .context("failed to confirm fail propagation depth change")?;
+        }
+        "/set_linear" => {
+            log_user_command(user, "set_linear");
+            let mut course = db_get_existing_course(course_id)?;
+            let order = match tail {
+                "" => {
+                    bot.send_message(
+                        user.id,
+                        "set_linear command expects 'off', 'topological' or list of cards.",
+                    )
+                    .await
+                    .context("failed to notify user, that set_linear argument is invalid")?;
+                    return Ok(());
+                }
+                "off" => None,
+                "topological" => Some(Vec::new()),
+                tail => {
+                    let (cards, unknown) = parse_card_list(tail, &course);
+                    if !unknown.is_empty() {
+                        bot.send_message(
+                            user.id,
+                            format!("Cards with these names not found: {}", unknown.join(", ")),
+                        )
+                        .await
+                        .context("failed to notify user about unknown cards")?;
+                        return Ok(());
+                    }
+                    Some(cards)
+                }
+            };
+            course.settings.linear_order = order;
+            let message = match course.card_sequence() {
+                Some(sequence) => format!(
+                    "Learners now start cards in this order: {}.",
+                    sequence
+                        .iter()
+                        .map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                None => "Learners now choose cards freely.".into(),
+            };
+            db_set_course(course_id, course)?;
+            bot.send_message(user.id, message)
+                .await
+                .context("failed to confirm linear mode change")?;
+        }
+        "/set_move_on_after" => {
+            log_user_command(user, "set_move_on_after");
+            let limit = match tail {
+                "never" => None,
+                tail => match tail.parse::<u32>() {
+                    Ok(limit) if limit > 0 => Some(limit),
+                    _ => {
+                        bot.send_message(
+                            user.id,
+                            "set_move_on_after command expects positive number or 'never'.",
+                        )
+                        .await
+                        .context(
+                            "failed to notify user, that set_move_on_after argument is invalid",
+                        )?;
+                        return Ok(());
+                    }
+                },
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.move_on_after = limit;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                match limit {
+                    Some(limit) => {
+                        format!("Learners move on after {limit} consecutive fails of one card.")
+                    }
+                    None => "Learners are never asked to move on from failed cards.".into(),
+                },
+            )
+            .await
+            .context("failed to confirm move on limit change")?;
+        }
+        "/set_daily_limit" => {
+            log_user_command(user, "set_daily_limit");
+            let Some(limit) = parse_daily_limit(tail, "none") else {
+                bot.send_message(
+                    user.id,
+                    format!(
+                        "set_daily_limit command expects number from 1 to {MAX_DAILY_REVIEW_LIMIT} or 'none'."
+                    ),
+                )
+                .await
+                .context("failed to notify user, that set_daily_limit argument is invalid")?;
+                return Ok(());
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.daily_review_limit = limit;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                match limit {
+                    Some(limit) => format!("Learners can do at most {limit} reviews per day."),
+                    None => "Learners can do unlimited number of reviews per day.".into(),
+                },
+            )
+            .await
+            .context("failed to confirm daily limit change")?;
+        }
+        "/set_max_explanation_len" => {
+            log_user_command(user, "set_max_explanation_len");
+            let max_len = if tail == "default" {
+                Some(None)
+            } else {
+                tail.parse()
+                    .ok()
+                    .filter(|len| (MIN_EXPLANATION_LEN..=MAX_EXPLANATION_LEN).contains(len))
+                    .map(Some)
+            };
+            let Some(max_len) = max_len else {
+                bot.send_message(
+                    user.id,
+                    format!(
+                        "set_max_explanation_len command expects number from {MIN_EXPLANATION_LEN} to {MAX_EXPLANATION_LEN} or 'default'."
+                    ),
+                )
+                .await
+                .context("failed to notify user, that set_max_explanation_len argument is invalid")?;
+                return Ok(());
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.max_explanation_len = max_len;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                format!(
+                    "Explanations longer than {} characters are truncated and sent as file.",
+                    max_len.unwrap_or(MAX_EXPLANATION_LEN)
+                ),
+            )
+            .await
+            .context("failed to confirm max explanation length change")?;
+        }
+        "/set_compact_questions" => {
+            log_user_command(user, "set_compact_questions");
+            let compact_questions = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(
+                        user.id,
+                        "set_compact_questions command expects 'on' or 'off'.",
+                    )
+                    .await
+                    .context(
+                        "failed to notify user, that set_compact_questions command expects 'on' or 'off'",
+                    )?;
+                    return Ok(());
+                }
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.compact_questions = compact_questions;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                if compact_questions {
+                    "Question texts and images are sent as one message."
+                } else {
+                    "Question texts and images are sent as separate messages."
+                },
+            )
+            .await
+            .context("failed to confirm compact questions change")?;
+        }
+        "/set_cascade_fails" => {
+            log_user_command(user, "set_cascade_fails");
+            let cascade_fails = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(user.id, "set_cascade_fails command expects 'on' or 'off'.")
+                        .await
+                        .context(
+                            "failed to notify user, that set_cascade_fails command expects 'on' or 'off'",
+                        )?;
+                    return Ok(());
+                }
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.cascade_fails = cascade_fails;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                if cascade_fails {
+                    "Fails now make dependents yellow."
+                } else {
+                    "Fails now don't affect dependents."
+                },
+            )
+            .await
+            .context("failed to confirm fail cascading change")?;
+        }
+        "/set_layered_graph" => {
+            log_user_command(user, "set_layered_graph");
+            let layered_graph = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(user.id, "set_layered_graph command expects 'on' or 'off'.")
+                        .await
+                        .context(
+                            "failed to notify user, that set_layered_graph command expects 'on' or 'off'",
+                        )?;
                     return Ok(());
-                };
-                interaction_types::card::random_task(tasks, rand::rng()).clone()
+                }
             };
-            complete_card(bot, user.id, task, user_state, user_states).await;
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.layered_graph = layered_graph;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                if layered_graph {
+                    "Graph cards are drawn in rows by depth."
+                } else {
+                    "Graph cards are placed freely."
+                },
+            )
+            .await
+            .context("failed to confirm layered graph change")?;
         }
-        "/graph" => {
-            log_user_command(user, "graph");
-            if !tail.is_empty() {
-                bot.send_message(user.id, "graph command doesn't expect any arguments.")
-                    .await
-                    .context(
-                        "failed to notify user, that graph command doesn't have any arguments",
-                    )?;
-                return Ok(());
-            }
-
-            let Some(course) = db_get_course(course_id) else {
+        "/set_native_polls" => {
+            log_user_command(user, "set_native_polls");
+            let native_polls = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(user.id, "set_native_polls command expects 'on' or 'off'.")
+                        .await
+                        .context(
+                            "failed to notify user, that set_native_polls command expects 'on' or 'off'",
+                        )?;
+                    return Ok(());
+                }
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.native_polls = native_polls;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                if native_polls {
+                    "Multiple choice questions are now asked as quiz polls, when they fit Telegram limits."
+                } else {
+                    "Multiple choice questions are now asked with buttons."
+                },
+            )
+            .await
+            .context("failed to confirm native polls change")?;
+        }
+        "/set_ask_difficulty" => {
+            log_user_command(user, "set_ask_difficulty");
+            let ask_difficulty = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(user.id, "set_ask_difficulty command expects 'on' or 'off'.")
+                        .await
+                        .context(
+                            "failed to notify user, that set_ask_difficulty command expects 'on' or 'off'",
+                        )?;
+                    return Ok(());
+                }
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.ask_difficulty = ask_difficulty;
+            db_set_course(course_id, course)?;
+            bot.send_message(
+                user.id,
+                if ask_difficulty {
+                    "Learners are asked how hard correct answers were."
+                } else {
+                    "Correct answers are graded as 'Good' without asking."
+                },
+            )
+            .await
+            .context("failed to confirm ask difficulty change")?;
+        }
+        "/set_difficulty_by_time" => {
+            log_user_command(user, "set_difficulty_by_time");
+            let Some(thresholds) = parse_latency_thresholds(tail) else {
                 bot.send_message(
                     user.id,
-                    format!("Course with id {} not found.", course_id.0),
+                    format!(
+                        "set_difficulty_by_time command expects two numbers of seconds, like '5 20', up to {MAX_LATENCY_THRESHOLD_SECS}, or 'off'."
+                    ),
                 )
                 .await
-                .context("failed to notify user, that there is no course with this id")?;
+                .context("failed to notify user, that set_difficulty_by_time argument is invalid")?;
                 return Ok(());
             };
-            let graph = course.structure.generate_structure_graph();
-
-            send_interactions(
-                bot,
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.difficulty_by_time = thresholds;
+            db_set_course(course_id, course)?;
+            bot.send_message(
                 user.id,
-                [TelegramInteraction::PersonalImage(
-                    tokio::task::spawn_blocking(move || {
-                        graphviz_rust::exec(
-                            graph.clone(),
-                            &mut PrinterContext::default(),
-                            Vec::from([Format::Jpeg.into()]),
-                        )
-                        .unwrap_or_else(|err| {
-                            log::error!(
-                                "Failed to run dot with this source: \n`{}`\n, because of this error: {err}",
-                                graph.print(&mut PrinterContext::default())
-                                // course.structure.get_source()
-                            );
-                            panic!("Failed to run 'dot'");
-                        })
-                    })
-                    .await
-                    .unwrap(),
-                )],
-                user_state,
+                match thresholds {
+                    Some(LatencyThresholds {
+                        easy_secs,
+                        hard_secs,
+                    }) => format!(
+                        "Correct answers within {easy_secs}s are graded as easy, after {hard_secs}s as hard."
+                    ),
+                    None => "Correct answers are graded without looking at response time.".into(),
+                },
             )
             .await
-            .context("failed to send graph image")?;
+            .context("failed to confirm difficulty by time change")?;
         }
-        "/revise" => {
-            // TODO
-            log_user_command(user, "revise");
-            bot.send_message(user.id, "This command is temporarily disabled")
-                .await?;
+        "/set_public" => {
+            log_user_command(user, "set_public");
+            let public = match tail {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    bot.send_message(user.id, "set_public command expects 'on' or 'off'.")
+                        .await
+                        .context(
+                            "failed to notify user, that set_public command expects 'on' or 'off'",
+                        )?;
+                    return Ok(());
+                }
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            course.settings.public = public;
+            let has_title = course.title().is_some();
+            db_set_course(course_id, course)?;
+            let reply = match (public, has_title) {
+                (true, true) => "Course can now be found with inline search.",
+                (true, false) => {
+                    "Course is public, but it needs description to be found. Use /set_description."
+                }
+                (false, _) => "Course is hidden from inline search.",
+            };
+            bot.send_message(user.id, reply)
+                .await
+                .context("failed to confirm course visibility change")?;
         }
-        "/change_course_graph" => {
-            log_user_command(user, "change_course_graph");
-            if !tail.is_empty() {
+        "/set_description" => {
+            log_user_command(user, "set_description");
+            if tail.trim().chars().count() > MAX_DESCRIPTION_LEN {
                 bot.send_message(
                     user.id,
-                    "change_course_graph command doesn't expect any arguments.",
+                    format!("Description should be at most {MAX_DESCRIPTION_LEN} characters."),
                 )
                 .await
-                .context(
-                    "failed to notify user, that change_course_graph command doesn't arguments",
-                )?;
+                .context("failed to notify user, that description is too long")?;
                 return Ok(());
             }
-            handle_changing_course_graph(bot, user_state, user.id, course_id)
+            let mut course = db_get_existing_course(course_id)?;
+            course.description = tail.trim().to_owned();
+            let reply = if course.description.is_empty() {
+                "Description removed."
+            } else {
+                "Description changed. Learners will see it, when they enter the course."
+            };
+            db_set_course(course_id, course)?;
+            bot.send_message(user.id, reply)
                 .await
-                .context("failed to change course graph")?;
+                .context("failed to confirm description change")?;
         }
-        "/change_deque" => {
-            log_user_command(user, "change_deque");
+        "/stats_global" => {
+            log_user_command(user, "stats_global");
+            let count = if tail.is_empty() {
+                10
+            } else {
+                let Ok(count) = tail.parse() else {
+                    bot.send_message(
+                        user.id,
+                        "stats_global command expects number of cards to show.",
+                    )
+                    .await
+                    .context("failed to notify user, that stats_global argument is invalid")?;
+                    return Ok(());
+                };
+                count
+            };
+            let progresses = db_all_progress_for_course(course_id)?;
+            let stats = CourseStats::aggregate(progresses.iter().map(|(_, progress)| progress));
+            bot.send_message(user.id, stats.hardest_cards_message(count))
+                .await
+                .context("failed to send global course stats")?;
+        }
+        "/export_analytics" => {
+            log_user_command(user, "export_analytics");
             if !tail.is_empty() {
                 bot.send_message(
                     user.id,
-                    "change_deque command doesn't expect any arguments.",
+                    "export_analytics command doesn't expect any arguments.",
                 )
                 .await
                 .context(
-                    "failed to notify user, that change_deque command doesn't have arguments",
+                    "failed to notify user, that export_analytics command doesn't have arguments",
                 )?;
                 return Ok(());
             }
-            handle_changing_deque(bot, user_state, user.id, course_id)
-                .await
-                .context("failed to change deque")?;
+            let progresses = db_all_progress_for_course(course_id)?;
+            let stats = CourseStats::aggregate(progresses.iter().map(|(_, progress)| progress));
+            bot.send_document(
+                user.id,
+                InputFile::memory(stats.to_csv().into_bytes())
+                    .file_name(format!("course_{}_analytics.csv", course_id.0)),
+            )
+            .await
+            .context("failed to send analytics csv")?;
         }
-        "/view_course_graph_source" => {
-            log_user_command(user, "view_course_graph_source");
-            if !tail.is_empty() {
-                bot.send_message(
-                    user.id,
-                    "view_course_graph_source command doesn't expect any arguments.",
-                )
-                .await.context("failed to notify user, that view_course_graph_source command doesn't have arguments")?;
+        "/merge_course" => {
+            log_user_command(user, "merge_course");
+            let Some(other_id) = tail.parse().ok().map(CourseId) else {
+                bot.send_message(user.id, "merge_course command expects course id.")
+                    .await
+                    .context(
+                        "failed to notify user, that merge_course command expects course id",
+                    )?;
                 return Ok(());
-            }
-            send_interactions(
-                bot,
+            };
+            let Some(other) = db_get_course(other_id)?
+                .filter(|other| other.owner_id == user.id && other_id != course_id)
+            else {
+                bot.send_message(user.id, "You can merge only other course of yours.")
+                    .await
+                    .context("failed to notify user, that he can merge only his courses")?;
+                return Ok(());
+            };
+            let mut course = db_get_existing_course(course_id)?;
+            let case_sensitive_names = course.settings.case_sensitive_names;
+            let merged = course
+                .structure
+                .merge(&other.structure, case_sensitive_names)
+                .and_then(|structure| {
+                    let tasks = course.tasks.merge(&other.tasks, case_sensitive_names)?;
+                    Ok((structure, tasks))
+                });
+            let (structure, tasks) = match merged {
+                Ok(merged) => merged,
+                Err(err) => {
+                    let err = strip_ansi_escapes::strip_str(err);
+                    bot.send_message(user.id, format!("Courses can't be merged: {err}."))
+                        .await
+                        .context("failed to notify user, that courses can't be merged")?;
+                    return Ok(());
+                }
+            };
+            if !confirm(
+                bot.clone(),
                 user.id,
-                vec![
-                    "Course graph source:".into(),
-                    format!(
-                        "```\n{}\n```",
-                        db_get_course(course_id).unwrap().structure.get_source()
-                    )
-                    .into(),
-                ],
+                &format!(
+                    "{} cards of course {} will be added to this course. Continue?",
+                    other.tasks.tasks.len(),
+                    other_id.0
+                ),
                 user_state,
             )
-            .await
-            .context("failed to send course graph source")?;
-        }
-        "/view_deque_source" => {
-            log_user_command(user, "view_deque_source");
-            if !tail.is_empty() {
-                bot.send_message(
-                    user.id,
-                    "view_deque_source command doesn't expect any arguments.",
-                )
-                .await
-                .context(
-                    "failed to notify user, that view_deque_source command doesn't have arguments",
-                )?;
+            .await?
+            {
+                bot.send_message(user.id, "Course is kept.")
+                    .await
+                    .context("failed to notify user, that merge is cancelled")?;
                 return Ok(());
             }
-            send_interactions(
-                bot,
+            course.structure = structure;
+            course.tasks = tasks;
+            db_set_course(course_id, course)?;
+            bot.send_message(
                 user.id,
-                vec![
-                    "Deque source:".into(),
-                    format!(
-                        "```\n{}\n```",
-                        db_get_course(course_id).unwrap().tasks.source.to_owned()
-                    )
-                    .into(),
-                ],
-                user_state,
+                format!(
+                    "Courses are merged. Course {} is kept unchanged.",
+                    other_id.0
+                ),
             )
             .await
-            .context("failed to send deque source")?;
+            .context("failed to confirm courses merge")?;
+        }
+        "/lint_markdown" => {
+            log_user_command(user, "lint_markdown");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "lint_markdown command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that lint_markdown command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            const MAX_SHOWN: usize = 30;
+            let problems = course_markdown_problems(&db_get_existing_course(course_id)?);
+            let text = if problems.is_empty() {
+                "No markdown problems found.".to_owned()
+            } else {
+                let mut text = problems[..problems.len().min(MAX_SHOWN)].join("\n");
+                if problems.len() > MAX_SHOWN {
+                    text.push_str(&format!("\n...and {} more.", problems.len() - MAX_SHOWN));
+                }
+                text
+            };
+            bot.send_message(user.id, text)
+                .await
+                .context("failed to send markdown problems")?;
         }
         "/view_course_errors" => {
             log_user_command(user, "view_course_errors");
@@ -664,10 +2663,11 @@ async fn handle_owned_course_interaction(
                 )?;
                 return Ok(());
             }
-            match generate_message_about_course_errors(course_id) {
+            match generate_message_about_course_errors(course_id)? {
                 Some(msgs) => {
+                    // Card names are written by owner and may contain any markdown characters.
                     for msg in msgs {
-                        send_markdown(&bot, user.id, &msg)
+                        send_literal(&bot, user.id, &msg)
                             .await
                             .context("failed to send course errors")?;
                     }
@@ -679,6 +2679,55 @@ async fn handle_owned_course_interaction(
                 }
             }
         }
+        "/leave" => {
+            log_user_command(user, "leave");
+            bot.send_message(
+                user.id,
+                "You can't leave your own course. Use /delete_course to remove it.",
+            )
+            .await
+            .context("failed to notify user, that he can't leave his own course")?;
+        }
+        "/delete_course" => {
+            log_user_command(user, "delete_course");
+            if !tail.is_empty() {
+                bot.send_message(user.id, "delete_course command doesn't expect any arguments.")
+                    .await
+                    .context(
+                        "failed to notify user, that delete_course command doesn't expect any arguments",
+                    )?;
+                return Ok(());
+            }
+            if db_get_course(course_id)?.is_none_or(|course| course.owner_id != user.id) {
+                bot.send_message(user.id, "It's not your course.")
+                    .await
+                    .context("failed to notify user, that he can delete only his courses")?;
+                return Ok(());
+            }
+            let learners = db_all_progress_for_course(course_id)?.len();
+            if !confirm(
+                bot.clone(),
+                user.id,
+                &format!(
+                    "Course {} and progress of its {learners} learners will be deleted. This can't be undone. Continue?",
+                    course_id.0
+                ),
+                user_state,
+            )
+            .await?
+            {
+                bot.send_message(user.id, "Course is kept.")
+                    .await
+                    .context("failed to notify user, that course deletion is cancelled")?;
+                return Ok(());
+            }
+            db_delete_course(course_id)?;
+            // Owner is moved to main menu too.
+            leave_deleted_courses(user_states, &[course_id]);
+            bot.send_message(user.id, "Course is deleted. You are now in main menu.")
+                .await
+                .context("failed to confirm course deletion")?;
+        }
         _ => {
             handle_no_command(bot, user, message, user_state)
                 .await
@@ -688,17 +2737,32 @@ async fn handle_owned_course_interaction(
     Ok(())
 }
 
-fn generate_message_about_course_errors(course_id: CourseId) -> Option<Vec<String>> {
-    if let Some(errors) = db_get_course(course_id).unwrap().get_errors() {
-        let mut msgs = Vec::new();
+/// Errors, and info about duplicated tasks and similar card names.
+fn generate_message_about_course_errors(course_id: CourseId) -> DbResult<Option<Vec<String>>> {
+    let course = db_get_existing_course(course_id)?;
+    let mut msgs = Vec::new();
+    if let Some(errors) = course.get_errors() {
         msgs.push("Errors:".into());
         for error in errors {
             msgs.push(error);
         }
-        Some(msgs)
-    } else {
-        None
     }
+    let duplicates = course.tasks.find_duplicate_tasks();
+    let similar_names = course.tasks.find_similar_names();
+    if !duplicates.is_empty() || !similar_names.is_empty() {
+        msgs.push("Info:".into());
+        for (first, second) in duplicates {
+            msgs.push(format!(
+                "Cards '{first}' and '{second}' have identical task."
+            ));
+        }
+        for (first, second) in similar_names {
+            msgs.push(format!(
+                "Cards '{first}' and '{second}' differ only in case or spaces, learners can confuse them."
+            ));
+        }
+    }
+    Ok((!msgs.is_empty()).then_some(msgs))
 }
 
 async fn handle_no_command(
@@ -714,7 +2778,11 @@ async fn handle_no_command(
             current_id,
             current_message,
             answers,
+            chosen_items: _,
+            current_poll: _,
             channel: _,
+            transient_messages: _,
+            cleanup: _,
         }) => match &interactions[*current] {
             TelegramInteraction::UserInput => {
                 let user_input = message.to_owned();
@@ -746,3 +2814,335 @@ async fn handle_no_command(
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use teloxide_core::types::CallbackQuery;
+
+    use super::*;
+    use crate::{interaction_types::deque::Deque, settings::CourseSettings};
+
+    #[test]
+    fn card_without_tasks_is_not_served() {
+        let user_id = UserId(258_000);
+        assert!(choose_task(&BTreeMap::new(), 0, user_id).is_none());
+        assert!(choose_task(&BTreeMap::new(), 5, user_id).is_none());
+
+        let tasks = Deque::default().tasks["countries"].clone();
+        for repetitions in 0..=tasks.len() as u32 {
+            assert!(choose_task(&tasks, repetitions, user_id).is_some());
+        }
+    }
+
+    #[test]
+    fn start_payload_enters_course() {
+        db_create_tables().unwrap();
+        let course_id = db_insert(Course::empty(UserId(220_000))).unwrap();
+        let user_id = UserId(220_001);
+        let mut user_state = UserState::default();
+
+        let payload = format!("course_{}", course_id.0);
+        let parsed = parse_start_payload(&payload).unwrap();
+        assert!(
+            switch_to_course(user_id, parsed, &mut user_state)
+                .unwrap()
+                .is_some()
+        );
+        assert!(matches!(user_state.current_screen, Screen::Course(id) if id == course_id));
+        assert!(
+            db_list_user_learned_courses(user_id)
+                .unwrap()
+                .contains(&course_id)
+        );
+
+        for payload in [
+            "course_",
+            "course_-1",
+            "course_+1",
+            "course_1 2",
+            "lesson_1",
+        ] {
+            assert_eq!(parse_start_payload(payload), None, "{payload}");
+        }
+    }
+
+    /// Telegram API, which accepts every request. Methods, that return message,
+    /// get placeholder one. Returns bot, talking to it, and texts of sent messages.
+    async fn fake_bot() -> (Bot, Arc<Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let texts = Arc::new(Mutex::new(Vec::new()));
+        let sent = texts.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let (head, body) = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    assert_ne!(read, 0, "request is cut");
+                    request.extend_from_slice(&buf[..read]);
+                    let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") else {
+                        continue;
+                    };
+                    let head = String::from_utf8_lossy(&request[..end]).to_lowercase();
+                    let len = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .map_or(0, |len| len.trim().parse().unwrap());
+                    if request.len() >= end + 4 + len {
+                        break (head, request[end + 4..end + 4 + len].to_vec());
+                    }
+                };
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                if let Some(text) = body["text"].as_str() {
+                    sent.lock().unwrap().push(text.to_owned());
+                }
+                let method = head.lines().next().unwrap_or_default();
+                let result = if method.contains("answercallbackquery") {
+                    serde_json::json!(true)
+                } else {
+                    serde_json::json!({
+                        "message_id": 1,
+                        "date": 0,
+                        "chat": {"id": body["chat_id"], "type": "private", "first_name": "Learner"},
+                        "from": {"id": 1, "is_bot": true, "first_name": "Bot"},
+                        "text": "placeholder",
+                    })
+                };
+                let response = serde_json::json!({"ok": true, "result": result}).to_string();
+                stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        (Bot::new("test").set_api_url(url.parse().unwrap()), texts)
+    }
+
+    #[tokio::test]
+    async fn schedule_reset_follows_confirmation() {
+        db_create_tables().unwrap();
+        let (bot, texts) = fake_bot().await;
+        let course_id = db_insert(Course::empty(UserId(214_000))).unwrap();
+        let users_state = DashMap::new();
+        for (learner, answer, reset) in [
+            (UserId(214_001), "No", false),
+            (UserId(214_002), "Yes", true),
+        ] {
+            db_add_course_to_user(learner, course_id).unwrap();
+            let mut progress = db_get_progress(learner, course_id).unwrap();
+            progress.repetition(
+                &"intro".to_owned(),
+                ssr_algorithms::fsrs::level::RepetitionContext {
+                    quality: ssr_algorithms::fsrs::level::Quality::Good,
+                    review_time: chrono::Local::now(),
+                },
+                true,
+            );
+            db_set_course_progress(learner, course_id, progress).unwrap();
+            assert_eq!(review_counts(learner, course_id).unwrap(), (0, 0));
+            users_state.insert(
+                learner,
+                UserState {
+                    current_screen: Screen::Course(course_id),
+                    ..Default::default()
+                },
+            );
+            let user: User = serde_json::from_value(serde_json::json!({
+                "id": learner.0,
+                "is_bot": false,
+                "first_name": "Learner",
+            }))
+            .unwrap();
+
+            let command =
+                handle_text_message(bot.clone(), &user, "/reset_course_schedule", &users_state);
+            let tap = async {
+                // Command holds user state, until prompt is sent.
+                let current_id = loop {
+                    let prompt = users_state
+                        .try_get(&learner)
+                        .try_unwrap()
+                        .and_then(|state| {
+                            let interaction = state.current_interaction.as_ref()?;
+                            interaction.current_message.map(|_| interaction.current_id)
+                        });
+                    if let Some(current_id) = prompt {
+                        break current_id;
+                    }
+                    tokio::task::yield_now().await;
+                };
+                let query: CallbackQuery = serde_json::from_value(serde_json::json!({
+                    "id": "1",
+                    "from": {"id": learner.0, "is_bot": false, "first_name": "Learner"},
+                    "chat_instance": "1",
+                    "data": format!("{current_id} {answer}"),
+                }))
+                .unwrap();
+                callback_handler(bot.clone(), query, &users_state)
+                    .await
+                    .unwrap();
+            };
+            tokio::join!(command, tap);
+
+            assert_eq!(
+                review_counts(learner, course_id).unwrap(),
+                (usize::from(reset), 0)
+            );
+            let reply = texts.lock().unwrap().last().cloned().unwrap();
+            if reset {
+                assert_eq!(reply, "1 cards are due now. Your progress is kept.");
+            } else {
+                assert_eq!(reply, "Schedule is kept.");
+            }
+        }
+    }
+
+    #[test]
+    fn start_link_hides_private_course() {
+        db_create_tables().unwrap();
+        let owner = UserId(220_100);
+        let course_id = db_insert(Course::empty(owner)).unwrap();
+        let stranger = UserId(220_101);
+        let learner = UserId(220_102);
+        db_add_course_to_user(learner, course_id).unwrap();
+
+        assert!(!is_reachable_by_link(stranger, course_id).unwrap());
+        assert!(is_reachable_by_link(owner, course_id).unwrap());
+        assert!(is_reachable_by_link(learner, course_id).unwrap());
+        assert!(!is_reachable_by_link(stranger, CourseId(i64::MAX as u64)).unwrap());
+        assert!(
+            !db_list_user_learned_courses(stranger)
+                .unwrap()
+                .contains(&course_id)
+        );
+
+        let mut course = db_get_existing_course(course_id).unwrap();
+        course.settings.public = true;
+        db_set_course(course_id, course).unwrap();
+        assert!(is_reachable_by_link(stranger, course_id).unwrap());
+    }
+
+    #[test]
+    fn graph_view_follows_preference() {
+        let mut settings = UserSettings::default();
+        assert_eq!(graph_view_for("", &settings), Some(GraphView::Image));
+        settings.graph_view = GraphView::List;
+        assert_eq!(graph_view_for("", &settings), Some(GraphView::List));
+        assert_eq!(graph_view_for("image", &settings), Some(GraphView::Image));
+        assert_eq!(graph_view_for("text", &settings), Some(GraphView::Text));
+        assert_eq!(graph_view_for("tree", &settings), None);
+    }
+
+    #[test]
+    fn card_list_keeps_order_and_reports_unknown() {
+        let course = Course {
+            owner_id: UserId(230_000),
+            structure: CourseGraph::default(),
+            tasks: Deque::default(),
+            settings: CourseSettings::default(),
+            description: String::new(),
+        };
+        let (cards, unknown) = parse_card_list("Capitals, oceans,countries, , two words", &course);
+        assert_eq!(cards, vec!["capitals", "countries"]);
+        assert_eq!(unknown, vec!["oceans", "two words"]);
+
+        let (cards, unknown) = parse_card_list("countries", &course);
+        assert_eq!(cards, vec!["countries"]);
+        assert!(unknown.is_empty());
+    }
+
+    #[tokio::test]
+    async fn locked_card_doesnt_stop_queue() {
+        db_create_tables().unwrap();
+        let deque = ["a", "x", "b", "c"]
+            .map(|name| format!("# Name\n{name}\n## Task 1\nQ\n\n* a\n- b"))
+            .join("\n-----\n");
+        let course = Course::empty(UserId(230_100))
+            .with(
+                CourseGraph::parse("a\nx\nb: x\nc", false).unwrap(),
+                interaction_types::deque::from_str(&deque, true, false).unwrap(),
+            )
+            .unwrap();
+        let course_id = db_insert(course).unwrap();
+        let learner = UserId(230_101);
+        db_add_course_to_user(learner, course_id).unwrap();
+        let queue = || ["a", "b", "c"].map(String::from).to_vec();
+
+        let mut learned = Vec::new();
+        let skipped = learn_queue(queue(), |card_name| {
+            let outcome = match prepare_card(learner, course_id, &card_name).unwrap() {
+                Ok(_) => {
+                    learned.push(card_name);
+                    CardOutcome::Answered
+                }
+                Err(_) => CardOutcome::Skipped,
+            };
+            async move { Ok(outcome) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(skipped, ["b"]);
+        assert_eq!(learned, ["a", "c"]);
+
+        let mut tried = Vec::new();
+        let skipped = learn_queue(queue(), |card_name| {
+            tried.push(card_name);
+            async { Ok(CardOutcome::Interrupted) }
+        })
+        .await
+        .unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(tried, ["a"]);
+    }
+
+    #[test]
+    fn entry_greeting_shows_counts() {
+        db_create_tables().unwrap();
+        let owner = UserId(231_000);
+        let course_id = db_insert(Course::with_default_cards(owner)).unwrap();
+        let learner = UserId(231_001);
+        db_add_course_to_user(learner, course_id).unwrap();
+        let course = db_get_course(course_id).unwrap().unwrap();
+
+        let (greeting, keyboard) = session_greeting(learner, course_id, &course)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            greeting,
+            "Welcome back! Cards to review: 0. New cards available: 1."
+        );
+        let keyboard = keyboard.unwrap();
+        assert_eq!(keyboard.inline_keyboard[0][0].text, "Review now");
+        assert_eq!(
+            parse_review_callback(&format!("review:{}", course_id.0)),
+            Some(course_id)
+        );
+        assert_eq!(parse_review_callback("review:"), None);
+
+        assert!(
+            session_greeting(owner, course_id, &course)
+                .unwrap()
+                .is_none()
+        );
+        let mut settings = db_get_user_settings(learner).unwrap();
+        settings.session_greeting = false;
+        db_set_user_settings(learner, &settings).unwrap();
+        assert!(
+            session_greeting(learner, course_id, &course)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
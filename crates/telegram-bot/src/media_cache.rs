@@ -0,0 +1,190 @@
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use dashmap::DashMap;
+use teloxide_core::types::{FileId, InputFile};
+use url::Url;
+
+/// Telegram downloads media by url on every send, but file id of already sent file is instant.
+pub static MEDIA_CACHE: LazyLock<MediaCache> = LazyLock::new(MediaCache::default);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaSource {
+    FileId(FileId),
+    Url(Url),
+    /// Downloaded by bot, when Telegram can't fetch url itself.
+    Memory(Vec<u8>),
+}
+impl From<MediaSource> for InputFile {
+    fn from(source: MediaSource) -> Self {
+        match source {
+            MediaSource::FileId(file_id) => InputFile::file_id(file_id),
+            MediaSource::Url(url) => InputFile::url(url),
+            MediaSource::Memory(bytes) => InputFile::memory(bytes),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MediaCache {
+    file_ids: DashMap<Url, FileId>,
+}
+impl MediaCache {
+    /// Sends by cached file id, if there is one. Falls back to url,
+    /// if Telegram rejects it(old file id can become stale).
+    ///
+    /// `send` returns file id of sent file.
+    pub async fn send<F, Fut>(&self, url: &Url, mut send: F) -> anyhow::Result<()>
+    where
+        F: FnMut(MediaSource) -> Fut,
+        Fut: Future<Output = anyhow::Result<Option<FileId>>>,
+    {
+        let cached = self.file_ids.get(url).map(|file_id| file_id.clone());
+        if let Some(file_id) = cached {
+            match send(MediaSource::FileId(file_id)).await {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    log::warn!("cached file id for '{url}' is rejected: {err:?}");
+                    self.file_ids.remove(url);
+                }
+            }
+        }
+        if let Some(file_id) = send(MediaSource::Url(url.clone())).await? {
+            self.file_ids.insert(url.clone(), file_id);
+        }
+        Ok(())
+    }
+    /// Like [`MediaCache::send`], but if Telegram can't fetch url(hotlink protection, slow server),
+    /// file is downloaded with `download` and uploaded.
+    pub async fn send_or_upload<F, Fut, D, DFut>(
+        &self,
+        url: &Url,
+        mut send: F,
+        download: D,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(MediaSource) -> Fut,
+        Fut: Future<Output = anyhow::Result<Option<FileId>>>,
+        D: FnOnce(Url) -> DFut,
+        DFut: Future<Output = anyhow::Result<Vec<u8>>>,
+    {
+        let Err(err) = self.send(url, &mut send).await else {
+            return Ok(());
+        };
+        log::warn!("telegram can't fetch '{url}', uploading it: {err:?}");
+        let bytes = download(url.clone())
+            .await
+            .with_context(|| format!("failed to download '{url}'"))?;
+        if let Some(file_id) = send(MediaSource::Memory(bytes)).await? {
+            self.file_ids.insert(url.clone(), file_id);
+        }
+        Ok(())
+    }
+    /// For media, that can't be sent with [`MediaCache::send`], like albums.
+    pub fn source(&self, url: &Url) -> MediaSource {
+        match self.file_ids.get(url) {
+            Some(file_id) => MediaSource::FileId(file_id.clone()),
+            None => MediaSource::Url(url.clone()),
+        }
+    }
+    pub fn remember(&self, url: &Url, file_id: FileId) {
+        self.file_ids.insert(url.clone(), file_id);
+    }
+    pub fn forget(&self, url: &Url) {
+        self.file_ids.remove(url);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn second_send_uses_file_id() {
+        let cache = MediaCache::default();
+        let url = Url::parse("https://example.com/image.png").unwrap();
+        let file_id = FileId("cached".into());
+        let sent = RefCell::new(Vec::new());
+        let send = |source| {
+            sent.borrow_mut().push(source);
+            let file_id = file_id.clone();
+            async move { Ok(Some(file_id)) }
+        };
+
+        cache.send(&url, send).await.unwrap();
+        cache.send(&url, send).await.unwrap();
+        assert_eq!(
+            sent.into_inner(),
+            vec![
+                MediaSource::Url(url.clone()),
+                MediaSource::FileId(file_id.clone())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_file_id_is_invalidated() {
+        let cache = MediaCache::default();
+        let url = Url::parse("https://example.com/image.png").unwrap();
+        cache.file_ids.insert(url.clone(), FileId("stale".into()));
+        let sent = RefCell::new(Vec::new());
+
+        cache
+            .send(&url, |source| {
+                let rejected = matches!(source, MediaSource::FileId(_));
+                sent.borrow_mut().push(source);
+                async move {
+                    if rejected {
+                        anyhow::bail!("wrong file identifier");
+                    }
+                    Ok(Some(FileId("fresh".into())))
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            sent.into_inner(),
+            vec![
+                MediaSource::FileId(FileId("stale".into())),
+                MediaSource::Url(url.clone())
+            ]
+        );
+        assert_eq!(*cache.file_ids.get(&url).unwrap(), FileId("fresh".into()));
+    }
+
+    #[tokio::test]
+    async fn unreachable_url_is_uploaded() {
+        let cache = MediaCache::default();
+        let url = Url::parse("https://example.com/protected.png").unwrap();
+        let bytes = vec![1, 2, 3];
+        let sent = RefCell::new(Vec::new());
+
+        cache
+            .send_or_upload(
+                &url,
+                |source| {
+                    let rejected = matches!(source, MediaSource::Url(_));
+                    sent.borrow_mut().push(source);
+                    async move {
+                        if rejected {
+                            anyhow::bail!("failed to get HTTP URL content");
+                        }
+                        Ok(Some(FileId("uploaded".into())))
+                    }
+                },
+                |_| async { Ok(bytes.clone()) },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            sent.into_inner(),
+            vec![MediaSource::Url(url.clone()), MediaSource::Memory(bytes)]
+        );
+        assert_eq!(
+            *cache.file_ids.get(&url).unwrap(),
+            FileId("uploaded".into())
+        );
+    }
+}
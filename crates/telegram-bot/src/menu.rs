@@ -0,0 +1,170 @@
+//! `/menu` keyboard, so bot can be used without remembering commands.
+//! Buttons run the same flows, as typed commands.
+
+use teloxide_core::types::{InlineKeyboardButton, InlineKeyboardMarkup};
+
+/// Button of `/menu` keyboard. Callback data is `menu:<command>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    Help,
+    Exit,
+    List,
+    Templates,
+    Settings,
+    Next,
+    Review,
+    Graph,
+    Stats,
+    Goals,
+    Revise,
+    CourseErrors,
+    LearnerStats,
+}
+
+/// Screen, menu is shown on. Owner and learner of course have different commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuScreen {
+    Main,
+    Settings,
+    LearnedCourse,
+    OwnedCourse,
+}
+
+impl MenuAction {
+    pub const CALLBACK_PREFIX: &str = "menu:";
+    const ALL: [MenuAction; 13] = [
+        MenuAction::Help,
+        MenuAction::Exit,
+        MenuAction::List,
+        MenuAction::Templates,
+        MenuAction::Settings,
+        MenuAction::Next,
+        MenuAction::Review,
+        MenuAction::Graph,
+        MenuAction::Stats,
+        MenuAction::Goals,
+        MenuAction::Revise,
+        MenuAction::CourseErrors,
+        MenuAction::LearnerStats,
+    ];
+
+    /// Typed command, that button stands for.
+    pub fn command(self) -> &'static str {
+        match self {
+            MenuAction::Help => "/help",
+            MenuAction::Exit => "/exit",
+            MenuAction::List => "/list",
+            MenuAction::Templates => "/templates",
+            MenuAction::Settings => "/settings",
+            MenuAction::Next => "/next",
+            MenuAction::Review => "/review",
+            MenuAction::Graph => "/graph",
+            MenuAction::Stats => "/stats",
+            MenuAction::Goals => "/goal",
+            MenuAction::Revise => "/revise",
+            MenuAction::CourseErrors => "/view_course_errors",
+            MenuAction::LearnerStats => "/stats_global",
+        }
+    }
+    fn label(self) -> &'static str {
+        match self {
+            MenuAction::Help => "All commands",
+            MenuAction::Exit => "Main menu",
+            MenuAction::List => "My courses",
+            MenuAction::Templates => "Starter courses",
+            MenuAction::Settings => "Settings",
+            MenuAction::Next => "Next card",
+            MenuAction::Review => "Review",
+            MenuAction::Graph => "Graph",
+            MenuAction::Stats => "Stats",
+            MenuAction::Goals => "Goals",
+            MenuAction::Revise => "Revise",
+            MenuAction::CourseErrors => "Course errors",
+            MenuAction::LearnerStats => "Learner stats",
+        }
+    }
+    pub fn callback_data(self) -> String {
+        format!("{}{}", Self::CALLBACK_PREFIX, self.command())
+    }
+    pub fn from_callback_data(data: &str) -> Option<Self> {
+        let command = data.strip_prefix(Self::CALLBACK_PREFIX)?;
+        Self::ALL
+            .into_iter()
+            .find(|action| action.command() == command)
+    }
+}
+
+fn menu_actions(screen: MenuScreen) -> &'static [MenuAction] {
+    match screen {
+        MenuScreen::Main => &[
+            MenuAction::List,
+            MenuAction::Templates,
+            MenuAction::Settings,
+            MenuAction::Help,
+        ],
+        MenuScreen::Settings => &[MenuAction::Exit, MenuAction::Help],
+        MenuScreen::LearnedCourse => &[
+            MenuAction::Next,
+            MenuAction::Review,
+            MenuAction::Graph,
+            MenuAction::Stats,
+            MenuAction::Goals,
+            MenuAction::Exit,
+            MenuAction::Help,
+        ],
+        MenuScreen::OwnedCourse => &[
+            MenuAction::Graph,
+            MenuAction::Revise,
+            MenuAction::CourseErrors,
+            MenuAction::LearnerStats,
+            MenuAction::Exit,
+            MenuAction::Help,
+        ],
+    }
+}
+
+/// Two buttons per row, so labels aren't cut on phones.
+pub fn menu_keyboard(screen: MenuScreen) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(menu_actions(screen).chunks(2).map(|row| {
+        row.iter()
+            .map(|action| InlineKeyboardButton::callback(action.label(), action.callback_data()))
+            .collect::<Vec<_>>()
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use teloxide_core::types::InlineKeyboardButtonKind;
+
+    use super::*;
+
+    #[test]
+    fn graph_button_runs_graph_command() {
+        for screen in [MenuScreen::LearnedCourse, MenuScreen::OwnedCourse] {
+            let keyboard = menu_keyboard(screen);
+            let graph = keyboard
+                .inline_keyboard
+                .iter()
+                .flatten()
+                .find(|button| button.text == "Graph")
+                .unwrap();
+            let InlineKeyboardButtonKind::CallbackData(data) = &graph.kind else {
+                panic!("menu button should have callback data");
+            };
+            let action = MenuAction::from_callback_data(data).unwrap();
+            assert_eq!(action.command(), "/graph");
+        }
+        assert_eq!(MenuAction::from_callback_data("menu:/delete_course"), None);
+        assert_eq!(MenuAction::from_callback_data("/graph"), None);
+    }
+
+    #[test]
+    fn menu_callback_roundtrip() {
+        for action in MenuAction::ALL {
+            assert_eq!(
+                MenuAction::from_callback_data(&action.callback_data()),
+                Some(action)
+            );
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+
+/// Per-course options, stored as JSON next to the course.
+/// Every field should have a default, so old courses keep working.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CourseSettings {
+    /// Treat `Foo` and `foo` as different cards.
+    pub case_sensitive_names: bool,
+    /// Failed card makes its dependents recursive failed.
+    pub cascade_fails: bool,
+    /// How many levels of dependents become recursive failed after a fail.
+    /// None means unlimited.
+    pub fail_propagation_depth: Option<usize>,
+    /// Learners start cards strictly one after another, in this order.
+    /// Empty order means topological order. None means learner chooses cards freely.
+    pub linear_order: Option<Vec<String>>,
+    /// Course can be found with inline search.
+    pub public: bool,
+    /// After this many consecutive fails of one card in a session, learner is asked to move on.
+    /// None means never.
+    pub move_on_after: Option<u32>,
+    /// Ask multiple choice questions as Telegram quiz polls instead of buttons.
+    pub native_polls: bool,
+    /// Send question texts and images as one message.
+    pub compact_questions: bool,
+    /// Graph images show cards of the same depth in one row.
+    pub layered_graph: bool,
+    /// Maximum number of reviews per learner's day. None means unlimited.
+    pub daily_review_limit: Option<u32>,
+    /// Longer explanations are truncated and attached as a file.
+    /// None means [`MAX_EXPLANATION_LEN`].
+    pub max_explanation_len: Option<usize>,
+    /// After correct answer, learner grades how hard it was.
+    pub ask_difficulty: bool,
+    /// Grade correct answers by response time. Not used, when `ask_difficulty` is on.
+    /// None means every correct answer is `Good`.
+    pub difficulty_by_time: Option<LatencyThresholds>,
+}
+impl Default for CourseSettings {
+    fn default() -> Self {
+        Self {
+            case_sensitive_names: false,
+            cascade_fails: true,
+            fail_propagation_depth: None,
+            linear_order: None,
+            public: false,
+            move_on_after: None,
+            native_polls: false,
+            compact_questions: false,
+            layered_graph: false,
+            daily_review_limit: None,
+            max_explanation_len: None,
+            ask_difficulty: false,
+            difficulty_by_time: None,
+        }
+    }
+}
+
+/// Correct answer within `easy_secs` is `Easy`, after `hard_secs` is `Hard`, otherwise `Good`.
+/// Time is counted from sending question to receiving answer.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LatencyThresholds {
+    pub easy_secs: u32,
+    pub hard_secs: u32,
+}
+impl Default for LatencyThresholds {
+    fn default() -> Self {
+        Self {
+            easy_secs: 5,
+            hard_secs: 20,
+        }
+    }
+}
+
+/// Per-user preferences, edited from `/settings` screen.
+/// Same rules as for [`CourseSettings`] apply.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct UserSettings {
+    /// Desired probability to remember card at repetition, in percents.
+    pub desired_retention: u8,
+    /// Hours from UTC.
+    pub utc_offset: i8,
+    /// Show due and new cards when entering learned course.
+    pub session_greeting: bool,
+    /// Own daily review limit. It can only lower course limit.
+    pub daily_review_limit: Option<u32>,
+    /// Delete "choose answer" prompts after card is answered.
+    pub cleanup_prompts: bool,
+    /// What `/graph` without arguments sends.
+    pub graph_view: GraphView,
+    /// After answer, list options with correct one marked, so screen readers don't depend on buttons.
+    pub options_summary: bool,
+}
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            desired_retention: 85,
+            utc_offset: 0,
+            session_greeting: true,
+            daily_review_limit: None,
+            cleanup_prompts: false,
+            graph_view: GraphView::default(),
+            options_summary: false,
+        }
+    }
+}
+
+/// How learner's progress is shown by `/graph`.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphView {
+    #[default]
+    Image,
+    /// Cards indented by depth, dependencies first.
+    Text,
+    /// Cards grouped by state.
+    List,
+}
+impl GraphView {
+    pub fn name(self) -> &'static str {
+        match self {
+            GraphView::Image => "image",
+            GraphView::Text => "text",
+            GraphView::List => "list",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        [GraphView::Image, GraphView::Text, GraphView::List]
+            .into_iter()
+            .find(|view| view.name() == name)
+    }
+    fn next(self) -> Self {
+        match self {
+            GraphView::Image => GraphView::Text,
+            GraphView::Text => GraphView::List,
+            GraphView::List => GraphView::Image,
+        }
+    }
+}
+
+pub const MIN_RETENTION: u8 = 70;
+pub const MAX_RETENTION: u8 = 95;
+const RETENTION_STEP: u8 = 5;
+pub const MIN_UTC_OFFSET: i8 = -12;
+pub const MAX_UTC_OFFSET: i8 = 14;
+pub const MAX_DAILY_REVIEW_LIMIT: u32 = 1000;
+pub const MAX_LATENCY_THRESHOLD_SECS: u32 = 600;
+pub const MIN_EXPLANATION_LEN: usize = 100;
+/// Escaping can double text length, so it's half of Telegram message limit.
+pub const MAX_EXPLANATION_LEN: usize = 2000;
+
+/// Stricter of course and learner limits.
+pub fn effective_daily_limit(course: Option<u32>, user: Option<u32>) -> Option<u32> {
+    match (course, user) {
+        (Some(course), Some(user)) => Some(course.min(user)),
+        (course, user) => course.or(user),
+    }
+}
+
+/// Button of settings keyboard. Callback data is `settings:<action>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingsAction {
+    RetentionDown,
+    RetentionUp,
+    UtcOffsetDown,
+    UtcOffsetUp,
+    ToggleGreeting,
+    ToggleCleanup,
+    NextGraphView,
+    ToggleOptionsSummary,
+    Done,
+}
+impl SettingsAction {
+    pub const CALLBACK_PREFIX: &str = "settings:";
+
+    pub fn callback_data(self) -> String {
+        let action = match self {
+            SettingsAction::RetentionDown => "retention-",
+            SettingsAction::RetentionUp => "retention+",
+            SettingsAction::UtcOffsetDown => "utc-",
+            SettingsAction::UtcOffsetUp => "utc+",
+            SettingsAction::ToggleGreeting => "greeting",
+            SettingsAction::ToggleCleanup => "cleanup",
+            SettingsAction::NextGraphView => "graph_view",
+            SettingsAction::ToggleOptionsSummary => "options_summary",
+            SettingsAction::Done => "done",
+        };
+        format!("{}{action}", Self::CALLBACK_PREFIX)
+    }
+    pub fn from_callback_data(data: &str) -> Option<Self> {
+        Some(match data.strip_prefix(Self::CALLBACK_PREFIX)? {
+            "retention-" => SettingsAction::RetentionDown,
+            "retention+" => SettingsAction::RetentionUp,
+            "utc-" => SettingsAction::UtcOffsetDown,
+            "utc+" => SettingsAction::UtcOffsetUp,
+            "greeting" => SettingsAction::ToggleGreeting,
+            "cleanup" => SettingsAction::ToggleCleanup,
+            "graph_view" => SettingsAction::NextGraphView,
+            "options_summary" => SettingsAction::ToggleOptionsSummary,
+            "done" => SettingsAction::Done,
+            _ => return None,
+        })
+    }
+}
+
+impl UserSettings {
+    /// Values are clamped to allowed bounds.
+    pub fn apply(&mut self, action: SettingsAction) {
+        match action {
+            SettingsAction::RetentionDown => {
+                self.desired_retention =
+                    (self.desired_retention.saturating_sub(RETENTION_STEP)).max(MIN_RETENTION)
+            }
+            SettingsAction::RetentionUp => {
+                self.desired_retention =
+                    (self.desired_retention + RETENTION_STEP).min(MAX_RETENTION)
+            }
+            SettingsAction::UtcOffsetDown => {
+                self.utc_offset = (self.utc_offset - 1).max(MIN_UTC_OFFSET)
+            }
+            SettingsAction::UtcOffsetUp => {
+                self.utc_offset = (self.utc_offset + 1).min(MAX_UTC_OFFSET)
+            }
+            SettingsAction::ToggleGreeting => self.session_greeting = !self.session_greeting,
+            SettingsAction::ToggleCleanup => self.cleanup_prompts = !self.cleanup_prompts,
+            SettingsAction::NextGraphView => self.graph_view = self.graph_view.next(),
+            SettingsAction::ToggleOptionsSummary => self.options_summary = !self.options_summary,
+            SettingsAction::Done => (),
+        }
+    }
+    pub fn message(&self) -> String {
+        format!(
+            "Settings\n\nDesired retention: {}%\nTimezone: UTC{:+}\nGreeting on course entry: {}\nClean up prompts: {}\nGraph view: {}\nOptions summary: {}",
+            self.desired_retention,
+            self.utc_offset,
+            if self.session_greeting { "on" } else { "off" },
+            if self.cleanup_prompts { "on" } else { "off" },
+            self.graph_view.name(),
+            if self.options_summary { "on" } else { "off" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn settings_callback_roundtrip() {
+        for action in [
+            SettingsAction::RetentionDown,
+            SettingsAction::RetentionUp,
+            SettingsAction::UtcOffsetDown,
+            SettingsAction::UtcOffsetUp,
+            SettingsAction::ToggleGreeting,
+            SettingsAction::ToggleCleanup,
+            SettingsAction::NextGraphView,
+            SettingsAction::ToggleOptionsSummary,
+            SettingsAction::Done,
+        ] {
+            assert_eq!(
+                SettingsAction::from_callback_data(&action.callback_data()),
+                Some(action)
+            );
+        }
+        assert_eq!(
+            SettingsAction::from_callback_data("123 settings:done"),
+            None
+        );
+    }
+
+    #[test]
+    fn settings_values_are_clamped() {
+        let mut settings = UserSettings::default();
+        for _ in 0..10 {
+            settings.apply(SettingsAction::RetentionUp);
+            settings.apply(SettingsAction::UtcOffsetDown);
+        }
+        assert_eq!(settings.desired_retention, MAX_RETENTION);
+        assert_eq!(settings.utc_offset, -10);
+        for _ in 0..10 {
+            settings.apply(SettingsAction::UtcOffsetDown);
+        }
+        assert_eq!(settings.utc_offset, MIN_UTC_OFFSET);
+    }
+
+    #[test]
+    fn settings_with_dropped_fields_load() {
+        let settings: UserSettings =
+            serde_json::from_str(r#"{"notifications":false,"utc_offset":3}"#).unwrap();
+        assert_eq!(settings.utc_offset, 3);
+        assert_eq!(
+            SettingsAction::from_callback_data("settings:notifications"),
+            None
+        );
+    }
+
+    #[test]
+    fn learner_can_only_lower_daily_limit() {
+        assert_eq!(effective_daily_limit(None, None), None);
+        assert_eq!(effective_daily_limit(Some(20), None), Some(20));
+        assert_eq!(effective_daily_limit(None, Some(10)), Some(10));
+        assert_eq!(effective_daily_limit(Some(20), Some(10)), Some(10));
+        assert_eq!(effective_daily_limit(Some(20), Some(50)), Some(20));
+    }
+}
@@ -1,22 +1,72 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
 use dashmap::mapref::one::RefMut;
 use teloxide_core::types::{MessageId, UserId};
 use tokio::sync::oneshot;
 
-use crate::{database::CourseId, interaction_types::TelegramInteraction};
+use crate::{
+    database::CourseId,
+    interaction_types::{TelegramInteraction, telegram_interaction::QuestionElement},
+};
 
 #[derive(Default)]
 pub struct UserState {
     pub current_screen: Screen,
     pub current_interaction: Option<UserInteraction>,
+    pub session_fails: SessionFails,
+    /// Explanation of last task, learner was suggested to move on from.
+    pub move_on_explanation: Option<(CourseId, Vec<QuestionElement>)>,
 }
 
 pub type MutUserState<'a> = RefMut<'a, UserId, UserState>;
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Screen {
     #[default]
     Main,
     Course(CourseId),
+    Settings,
+}
+
+/// Consecutive fails of cards since bot start. Not persisted.
+#[derive(Default, Debug)]
+pub struct SessionFails {
+    fails: HashMap<(CourseId, String), u32>,
+    /// Cards, learner moved on from.
+    moved_on: HashSet<(CourseId, String)>,
+}
+impl SessionFails {
+    /// Returns true, when `limit` consecutive fails reached and learner should move on.
+    /// Counter starts again after that.
+    pub fn record(
+        &mut self,
+        course_id: CourseId,
+        card: &str,
+        failed: bool,
+        limit: Option<u32>,
+    ) -> bool {
+        let key = (course_id, card.to_owned());
+        if !failed {
+            self.fails.remove(&key);
+            self.moved_on.remove(&key);
+            return false;
+        }
+        let fails = self.fails.entry(key.clone()).or_default();
+        *fails += 1;
+        if limit.is_some_and(|limit| *fails >= limit) {
+            self.fails.remove(&key);
+            self.moved_on.insert(key);
+            return true;
+        }
+        false
+    }
+    /// Such cards shouldn't be offered again in this session.
+    pub fn is_moved_on(&self, course_id: CourseId, card: &str) -> bool {
+        self.moved_on.contains(&(course_id, card.to_owned()))
+    }
 }
 
 #[derive(Debug)]
@@ -26,5 +76,46 @@ pub struct UserInteraction {
     pub current_id: u64,
     pub current_message: Option<MessageId>,
     pub answers: Vec<String>,
+    /// Items, already tapped in current ordering interaction.
+    pub chosen_items: Vec<usize>,
+    /// Telegram id of current poll, answers to other polls are ignored.
+    pub current_poll: Option<String>,
     pub channel: Option<oneshot::Sender<Vec<String>>>,
+    /// Prompts, which are useless after interaction completes.
+    pub transient_messages: Vec<TransientMessage>,
+    /// Delete transient messages on completion.
+    pub cleanup: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TransientMessage {
+    pub id: MessageId,
+    pub sent_at: Instant,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn move_on_after_consecutive_fails() {
+        let course = CourseId(223);
+        let mut fails = SessionFails::default();
+        assert!(!fails.record(course, "card", true, Some(3)));
+        // Success resets counter.
+        assert!(!fails.record(course, "card", false, Some(3)));
+        assert!(!fails.record(course, "card", true, Some(3)));
+        assert!(!fails.record(course, "card", true, Some(3)));
+        assert!(!fails.record(course, "other", true, Some(3)));
+        assert!(!fails.is_moved_on(course, "card"));
+
+        assert!(fails.record(course, "card", true, Some(3)));
+        assert!(fails.is_moved_on(course, "card"));
+        assert!(!fails.is_moved_on(course, "other"));
+        assert!(!fails.record(course, "card", true, Some(3)));
+
+        for _ in 0..10 {
+            assert!(!fails.record(course, "card", true, None));
+        }
+    }
 }
@@ -0,0 +1,87 @@
+//! Starter courses, new owners can create course from.
+
+use course_graph::graph::CourseGraph;
+
+use crate::interaction_types::deque::{self, Deque};
+
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    graph: &'static str,
+    deque: &'static str,
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "geography",
+        description: "Countries and their capitals",
+        graph: include_str!("../../../graph"),
+        deque: include_str!("../../../cards.md"),
+    },
+    Template {
+        name: "arithmetic",
+        description: "Addition, subtraction and multiplication",
+        graph: include_str!("../templates/arithmetic.graph"),
+        deque: include_str!("../templates/arithmetic.md"),
+    },
+];
+
+pub fn find_template(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|template| template.name == name)
+}
+
+impl Template {
+    /// Bundled templates are checked by tests, so this doesn't fail.
+    pub fn build(&self) -> (CourseGraph, Deque) {
+        let graph = CourseGraph::parse(self.graph, false).unwrap();
+        let deque = deque::from_str(self.deque, true, false).unwrap();
+        (graph, deque)
+    }
+}
+
+/// Message for `/templates` command.
+pub fn templates_message() -> String {
+    let mut message = String::from("Available templates:\n");
+    for template in TEMPLATES {
+        message.push_str(&format!("\n{} — {}", template.name, template.description));
+    }
+    message.push_str("\n\nUse /create_course template=NAME to start from one of them.");
+    message
+}
+
+#[cfg(test)]
+mod test {
+    use teloxide_core::types::UserId;
+
+    use super::*;
+    use crate::database::*;
+
+    #[test]
+    fn templates_are_consistent() {
+        for template in TEMPLATES {
+            let (graph, deque) = template.build();
+            assert!(
+                deque.validate_against(&graph).is_consistent(),
+                "template {}",
+                template.name
+            );
+        }
+    }
+
+    #[test]
+    fn course_from_template_has_its_cards() {
+        db_create_tables().unwrap();
+        let (structure, tasks) = find_template("arithmetic").unwrap().build();
+        let course = Course::empty(UserId(244_000))
+            .with(structure, tasks)
+            .unwrap();
+        let course_id = db_insert(course).unwrap();
+        let course = db_get_course(course_id).unwrap().unwrap();
+        assert!(course.structure.cards().contains_key("multiplication"));
+        assert_eq!(
+            course.tasks.tasks.keys().collect::<Vec<_>>(),
+            ["addition", "multiplication", "subtraction"]
+        );
+        assert!(find_template("unknown").is_none());
+    }
+}
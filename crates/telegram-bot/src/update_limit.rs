@@ -0,0 +1,101 @@
+//! Limits number of update handlers, which work at the same time.
+//! Handler, waiting for learner's answer, releases its slot. Otherwise answers could wait
+//! for slots, taken by handlers waiting for these answers.
+
+use std::{cell::RefCell, sync::Arc};
+
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+
+pub const MAX_CONCURRENT_UPDATES_VAR: &str = "MAX_CONCURRENT_UPDATES";
+const DEFAULT_MAX_CONCURRENT_UPDATES: usize = 64;
+
+tokio::task_local! {
+    static SLOT: RefCell<Option<OwnedSemaphorePermit>>;
+}
+
+pub struct UpdateLimiter {
+    slots: Arc<Semaphore>,
+}
+impl UpdateLimiter {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+    pub fn from_env() -> Self {
+        let max_concurrent = match std::env::var(MAX_CONCURRENT_UPDATES_VAR) {
+            Ok(max) => max.parse().ok().filter(|max| *max > 0).unwrap_or_else(|| {
+                panic!("'{MAX_CONCURRENT_UPDATES_VAR}' should be positive integer")
+            }),
+            Err(_) => DEFAULT_MAX_CONCURRENT_UPDATES,
+        };
+        Self::new(max_concurrent)
+    }
+    /// Waits for free slot, so no more updates are fetched, while all slots are busy.
+    pub async fn spawn(
+        &self,
+        handler: impl Future<Output = ()> + Send + 'static,
+    ) -> JoinHandle<()> {
+        let slot = self.slots.clone().acquire_owned().await.unwrap();
+        tokio::spawn(SLOT.scope(RefCell::new(Some(slot)), handler))
+    }
+}
+
+/// Should be called before waiting for learner. Does nothing outside of limited handler.
+pub fn release_slot() {
+    let _ = SLOT.try_with(|slot| slot.borrow_mut().take());
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn burst_is_limited() {
+        static RUNNING: AtomicUsize = AtomicUsize::new(0);
+        static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+        let limiter = UpdateLimiter::new(3);
+        let mut handlers = Vec::new();
+        for _ in 0..20 {
+            handlers.push(
+                limiter
+                    .spawn(async {
+                        let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+                        MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        RUNNING.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await,
+            );
+        }
+        for handler in handlers {
+            handler.await.unwrap();
+        }
+        assert_eq!(MAX_RUNNING.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn waiting_handler_releases_slot() {
+        let limiter = UpdateLimiter::new(1);
+        let (tx, rx) = oneshot::channel();
+        let waiting = limiter
+            .spawn(async move {
+                release_slot();
+                rx.await.unwrap();
+            })
+            .await;
+        // Would wait forever, if waiting handler kept its slot.
+        limiter.spawn(async move { tx.send(()).unwrap() }).await;
+        waiting.await.unwrap();
+    }
+}